@@ -9,6 +9,9 @@
 //! * Client: `iperf3 veth0 10.0.0.1/24 ac:ff:ff:ff:ff:ff 10.0.0.2/24 -c 10.0.0.2 5001 -n 10000 -l 1470 --udp`
 //! * Server: `iperf3 veth1 10.0.0.2/24 ac:ff:ff:fe:ff:ff 10.0.0.1/24 -s 5001 --udp`
 //!
+//! The server runs until interrupted and prints the result of each client. Use `--tcp` instead of
+//! `--udp` for the tcp variants.
+//!
 //! (This uses a locally administered unicast MAC address)
 pub use ethox_iperf::{config, iperf2};
 
@@ -65,9 +68,21 @@ fn main() {
                 &mut eth,
                 &mut ip,
                 iperf2::Server::new(server),
+                |result| println!("{}", result),
             )
-        }
-        _ => unimplemented!("Tcp server is not yet implemented!"),
+        },
+        config::Iperf3Config::Server(
+            config::IperfServer { kind: config::Transport::Tcp, server }
+        ) => {
+            ethox_iperf::server(
+                &mut interface,
+                10,
+                &mut eth,
+                &mut ip,
+                iperf2::ServerTcp::new(server, config.host.address()),
+                |result| println!("{}", result),
+            )
+        },
     };
 
     println!("[+] Done\n");
@@ -9,6 +9,7 @@
 //! There is no control channel as for iperf3. This may have negative impact on the accuracy of the
 //! measurement but greatly simplifies the independent implementation for udp.
 use core::{mem, ptr};
+use core::convert::TryFrom;
 
 use ethox::layer::{ip, tcp, udp, Error};
 use ethox::time::{Duration, Instant};
@@ -38,6 +39,15 @@ pub struct Server {
     udp: udp::Endpoint<'static>,
 }
 
+/// An iperf2 tcp server instance.
+///
+/// Accepts a bounded number of concurrent clients and reports the result of each client after its
+/// connection has been closed.
+pub struct ServerTcp {
+    server: tcp::Server<'static, Counter, tcp::io::Empty>,
+    tcp: tcp::Endpoint<'static>,
+}
+
 struct Connection {
     /// The init parameters for udp.
     send_init: udp::Init,
@@ -116,6 +126,30 @@ struct PatternBuffer {
     at: Option<SeqNumber>,
 }
 
+/// A 'TCP-buffer' counting the received data of one client.
+///
+/// Like `tcp::io::Sink` the data itself is discarded.
+#[derive(Default)]
+struct Counter {
+    /// The sequence number following the received data.
+    highest: Option<SeqNumber>,
+
+    /// Number of bytes received in order.
+    received_bytes: u64,
+
+    /// Number of segments with new data.
+    received_packets: u32,
+
+    /// The largest observed segment.
+    packet_size: usize,
+
+    /// Timestamp of the first segment with data.
+    first: Option<Instant>,
+
+    /// Timestamp of the last segment with data.
+    last: Option<Instant>,
+}
+
 /// The result memory representation.
 ///
 /// Annotations on members are example values observed in real world usage of the original iperf
@@ -316,6 +350,53 @@ impl Server {
     }
 }
 
+impl ServerTcp {
+    /// The number of concurrent clients.
+    const BACKLOG: usize = 4;
+
+    /// Create a server listening on the bind address or the given host address.
+    pub fn new(config: &config::Server, host: v4::Address) -> Self {
+        let local = config.host.map(v4::Address::from).unwrap_or(host);
+        let backlog = (0..Self::BACKLOG)
+            .map(|_| tcp::Client::from_buffers(Counter::default(), tcp::io::Empty::default()))
+            .collect::<Vec<_>>();
+
+        ServerTcp {
+            server: tcp::Server::new(local.into(), config.port, backlog.into()),
+            tcp: Self::generate_tcp(config),
+        }
+    }
+
+    fn generate_tcp(_: &config::Server) -> tcp::Endpoint<'static> {
+        let isn = tcp::IsnGenerator::from_std_hash();
+        // Each entry of the backlog requires its own connection.
+        tcp::Endpoint::new(
+            Map::Pairs(Partial::new(
+                vec![Default::default(); Self::BACKLOG].into())),
+            SlotMap::new(
+                vec![Default::default(); Self::BACKLOG].into(),
+                vec![Default::default(); Self::BACKLOG].into()),
+            isn)
+    }
+}
+
+impl Counter {
+    fn result(&self) -> ServerResult {
+        let duration = match (self.first, self.last) {
+            (Some(first), Some(last)) => last - first,
+            _ => Duration::from_millis(0),
+        };
+
+        ServerResult {
+            packet_size: u32::try_from(self.packet_size).unwrap_or(u32::MAX),
+            packet_count: self.received_packets,
+            received_bytes: self.received_bytes,
+            total_count: self.received_packets,
+            duration,
+        }
+    }
+}
+
 impl ServerConnection {
     pub fn new(config: &config::Server) -> Self {
         let config::Server { host, port } = config;
@@ -342,6 +423,24 @@ impl ServerConnection {
         }
     }
 
+    /// Forget the client, to accept the next one.
+    fn reset(&mut self) {
+        *self = ServerConnection {
+            send_init: udp::Init {
+                dst_addr: Default::default(),
+                dst_port: 0,
+                .. self.send_init
+            },
+            packet_size: 0,
+            received_bytes: 0,
+            max_packet_id: 0,
+            received_packets: 0,
+            result: None,
+            begin_ts: Instant::from_millis(0),
+            result_sent: false,
+        };
+    }
+
     fn fill_report(&self, payload: &mut [u8]) {
         // We prepared this packet, so assert is correct.
         assert_eq!(payload.len(), 20 + mem::size_of::<WireResult>());
@@ -462,6 +561,20 @@ impl<P: PayloadMut> ip::Recv<P> for Server {
     }
 }
 
+impl<P: PayloadMut> ip::Send<P> for ServerTcp {
+    fn send(&mut self, packet: ip::RawPacket<P>) {
+        self.tcp.send(&mut self.server)
+            .send(packet)
+    }
+}
+
+impl<P: PayloadMut> ip::Recv<P> for ServerTcp {
+    fn receive(&mut self, packet: ip::InPacket<P>) {
+        self.tcp.recv(&mut self.server)
+            .receive(packet)
+    }
+}
+
 impl<P: PayloadMut> ip::Send<P> for IperfTcp {
     fn send(&mut self, packet: ip::RawPacket<P>) {
         if !self.client.is_closed() {
@@ -516,17 +629,65 @@ where
     }
 }
 
-impl<Nic> super::Client<Nic> for Server
+impl<Nic> super::Server<Nic> for Server
 where
     Nic: ethox::nic::Device,
     Nic::Payload: PayloadMut + Sized,
 {
-    fn result(&self) -> Option<super::Score> {
+    fn take_result(&mut self) -> Option<super::Score> {
         if !self.connection.result_sent {
             return None;
         }
 
-        self.connection.result.clone().map(|result| result.into())
+        let result = self.connection.result.take();
+        self.connection.reset();
+        result.map(|result| result.into())
+    }
+}
+
+impl<Nic> super::Server<Nic> for ServerTcp
+where
+    Nic: ethox::nic::Device,
+    Nic::Payload: PayloadMut + Sized,
+{
+    fn take_result(&mut self) -> Option<super::Score> {
+        let idx = self.server.backlog().iter()
+            .position(|client| client.is_closed())?;
+        let counter = mem::take(self.server.backlog_mut()[idx].recv_mut());
+        self.server.release(idx);
+        Some(counter.result().into())
+    }
+}
+
+impl tcp::RecvBuf for Counter {
+    fn receive(&mut self, _: &[u8], segment: tcp::ReceivedSegment) {
+        let highest = *self.highest.get_or_insert(segment.begin);
+
+        if !segment.contains_in_window(highest) {
+            return;
+        }
+
+        // Only count data that was not received before. SYN and FIN are not data.
+        let end = segment.sequence_end();
+        let new = (end - highest)
+            .saturating_sub(usize::from(segment.syn) + usize::from(segment.fin));
+        self.highest = Some(end);
+
+        if new > 0 {
+            self.received_bytes += new as u64;
+            self.received_packets += 1;
+            self.packet_size = self.packet_size.max(segment.data_len);
+            self.first.get_or_insert(segment.timestamp);
+            self.last = Some(segment.timestamp);
+        }
+    }
+
+    fn ack(&mut self) -> SeqNumber {
+        self.highest.expect("Must not be called before any isn indication")
+    }
+
+    fn window(&self) -> usize {
+        usize::max_value()
     }
 }
 
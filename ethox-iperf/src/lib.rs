@@ -21,6 +21,17 @@ where
     fn result(&self) -> Option<Score>;
 }
 
+pub trait Server<Nic>:
+    ethox::layer::ip::Recv<Nic::Payload> +
+    ethox::layer::ip::Send<Nic::Payload>
+where
+    Nic: ethox::nic::Device,
+    Nic::Payload: Sized,
+{
+    /// Take the result of a finished client, if any, making room for the next.
+    fn take_result(&mut self) -> Option<Score>;
+}
+
 pub fn client<Nic>(
     nic: &mut Nic,
     burst: usize,
@@ -43,28 +54,28 @@ where
     }
 }
 
-/// Just a clone of `client` for now but should be logically used for server.
+/// Run a server, reporting the result of each client.
+///
+/// The server is reset after each client and never terminates.
 pub fn server<Nic>(
     nic: &mut Nic,
     burst: usize,
     eth: &mut ethox::layer::eth::Endpoint,
     ip: &mut ethox::layer::ip::Endpoint,
-    mut client: impl Client<Nic>,
-) -> Score
+    mut server: impl Server<Nic>,
+    mut report: impl FnMut(Score),
+) -> !
 where
     Nic: ethox::nic::Device,
     Nic::Payload: ethox::wire::PayloadMut + Sized,
     Nic::Handle: Sized,
 {
-    // FIXME:
-    // * reset after a client instead of terminate
-    // * accept more than one client
     loop {
-        let _ = nic.rx(burst, eth.recv(ip.recv(&mut client)));
-        let _ = nic.tx(burst, eth.send(ip.send(&mut client)));
+        let _ = nic.rx(burst, eth.recv(ip.recv(&mut server)));
+        let _ = nic.tx(burst, eth.send(ip.send(&mut server)));
 
-        if let Some(result) = client.result() {
-            return result;
+        while let Some(result) = server.take_result() {
+            report(result);
         }
     }
 }
@@ -1,7 +1,10 @@
+use core::convert::TryFrom;
+
 use crate::layer::{self, FnHandler, Result};
-use crate::wire::{icmpv4, ip, Error, Payload, PayloadMut};
+use crate::layer::ip::ExpiredFragment;
+use crate::wire::{icmpv4, icmpv6, ip, Error, Payload, PayloadMut};
 
-use super::packet::{Controller, In, Raw};
+use super::packet::{Controller, IcmpPacket, In, Out, Raw};
 use super::{Recv, Send};
 
 /// The default handler type when none has been configured.
@@ -134,29 +137,272 @@ impl Endpoint {
 
 impl EndpointRef<'_> {
     /// Try to answer or otherwise handle the packet without propagating it upwards.
-    fn handle_internally<'a, P: PayloadMut>(&mut self, packet: In<'a, P>)
+    fn handle_internally<'a, P: PayloadMut>(&mut self, mut packet: In<'a, P>)
         -> Result<HandlingKind<'a, P>>
     {
-        match packet.packet.repr() {
-            icmpv4::Repr::EchoRequest { .. } if self.inner.manual_echo => {
-                Ok(HandlingKind::ToUpperLayer(packet))
+        match &packet.packet {
+            IcmpPacket::V4(icmp) => match icmp.repr() {
+                icmpv4::Repr::EchoRequest { .. } => self.handle_echo(packet),
+                icmpv4::Repr::DstUnreachable { reason: icmpv4::DstUnreachable::FragRequired, header } => {
+                    // Only a datagram we sent with Don't Fragment set can have caused the report.
+                    // Anything else is forged or corrupted, RFC 5927 section 4.
+                    let quoted = ip::v4::packet::new_unchecked(icmp.payload_slice());
+                    if header.src_addr != icmp.get_ref().repr().dst_addr || !quoted.dont_frag() {
+                        return Ok(HandlingKind::Internal)
+                    }
+
+                    let original_len = header.buffer_len() + header.payload_len;
+                    let mtu = match icmp.next_hop_mtu() {
+                        // Routers that predate RFC 1191 do not report the mtu.
+                        0 => plateau_below(original_len),
+                        mtu => mtu,
+                    };
+
+                    // The datagram did not exceed the reported mtu.
+                    if usize::from(mtu) >= original_len {
+                        return Ok(HandlingKind::Internal)
+                    }
+
+                    let mtu = mtu.max(IPV4_MIN_PATH_MTU);
+                    packet.control.inner.update_path_mtu(header.dst_addr.into(), mtu);
+
+                    // Upper layers may still want to retransmit immediately.
+                    Ok(HandlingKind::ToUpperLayer(packet))
+                },
+                _ => Ok(HandlingKind::ToUpperLayer(packet)),
             },
-            icmpv4::Repr::EchoRequest { .. } => {
-                if self.inner.deny_echo {
-                    return Ok(HandlingKind::Internal)
-                }
+            IcmpPacket::V6(icmp) => match icmp.repr() {
+                icmpv6::Repr::EchoRequest { .. } => self.handle_echo(packet),
+                icmpv6::Repr::PktTooBig { mtu, header } => {
+                    // The path MTU is never below the minimum link MTU, RFC 8201 section 4.
+                    if mtu < IPV6_MIN_MTU {
+                        return Ok(HandlingKind::Internal)
+                    }
+
+                    let mtu = u16::try_from(mtu).unwrap_or(u16::MAX);
+                    packet.control.inner.update_path_mtu(header.dst_addr.into(), mtu);
+
+                    // Upper layers may still want to retransmit immediately.
+                    Ok(HandlingKind::ToUpperLayer(packet))
+                },
+                _ => Ok(HandlingKind::ToUpperLayer(packet)),
+            },
+        }
+    }
 
-                packet
-                    .answer()?
-                    .send()?;
+    /// Answer an echo request, unless configured otherwise.
+    fn handle_echo<'a, P: PayloadMut>(&mut self, packet: In<'a, P>)
+        -> Result<HandlingKind<'a, P>>
+    {
+        if self.inner.manual_echo {
+            return Ok(HandlingKind::ToUpperLayer(packet))
+        }
 
-                Ok(HandlingKind::Internal)
-            },
-            _ => Ok(HandlingKind::ToUpperLayer(packet)),
+        if self.inner.deny_echo {
+            return Ok(HandlingKind::Internal)
         }
+
+        packet
+            .answer()?
+            .send()?;
+
+        Ok(HandlingKind::Internal)
     }
 }
 
+/// The minimum link MTU of IPv6, RFC 8200 section 5.
+const IPV6_MIN_MTU: u32 = 1280;
+
+/// The smallest path MTU learned for IPv4.
+///
+/// The minimum of 68 from RFC 791 would allow an attacker to force tiny fragments, so smaller
+/// reports are raised to the value used by common implementations instead.
+const IPV4_MIN_PATH_MTU: u16 = 552;
+
+/// Estimate the path MTU from the rejected packet size, RFC 1191 section 7.
+fn plateau_below(len: usize) -> u16 {
+    const PLATEAUS: [u16; 7] = [32000, 17914, 8166, 4352, 2002, 1492, 1006];
+    PLATEAUS.iter()
+        .cloned()
+        .find(|&plateau| usize::from(plateau) < len)
+        .unwrap_or(IPV4_MIN_PATH_MTU)
+}
+
+/// The quoted header of a datagram whose reassembly timed out, if it is reported.
+///
+/// Returns `None` when no Time Exceeded message must be sent, in particular never as an answer to
+/// another error message or for packets not between unicast addresses, see RFC 4443 section 2.4.
+fn time_exceeded(control: &layer::ip::Controller, expired: ExpiredFragment)
+    -> Option<ip::v6::Repr>
+{
+    let (src_addr, dst_addr) = match (expired.key.src_addr, expired.key.dst_addr) {
+        (ip::Address::Ipv6(src), ip::Address::Ipv6(dst)) => (src, dst),
+        _ => return None,
+    };
+
+    if !src_addr.is_unicast() || !dst_addr.is_unicast() {
+        return None;
+    }
+
+    let data = control.expired_fragment_data(expired.idx);
+    let informational = matches!(data.first(), Some(&kind) if kind >= 128);
+    if expired.protocol == ip::Protocol::Icmpv6 && !informational {
+        return None;
+    }
+
+    // The quoted packet is the first fragment. Extension headers before the fragment header are
+    // left out.
+    Some(ip::v6::Repr {
+        src_addr,
+        dst_addr,
+        next_header: ip::Protocol::Ipv6Frag,
+        payload_len: fragment_header(expired).buffer_len() + data.len(),
+        hop_limit: expired.hop_limit,
+        dscp: 0,
+        ecn: ip::Ecn::NotEct,
+    })
+}
+
+/// The fragment header of the first fragment of a timed out datagram.
+fn fragment_header(expired: ExpiredFragment) -> ip::v6::fragment::Repr {
+    ip::v6::fragment::Repr {
+        next_header: expired.protocol,
+        frag_offset: 0,
+        more_frags: true,
+        ident: expired.key.ident,
+    }
+}
+
+/// Send a Time Exceeded message, RFC 8200 section 4.5.
+///
+/// Quotes as much of the first fragment as fits into the message.
+fn send_time_exceeded<P: PayloadMut>(
+    raw: layer::ip::RawPacket<P>,
+    expired: ExpiredFragment,
+    header: ip::v6::Repr,
+) -> Result<()> {
+    let repr = icmpv6::Repr::TimeExceeded {
+        reason: icmpv6::TimeExceeded::FragReassemExceeded,
+        header,
+    };
+
+    let prepared = raw.prepare(layer::ip::Init {
+        // Be sure to send from the address the fragments were sent to.
+        source: layer::ip::Source::Exact(expired.key.dst_addr),
+        dst_addr: expired.key.src_addr,
+        protocol: ip::Protocol::Icmpv6,
+        payload: repr.buffer_len(),
+        dscp: 0,
+        ecn: ip::Ecn::NotEct,
+        fragment: false,
+    })?;
+
+    // Temporarily take the packet apart to copy the fragment from the endpoint.
+    let layer::ip::InPacket { control, mut packet } = prepared.into_incoming();
+    {
+        let icmp = icmpv6::packet::new_unchecked_mut(packet.payload_mut().as_mut_slice());
+        repr.emit(icmp, icmpv6::Checksum::Ignored);
+
+        let fragment = fragment_header(expired);
+        let quoted = &mut icmp.payload_mut_slice()[header.buffer_len()..];
+        let (extension, data) = quoted.split_at_mut(fragment.buffer_len());
+        fragment.emit(&mut ip::v6::fragment::Header::new_unchecked(extension));
+
+        let first = control.expired_fragment_data(expired.idx);
+        let len = data.len().min(first.len());
+        data[..len].copy_from_slice(&first[..len]);
+    }
+
+    let packet = match packet {
+        layer::ip::IpPacket::V6(packet) => packet,
+        layer::ip::IpPacket::V4(_) => unreachable!("Time Exceeded for IPv6 is sent with IPv6"),
+    };
+
+    Out::new(
+        Controller { inner: control },
+        IcmpPacket::V6(icmpv6::Packet::new_unchecked(packet, repr)),
+    ).send()
+}
+
+/// Check if a Parameter Problem message may be sent for a packet.
+///
+/// Never to sources that do not identify a single node and only for unrecognized options to
+/// multicast destinations, see RFC 4443 section 2.4.
+fn param_problem(header: ip::v6::Repr, problem: layer::ip::ParamProblem) -> bool {
+    if !header.src_addr.is_unicast() {
+        return false;
+    }
+
+    !header.dst_addr.is_multicast()
+        || problem.reason == icmpv6::ParamProblem::UnrecognizedOption
+}
+
+/// Send a Parameter Problem message, RFC 8200 section 4.
+///
+/// The message is initialized in the buffer of the discarded packet, quoting as much of it as fits.
+fn send_param_problem<'a, P: PayloadMut>(
+    control: layer::ip::Controller<'a>,
+    packet: layer::ip::V6Packet<'a, P>,
+    problem: layer::ip::ParamProblem,
+) -> Result<()> {
+    let header = packet.repr();
+    let repr = icmpv6::Repr::ParamProblem {
+        reason: problem.reason,
+        pointer: problem.pointer,
+        header,
+    };
+
+    // Move the quoted part to the end of the frame, which is the common tail kept when the
+    // buffer is reinitialized and thus ends up directly behind the new icmp header.
+    let mut frame = packet.into_inner();
+    let quoted = repr.buffer_len() - ICMPV6_HEADER_LEN;
+    {
+        let bytes = frame.payload_mut_slice();
+        let end = bytes.len();
+        bytes.copy_within(..quoted, end - quoted);
+    }
+
+    let source = if header.dst_addr.is_multicast() {
+        // Answer from the address of the interface instead.
+        layer::ip::Source::Mask { subnet: ip::Cidr::new(header.src_addr.into(), 0).subnet() }
+    } else {
+        layer::ip::Source::Exact(header.dst_addr.into())
+    };
+
+    let ip_in = layer::ip::InPacket {
+        control,
+        packet: layer::ip::IpPacket::V6(ip::v6::Packet::new_unchecked(frame, header)),
+    };
+
+    let ip_out = ip_in.reinit(layer::ip::Init {
+        source,
+        dst_addr: header.src_addr.into(),
+        protocol: ip::Protocol::Icmpv6,
+        payload: repr.buffer_len(),
+        dscp: 0,
+        ecn: ip::Ecn::NotEct,
+        fragment: false,
+    })?;
+
+    let layer::ip::InPacket { control, mut packet } = ip_out.into_incoming();
+    repr.emit(
+        icmpv6::packet::new_unchecked_mut(packet.payload_mut().as_mut_slice()),
+        icmpv6::Checksum::Ignored);
+
+    let packet = match packet {
+        layer::ip::IpPacket::V6(packet) => packet,
+        layer::ip::IpPacket::V4(_) => unreachable!("Parameter Problem for IPv6 is sent with IPv6"),
+    };
+
+    Out::new(
+        Controller { inner: control },
+        IcmpPacket::V6(icmpv6::Packet::new_unchecked(packet, repr)),
+    ).send()
+}
+
+/// The length of the fixed part of an icmpv6 error message, preceding the quoted packet.
+const ICMPV6_HEADER_LEN: usize = 8;
+
 impl<P, H> layer::ip::Recv<P> for Receiver<'_, H>
 where
     P: PayloadMut,
@@ -172,13 +418,24 @@ where
                 }
 
                 match icmpv4::Packet::new_checked(packet, capabilities.icmpv4().rx_checksum()) {
-                    Ok(packet) => packet,
+                    Ok(packet) => IcmpPacket::V4(packet),
                     Err(Error::Unsupported) => unimplemented!("Forward to upper layer"),
                     Err(_) => return,
                 }
             },
-            // Handle icmpv6
-            _ => return,
+            layer::ip::IpPacket::V6(packet) => {
+                if packet.upper_protocol() != ip::Protocol::Icmpv6 {
+                    return;
+                }
+
+                // The checksum covers a pseudo header of the addresses.
+                let checksum = capabilities.icmpv6().rx_checksum(packet.repr().into());
+                match icmpv6::Packet::new_checked(packet, checksum) {
+                    Ok(packet) => IcmpPacket::V6(packet),
+                    // Neighbor discovery and messages of unknown type are not handled.
+                    Err(_) => return,
+                }
+            },
         };
 
         let control = Controller { inner: control };
@@ -197,6 +454,22 @@ where
             _ => (),
         }
     }
+
+    fn receive_problem(
+        &mut self,
+        layer::ip::InPacket { control, packet }: layer::ip::InPacket<P>,
+        problem: layer::ip::ParamProblem,
+    ) {
+        let packet = match packet {
+            layer::ip::IpPacket::V6(packet) => packet,
+            layer::ip::IpPacket::V4(_) => return,
+        };
+
+        if param_problem(packet.repr(), problem) {
+            // The message is not essential, it is not retried when it could not be sent.
+            let _ = send_param_problem(control, packet, problem);
+        }
+    }
 }
 
 impl<P, T> layer::ip::Send<P> for Sender<'_, T>
@@ -207,6 +480,21 @@ where
     fn send(&mut self, packet: layer::ip::RawPacket<P>) {
         let layer::ip::RawPacket { control: mut eth_handle, payload } = packet;
 
+        // Timed out reassembly is reported before the buffer is offered to the handler.
+        if let Some(expired) = eth_handle.expired_fragment() {
+            let reported = time_exceeded(&eth_handle, expired);
+            if let Some(header) = reported {
+                let raw = layer::ip::RawPacket { control: eth_handle.borrow_mut(), payload };
+                // The message is not essential, it is not retried when it could not be sent.
+                let _ = send_time_exceeded(raw, expired, header);
+            }
+
+            eth_handle.release_expired(expired.idx);
+            if reported.is_some() {
+                return;
+            }
+        }
+
         self.handler.send(Raw {
             control: Controller {
                 inner: eth_handle.borrow_mut()
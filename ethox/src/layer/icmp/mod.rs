@@ -1,6 +1,8 @@
 //! Receiving and sending Icmp messages.
 //!
-//! Only supports Icmpv4 for now.
+//! Supports Icmpv4 within IPv4 and Icmpv6 within IPv6, distinguished by the enum [`IcmpPacket`].
+//! The checksum of Icmpv6 includes a pseudo header of the ip addresses, it is validated and filled
+//! for the respective ip packet.
 //!
 //! Tuned to automate most parts of the icmp procedures *internally*. Nevertheless it will has an
 //! optional interface to forward unhandled messages to a custom receiver. This is in accorance
@@ -27,7 +29,31 @@
 //! ## Other message types
 //!
 //! All other message types can be received in an upper layer or are simply discarded if there is
-//! no upper handler that is ready to inspect packets.
+//! no upper handler that is ready to inspect packets. This includes error messages, of which the
+//! Icmpv4 fragmentation required and the Icmpv6 packet too big message additionally update the
+//! path MTU cache of the ip layer. Icmpv6 messages that can not be represented, such as neighbor
+//! discovery or messages of unknown type, are discarded.
+//!
+//! ## Reassembly timeout
+//!
+//! When the ip layer gave up on reassembling an IPv6 datagram of which the first fragment arrived,
+//! the sender reports this to its source with an Icmpv6 time exceeded message before sending
+//! anything else. The buffer of the datagram is only freed afterwards.
+//!
+//! ## Parameter problems
+//!
+//! IPv6 packets discarded by the ip layer for an unrecognized option or a routing header with
+//! segments left are answered by the receiver with an Icmpv6 parameter problem message, pointing
+//! at the offending octet. The message is initialized in the buffer of the discarded packet.
+//!
+//! ## Neighbor discovery
+//!
+//! Neighbor solicitations are **not** answered and the link layer address of their sender is not
+//! learned. A peer can thus only reach the stack over IPv6, for example to ping it, with a static
+//! neighbor entry for our address, and the stack needs one for the peer in its [`NeighborCache`].
+//!
+//! [`NeighborCache`]: ../arp/struct.NeighborCache.html
+//! [`IcmpPacket`]: enum.IcmpPacket.html
 use crate::wire::Payload;
 
 mod endpoint;
@@ -43,6 +69,7 @@ pub use endpoint::{
 
 pub use packet::{
     Controller,
+    IcmpPacket,
     Init,
     In as InPacket,
     Out as OutPacket,
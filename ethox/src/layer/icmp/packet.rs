@@ -1,7 +1,7 @@
 use crate::nic::Info;
 use crate::layer::{Error, Result, ip};
-use crate::wire::{icmpv4, Checksum, Payload, PayloadMut};
-use crate::wire::ip::{Address as IpAddress, Protocol as IpProtocol};
+use crate::wire::{icmpv4, icmpv6, Checksum, Payload, PayloadMut};
+use crate::wire::ip::{Address as IpAddress, Ecn, Protocol as IpProtocol};
 
 /// An incoming packet.
 ///
@@ -12,7 +12,7 @@ pub struct In<'a, P: Payload> {
     /// A reference to the ICMP endpoint state.
     pub control: Controller<'a>,
     /// The valid packet inside the buffer.
-    pub packet: icmpv4::Packet<ip::V4Packet<'a, P>>,
+    pub packet: IcmpPacket<'a, P>,
 }
 
 /// An outgoing packet as prepared by the icmp layer.
@@ -22,7 +22,17 @@ pub struct In<'a, P: Payload> {
 #[must_use = "You need to call `send` explicitely on an OutPacket, otherwise no packet is sent."]
 pub struct Out<'a, P: Payload> {
     control: Controller<'a>,
-    packet: icmpv4::Packet<ip::V4Packet<'a, P>>,
+    packet: IcmpPacket<'a, P>,
+}
+
+/// A valid ICMP packet buffer.
+///
+/// The version follows the version of the ip packet, ICMPv4 within IPv4 and ICMPv6 within IPv6.
+pub enum IcmpPacket<'a, P: Payload> {
+    /// Containing an ICMPv4 packet.
+    V4(icmpv4::Packet<ip::V4Packet<'a, P>>),
+    /// Containing an ICMPv6 packet.
+    V6(icmpv6::Packet<ip::V6Packet<'a, P>>),
 }
 
 /// A buffer into which a packet can be placed.
@@ -91,7 +101,7 @@ impl<'a, P: Payload> In<'a, P> {
     {
         Raw {
             control: self.control,
-            payload: self.packet.into_inner().into_inner().into_inner(),
+            payload: self.packet.into_inner().into_raw(),
         }
     }
 }
@@ -99,58 +109,120 @@ impl<'a, P: Payload> In<'a, P> {
 impl<'a, P: PayloadMut> In<'a, P> {
     /// Try to answer an icmp ping request in-place.
     pub fn answer(self) -> Result<Out<'a, P>> {
-        let answer = match self.packet.repr() {
-            icmpv4::Repr::EchoRequest { ident, seq_no, payload } => {
-                icmpv4::Repr::EchoReply { ident, seq_no, payload }
+        let In { control, packet } = self;
+
+        match packet {
+            IcmpPacket::V4(packet) => {
+                let answer = packet.repr().echo_reply().ok_or(Error::Illegal)?;
+                let ip_out = reverse(control, ip::IpPacket::V4(packet.into_inner()), IpProtocol::Icmp)?;
+
+                // Temporarily take the packet apart for inner repr.
+                let ip::InPacket { control, mut packet } = ip_out.into_incoming();
+                answer.emit(
+                    icmpv4::packet::new_unchecked_mut(packet.payload_mut().as_mut_slice()),
+                    Checksum::Manual);
+                let packet = match packet {
+                    ip::IpPacket::V4(packet) => packet,
+                    ip::IpPacket::V6(_) => unreachable!("Answer to IPv4 is IPv4"),
+                };
+
+                Ok(Out {
+                    control: Controller { inner: control },
+                    packet: IcmpPacket::V4(icmpv4::Packet::new_unchecked(packet, answer)),
+                })
             },
-            _ => return Err(Error::Illegal),
-        };
+            IcmpPacket::V6(packet) => {
+                let answer = packet.repr().echo_reply().ok_or(Error::Illegal)?;
+                let ip_out = reverse(control, ip::IpPacket::V6(packet.into_inner()), IpProtocol::Icmpv6)?;
 
-        // Try to reverse the ip packet.
-        let ipv4_packet = self.packet.into_inner();
-        let ip_repr = ipv4_packet.repr();
-        let ip_in = ip::InPacket {
-            control: self.control.inner,
-            packet: ip::IpPacket::V4(ipv4_packet),
-        };
+                // The checksum is filled when sending, the pseudo header is not known here.
+                let ip::InPacket { control, mut packet } = ip_out.into_incoming();
+                answer.emit(
+                    icmpv6::packet::new_unchecked_mut(packet.payload_mut().as_mut_slice()),
+                    icmpv6::Checksum::Ignored);
+                let packet = match packet {
+                    ip::IpPacket::V6(packet) => packet,
+                    ip::IpPacket::V4(_) => unreachable!("Answer to IPv6 is IPv6"),
+                };
 
-        let ip_out = ip_in.reinit(ip::Init {
-            // Be sure to send from this exact address.
-            source: IpAddress::from(ip_repr.dst_addr).into(),
-            dst_addr: ip_repr.src_addr.into(),
-            protocol: IpProtocol::Icmp,
-            payload: ip_repr.payload_len,
-        })?;
-
-        // Temporarily take the packet apart for inner repr.
-        let ip::InPacket { control, mut packet } = ip_out.into_incoming();
-        answer.emit(
-            icmpv4::packet::new_unchecked_mut(packet.payload_mut().as_mut_slice()),
-            Checksum::Manual);
-        let packet = match packet {
-            ip::IpPacket::V4(packet) => packet,
-            ip::IpPacket::V6(_) => unreachable!("No icmpv6 outgoing traffic"),
-        };
+                Ok(Out {
+                    control: Controller { inner: control },
+                    packet: IcmpPacket::V6(icmpv6::Packet::new_unchecked(packet, answer)),
+                })
+            },
+        }
+    }
+}
 
-        Ok(Out {
-            control: Controller { inner: control },
-            packet: icmpv4::Packet::new_unchecked(packet, answer),
-        })
+/// Reinitialize an ip packet towards its source, keeping the payload in place.
+///
+/// The payload is the common tail of the buffer. With IPv6 extension headers in the received
+/// packet, the message thus still ends up directly behind the new fixed header.
+fn reverse<'a, P: PayloadMut>(control: Controller<'a>, packet: ip::IpPacket<'a, P>, protocol: IpProtocol)
+    -> Result<ip::OutPacket<'a, P>>
+{
+    let ip_repr = packet.repr();
+    let ip_in = ip::InPacket {
+        control: control.inner,
+        packet,
+    };
+
+    ip_in.reinit(ip::Init {
+        // Be sure to send from this exact address.
+        source: ip_repr.dst_addr().into(),
+        dst_addr: ip_repr.src_addr(),
+        protocol,
+        payload: ip_repr.payload_len(),
+        dscp: ip_repr.dscp(),
+        ecn: Ecn::NotEct,
+        fragment: false,
+    })
+}
+
+impl<'a, P: Payload> IcmpPacket<'a, P> {
+    /// Turn the packet into its ip layer representation.
+    pub fn into_inner(self) -> ip::IpPacket<'a, P> {
+        match self {
+            IcmpPacket::V4(packet) => ip::IpPacket::V4(packet.into_inner()),
+            IcmpPacket::V6(packet) => ip::IpPacket::V6(packet.into_inner()),
+        }
+    }
+}
+
+impl<'a, P: PayloadMut> IcmpPacket<'a, P> {
+    /// A mutable slice containing the payload of the icmp message.
+    pub fn payload_mut_slice(&mut self) -> &mut [u8] {
+        match self {
+            IcmpPacket::V4(packet) => packet.payload_mut_slice(),
+            IcmpPacket::V6(packet) => packet.payload_mut_slice(),
+        }
     }
 }
 
 
 impl<'a, P: Payload> Out<'a, P> {
+    /// Pair an initialized packet with its controller.
+    pub(crate) fn new(control: Controller<'a>, packet: IcmpPacket<'a, P>) -> Self {
+        Out { control, packet }
+    }
+
     /// Called last after having initialized the payload.
     pub fn send(mut self) -> Result<()>
         where P: PayloadMut,
     {
         let capabilities = self.control.info().capabilities();
-        let checksum = capabilities.icmpv4().tx_checksum();
-        self.packet.fill_checksum(checksum);
+        match &mut self.packet {
+            IcmpPacket::V4(packet) => {
+                packet.fill_checksum(capabilities.icmpv4().tx_checksum());
+            },
+            IcmpPacket::V6(packet) => {
+                let ip_repr = packet.get_ref().repr().into();
+                packet.fill_checksum(capabilities.icmpv6().tx_checksum(ip_repr));
+            },
+        }
         let lower = ip::OutPacket::new_unchecked(
             self.control.inner,
-            ip::IpPacket::V4(self.packet.into_inner()));
+            self.packet.into_inner());
         lower.send()
     }
 }
@@ -160,8 +232,6 @@ impl<'a, P: PayloadMut> Out<'a, P> {
     ///
     /// The semantics of the payload differ for the defined operations. See a guide to ICMP for the
     /// details.
-    ///
-    /// This function will also work for ICMPv6 (currently unimplemented).
     pub fn payload_mut_slice(&mut self) -> &mut [u8] {
         self.packet.payload_mut_slice()
     }
@@ -179,37 +249,53 @@ impl<'a, P: Payload + PayloadMut> Raw<'a, P> {
         let prepared = lower.prepare(lower_init)?;
         let ip::InPacket { control, packet } = prepared.into_incoming();
 
-        let mut packet = match packet {
-            ip::IpPacket::V4(packet) => packet,
-            ip::IpPacket::V6(_) => unreachable!(),
+        let packet = match packet {
+            ip::IpPacket::V4(mut packet) => {
+                let repr = init.initialize_v4(&mut packet);
+                IcmpPacket::V4(icmpv4::Packet::new_unchecked(packet, repr))
+            },
+            ip::IpPacket::V6(mut packet) => {
+                let repr = init.initialize_v6(&mut packet);
+                IcmpPacket::V6(icmpv6::Packet::new_unchecked(packet, repr))
+            },
         };
-        let repr = init.initialize(&mut packet)?;
 
         Ok(Out {
             control: Controller { inner: control },
-            packet: icmpv4::Packet::new_unchecked(packet, repr),
+            packet,
         })
     }
 }
 
 impl Init {
-    fn initialize(&self, payload: &mut impl PayloadMut) -> Result<icmpv4::Repr> {
-        let repr = self.repr();
+    fn initialize_v4(&self, payload: &mut impl PayloadMut) -> icmpv4::Repr {
+        let repr = match *self {
+            Init::EchoRequest { ident, seq_no, payload, .. } => {
+                icmpv4::Repr::EchoRequest { ident, seq_no, payload }
+            },
+        };
 
         // Assumes length was already dealt with.
         let packet = icmpv4::packet::new_unchecked_mut(
             payload.payload_mut().as_mut_slice());
         repr.emit(packet, Checksum::Ignored);
 
-        Ok(repr)
+        repr
     }
 
-    fn repr(&self) -> icmpv4::Repr {
-        match *self {
+    fn initialize_v6(&self, payload: &mut impl PayloadMut) -> icmpv6::Repr {
+        let repr = match *self {
             Init::EchoRequest { ident, seq_no, payload, .. } => {
-                icmpv4::Repr::EchoRequest { ident, seq_no, payload }
+                icmpv6::Repr::EchoRequest { ident, seq_no, payload }
             },
-        }
+        };
+
+        // Assumes length was already dealt with.
+        let packet = icmpv6::packet::new_unchecked_mut(
+            payload.payload_mut().as_mut_slice());
+        repr.emit(packet, icmpv6::Checksum::Ignored);
+
+        repr
     }
 
     fn ip_init(&self) -> Result<ip::Init> {
@@ -218,11 +304,18 @@ impl Init {
                 let len = payload
                     .checked_add(8)
                     .ok_or(Error::BadSize)?;
+                let protocol = match dst_addr {
+                    IpAddress::Ipv6(_) => IpProtocol::Icmpv6,
+                    _ => IpProtocol::Icmp,
+                };
                 ip::Init {
                     source,
                     dst_addr,
-                    protocol: IpProtocol::Icmp,
+                    protocol,
                     payload: len,
+                    dscp: 0,
+                    ecn: Ecn::NotEct,
+                    fragment: false,
                 }
             },
         })
@@ -1,12 +1,16 @@
 use crate::managed::Slice;
+use crate::storage::assembler::Contig;
 use crate::nic::{loopback::Loopback, Device};
 use crate::layer::{arp, eth, ip, icmp};
-use crate::wire::{ethernet::Address, ip::Cidr, ip::v4, PayloadMut};
+use crate::time::Instant;
+use crate::wire::{ethernet::Address, icmpv4, icmpv6, ip::Cidr, ip::v4, ip::v6, Checksum, PayloadMut};
 
 const MAC_ADDR_HOST: Address = Address([0, 1, 2, 3, 4, 5]);
 const IP_ADDR_HOST: v4::Address = v4::Address::new(127, 0, 0, 1);
 const MAC_ADDR_OTHER: Address = Address([6, 5, 4, 3, 2, 1]);
 const IP_ADDR_OTHER: v4::Address = v4::Address::new(127, 0, 0, 2);
+const IP6_ADDR_HOST: v6::Address = v6::Address::from_link_local_id(v6::InterfaceId::from_generated_ether(MAC_ADDR_HOST));
+const IP6_ADDR_OTHER: v6::Address = v6::Address::from_link_local_id(v6::InterfaceId::from_generated_ether(MAC_ADDR_OTHER));
 
 static PING_BYTES: [u8; 50] =
     [   
@@ -47,6 +51,393 @@ fn answer_ping() {
    assert_eq!(recv, Ok(1));
 }
 
+#[test]
+fn frag_required_lowers_path_mtu() {
+    let mut nic = Loopback::<Vec<u8>>::new(vec![0; 1 << 12].into());
+
+    let mut eth = eth::Endpoint::new(MAC_ADDR_HOST);
+
+    let mut neighbors = [arp::Neighbor::default(); 1];
+    let mut ip = ip::Endpoint::new(Cidr::new(IP_ADDR_HOST.into(), 24),
+        ip::Routes::new(Slice::empty()),
+        arp::NeighborCache::new(&mut neighbors[..]));
+    let mut path_mtu = [ip::PathMtu::default(); 1];
+    ip.set_path_mtu_cache(ip::PathMtuCache::new(&mut path_mtu[..]));
+
+    let mut icmp = icmp::Endpoint::new();
+    let remote = v4::Address::new(10, 0, 0, 1);
+
+    // Reports not matching a datagram we could have sent are ignored.
+    let forged = [
+        (IP_ADDR_OTHER, true, 1400),
+        (IP_ADDR_HOST, false, 1400),
+        (IP_ADDR_HOST, true, 1500),
+    ];
+    for &(quoted_src, dont_frag, mtu) in forged.iter() {
+        queue_frag_required(&mut nic, quoted_src, dont_frag, mtu);
+        let recv = nic.rx(1, eth.recv(ip.recv(
+            icmp.answer())));
+        assert_eq!(recv, Ok(1));
+        assert_eq!(ip.path_mtu_cache().lookup(remote.into(), Instant::from_millis(0)), None);
+    }
+
+    queue_frag_required(&mut nic, IP_ADDR_HOST, true, 1400);
+    let recv = nic.rx(1, eth.recv(ip.recv(
+        icmp.answer())));
+    assert_eq!(recv, Ok(1));
+    assert_eq!(ip.path_mtu_cache().lookup(remote.into(), Instant::from_millis(0)), Some(1400));
+
+    // Tiny values are raised to a sane minimum.
+    queue_frag_required(&mut nic, IP_ADDR_HOST, true, 68);
+    let recv = nic.rx(1, eth.recv(ip.recv(
+        icmp.answer())));
+    assert_eq!(recv, Ok(1));
+    assert_eq!(ip.path_mtu_cache().lookup(remote.into(), Instant::from_millis(0)), Some(552));
+}
+
+#[test]
+fn answer_ping_v6() {
+    let mut nic = Loopback::<Vec<u8>>::new(vec![0; 1 << 12].into());
+
+    let mut eth_other = eth::Endpoint::new(MAC_ADDR_OTHER);
+    let mut neighbors_other = [arp::Neighbor::default(); 1];
+    let mut ip_other = ip::Endpoint::new(Cidr::new(IP6_ADDR_OTHER.into(), 64),
+        ip::Routes::new(Slice::empty()),
+        {
+            let mut eth_cache = arp::NeighborCache::new(&mut neighbors_other[..]);
+            eth_cache.fill(IP6_ADDR_HOST.into(), MAC_ADDR_HOST, None).unwrap();
+            eth_cache
+        });
+    let mut icmp_other = icmp::Endpoint::new();
+
+    nic.tx(1, eth_other.send(ip_other.send(icmp_other.send_with(|packet: icmp::RawPacket<_>| {
+        let init = icmp::Init::EchoRequest {
+            source: ip::Source::Exact(IP6_ADDR_OTHER.into()),
+            dst_addr: IP6_ADDR_HOST.into(),
+            ident: 0x1234,
+            seq_no: 1,
+            payload: PING_BYTES.len(),
+        };
+        let mut packet = packet.prepare(init)
+            .expect("Can initialize to the host");
+        packet
+            .payload_mut_slice()
+            .copy_from_slice(&PING_BYTES[..]);
+        packet
+            .send()
+            .expect("Can send the packet");
+    })))).expect("Ping can be queued.");
+
+    let mut eth = eth::Endpoint::new(MAC_ADDR_HOST);
+    let mut neighbors = [arp::Neighbor::default(); 1];
+    let mut ip = ip::Endpoint::new(Cidr::new(IP6_ADDR_HOST.into(), 64),
+        ip::Routes::new(Slice::empty()),
+        {
+            let mut eth_cache = arp::NeighborCache::new(&mut neighbors[..]);
+            eth_cache.fill(IP6_ADDR_OTHER.into(), MAC_ADDR_OTHER, None).unwrap();
+            eth_cache
+        });
+    let mut icmp = icmp::Endpoint::new();
+
+    let recv = nic.rx(1, eth.recv(ip.recv(
+        icmp.answer())));
+    assert_eq!(recv, Ok(1));
+
+    // The reply only arrives with a valid checksum over the pseudo header.
+    let mut replies = 0;
+    let recv = nic.rx(1, eth_other.recv(ip_other.recv(icmp_other.recv_with(|mut packet: icmp::InPacket<_>| {
+        match &packet.packet {
+            icmp::IcmpPacket::V6(icmp) => assert_eq!(icmp.repr(), icmpv6::Repr::EchoReply {
+                ident: 0x1234,
+                seq_no: 1,
+                payload: PING_BYTES.len(),
+            }),
+            _ => panic!("Expected an ICMPv6 echo reply"),
+        }
+        assert_eq!(packet.packet.payload_mut_slice(), &PING_BYTES[..]);
+        replies += 1;
+    }))));
+    assert_eq!(recv, Ok(1));
+    assert_eq!(replies, 1);
+}
+
+#[test]
+fn pkt_too_big_lowers_path_mtu() {
+    let mut nic = Loopback::<Vec<u8>>::new(vec![0; 1 << 12].into());
+
+    // The host sent a full sized segment to some remote behind the other router.
+    let remote = v6::Address::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+    let repr = icmpv6::Repr::PktTooBig {
+        mtu: 1400,
+        header: v6::Repr {
+            src_addr: IP6_ADDR_HOST,
+            dst_addr: remote,
+            next_header: crate::wire::ip::Protocol::Tcp,
+            payload_len: 1460,
+            hop_limit: 64,
+            dscp: 0,
+            ecn: crate::wire::ip::Ecn::NotEct,
+        },
+    };
+
+    let mut eth = eth::Endpoint::new(MAC_ADDR_OTHER);
+    let mut neighbors = [arp::Neighbor::default(); 1];
+    let mut ip = ip::Endpoint::new(Cidr::new(IP6_ADDR_OTHER.into(), 64),
+        ip::Routes::new(Slice::empty()),
+        {
+            let mut eth_cache = arp::NeighborCache::new(&mut neighbors[..]);
+            eth_cache.fill(IP6_ADDR_HOST.into(), MAC_ADDR_HOST, None).unwrap();
+            eth_cache
+        });
+
+    nic.tx(1, eth.send(ip.send_with(|packet: ip::RawPacket<_>| {
+        let init = ip::Init {
+            source: ip::Source::Exact(IP6_ADDR_OTHER.into()),
+            dst_addr: IP6_ADDR_HOST.into(),
+            protocol: crate::wire::ip::Protocol::Icmpv6,
+            payload: repr.buffer_len(),
+            dscp: 0,
+            ecn: crate::wire::ip::Ecn::NotEct,
+            fragment: false,
+        };
+        let mut packet = packet.prepare(init)
+            .expect("Can initialize to the host");
+        let icmp = icmpv6::packet::new_unchecked_mut(packet.payload_mut_slice());
+        repr.emit(icmp, icmpv6::Checksum::Manual {
+            src_addr: IP6_ADDR_OTHER.into(),
+            dst_addr: IP6_ADDR_HOST.into(),
+        });
+        packet
+            .send()
+            .expect("Can send the packet");
+    }))).expect("Message can be queued.");
+
+    let mut eth = eth::Endpoint::new(MAC_ADDR_HOST);
+    let mut neighbors = [arp::Neighbor::default(); 1];
+    let mut ip = ip::Endpoint::new(Cidr::new(IP6_ADDR_HOST.into(), 64),
+        ip::Routes::new(Slice::empty()),
+        arp::NeighborCache::new(&mut neighbors[..]));
+    let mut path_mtu = [ip::PathMtu::default(); 1];
+    ip.set_path_mtu_cache(ip::PathMtuCache::new(&mut path_mtu[..]));
+
+    let mut icmp = icmp::Endpoint::new();
+
+    // The error is also given to the upper layer.
+    let mut errors = 0;
+    let recv = nic.rx(1, eth.recv(ip.recv(icmp.recv_with(|packet: icmp::InPacket<_>| {
+        match packet.packet {
+            icmp::IcmpPacket::V6(icmp) => assert_eq!(icmp.repr(), repr),
+            _ => panic!("Expected an ICMPv6 error"),
+        }
+        errors += 1;
+    }))));
+    assert_eq!(recv, Ok(1));
+    assert_eq!(errors, 1);
+
+    assert_eq!(ip.path_mtu_cache().lookup(remote.into(), Instant::from_millis(0)), Some(1400));
+}
+
+#[test]
+fn reassembly_timeout_v6() {
+    let mut nic = Loopback::<Vec<u8>>::new(vec![0; 1 << 12].into());
+
+    let mut eth_other = eth::Endpoint::new(MAC_ADDR_OTHER);
+    let mut neighbors_other = [arp::Neighbor::default(); 1];
+    let mut ip_other = ip::Endpoint::new(Cidr::new(IP6_ADDR_OTHER.into(), 64),
+        ip::Routes::new(Slice::empty()),
+        {
+            let mut eth_cache = arp::NeighborCache::new(&mut neighbors_other[..]);
+            eth_cache.fill(IP6_ADDR_HOST.into(), MAC_ADDR_HOST, None).unwrap();
+            eth_cache
+        });
+    let mut icmp_other = icmp::Endpoint::new();
+
+    // Only the first fragment of a datagram ever arrives.
+    let fragment = v6::fragment::Repr {
+        next_header: crate::wire::ip::Protocol::Udp,
+        frag_offset: 0,
+        more_frags: true,
+        ident: 0x1234_5678,
+    };
+    nic.tx(1, eth_other.send(ip_other.send_with(|packet: ip::RawPacket<_>| {
+        let init = ip::Init {
+            source: ip::Source::Exact(IP6_ADDR_OTHER.into()),
+            dst_addr: IP6_ADDR_HOST.into(),
+            protocol: crate::wire::ip::Protocol::Ipv6Frag,
+            payload: fragment.buffer_len() + 48,
+            dscp: 0,
+            ecn: crate::wire::ip::Ecn::NotEct,
+            fragment: false,
+        };
+        let mut packet = packet.prepare(init)
+            .expect("Can initialize to the host");
+        let (header, data) = packet.payload_mut_slice().split_at_mut(fragment.buffer_len());
+        fragment.emit(&mut v6::fragment::Header::new_unchecked(header));
+        data.copy_from_slice(&PING_BYTES[..48]);
+        packet
+            .send()
+            .expect("Can send the packet");
+    }))).expect("Fragment can be queued.");
+
+    let mut eth = eth::Endpoint::new(MAC_ADDR_HOST);
+    let mut neighbors = [arp::Neighbor::default(); 1];
+    let mut ip = ip::Endpoint::new(Cidr::new(IP6_ADDR_HOST.into(), 64),
+        ip::Routes::new(Slice::empty()),
+        {
+            let mut eth_cache = arp::NeighborCache::new(&mut neighbors[..]);
+            eth_cache.fill(IP6_ADDR_OTHER.into(), MAC_ADDR_OTHER, None).unwrap();
+            eth_cache
+        });
+    let mut buffers = [ip::ReassemblyBuffer::new(vec![0; 128], vec![Contig::default(); 128 / 16 + 1])];
+    ip.set_reassembly(ip::Reassembly::new(&mut buffers[..]));
+    let mut icmp = icmp::Endpoint::new();
+
+    let recv = nic.rx(1, eth.recv(ip.recv(
+        icmp.answer())));
+    assert_eq!(recv, Ok(1));
+    assert_eq!(ip.reassembly().in_progress(Instant::from_millis(0)), 1);
+
+    // Nothing to report before the timeout.
+    let sent = nic.tx(1, eth.send(ip.send(icmp.send_with(|_: icmp::RawPacket<_>| ()))));
+    assert_eq!(sent, Ok(0));
+
+    nic.set_current_time(Instant::from_millis(0) + ip::Reassembly::DEFAULT_TIMEOUT);
+    let sent = nic.tx(1, eth.send(ip.send(icmp.send_with(|_: icmp::RawPacket<_>| {
+        panic!("The timeout is reported first");
+    }))));
+    assert_eq!(sent, Ok(1));
+
+    // The source receives the first fragment back, with its fragment header.
+    let mut errors = 0;
+    let recv = nic.rx(1, eth_other.recv(ip_other.recv(icmp_other.recv_with(|packet: icmp::InPacket<_>| {
+        let icmp = match &packet.packet {
+            icmp::IcmpPacket::V6(icmp) => icmp,
+            _ => panic!("Expected an ICMPv6 error"),
+        };
+        assert_eq!(icmp.repr(), icmpv6::Repr::TimeExceeded {
+            reason: icmpv6::TimeExceeded::FragReassemExceeded,
+            header: v6::Repr {
+                src_addr: IP6_ADDR_OTHER,
+                dst_addr: IP6_ADDR_HOST,
+                next_header: crate::wire::ip::Protocol::Ipv6Frag,
+                payload_len: fragment.buffer_len() + 48,
+                hop_limit: u8::MAX,
+                dscp: 0,
+                ecn: crate::wire::ip::Ecn::NotEct,
+            },
+        });
+        let (header, data) = icmp.payload_slice()[40..].split_at(fragment.buffer_len());
+        assert_eq!(v6::fragment::Repr::parse(&v6::fragment::Header::new_unchecked(header)), Ok(fragment));
+        assert_eq!(data, &PING_BYTES[..48]);
+        errors += 1;
+    }))));
+    assert_eq!(recv, Ok(1));
+    assert_eq!(errors, 1);
+
+    // The buffer is free again and the timeout is reported only once.
+    assert_eq!(ip.reassembly().in_progress(Instant::from_millis(0)), 0);
+    let sent = nic.tx(1, eth.send(ip.send(icmp.send_with(|_: icmp::RawPacket<_>| ()))));
+    assert_eq!(sent, Ok(0));
+}
+
+#[test]
+fn param_problem_v6() {
+    let mut nic = Loopback::<Vec<u8>>::new(vec![0; 1 << 12].into());
+
+    let mut eth_other = eth::Endpoint::new(MAC_ADDR_OTHER);
+    let mut neighbors_other = [arp::Neighbor::default(); 1];
+    let mut ip_other = ip::Endpoint::new(Cidr::new(IP6_ADDR_OTHER.into(), 64),
+        ip::Routes::new(Slice::empty()),
+        {
+            let mut eth_cache = arp::NeighborCache::new(&mut neighbors_other[..]);
+            eth_cache.fill(IP6_ADDR_HOST.into(), MAC_ADDR_HOST, None).unwrap();
+            eth_cache
+        });
+    let mut icmp_other = icmp::Endpoint::new();
+
+    let mut eth = eth::Endpoint::new(MAC_ADDR_HOST);
+    let mut neighbors = [arp::Neighbor::default(); 1];
+    let mut ip = ip::Endpoint::new(Cidr::new(IP6_ADDR_HOST.into(), 64),
+        ip::Routes::new(Slice::empty()),
+        {
+            let mut eth_cache = arp::NeighborCache::new(&mut neighbors[..]);
+            eth_cache.fill(IP6_ADDR_OTHER.into(), MAC_ADDR_OTHER, None).unwrap();
+            eth_cache
+        });
+    let mut icmp = icmp::Endpoint::new();
+
+    let problems: [(_, [u8; 8], _, u32); 2] = [
+        // An unknown destination option whose action requires a report, behind padding.
+        (
+            crate::wire::ip::Protocol::Ipv6Opts,
+            [17, 0, 1, 0, 0x9e, 2, 0, 0],
+            icmpv6::ParamProblem::UnrecognizedOption,
+            44,
+        ),
+        // A routing header with segments left, reported at its routing type.
+        (
+            crate::wire::ip::Protocol::Ipv6Route,
+            [17, 0, 0, 1, 0, 0, 0, 0],
+            icmpv6::ParamProblem::ErroneousHdrField,
+            42,
+        ),
+    ];
+
+    for &(protocol, extension, reason, pointer) in problems.iter() {
+        nic.tx(1, eth_other.send(ip_other.send_with(|packet: ip::RawPacket<_>| {
+            let init = ip::Init {
+                source: ip::Source::Exact(IP6_ADDR_OTHER.into()),
+                dst_addr: IP6_ADDR_HOST.into(),
+                protocol,
+                payload: 16,
+                dscp: 0,
+                ecn: crate::wire::ip::Ecn::NotEct,
+                fragment: false,
+            };
+            let mut packet = packet.prepare(init)
+                .expect("Can initialize to the host");
+            let (header, data) = packet.payload_mut_slice().split_at_mut(8);
+            header.copy_from_slice(&extension);
+            data.copy_from_slice(&PING_BYTES[..8]);
+            packet
+                .send()
+                .expect("Can send the packet");
+        }))).expect("Packet can be queued.");
+
+        let recv = nic.rx(1, eth.recv(ip.recv(
+            icmp.answer())));
+        assert_eq!(recv, Ok(1));
+
+        // The source receives the whole packet back, with a pointer to the offending octet.
+        let mut errors = 0;
+        let recv = nic.rx(1, eth_other.recv(ip_other.recv(icmp_other.recv_with(|packet: icmp::InPacket<_>| {
+            let icmp = match &packet.packet {
+                icmp::IcmpPacket::V6(icmp) => icmp,
+                _ => panic!("Expected an ICMPv6 error"),
+            };
+            assert_eq!(icmp.repr(), icmpv6::Repr::ParamProblem {
+                reason,
+                pointer,
+                header: v6::Repr {
+                    src_addr: IP6_ADDR_OTHER,
+                    dst_addr: IP6_ADDR_HOST,
+                    next_header: protocol,
+                    payload_len: 16,
+                    hop_limit: u8::MAX,
+                    dscp: 0,
+                    ecn: crate::wire::ip::Ecn::NotEct,
+                },
+            });
+            let (header, data) = icmp.payload_slice()[40..].split_at(8);
+            assert_eq!(header, &extension[..]);
+            assert_eq!(data, &PING_BYTES[..8]);
+            errors += 1;
+        }))));
+        assert_eq!(recv, Ok(1));
+        assert_eq!(errors, 1);
+    }
+}
+
 fn queue_ping(nic: &mut Loopback<Vec<u8>>) {
     fn prepare_ping<P: PayloadMut>(packet: icmp::RawPacket<P>) {
         let init = icmp::Init::EchoRequest {
@@ -86,3 +477,56 @@ fn queue_ping(nic: &mut Loopback<Vec<u8>>) {
         icmp.send_with(prepare_ping)))
     ).expect("Ping can be queued.");
 }
+
+fn queue_frag_required(nic: &mut Loopback<Vec<u8>>, quoted_src: v4::Address, dont_frag: bool, mtu: u16) {
+    // The host sent a full sized tcp segment to some remote behind the other router.
+    let repr = icmpv4::Repr::DstUnreachable {
+        reason: icmpv4::DstUnreachable::FragRequired,
+        header: v4::Repr {
+            src_addr: quoted_src,
+            dst_addr: v4::Address::new(10, 0, 0, 1),
+            protocol: crate::wire::ip::Protocol::Tcp,
+            payload_len: 1480,
+            hop_limit: 64,
+            dscp: 0,
+            ecn: crate::wire::ip::Ecn::NotEct,
+        },
+    };
+
+    let mut eth = eth::Endpoint::new(MAC_ADDR_OTHER);
+
+    let mut neighbors = [arp::Neighbor::default(); 1];
+    let neighbors = {
+        let mut eth_cache = arp::NeighborCache::new(&mut neighbors[..]);
+        eth_cache.fill(IP_ADDR_HOST.into(), MAC_ADDR_HOST, None).unwrap();
+        eth_cache
+    };
+    let mut ip = ip::Endpoint::new(
+        Cidr::new(IP_ADDR_OTHER.into(), 24),
+        ip::Routes::new(Slice::empty()),
+        neighbors);
+
+    nic.tx(1, eth.send(ip.send_with(|packet: ip::RawPacket<_>| {
+        let init = ip::Init {
+            source: ip::Source::Exact(IP_ADDR_OTHER.into()),
+            dst_addr: IP_ADDR_HOST.into(),
+            protocol: crate::wire::ip::Protocol::Icmp,
+            payload: repr.buffer_len(),
+            dscp: 0,
+            ecn: crate::wire::ip::Ecn::NotEct,
+            fragment: false,
+        };
+        let mut packet = packet.prepare(init)
+            .expect("Can initialize to the host");
+        let icmp = icmpv4::packet::new_unchecked_mut(packet.payload_mut_slice());
+        repr.emit(icmp, Checksum::Manual);
+        let quoted = v4::packet::new_unchecked_mut(icmp.payload_mut_slice());
+        quoted.set_dont_frag(dont_frag);
+        quoted.fill_checksum();
+        icmp.set_next_hop_mtu(mtu);
+        icmp.fill_checksum();
+        packet
+            .send()
+            .expect("Can send the packet");
+    }))).expect("Message can be queued.");
+}
@@ -1,11 +1,15 @@
 use crate::layer::{self, FnHandler};
 use crate::layer::{Error, Result};
 use crate::managed::Slice;
-use crate::wire::{ip, ethernet, Payload, PayloadMut};
+use crate::wire::{ip, ethernet, Checksum, Payload, PayloadMut};
 use crate::time::Instant;
 
 use super::{Recv, Send};
+use super::extension;
+use super::fragment::{Datagram, Fragmenter, Piece, FRAGMENT_HEADER_LEN};
 use super::packet::{self, Controller, IpPacket, Route};
+use super::path_mtu::Cache as PathMtuCache;
+use super::reassembly::{Expired, Fragment, Key, Table as Reassembly};
 use super::route::Routes;
 
 /// Handles IP connection states.
@@ -22,6 +26,15 @@ pub struct Endpoint<'a> {
 
     /// Internal ipv4/ipv6 arp state.
     arp: layer::arp::Endpoint<'a>,
+
+    /// Learned path MTUs, empty unless configured.
+    path_mtu: PathMtuCache<'a>,
+
+    /// Buffers for reassembling fragmented datagrams, empty unless configured.
+    reassembly: Reassembly<'a>,
+
+    /// Buffer for fragmenting outgoing datagrams, empty unless configured.
+    fragmenter: Option<Fragmenter<'a>>,
 }
 
 /// Routing information of an ip endpoint.
@@ -98,9 +111,51 @@ impl<'a> Endpoint<'a> {
                 routes: routes.into(),
             },
             arp: layer::arp::Endpoint::new(neighbors.into()),
+            path_mtu: PathMtuCache::new(Slice::empty()),
+            reassembly: Reassembly::new(Slice::empty()),
+            fragmenter: None,
         }
     }
 
+    /// Replace the cache for learned path MTUs.
+    ///
+    /// By default the cache has no storage and all packets are sized after the link MTU. Any
+    /// previously learned values are dropped.
+    pub fn set_path_mtu_cache(&mut self, cache: PathMtuCache<'a>) {
+        self.path_mtu = cache;
+    }
+
+    /// Get the cache of learned path MTUs.
+    pub fn path_mtu_cache(&self) -> &PathMtuCache<'a> {
+        &self.path_mtu
+    }
+
+    /// Replace the buffers for reassembling fragmented datagrams.
+    ///
+    /// By default there are no buffers and all fragments are dropped. Any datagrams currently in
+    /// reassembly are dropped.
+    pub fn set_reassembly(&mut self, reassembly: Reassembly<'a>) {
+        self.reassembly = reassembly;
+    }
+
+    /// Get the buffers for reassembling fragmented datagrams.
+    pub fn reassembly(&self) -> &Reassembly<'a> {
+        &self.reassembly
+    }
+
+    /// Replace the buffer for fragmenting outgoing datagrams.
+    ///
+    /// By default there is no fragmenter, only datagrams fitting into a single packet can be sent
+    /// and IPv4 packets are sent with Don't Fragment set. Any fragments not yet sent are dropped.
+    pub fn set_fragmenter(&mut self, fragmenter: Fragmenter<'a>) {
+        self.fragmenter = Some(fragmenter);
+    }
+
+    /// Get the fragmenter of outgoing datagrams, if any.
+    pub fn fragmenter(&self) -> Option<&Fragmenter<'a>> {
+        self.fragmenter.as_ref()
+    }
+
     /// Receive packet using this mutably borrowed endpoint.
     pub fn recv<H>(&mut self, handler: H) -> Receiver<'_, 'a, H> {
         Receiver { endpoint: self.ip(), handler, }
@@ -200,13 +255,174 @@ impl<'data> IpEndpoint<'_, 'data> {
         self.inner.arp.neighbors_mut()
     }
 
+    /// Reassemble an IPv4 datagram in the buffer of its last fragment.
+    ///
+    /// Returns `false` if the packet should not be processed any further, because it is an
+    /// invalid fragment or the datagram is not yet complete.
+    fn reassemble_v4<P>(&mut self, frame: &mut ethernet::Frame<&mut P>, checksum: Checksum, time: Instant)
+        -> bool
+    where
+        P: PayloadMut,
+    {
+        let reassembly = &mut self.inner.reassembly;
+        let (idx, header_len) = {
+            let packet = match ip::v4::packet::new_checked(frame.payload_slice()) {
+                Ok(packet) => packet,
+                // Rejected by the regular parsing.
+                Err(_) => return true,
+            };
+
+            if !packet.more_frags() && packet.frag_offset() == 0 {
+                return true;
+            }
+
+            if checksum.manual() && !packet.verify_checksum() {
+                return false;
+            }
+
+            let key = Key {
+                src_addr: packet.src_addr().into(),
+                dst_addr: packet.dst_addr().into(),
+                protocol: packet.protocol(),
+                ident: packet.ident().into(),
+            };
+
+            if !self.inner.routing.accepts(key.dst_addr) {
+                return false;
+            }
+
+            let fragment = Fragment {
+                offset: packet.frag_offset().into(),
+                more: packet.more_frags(),
+                protocol: packet.protocol(),
+                hop_limit: packet.hop_limit(),
+                headers: &[],
+            };
+
+            match reassembly.add(key, fragment, packet.payload_slice(), time) {
+                Some(idx) => (idx, usize::from(packet.header_len())),
+                None => return false,
+            }
+        };
+
+        let datagram = reassembly.datagram(idx);
+        let total_len = header_len + datagram.len();
+        let complete = total_len <= usize::from(u16::MAX) && frame.resize(total_len).is_ok();
+
+        if complete {
+            let packet = ip::v4::packet::new_unchecked_mut(frame.payload_mut_slice());
+            packet.set_total_len(total_len as u16);
+            packet.set_more_frags(false);
+            packet.set_frag_offset(0);
+            packet.payload_mut_slice().copy_from_slice(datagram);
+            packet.fill_checksum();
+        }
+
+        reassembly.release(idx);
+        complete
+    }
+
+    /// Reassemble an IPv6 datagram in the buffer of its last fragment.
+    ///
+    /// The fixed header and the extension headers preceding the fragment header are those of the
+    /// first fragment, RFC 8200 section 4.5. Atomic fragments are delivered immediately, without touching the reassembly buffers (RFC
+    /// 6946).
+    fn reassemble_v6<P>(&mut self, frame: &mut ethernet::Frame<&mut P>, time: Instant) -> bool
+    where
+        P: PayloadMut,
+    {
+        let reassembly = &mut self.inner.reassembly;
+        let (idx, header_len) = {
+            let packet = match ip::v6::packet::new_checked(frame.payload_slice()) {
+                Ok(packet) => packet,
+                // Rejected by the regular parsing.
+                Err(_) => return true,
+            };
+
+            let link = match extension::find_fragment(packet) {
+                Some(link) => link,
+                None => return true,
+            };
+
+            let payload = &packet.as_bytes()[link.offset..packet.total_len()];
+            let repr = match ip::v6::fragment::Header::new_checked(payload)
+                .and_then(|header| ip::v6::fragment::Repr::parse(&header))
+            {
+                Ok(repr) => repr,
+                Err(_) => return false,
+            };
+
+            let data = &payload[FRAGMENT_HEADER_LEN..];
+            if repr.frag_offset == 0 && !repr.more_frags {
+                let start = link.offset + FRAGMENT_HEADER_LEN;
+                let total_len = link.offset + data.len();
+                let payload_len = total_len - packet.header_len();
+                let packet = ip::v6::packet::new_unchecked_mut(frame.payload_mut_slice());
+                let bytes = packet.as_bytes_mut();
+                bytes.copy_within(start..total_len + FRAGMENT_HEADER_LEN, link.offset);
+                bytes[link.field] = repr.next_header.into();
+                packet.set_payload_len(payload_len as u16);
+                // A fixed size buffer keeps some trailing bytes, these are ignored when parsing.
+                let _ = frame.resize(total_len);
+                return true;
+            }
+
+            let key = Key {
+                src_addr: packet.src_addr().into(),
+                dst_addr: packet.dst_addr().into(),
+                protocol: ip::Protocol::Ipv6Frag,
+                ident: repr.ident,
+            };
+
+            if !self.inner.routing.accepts(key.dst_addr) {
+                return false;
+            }
+
+            let fragment = Fragment {
+                offset: usize::from(repr.frag_offset) * 8,
+                more: repr.more_frags,
+                protocol: repr.next_header,
+                hop_limit: packet.hop_limit(),
+                headers: &packet.as_bytes()[..link.offset],
+            };
+
+            match reassembly.add(key, fragment, data, time) {
+                Some(idx) => (idx, packet.header_len()),
+                None => return false,
+            }
+        };
+
+        let headers = reassembly.headers(idx);
+        let datagram = reassembly.datagram(idx);
+        let total_len = headers.len() + datagram.len();
+        let payload_len = total_len - header_len;
+        let mut complete = payload_len <= usize::from(u16::MAX) && frame.resize(total_len).is_ok();
+
+        if complete {
+            let bytes = frame.payload_mut_slice();
+            bytes[..headers.len()].copy_from_slice(headers);
+            bytes[headers.len()..total_len].copy_from_slice(datagram);
+            let packet = ip::v6::packet::new_unchecked_mut(bytes);
+            packet.set_payload_len(payload_len as u16);
+
+            // Walk the headers of the first fragment again to replace the fragment header.
+            match extension::find_fragment(packet) {
+                Some(link) => packet.as_bytes_mut()[link.field] = reassembly.protocol(idx).into(),
+                None => complete = false,
+            }
+        }
+
+        reassembly.release(idx);
+        complete
+    }
+
     fn into_arp_receiver(&mut self) -> layer::arp::Receiver<'_, 'data> {
-        let Endpoint { routing, arp } = self.inner;
+        let Endpoint { routing, arp, .. } = self.inner;
         arp.answer_for(routing)
     }
 
     fn into_arp_sender(&mut self) -> layer::arp::Sender<'_, 'data> {
-        let Endpoint { routing, arp } = self.inner;
+        let Endpoint { routing, arp, .. } = self.inner;
         arp.query_for(routing)
     }
 }
@@ -225,6 +441,51 @@ impl packet::Endpoint for IpEndpoint<'_, '_> {
         self.inner.routing.route(dst_addr, time)
     }
 
+    fn path_mtu(&self, dst_addr: ip::Address, time: Instant) -> Option<u16> {
+        self.inner.path_mtu.lookup(dst_addr, time)
+    }
+
+    fn update_path_mtu(&mut self, dst_addr: ip::Address, mtu: u16, time: Instant) {
+        self.inner.path_mtu.update(dst_addr, mtu, time)
+    }
+
+    fn expired_fragment(&self, time: Instant) -> Option<Expired> {
+        self.inner.reassembly.expired(time)
+    }
+
+    fn expired_fragment_data(&self, idx: usize) -> &[u8] {
+        self.inner.reassembly.first_fragment(idx)
+    }
+
+    fn release_expired(&mut self, idx: usize) {
+        self.inner.reassembly.release(idx)
+    }
+
+    fn next_ident(&mut self, src_addr: ip::Address, dst_addr: ip::Address) -> Option<u32> {
+        let fragmenter = self.inner.fragmenter.as_mut()?;
+        Some(fragmenter.next_ident(src_addr, dst_addr))
+    }
+
+    fn fragment_payload(&mut self, len: usize) -> Result<&mut [u8]> {
+        match &mut self.inner.fragmenter {
+            Some(fragmenter) => fragmenter.payload(len),
+            None => Err(Error::BadSize),
+        }
+    }
+
+    fn fragment_start(&mut self, datagram: Datagram) -> Result<Piece<'_>> {
+        match &mut self.inner.fragmenter {
+            Some(fragmenter) => fragmenter.start(datagram),
+            None => Err(Error::BadSize),
+        }
+    }
+
+    fn fragment_clear(&mut self) {
+        if let Some(fragmenter) = &mut self.inner.fragmenter {
+            fragmenter.clear()
+        }
+    }
+
     fn resolve(&mut self, addr: ip::Address, time: Instant, look: bool) -> Result<ethernet::Address> {
         match self.neighbors().lookup_pure(addr, time) {
             Some(addr) => return Ok(addr),
@@ -244,18 +505,51 @@ where
     P: PayloadMut,
     T: Recv<P>,
 {
-    fn receive(&mut self, layer::eth::InPacket { mut control, frame }: layer::eth::InPacket<P>) {
+    fn receive(&mut self, layer::eth::InPacket { mut control, mut frame }: layer::eth::InPacket<P>) {
         let capabilities = control.info().capabilities();
+        let time = control.info().timestamp();
         let packet = match frame.repr().ethertype {
             ethernet::EtherType::Ipv4 => {
+                if !self.endpoint.reassemble_v4(&mut frame, capabilities.ipv4().rx_checksum(), time) {
+                    return;
+                }
+
                 match ip::v4::Packet::new_checked(frame, capabilities.ipv4().rx_checksum()) {
                     Ok(packet) => IpPacket::V4(packet),
                     Err(_) => return,
                 }
             },
             ethernet::EtherType::Ipv6 => {
-                match ip::v6::Packet::new_checked(frame) {
-                    Ok(packet) => IpPacket::V6(packet),
+                if !self.endpoint.reassemble_v6(&mut frame, time) {
+                    return;
+                }
+
+                let mut packet = match ip::v6::Packet::new_checked(frame) {
+                    Ok(packet) => packet,
+                    Err(_) => return,
+                };
+
+                let upper = match extension::upper(&packet) {
+                    Ok(upper) => upper,
+                    Err(Some(problem)) => {
+                        let dst_addr = ip::Address::Ipv6(packet.repr().dst_addr);
+                        if !self.endpoint.inner.accepts(dst_addr) {
+                            return;
+                        }
+
+                        return self.handler.receive_problem(packet::In {
+                            control: Controller {
+                                eth: control.borrow_mut(),
+                                endpoint: &mut self.endpoint,
+                            },
+                            packet: IpPacket::V6(packet),
+                        }, problem);
+                    },
+                    Err(None) => return,
+                };
+
+                match packet.set_upper(upper.protocol, upper.offset) {
+                    Ok(()) => IpPacket::V6(packet),
                     Err(_) => return,
                 }
             },
@@ -291,6 +585,16 @@ where
             return self.endpoint.into_arp_sender().send(packet);
         }
 
+        // Remaining fragments of a datagram are sent before any new packet.
+        if let Some(fragmenter) = &mut self.endpoint.inner.fragmenter {
+            if let Some(piece) = fragmenter.pop() {
+                if piece.send(packet).is_err() {
+                    fragmenter.clear();
+                }
+                return;
+            }
+        }
+
         let layer::eth::RawPacket { control: mut eth_handle, payload } = packet;
 
         self.handler.send(packet::Raw {
@@ -0,0 +1,243 @@
+// Walking the chain of IPv6 extension headers, RFC 8200 section 4. Hop-by-Hop, Destination
+// Options and Routing headers share the layout of their first two octets, the next header and the
+// length in 8-octet units not counting the first 8 octets. Options are evaluated as a host would,
+// routing headers are never followed since we do not forward packets.
+use crate::wire::icmpv6;
+use crate::wire::ip::{self, v6::{hopbyhop, options, routing}};
+
+/// A problem in the extension headers of a discarded IPv6 packet.
+///
+/// The source of the packet is notified with an ICMPv6 Parameter Problem message, RFC 8200
+/// section 4. The icmp layer sends these when it is the receiver of the ip layer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Problem {
+    /// The code of the Parameter Problem message.
+    pub reason: icmpv6::ParamProblem,
+    /// The offset of the offending octet in the packet.
+    pub pointer: u32,
+}
+
+/// The position of a header in the extension header chain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Link {
+    /// The protocol of the header.
+    pub(crate) protocol: ip::Protocol,
+    /// The offset of the header in the packet.
+    pub(crate) offset: usize,
+    /// The offset of the next header field naming this header.
+    pub(crate) field: usize,
+}
+
+/// Offset of the next header field in the fixed IPv6 header.
+const FIXED_NEXT_HEADER: usize = 6;
+
+/// Offset of the routing type field in a routing header.
+const ROUTING_TYPE: usize = 2;
+
+impl Link {
+    /// The first header following the fixed header of a packet.
+    pub(crate) fn first(packet: &ip::v6::packet) -> Self {
+        Link {
+            protocol: packet.next_header(),
+            offset: packet.header_len(),
+            field: FIXED_NEXT_HEADER,
+        }
+    }
+
+    /// Check if this is one of the extension headers with options or a routing header.
+    fn is_extension(&self) -> bool {
+        matches!(self.protocol, ip::Protocol::HopByHop | ip::Protocol::Ipv6Opts | ip::Protocol::Ipv6Route)
+    }
+
+    /// Step over this extension header, without evaluating it.
+    ///
+    /// Returns `None` if the header is truncated.
+    fn next(&self, packet: &ip::v6::packet) -> Option<Self> {
+        let data = packet.as_bytes().get(self.offset..packet.total_len())?;
+        if data.len() < 8 {
+            return None;
+        }
+
+        let len = (usize::from(data[1]) + 1) * 8;
+        if data.len() < len {
+            return None;
+        }
+
+        Some(Link {
+            protocol: ip::Protocol::from(data[0]),
+            offset: self.offset + len,
+            field: self.offset,
+        })
+    }
+}
+
+/// Find the fragment header, stepping over the extension headers preceding it.
+pub(crate) fn find_fragment(packet: &ip::v6::packet) -> Option<Link> {
+    let mut link = Link::first(packet);
+    while link.is_extension() {
+        link = link.next(packet)?;
+    }
+
+    match link.protocol {
+        ip::Protocol::Ipv6Frag => Some(link),
+        _ => None,
+    }
+}
+
+/// Walk all extension headers and find the upper layer protocol.
+///
+/// Returns an error if the packet must be discarded, for an unrecognized option or routing header
+/// that requires it, a malformed chain, or a fragment header left after reassembly. The error
+/// contains the problem to report to the source, if any.
+pub(crate) fn upper(packet: &ip::v6::packet) -> Result<Link, Option<Problem>> {
+    let mut link = Link::first(packet);
+    loop {
+        let data = packet.as_bytes().get(link.offset..packet.total_len()).ok_or(None)?;
+        match link.protocol {
+            // Only allowed immediately following the fixed header.
+            ip::Protocol::HopByHop if link.field != FIXED_NEXT_HEADER => return Err(None),
+            ip::Protocol::HopByHop | ip::Protocol::Ipv6Opts => {
+                let header = hopbyhop::Header::new_checked(data).map_err(|_| None)?;
+                let repr = hopbyhop::Repr::parse(&header).map_err(|_| None)?;
+                accept_options(packet, link, repr)?;
+            },
+            ip::Protocol::Ipv6Route => {
+                let header = routing::Header::new_checked(data).map_err(|_| None)?;
+                // Type 0 must not be followed (RFC 5095) and other types are only ever followed
+                // by routers. Without any segments left the header is ignored.
+                if header.segments_left() != 0 {
+                    return Err(Some(Problem {
+                        reason: icmpv6::ParamProblem::ErroneousHdrField,
+                        pointer: (link.offset + ROUTING_TYPE) as u32,
+                    }));
+                }
+            },
+            ip::Protocol::Ipv6Frag => return Err(None),
+            _ => return Ok(link),
+        }
+
+        link = link.next(packet).ok_or(None)?;
+    }
+}
+
+/// Check the action of unrecognized options, only those to be skipped are accepted.
+fn accept_options(packet: &ip::v6::packet, link: Link, repr: hopbyhop::Repr)
+    -> Result<(), Option<Problem>>
+{
+    // The options follow the next header and length octets.
+    let mut offset = link.offset + 2;
+    for option in repr.options() {
+        let option = option.map_err(|_| None)?;
+        if let options::Repr::Unknown { type_, .. } = option {
+            let problem = Problem {
+                reason: icmpv6::ParamProblem::UnrecognizedOption,
+                pointer: offset as u32,
+            };
+
+            match options::FailureType::from(type_) {
+                options::FailureType::Skip => (),
+                options::FailureType::DiscardSendAll => return Err(Some(problem)),
+                options::FailureType::DiscardSendUnicast if !packet.dst_addr().is_multicast() => {
+                    return Err(Some(problem))
+                },
+                _ => return Err(None),
+            }
+        }
+
+        offset += option.buffer_len();
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wire::ip::v6;
+
+    /// A packet with the given extension headers, each a next header and its remaining octets.
+    fn packet(first: ip::Protocol, headers: &[(ip::Protocol, &[u8])]) -> Vec<u8> {
+        let mut buffer = vec![0; 40];
+        for (next, data) in headers {
+            buffer.push((*next).into());
+            buffer.extend_from_slice(data);
+        }
+        let payload_len = (buffer.len() - 40) as u16;
+        let packet = v6::packet::new_unchecked_mut(&mut buffer);
+        packet.set_version(6);
+        packet.set_next_header(first);
+        packet.set_payload_len(payload_len);
+        buffer
+    }
+
+    #[test]
+    fn skips_options() {
+        // A hop-by-hop header with an unknown option to skip, then destination options padding.
+        let buffer = packet(ip::Protocol::HopByHop, &[
+            (ip::Protocol::Ipv6Opts, &[0, 0x1e, 2, 0, 0, 1, 0]),
+            (ip::Protocol::Udp, &[0, 1, 4, 0, 0, 0, 0]),
+        ]);
+        let packet = v6::packet::new_checked(&buffer).unwrap();
+        assert_eq!(upper(packet), Ok(Link {
+            protocol: ip::Protocol::Udp,
+            offset: 56,
+            field: 48,
+        }));
+    }
+
+    #[test]
+    fn discards() {
+        // Unknown option with the discard action.
+        let buffer = packet(ip::Protocol::HopByHop, &[
+            (ip::Protocol::Udp, &[0, 0x5e, 2, 0, 0, 1, 0]),
+        ]);
+        assert_eq!(upper(v6::packet::new_checked(&buffer).unwrap()), Err(None));
+
+        // Hop-by-hop header not directly after the fixed header.
+        let buffer = packet(ip::Protocol::Ipv6Opts, &[
+            (ip::Protocol::HopByHop, &[0, 1, 4, 0, 0, 0, 0]),
+            (ip::Protocol::Udp, &[0, 1, 4, 0, 0, 0, 0]),
+        ]);
+        assert_eq!(upper(v6::packet::new_checked(&buffer).unwrap()), Err(None));
+
+        // But ignored once no segments are left.
+        let buffer = packet(ip::Protocol::Ipv6Route, &[
+            (ip::Protocol::Udp, &[0, 0, 0, 0, 0, 0, 0]),
+        ]);
+        assert!(upper(v6::packet::new_checked(&buffer).unwrap()).is_ok());
+
+        // Header length beyond the packet.
+        let buffer = packet(ip::Protocol::Ipv6Opts, &[
+            (ip::Protocol::Udp, &[1, 1, 4, 0, 0, 0, 0]),
+        ]);
+        assert_eq!(upper(v6::packet::new_checked(&buffer).unwrap()), Err(None));
+    }
+
+    #[test]
+    fn reports_problems() {
+        // Unknown option that must be reported, behind padding.
+        let buffer = packet(ip::Protocol::Ipv6Opts, &[
+            (ip::Protocol::Udp, &[0, 1, 0, 0x9e, 2, 0, 0]),
+        ]);
+        assert_eq!(upper(v6::packet::new_checked(&buffer).unwrap()), Err(Some(Problem {
+            reason: icmpv6::ParamProblem::UnrecognizedOption,
+            pointer: 44,
+        })));
+
+        // Only reported to unicast destinations.
+        let mut buffer = packet(ip::Protocol::Ipv6Opts, &[
+            (ip::Protocol::Udp, &[0, 0xde, 2, 0, 0, 1, 0]),
+        ]);
+        v6::packet::new_unchecked_mut(&mut buffer).set_dst_addr(v6::Address::LINK_LOCAL_ALL_NODES);
+        assert_eq!(upper(v6::packet::new_checked(&buffer).unwrap()), Err(None));
+
+        // Type 0 routing header with segments left, pointing at the routing type.
+        let buffer = packet(ip::Protocol::Ipv6Route, &[
+            (ip::Protocol::Udp, &[0, 0, 1, 0, 0, 0, 0]),
+        ]);
+        assert_eq!(upper(v6::packet::new_checked(&buffer).unwrap()), Err(Some(Problem {
+            reason: icmpv6::ParamProblem::ErroneousHdrField,
+            pointer: 42,
+        })));
+    }
+}
@@ -0,0 +1,351 @@
+// Fragmentation of outgoing datagrams as in RFC 791 and RFC 8200 section 4.5. The payload of a
+// datagram is written to a buffer provided by the user, as it does not fit into a single packet
+// buffer. The first fragment is sent immediately, the others are sent in the following packet
+// buffers of the ip sender, before the upper layer gets to see them.
+use crate::layer::{eth, Error, Result};
+use crate::layer::hash::HashKey;
+use crate::managed::Slice;
+use crate::wire::{ethernet, ip, Checksum, PayloadMut};
+
+/// A datagram whose fragments are being sent.
+#[derive(Clone, Debug)]
+pub(crate) struct Datagram {
+    pub(crate) src_mac: ethernet::Address,
+    pub(crate) next_mac: ethernet::Address,
+    /// The header of the unfragmented datagram.
+    pub(crate) repr: ip::Repr,
+    /// The path MTU towards the destination, if it is smaller than that of the link.
+    pub(crate) path_mtu: Option<u16>,
+    /// If the datagram may be fragmented, otherwise it must fit into a single packet.
+    pub(crate) fragment: bool,
+}
+
+/// One fragment of the datagram, ready to be sent.
+pub(crate) struct Piece<'b> {
+    datagram: Datagram,
+    ident: u32,
+    offset: usize,
+    more: bool,
+    data: &'b [u8],
+}
+
+/// Sends datagrams as fragments when they exceed the MTU, buffering their payload.
+///
+/// # Examples
+///
+/// On systems without heap, use:
+///
+/// ```rust
+/// use ethox::layer::hash::HashKey;
+/// use ethox::layer::ip::Fragmenter;
+///
+/// let mut buffer = [0; 1 << 16];
+/// // Use a secret key from some cryptographic random source instead.
+/// let key = HashKey::from_secret_key_bytes([0; 16]);
+/// let mut fragmenter = Fragmenter::new(&mut buffer[..], key);
+/// ```
+///
+/// ## Details
+///
+/// A single datagram is fragmented at a time and its payload is limited by the size of the buffer.
+/// Each fragmented datagram gets a new identification value from a counter, which is unique until
+/// it wraps around. For IPv4 that counter is truncated to 16 bits. A single counter would be
+/// predictable and reveal the rate of datagrams sent to all destinations, so the counters are
+/// chosen from a table with a keyed hash of the addresses and offset by another hash of them, as
+/// in RFC 7739 section 5.3.3. Each destination sees its own unpredictable sequence.
+///
+/// Fragments are sized after the smaller of the link MTU and the path MTU towards the
+/// destination. Only the first fragment is sent by the call that sends the datagram. The other
+/// fragments are sent in the packet buffers which the ip sender is given afterwards, usually by
+/// the same batch of the network device.
+#[derive(Debug)]
+pub struct Fragmenter<'a> {
+    buffer: Slice<'a, u8>,
+    pending: Option<(Datagram, u32)>,
+    /// The payload offset of the next fragment.
+    offset: usize,
+    /// Counters of identifications, selected by the destination.
+    idents: [u32; IDENT_COUNTERS],
+    key: HashKey,
+    mtu: u16,
+}
+
+impl<'a> Fragmenter<'a> {
+    /// The MTU of an ethernet link.
+    pub const DEFAULT_MTU: u16 = 1500;
+
+    /// Create a fragmenter buffering payloads in the storage.
+    ///
+    /// The key randomizes the identification values, prefer one separate from the key of TCP
+    /// sequence numbers.
+    pub fn new<T>(storage: T, key: HashKey) -> Self
+        where T: Into<Slice<'a, u8>>
+    {
+        Fragmenter {
+            buffer: storage.into(),
+            pending: None,
+            offset: 0,
+            idents: [0; IDENT_COUNTERS],
+            key,
+            mtu: Self::DEFAULT_MTU,
+        }
+    }
+
+    /// Change the MTU of the link, the size of the largest ip packet that is sent.
+    pub fn set_mtu(&mut self, mtu: u16) {
+        self.mtu = mtu;
+    }
+
+    /// The MTU of the link.
+    pub fn mtu(&self) -> u16 {
+        self.mtu
+    }
+
+    /// The largest datagram payload that can be fragmented.
+    pub fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Check if fragments of a datagram are still waiting to be sent.
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Drop the remaining fragments of a datagram.
+    pub fn clear(&mut self) {
+        self.pending = None;
+    }
+
+    /// Get a new identification value for a datagram between the addresses.
+    pub(crate) fn next_ident(&mut self, src: ip::Address, dst: ip::Address) -> u32 {
+        let (idx, offset) = self.ident_hash(src, dst);
+        let ident = self.idents[idx];
+        self.idents[idx] = ident.wrapping_add(1);
+        ident.wrapping_add(offset)
+    }
+
+    /// The counter and the offset of identifications between the addresses.
+    fn ident_hash(&self, src: ip::Address, dst: ip::Address) -> (usize, u32) {
+        let hash = self.key.hash_addresses(
+            (src, 0),
+            (dst, 0),
+            Some(u64::from_le_bytes(*b"fragment")));
+        // The upper half selects the counter and the lower half is the offset.
+        ((hash >> 32) as usize % IDENT_COUNTERS, hash as u32)
+    }
+
+    /// Get the buffer for the payload of a new datagram.
+    pub(crate) fn payload(&mut self, len: usize) -> Result<&mut [u8]> {
+        if self.is_pending() {
+            return Err(Error::Exhausted);
+        }
+
+        self.buffer.get_mut(..len).ok_or(Error::BadSize)
+    }
+
+    /// Start sending a datagram, returning its first fragment.
+    ///
+    /// The payload must have been written to the buffer before.
+    pub(crate) fn start(&mut self, datagram: Datagram) -> Result<Piece<'_>> {
+        let payload_len = datagram.repr.payload_len();
+        let fits = payload_len <= self.fragment_len(&datagram.repr, datagram.path_mtu);
+        if payload_len > self.buffer.len() || !(datagram.fragment || fits) {
+            return Err(Error::BadSize);
+        }
+
+        let ident = self.next_ident(datagram.repr.src_addr(), datagram.repr.dst_addr());
+        self.pending = Some((datagram, ident));
+        self.offset = 0;
+        self.pop().ok_or(Error::Illegal)
+    }
+
+    /// Take the next fragment of the pending datagram.
+    pub(crate) fn pop(&mut self) -> Option<Piece<'_>> {
+        let (datagram, ident) = self.pending.take()?;
+        let total = datagram.repr.payload_len();
+        let offset = self.offset;
+        let end = total.min(offset + self.fragment_len(&datagram.repr, datagram.path_mtu));
+        let more = end < total;
+
+        self.offset = end;
+        if more {
+            self.pending = Some((datagram.clone(), ident));
+        }
+
+        Some(Piece {
+            datagram,
+            ident,
+            offset,
+            more,
+            data: &self.buffer[offset..end],
+        })
+    }
+
+    /// The largest payload in one fragment, a multiple of 8 octets.
+    fn fragment_len(&self, repr: &ip::Repr, path_mtu: Option<u16>) -> usize {
+        let mtu = path_mtu.map_or(self.mtu, |path| path.min(self.mtu));
+        let header = match repr {
+            ip::Repr::Ipv6(_) => repr.buffer_len() + FRAGMENT_HEADER_LEN,
+            _ => repr.buffer_len(),
+        };
+        // Never less than the minimum fragment size, the MTU of any link fits that.
+        (usize::from(mtu).saturating_sub(header) & !7).max(8)
+    }
+}
+
+/// The length of the IPv6 fragment extension header.
+pub(crate) const FRAGMENT_HEADER_LEN: usize = 8;
+
+/// The number of identification counters, destinations with the same counter share a sequence.
+const IDENT_COUNTERS: usize = 64;
+
+impl Piece<'_> {
+    /// Write the fragment into a packet buffer and send it.
+    pub(crate) fn send<P: PayloadMut>(&self, raw: eth::RawPacket<P>) -> Result<()> {
+        let Datagram { src_mac, next_mac, repr, .. } = &self.datagram;
+        // A datagram fitting a single packet needs no IPv6 fragment header, RFC 8021.
+        let atomic = self.offset == 0 && !self.more;
+
+        let mut header = repr.clone();
+        let (ethertype, extension) = match &mut header {
+            ip::Repr::Ipv4(_) => (ethernet::EtherType::Ipv4, 0),
+            ip::Repr::Ipv6(_) if atomic => (ethernet::EtherType::Ipv6, 0),
+            ip::Repr::Ipv6(repr) => {
+                repr.next_header = ip::Protocol::Ipv6Frag;
+                (ethernet::EtherType::Ipv6, FRAGMENT_HEADER_LEN)
+            },
+            _ => return Err(Error::Illegal),
+        };
+        header.set_payload_len(extension + self.data.len());
+
+        let tx_checksum = raw.control.info().capabilities().ipv4().tx_checksum();
+        let mut out = raw.prepare(eth::Init {
+            src_addr: *src_mac,
+            dst_addr: *next_mac,
+            ethertype,
+            payload: header.total_len(),
+        })?;
+
+        let buffer = out.payload_mut_slice();
+        header.emit(&mut *buffer, Checksum::Ignored);
+
+        match header {
+            ip::Repr::Ipv4(_) => {
+                let packet = ip::v4::packet::new_unchecked_mut(buffer);
+                packet.set_ident(self.ident as u16);
+                packet.set_dont_frag(!self.datagram.fragment);
+                packet.set_more_frags(self.more);
+                packet.set_frag_offset(self.offset as u16);
+                packet.payload_mut_slice().copy_from_slice(self.data);
+                if tx_checksum.manual() {
+                    packet.fill_checksum();
+                }
+            },
+            _ => {
+                let packet = ip::v6::packet::new_unchecked_mut(buffer);
+                let (extension, payload) = packet.payload_mut_slice().split_at_mut(extension);
+                if !atomic {
+                    let fragment = ip::v6::fragment::Repr {
+                        next_header: repr.protocol(),
+                        frag_offset: (self.offset / 8) as u16,
+                        more_frags: self.more,
+                        ident: self.ident,
+                    };
+                    fragment.emit(&mut ip::v6::fragment::Header::new_unchecked(extension));
+                }
+                payload.copy_from_slice(self.data);
+            },
+        }
+
+        out.send()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAC: ethernet::Address = ethernet::Address([0, 1, 2, 3, 4, 5]);
+
+    fn datagram(payload_len: usize) -> Datagram {
+        Datagram {
+            src_mac: MAC,
+            next_mac: MAC,
+            repr: ip::Repr::Ipv4(ip::v4::Repr {
+                src_addr: ip::v4::Address::new(10, 0, 0, 1),
+                dst_addr: ip::v4::Address::new(10, 0, 0, 2),
+                protocol: ip::Protocol::Udp,
+                payload_len,
+                hop_limit: 64,
+                dscp: 0,
+                ecn: ip::Ecn::NotEct,
+            }),
+            path_mtu: None,
+            fragment: true,
+        }
+    }
+
+    #[test]
+    fn splits_payload() {
+        let mut fragmenter = Fragmenter::new(vec![0; 4096], HashKey::from_key(0, 0));
+        fragmenter.set_mtu(1000);
+        fragmenter.payload(2000).unwrap().iter_mut().for_each(|b| *b = 0xaa);
+
+        let first = fragmenter.start(datagram(2000)).unwrap();
+        assert_eq!((first.offset, first.data.len(), first.more), (0, 976, true));
+        let ident = first.ident;
+
+        let second = fragmenter.pop().unwrap();
+        assert_eq!((second.offset, second.data.len(), second.more), (976, 976, true));
+        assert_eq!(second.ident, ident);
+        // Busy while fragments are pending.
+        assert_eq!(fragmenter.payload(10).err(), Some(Error::Exhausted));
+
+        let last = fragmenter.pop().unwrap();
+        assert_eq!((last.offset, last.data.len(), last.more), (1952, 48, false));
+        assert!(fragmenter.pop().is_none());
+        assert!(!fragmenter.is_pending());
+
+        // The next datagram gets a new identification.
+        let mut path = datagram(1000);
+        path.path_mtu = Some(576);
+        let first = fragmenter.start(path).unwrap();
+        assert_ne!(first.ident, ident);
+        assert_eq!(first.data.len(), 552);
+    }
+
+    #[test]
+    fn too_large() {
+        let mut fragmenter = Fragmenter::new(vec![0; 128], HashKey::from_key(0, 0));
+        assert_eq!(fragmenter.payload(256).err(), Some(Error::BadSize));
+        assert!(fragmenter.start(datagram(256)).is_err());
+        assert!(!fragmenter.is_pending());
+
+        // Without permission to fragment, it must fit into one packet.
+        let mut fragmenter = Fragmenter::new(vec![0; 4096], HashKey::from_key(0, 0));
+        let mut whole = datagram(2000);
+        whole.fragment = false;
+        assert_eq!(fragmenter.start(whole).err(), Some(Error::BadSize));
+        assert!(!fragmenter.is_pending());
+    }
+
+    #[test]
+    fn keyed_ident() {
+        let here = ip::Address::v4(10, 0, 0, 1);
+        let there = ip::Address::v4(10, 0, 0, 2);
+
+        let mut fragmenter = Fragmenter::new(vec![0; 128], HashKey::from_key(1, 2));
+        let first = fragmenter.next_ident(here, there);
+        assert_ne!(first, 0);
+        assert_eq!(fragmenter.next_ident(here, there), first.wrapping_add(1));
+
+        // Datagrams to another destination do not reveal or advance the sequence of this one.
+        let (counter, _) = fragmenter.ident_hash(here, there);
+        let elsewhere = (3..=255)
+            .map(|host| ip::Address::v4(10, 0, 0, host))
+            .find(|&other| fragmenter.ident_hash(here, other).0 != counter)
+            .unwrap();
+        assert_ne!(fragmenter.next_ident(here, elsewhere), first.wrapping_add(2));
+        assert_eq!(fragmenter.next_ident(here, there), first.wrapping_add(2));
+    }
+}
@@ -11,7 +11,19 @@
 //! addresses ([`IpAddress`]) and a unified [`Init`] structure. This generally enables the layer to
 //! transparently dispatch into the desired underlying layer.
 //!
-//! It does **not yet** provide (transparent) fragment reassembly.
+//! Fragmented IPv4 and IPv6 datagrams are reassembled transparently when the endpoint has been
+//! configured with [`Reassembly`] buffers, otherwise fragments are dropped. The complete datagram
+//! is delivered as a single packet in the buffer of the last fragment that arrived, with the
+//! headers of the first fragment for IPv6. IPv6 atomic fragments, carrying a fragment header but
+//! no other fragments, are always accepted. The first fragment of a timed out IPv6 datagram is
+//! kept until the icmp layer reported the timeout. In the other direction, datagrams exceeding
+//! the MTU can be sent as fragments with the help of a [`Fragmenter`].
+//!
+//! The extension headers of received IPv6 packets are walked before delivery. Packets with
+//! Hop-by-Hop or Destination options whose action requires it, or with a routing header that still
+//! has segments left, are discarded. Those that must be reported with a Parameter Problem message
+//! are handed to `Recv::receive_problem` instead, which the icmp layer answers. The payload of a
+//! delivered [`V6Packet`] starts at the upper layer, whose protocol and offset it reports.
 //!
 //! ## Structure
 //!
@@ -19,6 +31,10 @@
 //! enables it to match received packet destinations against the configured addresses of the
 //! network device and to find next hops for transmitted packets.
 //!
+//! Optionally, it also keeps a [`PathMtuCache`] of destinations that reported a smaller maximum
+//! transmission unit than that of the link. The ICMP layer fills it from `fragmentation needed`
+//! messages and upper layers can query it through the [`Controller`] to size their packets.
+//!
 //! ## Receiving packets
 //!
 //! The IP endpoint acts as an ethernet receiver. Note that it not only processes IP packets but
@@ -38,19 +54,34 @@
 //! headers *is not* supported (but you could rewrite the packet buffer after initialization
 //! yourself).
 //!
+//! A payload too large for a single packet buffer is instead prepared as a [`Fragmented`] datagram.
+//! It is buffered by the [`Fragmenter`] of the endpoint and sent in several packets, the first in
+//! the current buffer and the others in the next buffers handed to the sender. These do not reach
+//! the upper layer until all fragments have been sent.
+//!
 //! Note that the configured next hop might be missing a resolved link-layer address. In this case,
 //! the init call will return an error but the request for this resolution is stored in an internal
 //! table. The IP layer will send a probe as soon as possible, which is subject to both a packet
 //! buffer begin available and an internal rate limit. Only buffers that are not used for the
 //! purpose of neighbor discovery are available to the upper layers.
 //!
+//! [`Controller`]: struct.Controller.html
+//! [`Fragmented`]: struct.Fragmented.html
+//! [`Fragmenter`]: struct.Fragmenter.html
 //! [`Init`]: struct.Init.html
 //! [`IpAddress`]: ../../wire/enum.IpAddress.html
 //! [`IpPacket`]: enum.IpPacket.html
+//! [`PathMtuCache`]: struct.PathMtuCache.html
+//! [`Reassembly`]: struct.Reassembly.html
+//! [`V6Packet`]: type.V6Packet.html
 use crate::wire::Payload;
 
 mod endpoint;
+mod extension;
+mod fragment;
 mod packet;
+mod path_mtu;
+mod reassembly;
 mod route;
 #[cfg(test)]
 mod tests;
@@ -61,8 +92,13 @@ pub use endpoint::{
     Sender,
 };
 
+pub use extension::Problem as ParamProblem;
+
+pub use fragment::Fragmenter;
+
 pub use packet::{
     Controller,
+    Fragmented,
     Init,
     IpPacket,
     V4Packet,
@@ -73,6 +109,16 @@ pub use packet::{
     Source,
 };
 
+pub use path_mtu::{
+    Cache as PathMtuCache,
+    PathMtu,
+};
+
+pub use reassembly::{
+    Buffer as ReassemblyBuffer,
+    Table as Reassembly,
+};
+
 pub use route::{
     Route,
     Routes,
@@ -87,6 +133,14 @@ pub trait Recv<P: Payload> {
     ///
     /// The packet might be IPv4 or IPv6 traffic.
     fn receive(&mut self, frame: InPacket<P>);
+
+    /// Inspect an IPv6 packet discarded for a problem in its extension headers.
+    ///
+    /// The payload of the packet starts after the fixed header. The icmp layer answers with a
+    /// Parameter Problem message, other receivers ignore the packet by default.
+    fn receive_problem(&mut self, frame: InPacket<P>, problem: ParamProblem) {
+        let _ = (frame, problem);
+    }
 }
 
 
@@ -104,6 +158,7 @@ pub trait Send<P: Payload> {
 }
 
 pub(crate) use endpoint::Routing;
+pub(crate) use reassembly::Expired as ExpiredFragment;
 
 impl<P: Payload, E> Recv<P> for &'_ mut E
     where E: Recv<P>
@@ -111,6 +166,10 @@ impl<P: Payload, E> Recv<P> for &'_ mut E
     fn receive(&mut self, frame: InPacket<P>) {
         (**self).receive(frame)
     }
+
+    fn receive_problem(&mut self, frame: InPacket<P>, problem: ParamProblem) {
+        (**self).receive_problem(frame, problem)
+    }
 }
 
 impl<P: Payload, E> Send<P> for &'_ mut E
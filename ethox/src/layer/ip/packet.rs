@@ -4,6 +4,9 @@ use crate::time::Instant;
 use crate::wire::{ethernet, ip};
 use crate::wire::{Checksum, Reframe, Payload, PayloadMut, PayloadResult, payload};
 
+use super::fragment::{Datagram, Piece};
+use super::reassembly::Expired;
+
 /// An incoming packet.
 ///
 /// The contents were inspected and could be handled up to the ip layer.
@@ -24,6 +27,21 @@ pub struct Out<'a, P: Payload> {
     packet: IpPacket<'a, P>,
 }
 
+/// An outgoing datagram as prepared by the ip layer, to be split into fragments if necessary.
+///
+/// The payload is not placed in the packet buffer but in the [`Fragmenter`] of the endpoint, from
+/// which it is copied into the fragments when sending.
+///
+/// [`Fragmenter`]: struct.Fragmenter.html
+#[must_use = "You need to call `send` explicitely on a Fragmented, otherwise no packet is sent."]
+pub struct Fragmented<'a, P: Payload> {
+    control: Controller<'a>,
+    payload: &'a mut P,
+    route: EthRoute,
+    repr: ip::Repr,
+    fragment: bool,
+}
+
 /// A buffer into which a packet can be placed.
 pub struct Raw<'a, P: Payload> {
     /// A reference to the IP endpoint state.
@@ -72,6 +90,19 @@ pub struct Init {
     pub protocol: ip::Protocol,
     /// The length to reserved for the payload.
     pub payload: usize,
+    /// The Differentiated Services Code Point to request.
+    pub dscp: u8,
+    /// The Explicit Congestion Notification codepoint.
+    ///
+    /// Only transports that react to congestion echoed by the remote should set an ECN-capable
+    /// codepoint, see RFC3168.
+    pub ecn: ip::Ecn,
+    /// Permit the datagram to be fragmented.
+    ///
+    /// An IPv4 packet is otherwise sent with the `Don't Fragment` flag and, when it is too large,
+    /// `Raw::prepare_fragmented` refuses it instead of sending fragments. Set this only if the
+    /// remote can reassemble datagrams, a transport such as TCP sizes its segments instead.
+    pub fragment: bool,
 }
 
 /// A source selector specification.
@@ -109,8 +140,27 @@ pub(crate) trait Endpoint{
     fn local_ip(&self, subnet: ip::Subnet) -> Option<ip::Address>;
     /// Find a Route a destination at the current time.
     fn route(&self, dst_addr: ip::Address, time: Instant) -> Option<Route>;
+    /// Find the learned path MTU towards a destination.
+    fn path_mtu(&self, dst_addr: ip::Address, time: Instant) -> Option<u16>;
+    /// Lower the path MTU towards a destination after a report.
+    fn update_path_mtu(&mut self, dst_addr: ip::Address, mtu: u16, time: Instant);
     /// Resolve an address. If `look` is true, try to actively lookup it up later.
     fn resolve(&mut self, _: ip::Address, _: Instant, look: bool) -> Result<ethernet::Address>;
+    /// Find a datagram whose reassembly timed out after its first fragment arrived.
+    fn expired_fragment(&self, time: Instant) -> Option<Expired>;
+    /// The payload of the first fragment of a timed out datagram.
+    fn expired_fragment_data(&self, idx: usize) -> &[u8];
+    /// Drop the first fragment of a timed out datagram.
+    fn release_expired(&mut self, idx: usize);
+    /// Get a new identification value for a datagram that may be fragmented, if there is a
+    /// fragmenter.
+    fn next_ident(&mut self, src_addr: ip::Address, dst_addr: ip::Address) -> Option<u32>;
+    /// Get the buffer for the payload of a fragmented datagram.
+    fn fragment_payload(&mut self, len: usize) -> Result<&mut [u8]>;
+    /// Start sending the fragments of a datagram whose payload has been buffered.
+    fn fragment_start(&mut self, datagram: Datagram) -> Result<Piece<'_>>;
+    /// Drop the remaining fragments of a datagram.
+    fn fragment_clear(&mut self);
 }
 
 impl<'a> Controller<'a> {
@@ -139,6 +189,42 @@ impl<'a> Controller<'a> {
         self.endpoint.local_ip(subnet)
     }
 
+    /// Get the learned path MTU towards a destination.
+    ///
+    /// This is the largest ip packet, including its header, that is known to reach the
+    /// destination. It is `None` when no smaller value than the link MTU has been reported.
+    pub fn path_mtu(&self, dst_addr: ip::Address) -> Option<u16> {
+        let time = self.info().timestamp();
+        self.endpoint.path_mtu(dst_addr, time)
+    }
+
+    /// Record a smaller path MTU towards a destination.
+    ///
+    /// This is called by the ICMP layer on `fragmentation needed` messages but may also be used
+    /// by upper layers that probe the path themselves. Increases of the MTU are ignored.
+    pub fn update_path_mtu(&mut self, dst_addr: ip::Address, mtu: u16) {
+        let time = self.info().timestamp();
+        self.endpoint.update_path_mtu(dst_addr, mtu, time)
+    }
+
+    /// Find an IPv6 datagram whose reassembly timed out after its first fragment arrived.
+    ///
+    /// The ICMP layer reports these to their source with a Time Exceeded message.
+    pub(crate) fn expired_fragment(&self) -> Option<Expired> {
+        let time = self.info().timestamp();
+        self.endpoint.expired_fragment(time)
+    }
+
+    /// The payload of the first fragment of a timed out datagram.
+    pub(crate) fn expired_fragment_data(&self, idx: usize) -> &[u8] {
+        self.endpoint.expired_fragment_data(idx)
+    }
+
+    /// Drop the first fragment of a timed out datagram, once it was reported.
+    pub(crate) fn release_expired(&mut self, idx: usize) {
+        self.endpoint.release_expired(idx)
+    }
+
     /// Try to initialize the destination from an upper layer protocol address.
     ///
     /// Failure to satisfy the request is clearly signalled. Use the result to initialize the
@@ -193,7 +279,7 @@ impl<'a, P: PayloadMut> In<'a, P> {
         // TODO: optimize in case frame already contains the right IP packet.
         let packet = eth_packet.reinit(lower_init)?;
         let eth::InPacket { control, mut frame } = packet.into_incoming();
-        let repr = init.initialize(route.src_addr, &mut frame)?;
+        let repr = init.initialize(route.src_addr, &mut *self.control.endpoint, &mut frame)?;
 
         Ok(Out {
             control: Controller {
@@ -263,11 +349,78 @@ impl<'a, P: PayloadMut> Out<'a, P> {
     }
 }
 
+impl<'a, P: PayloadMut> Fragmented<'a, P> {
+    /// Retrieve the representation of the unfragmented datagram.
+    pub fn repr(&self) -> ip::Repr {
+        self.repr.clone()
+    }
+
+    /// A mutable slice containing the whole payload of the datagram.
+    pub fn payload_mut_slice(&mut self) -> &mut [u8] {
+        self.control.endpoint
+            .fragment_payload(self.repr.payload_len())
+            .expect("Payload was reserved when preparing the datagram")
+    }
+
+    /// Send the datagram, called last after having initialized the payload.
+    ///
+    /// Only the first fragment is sent in the packet buffer, the others are queued in the
+    /// endpoint and take precedence over the upper layer in its next packet buffers.
+    pub fn send(self) -> Result<()> {
+        let Fragmented { control, payload, route, repr, fragment } = self;
+        let Controller { eth, endpoint } = control;
+        let path_mtu = endpoint.path_mtu(repr.dst_addr(), eth.info().timestamp());
+
+        let datagram = Datagram {
+            src_mac: route.src_mac,
+            next_mac: route.next_mac,
+            repr,
+            path_mtu,
+            fragment,
+        };
+
+        let result = endpoint
+            .fragment_start(datagram)?
+            .send(eth::RawPacket { control: eth, payload });
+
+        if result.is_err() {
+            endpoint.fragment_clear();
+        }
+
+        result
+    }
+}
+
 impl<'a, P: Payload + PayloadMut> Raw<'a, P> {
     pub fn control(&self) -> &Controller<'a> {
         &self.control
     }
 
+    /// Initialize a datagram that may exceed the MTU.
+    ///
+    /// The payload is buffered in the [`Fragmenter`] of the endpoint and is split into fragments
+    /// when sent, which requires `init.fragment` to be set. Otherwise sending fails with `BadSize`
+    /// if the datagram does not fit into a single packet. Prefer `prepare` when the payload is
+    /// known to fit, which writes it into the packet buffer directly.
+    ///
+    /// Fails with `Exhausted` while the fragments of another datagram have not all been sent, and
+    /// with `BadSize` if the payload is larger than the buffer of the fragmenter.
+    ///
+    /// [`Fragmenter`]: struct.Fragmenter.html
+    pub fn prepare_fragmented(mut self, init: Init) -> Result<Fragmented<'a, P>> {
+        let route = self.control.route_to(init.dst_addr)?;
+        let repr = init.ip_repr(route.src_addr)?;
+        self.control.endpoint.fragment_payload(init.payload)?;
+
+        Ok(Fragmented {
+            control: self.control,
+            payload: self.payload,
+            route,
+            repr,
+            fragment: init.fragment,
+        })
+    }
+
     /// Initialize to a valid ip packet.
     pub fn prepare(mut self, init: Init) -> Result<Out<'a, P>> {
         let route = self.control.route_to(init.dst_addr)?;
@@ -280,7 +433,7 @@ impl<'a, P: Payload + PayloadMut> Raw<'a, P> {
 
         let packet = lower.prepare(lower_init)?;
         let eth::InPacket { control, mut frame } = packet.into_incoming();
-        let repr = init.initialize(route.src_addr, &mut frame)?;
+        let repr = init.initialize(route.src_addr, &mut *self.control.endpoint, &mut frame)?;
 
         Ok(Out {
             control: Controller {
@@ -293,11 +446,24 @@ impl<'a, P: Payload + PayloadMut> Raw<'a, P> {
 }
 
 impl Init {
-    fn initialize(&self, src_addr: ip::Address, payload: &mut impl PayloadMut) -> Result<ip::Repr> {
+    fn initialize(&self, src_addr: ip::Address, endpoint: &mut dyn Endpoint, payload: &mut impl PayloadMut)
+        -> Result<ip::Repr>
+    {
         let repr = self.ip_repr(src_addr)?;
         // Emit the packet but ignore the checksum for now. it is filled in later when calling
         // `OutPacket::send`.
         repr.emit(payload.payload_mut().as_mut_slice(), Checksum::Ignored);
+
+        if let (ip::Repr::Ipv4(_), true) = (&repr, self.fragment) {
+            // Routers may fragment the packet, which then requires a unique identification. Without
+            // a fragmenter to provide one, Don't Fragment stays set.
+            if let Some(ident) = endpoint.next_ident(repr.src_addr(), repr.dst_addr()) {
+                let packet = ip::v4::packet::new_unchecked_mut(payload.payload_mut().as_mut_slice());
+                packet.set_dont_frag(false);
+                packet.set_ident(ident as u16);
+            }
+        }
+
         Ok(repr)
     }
 
@@ -310,7 +476,19 @@ impl Init {
             protocol: self.protocol,
             payload_len: self.payload,
         };
-        repr.lower(&[]).ok_or(Error::Illegal)
+        let mut repr = repr.lower(&[]).ok_or(Error::Illegal)?;
+        match &mut repr {
+            ip::Repr::Ipv4(repr) => {
+                repr.dscp = self.dscp;
+                repr.ecn = self.ecn;
+            },
+            ip::Repr::Ipv6(repr) => {
+                repr.dscp = self.dscp;
+                repr.ecn = self.ecn;
+            },
+            _ => (),
+        }
+        Ok(repr)
     }
 
     fn init_eth(&self, route: EthRoute, payload: usize) -> Result<eth::Init> {
@@ -354,10 +532,18 @@ impl<'a, P: Payload> IpPacket<'a, P> {
     }
 
     /// Retrieve the representation of the packet.
+    ///
+    /// For IPv6 the protocol and payload length are those of the upper layer, following any
+    /// extension headers.
     pub fn repr(&self) -> ip::Repr {
         match self {
             IpPacket::V4(packet) => packet.repr().into(),
-            IpPacket::V6(packet) => packet.repr().into(),
+            IpPacket::V6(packet) => {
+                let mut repr = packet.repr();
+                repr.next_header = packet.upper_protocol();
+                repr.payload_len = packet.total_len() - packet.upper_offset();
+                repr.into()
+            },
         }
     }
 
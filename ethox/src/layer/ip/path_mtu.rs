@@ -0,0 +1,174 @@
+// Path MTU discovery as in RFC 1191 for IPv4 and RFC 8201 for IPv6. The cache only stores the
+// learned values, the ICMP layer feeds it and upper layers clamp their segment sizes to it.
+use crate::managed::Slice;
+use crate::time::{Duration, Expiration, Instant};
+use crate::wire::ip;
+
+/// A learned path MTU towards a single destination.
+///
+/// The entry is invalid and considered free when its destination address is unspecified, which
+/// is also the default value.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PathMtu {
+    dst_addr:   ip::Address,
+    mtu:        u16,
+    expires_at: Expiration,
+}
+
+/// A cache of path MTUs backed by a preallocated slice.
+///
+/// # Examples
+///
+/// On systems without heap, use:
+///
+/// ```rust
+/// use ethox::layer::ip::{PathMtu, PathMtuCache};
+///
+/// let mut path_mtu_storage = [PathMtu::default(); 8];
+/// let mut path_mtu_cache = PathMtuCache::new(&mut path_mtu_storage[..]);
+/// ```
+///
+/// ## Details
+///
+/// The cache is searched linearly, it is meant to hold the handful of destinations that have
+/// actually reported a smaller MTU. When it is full the entry closest to expiry is evicted. This
+/// only ever costs a few oversized packets that trigger a new report.
+#[derive(Debug)]
+pub struct Cache<'a> {
+    entries: Slice<'a, PathMtu>,
+}
+
+impl PathMtu {
+    /// The destination of this path.
+    pub fn dst_addr(&self) -> ip::Address {
+        self.dst_addr
+    }
+
+    /// The largest packet size, including the ip header, that can traverse the path.
+    pub fn mtu(&self) -> u16 {
+        self.mtu
+    }
+
+    fn is_valid(&self, time: Instant) -> bool {
+        !self.dst_addr.is_unspecified() && self.expires_at > Expiration::When(time)
+    }
+}
+
+impl<'a> Cache<'a> {
+    /// How long a learned path MTU stays valid.
+    ///
+    /// After this time the path is assumed to have returned to the link MTU such that increases
+    /// are eventually detected. This is the value suggested by RFC 1191 and RFC 8201.
+    pub(crate) const ENTRY_LIFETIME: Duration = Duration::from_millis(600_000);
+
+    /// The smallest MTU every IPv4 host must be able to forward, RFC 791.
+    pub const MIN_IPV4_MTU: u16 = 68;
+
+    /// The smallest MTU of any IPv6 link, RFC 8200.
+    pub const MIN_IPV6_MTU: u16 = 1280;
+
+    /// Create a cache with all entries in the storage cleared.
+    pub fn new<T>(storage: T) -> Self
+        where T: Into<Slice<'a, PathMtu>>
+    {
+        let mut entries = storage.into();
+        for entry in entries.iter_mut() {
+            *entry = PathMtu::default();
+        }
+        Cache { entries }
+    }
+
+    /// Find the currently known path MTU towards a destination.
+    ///
+    /// Returns `None` if no smaller MTU than that of the link has been learned or the learned
+    /// value has expired.
+    pub fn lookup(&self, dst_addr: ip::Address, time: Instant) -> Option<u16> {
+        self.entries.iter()
+            .find(|entry| entry.dst_addr == dst_addr && entry.is_valid(time))
+            .map(|entry| entry.mtu)
+    }
+
+    /// Record a reported MTU for a destination.
+    ///
+    /// Reports can only lower the estimate, an increase is ignored as mandated by RFC 1191 and
+    /// RFC 8201. Values below the protocol minimum are raised to that minimum instead.
+    pub fn update(&mut self, dst_addr: ip::Address, mtu: u16, time: Instant) {
+        let mtu = match dst_addr {
+            ip::Address::Ipv4(_) => mtu.max(Self::MIN_IPV4_MTU),
+            ip::Address::Ipv6(_) => mtu.max(Self::MIN_IPV6_MTU),
+            _ => return,
+        };
+
+        if let Some(current) = self.lookup(dst_addr, time) {
+            if current <= mtu {
+                return;
+            }
+        }
+
+        let entry = PathMtu {
+            dst_addr,
+            mtu,
+            expires_at: Expiration::When(time + Self::ENTRY_LIFETIME),
+        };
+
+        // Reuse the entry of the same destination, then a free one, then the oldest one.
+        let slot = self.entries.iter().position(|old| old.dst_addr == dst_addr)
+            .or_else(|| self.entries.iter().position(|old| !old.is_valid(time)))
+            .or_else(|| self.entries.iter()
+                .enumerate()
+                .min_by_key(|(_, old)| Option::<Instant>::from(old.expires_at))
+                .map(|(idx, _)| idx));
+
+        if let Some(slot) = slot {
+            self.entries[slot] = entry;
+        }
+    }
+
+    /// Forget all learned path MTUs.
+    pub fn clear(&mut self) {
+        for entry in self.entries.iter_mut() {
+            *entry = PathMtu::default();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REMOTE_V4: ip::Address = ip::Address::v4(10, 0, 0, 2);
+    const OTHER_V4: ip::Address = ip::Address::v4(10, 0, 0, 3);
+
+    #[test]
+    fn only_decreases() {
+        let mut storage = [PathMtu::default(); 2];
+        let mut cache = Cache::new(&mut storage[..]);
+        let time = Instant::from_millis(0);
+
+        assert_eq!(cache.lookup(REMOTE_V4, time), None);
+        cache.update(REMOTE_V4, 1400, time);
+        assert_eq!(cache.lookup(REMOTE_V4, time), Some(1400));
+        cache.update(REMOTE_V4, 1450, time);
+        assert_eq!(cache.lookup(REMOTE_V4, time), Some(1400));
+        cache.update(REMOTE_V4, 20, time);
+        assert_eq!(cache.lookup(REMOTE_V4, time), Some(Cache::MIN_IPV4_MTU));
+
+        let remote_v6 = ip::Address::Ipv6(ip::v6::Address::LOOPBACK);
+        cache.update(remote_v6, 1000, time);
+        assert_eq!(cache.lookup(remote_v6, time), Some(Cache::MIN_IPV6_MTU));
+    }
+
+    #[test]
+    fn expires_and_evicts() {
+        let mut storage = [PathMtu::default(); 1];
+        let mut cache = Cache::new(&mut storage[..]);
+        let time = Instant::from_millis(0);
+
+        cache.update(REMOTE_V4, 1400, time);
+        assert_eq!(cache.lookup(REMOTE_V4, time + Cache::ENTRY_LIFETIME), None);
+
+        cache.update(OTHER_V4, 1300, time + Duration::from_millis(1));
+        assert_eq!(cache.lookup(REMOTE_V4, time), None);
+        assert_eq!(cache.lookup(OTHER_V4, time), Some(1300));
+    }
+}
@@ -0,0 +1,595 @@
+// Reassembly of fragmented datagrams as in RFC 791 and RFC 8200 section 4.5. Every datagram in
+// progress occupies one buffer provided by the user, its fragments are copied to their offset and
+// an `Assembler` tracks the holes in between. Duplicates of data already received are ignored, the
+// network may well produce them. For IPv4 the data received first is kept when fragments overlap
+// partially (RFC 815). An IPv6 fragment that partially overlaps others drops the whole datagram, a
+// legitimate sender never produces them and they are a well known attack on reassembly (RFC 5722).
+// An IPv6 datagram that times out after its first fragment arrived keeps
+// that fragment until the ICMP layer reported the timeout to the source (RFC 8200 section 4.5).
+// The headers preceding the fragment header of the first IPv6 fragment, the unfragmentable part,
+// are kept at the end of the buffer as the reassembled packet is rebuilt from them.
+use crate::managed::Slice;
+use crate::storage::assembler::{assembly, Contig};
+use crate::time::{Duration, Expiration, Instant};
+use crate::wire::ip;
+
+/// Identifies the fragments belonging to one datagram.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Key {
+    pub(crate) src_addr: ip::Address,
+    pub(crate) dst_addr: ip::Address,
+    pub(crate) protocol: ip::Protocol,
+    pub(crate) ident: u32,
+}
+
+/// The position of a fragment within its datagram.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Fragment<'a> {
+    /// Offset of the fragment payload in the payload of the datagram.
+    pub(crate) offset: usize,
+    /// If more fragments follow this one.
+    pub(crate) more: bool,
+    /// The protocol of the payload, only the one of the first fragment is used.
+    pub(crate) protocol: ip::Protocol,
+    /// The hop limit of the packet, only the one of the first fragment is used.
+    pub(crate) hop_limit: u8,
+    /// The headers preceding the fragment header, only those of the first fragment are kept.
+    pub(crate) headers: &'a [u8],
+}
+
+/// An IPv6 datagram that timed out after its first fragment arrived.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Expired {
+    /// The index of the buffer holding the first fragment.
+    pub(crate) idx: usize,
+    /// The addresses and identification of the datagram.
+    pub(crate) key: Key,
+    /// The protocol following the fragment header.
+    pub(crate) protocol: ip::Protocol,
+    /// The hop limit of the first fragment.
+    pub(crate) hop_limit: u8,
+}
+
+/// A buffer for reassembling a single datagram.
+///
+/// The buffer should be able to hold the payload of the largest datagram that is expected, larger
+/// datagrams are dropped. Every range of data received out of order, separated from the others by
+/// a hole, occupies one `Contig` of the buffer.
+#[derive(Debug)]
+pub struct Buffer<'a> {
+    key: Option<Key>,
+    expires_at: Expiration,
+    /// Length of the payload that is complete from its start.
+    assembled: usize,
+    /// The length of the payload, known once the last fragment arrived.
+    total: Option<usize>,
+    /// The protocol of the payload, from the first fragment.
+    protocol: ip::Protocol,
+    /// The payload length and hop limit of the first fragment, once it arrived.
+    first: Option<(usize, u8)>,
+    /// The length of the headers of the first fragment, kept at the end of the data.
+    headers: usize,
+    holes: Slice<'a, Contig>,
+    data: Slice<'a, u8>,
+}
+
+/// A table of datagrams in reassembly, backed by preallocated buffers.
+///
+/// # Examples
+///
+/// On systems without heap, use:
+///
+/// ```rust
+/// use ethox::layer::ip::{Reassembly, ReassemblyBuffer};
+/// use ethox::storage::assembler::Contig;
+///
+/// let mut first = [0; 1 << 14];
+/// let mut second = [0; 1 << 14];
+/// let mut first_holes = [Contig::default(); 16];
+/// let mut second_holes = [Contig::default(); 16];
+/// let mut buffers = [
+///     ReassemblyBuffer::new(&mut first[..], &mut first_holes[..]),
+///     ReassemblyBuffer::new(&mut second[..], &mut second_holes[..]),
+/// ];
+/// let mut reassembly = Reassembly::new(&mut buffers[..]);
+/// ```
+///
+/// ## Details
+///
+/// The number of buffers caps the number of datagrams in reassembly at the same time. Fragments
+/// of further datagrams are dropped until a buffer completes or times out, an attacker can not
+/// evict datagrams that are already in progress. The timer of a datagram starts with its first
+/// fragment and is not extended by later ones.
+///
+/// The fragments of a datagram may arrive in any order but a buffer only tracks as many separate
+/// ranges of data as it has `Contig`s. A datagram arriving in more pieces out of order is dropped.
+///
+/// When an IPv6 datagram times out, its buffer keeps the first fragment so that the ICMP layer
+/// can send a Time Exceeded message to the source. New datagrams prefer unused buffers over such
+/// expired ones.
+///
+/// A completed datagram is copied into the packet buffer of its last fragment, which is resized
+/// accordingly. IPv6 packets get the extension headers of the first fragment preceding the
+/// fragment header, which the buffer keeps behind the payload. The datagram is dropped if the packet buffer can not be grown to that size.
+#[derive(Debug)]
+pub struct Table<'a> {
+    buffers: Slice<'a, Buffer<'a>>,
+    timeout: Duration,
+}
+
+impl<'a> Buffer<'a> {
+    /// Create an unused buffer from its storage and the storage for tracking holes.
+    ///
+    /// Datagrams whose fragments arrive in more separate ranges than there are `Contig`s are
+    /// dropped. All fragments but the last are a multiple of 8 octets long and so is every hole
+    /// between them, `storage.len() / 16 + 1` holes suffice for fragments arriving in any order.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `holes` is empty.
+    pub fn new<T, H>(storage: T, holes: H) -> Self
+        where T: Into<Slice<'a, u8>>, H: Into<Slice<'a, Contig>>,
+    {
+        let mut holes = holes.into();
+        assembly::new(&mut holes);
+        Buffer {
+            key: None,
+            expires_at: Expiration::Never,
+            assembled: 0,
+            total: None,
+            protocol: ip::Protocol::Unknown(0),
+            first: None,
+            headers: 0,
+            holes,
+            data: storage.into(),
+        }
+    }
+
+    /// The largest datagram payload that fits into the buffer.
+    ///
+    /// An IPv6 datagram also stores the extension headers preceding the fragment header of its
+    /// first fragment in the buffer, reducing the space for its payload.
+    pub fn capacity(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Check if the buffer does not hold a datagram in reassembly.
+    pub fn is_free(&self, time: Instant) -> bool {
+        self.key.is_none() || self.expires_at <= Expiration::When(time)
+    }
+
+    fn start(&mut self, key: Key, expires_at: Instant) {
+        self.key = Some(key);
+        self.expires_at = Expiration::When(expires_at);
+        self.assembled = 0;
+        self.total = None;
+        self.protocol = key.protocol;
+        self.first = None;
+        self.headers = 0;
+        assembly::new(&mut self.holes);
+    }
+
+    fn clear(&mut self) {
+        self.key = None;
+        self.expires_at = Expiration::Never;
+    }
+
+    /// The ranges of data received so far, in order.
+    fn received(&self) -> impl Iterator<Item=(usize, usize)> + '_ {
+        Self::ranges(self.assembled, &self.holes)
+    }
+
+    fn ranges(assembled: usize, holes: &[Contig]) -> impl Iterator<Item=(usize, usize)> + '_ {
+        let pending = assembly::from_slice_unchecked(holes)
+            .iter()
+            .map(move |(start, end)| (assembled + start as usize, assembled + end as usize));
+        core::iter::once((0, assembled)).chain(pending)
+    }
+
+    /// The end of the furthest fragment received so far.
+    fn received_end(&self) -> usize {
+        self.received().last().map_or(0, |(_, end)| end)
+    }
+
+    fn is_complete(&self) -> bool {
+        self.total == Some(self.assembled)
+    }
+
+    /// Copy a fragment into the buffer.
+    ///
+    /// Fragments whose data was already received are ignored. Fails if the fragment is
+    /// inconsistent with the others, overlaps them partially when `strict`, or does not fit.
+    fn insert(&mut self, fragment: Fragment, data: &[u8], strict: bool) -> Result<(), ()> {
+        let offset = fragment.offset;
+        let end = offset.checked_add(data.len()).ok_or(())?;
+
+        // The headers of the first fragment are kept behind the payload.
+        let first = offset == 0 && self.first.is_none();
+        let headers = if first { fragment.headers.len() } else { self.headers };
+        let limit = self.data.len().checked_sub(headers).ok_or(())?;
+        if end > limit || (first && self.received_end() > limit) {
+            return Err(());
+        }
+
+        // All but the last fragment are a multiple of 8 octets long.
+        if fragment.more && data.len() & 7 != 0 {
+            return Err(());
+        }
+
+        match self.total {
+            // Only a single last fragment, nothing beyond it.
+            Some(total) if end > total || (!fragment.more && end != total) => return Err(()),
+            None if !fragment.more && end < self.received_end() => return Err(()),
+            None if !fragment.more => self.total = Some(end),
+            _ => (),
+        }
+
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        if self.received().any(|(start, stop)| start <= offset && end <= stop) {
+            return Ok(());
+        }
+
+        let overlaps = self.received().any(|(start, stop)| start < end && offset < stop);
+        if overlaps && strict {
+            return Err(());
+        }
+
+        // Only fill the holes, data that was received first is kept.
+        let mut next = offset;
+        for (start, stop) in Self::ranges(self.assembled, &self.holes) {
+            if stop <= next {
+                continue;
+            }
+            if end <= start {
+                break;
+            }
+            if next < start {
+                self.data[next..start].copy_from_slice(&data[next - offset..start - offset]);
+            }
+            next = stop;
+        }
+        if next < end {
+            self.data[next..end].copy_from_slice(&data[next - offset..]);
+        }
+
+        // Offsets and lengths are bounded by the largest datagram, they fit into `u32`.
+        let relative = offset.saturating_sub(self.assembled);
+        let len = end - offset.max(self.assembled);
+        let new = assembly::from_mut_slice_unchecked(&mut self.holes)
+            .add(relative as u32, len as u32)?;
+        self.assembled += new as usize;
+        if first {
+            self.protocol = fragment.protocol;
+            self.first = Some((data.len(), fragment.hop_limit));
+            self.headers = headers;
+            self.data[limit..].copy_from_slice(fragment.headers);
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Table<'a> {
+    /// How long the fragments of a datagram are kept by default.
+    ///
+    /// RFC 1122 recommends a fixed value between 60 and 120 seconds. We choose a smaller one, like
+    /// other common stacks, as the buffers are a scarce resource.
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_millis(30_000);
+
+    /// Create a table with all buffers cleared.
+    pub fn new<T>(storage: T) -> Self
+        where T: Into<Slice<'a, Buffer<'a>>>
+    {
+        let mut buffers = storage.into();
+        for buffer in buffers.iter_mut() {
+            buffer.clear();
+        }
+        Table {
+            buffers,
+            timeout: Self::DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Change the time after which an incomplete datagram is dropped.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// The time after which an incomplete datagram is dropped.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Get the buffers of the table.
+    pub fn buffers(&self) -> &[Buffer<'a>] {
+        &self.buffers
+    }
+
+    /// The number of datagrams currently in reassembly.
+    pub fn in_progress(&self, time: Instant) -> usize {
+        self.buffers.iter()
+            .filter(|buffer| !buffer.is_free(time))
+            .count()
+    }
+
+    /// Drop all datagrams in reassembly.
+    pub fn clear(&mut self) {
+        for buffer in self.buffers.iter_mut() {
+            buffer.clear();
+        }
+    }
+
+    /// Add a fragment of a datagram.
+    ///
+    /// Returns the index of the buffer when this completed the datagram. Its payload must then be
+    /// retrieved with `datagram` and the buffer freed with `release`.
+    pub(crate) fn add(&mut self, key: Key, fragment: Fragment, data: &[u8], time: Instant)
+        -> Option<usize>
+    {
+        let idx = match self.find(key, time) {
+            Some(idx) => idx,
+            None => {
+                let idx = self.buffers.iter()
+                    .position(|buffer| buffer.key.is_none())
+                    .or_else(|| self.buffers.iter().position(|buffer| buffer.is_free(time)))?;
+                self.buffers[idx].start(key, time + self.timeout);
+                idx
+            },
+        };
+
+        // Partial overlaps only drop IPv6 datagrams, RFC 5722.
+        let strict = key.protocol == ip::Protocol::Ipv6Frag;
+        let buffer = &mut self.buffers[idx];
+        if buffer.insert(fragment, data, strict).is_err() {
+            buffer.clear();
+            return None;
+        }
+
+        if buffer.is_complete() {
+            Some(idx)
+        } else {
+            None
+        }
+    }
+
+    /// The payload of a completed datagram.
+    pub(crate) fn datagram(&self, idx: usize) -> &[u8] {
+        let buffer = &self.buffers[idx];
+        &buffer.data[..buffer.assembled]
+    }
+
+    /// The protocol of the payload of a completed datagram.
+    pub(crate) fn protocol(&self, idx: usize) -> ip::Protocol {
+        self.buffers[idx].protocol
+    }
+
+    /// The headers preceding the fragment header of the first fragment of a completed datagram.
+    pub(crate) fn headers(&self, idx: usize) -> &[u8] {
+        let buffer = &self.buffers[idx];
+        &buffer.data[buffer.data.len() - buffer.headers..]
+    }
+
+    /// Free the buffer of a datagram.
+    pub(crate) fn release(&mut self, idx: usize) {
+        self.buffers[idx].clear();
+    }
+
+    /// Find an IPv6 datagram that timed out after its first fragment arrived.
+    pub(crate) fn expired(&self, time: Instant) -> Option<Expired> {
+        self.buffers.iter()
+            .enumerate()
+            .filter(|(_, buffer)| buffer.expires_at <= Expiration::When(time))
+            .find_map(|(idx, buffer)| {
+                let key = buffer.key?;
+                let (_, hop_limit) = buffer.first?;
+                if key.protocol != ip::Protocol::Ipv6Frag {
+                    return None;
+                }
+
+                Some(Expired {
+                    idx,
+                    key,
+                    protocol: buffer.protocol,
+                    hop_limit,
+                })
+            })
+    }
+
+    /// The payload of the first fragment of an expired datagram.
+    pub(crate) fn first_fragment(&self, idx: usize) -> &[u8] {
+        let buffer = &self.buffers[idx];
+        let (len, _) = buffer.first.unwrap_or((0, 0));
+        &buffer.data[..len]
+    }
+
+    fn find(&self, key: Key, time: Instant) -> Option<usize> {
+        self.buffers.iter()
+            .position(|buffer| buffer.key == Some(key) && !buffer.is_free(time))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: Key = Key {
+        src_addr: ip::Address::v4(10, 0, 0, 2),
+        dst_addr: ip::Address::v4(10, 0, 0, 1),
+        protocol: ip::Protocol::Udp,
+        ident: 42,
+    };
+
+    fn fragment(offset: usize, more: bool) -> Fragment<'static> {
+        Fragment { offset, more, protocol: ip::Protocol::Udp, hop_limit: 64, headers: &[] }
+    }
+
+    fn buffer() -> Buffer<'static> {
+        Buffer::new(vec![0; 64], vec![Contig::default(); 64 / 16 + 1])
+    }
+
+    #[test]
+    fn out_of_order() {
+        let mut storage = [buffer()];
+        let mut table = Table::new(&mut storage[..]);
+        let time = Instant::from_millis(0);
+
+        assert_eq!(table.add(KEY, fragment(16, false), &[3; 4], time), None);
+        assert_eq!(table.add(KEY, fragment(0, true), &[1; 8], time), None);
+        assert_eq!(table.in_progress(time), 1);
+
+        // Other datagrams do not fit while this one is in progress.
+        let other = Key { ident: 43, ..KEY };
+        assert_eq!(table.add(other, fragment(0, true), &[0; 8], time), None);
+
+        let idx = table.add(KEY, fragment(8, true), &[2; 8], time).unwrap();
+        assert_eq!(table.datagram(idx), &[1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 3, 3, 3, 3]);
+        table.release(idx);
+        assert_eq!(table.in_progress(time), 0);
+    }
+
+    #[test]
+    fn drops_inconsistent() {
+        let mut storage = [buffer()];
+        let mut table = Table::new(&mut storage[..]);
+        let time = Instant::from_millis(0);
+
+        // Partially overlapping data of an IPv6 datagram.
+        let key = Key { protocol: ip::Protocol::Ipv6Frag, ..KEY };
+        assert_eq!(table.add(key, fragment(0, true), &[0; 16], time), None);
+        assert_eq!(table.add(key, fragment(8, true), &[0; 16], time), None);
+        assert_eq!(table.in_progress(time), 0);
+
+        // Data beyond the last fragment.
+        assert_eq!(table.add(KEY, fragment(8, false), &[0; 8], time), None);
+        assert_eq!(table.add(KEY, fragment(16, true), &[0; 8], time), None);
+        assert_eq!(table.in_progress(time), 0);
+
+        // Larger than the buffer.
+        assert_eq!(table.add(KEY, fragment(56, false), &[0; 16], time), None);
+        assert_eq!(table.in_progress(time), 0);
+
+        // Not a multiple of 8 octets but more fragments follow.
+        assert_eq!(table.add(KEY, fragment(0, true), &[0; 12], time), None);
+        assert_eq!(table.in_progress(time), 0);
+    }
+
+    #[test]
+    fn duplicates() {
+        let mut storage = [buffer()];
+        let mut table = Table::new(&mut storage[..]);
+        let time = Instant::from_millis(0);
+
+        // Exact duplicates are ignored, also of the last fragment.
+        let key = Key { protocol: ip::Protocol::Ipv6Frag, ..KEY };
+        assert_eq!(table.add(key, fragment(0, true), &[1; 8], time), None);
+        assert_eq!(table.add(key, fragment(0, true), &[1; 8], time), None);
+        assert_eq!(table.add(key, fragment(16, false), &[3; 4], time), None);
+        assert_eq!(table.add(key, fragment(16, false), &[3; 4], time), None);
+        assert_eq!(table.in_progress(time), 1);
+        let idx = table.add(key, fragment(8, true), &[2; 8], time).unwrap();
+        assert_eq!(table.datagram(idx), &[1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 3, 3, 3, 3]);
+        table.release(idx);
+
+        // IPv4 fragments may overlap partially, the data received first is kept.
+        assert_eq!(table.add(KEY, fragment(8, true), &[2; 8], time), None);
+        assert_eq!(table.add(KEY, fragment(0, true), &[1; 16], time), None);
+        let idx = table.add(KEY, fragment(16, false), &[3; 4], time).unwrap();
+        assert_eq!(table.datagram(idx), &[1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 3, 3, 3, 3]);
+    }
+
+    #[test]
+    fn many_holes() {
+        let mut storage = [Buffer::new(vec![0; 128], vec![Contig::default(); 128 / 16 + 1])];
+        let mut table = Table::new(&mut storage[..]);
+        let time = Instant::from_millis(0);
+
+        // Every other fragment first, leaving a hole before each.
+        for offset in (8..128).step_by(16) {
+            let more = offset + 8 < 128;
+            assert_eq!(table.add(KEY, fragment(offset, more), &[offset as u8; 8], time), None);
+        }
+        for offset in (16..128).step_by(16) {
+            assert_eq!(table.add(KEY, fragment(offset, true), &[offset as u8; 8], time), None);
+        }
+        let idx = table.add(KEY, fragment(0, true), &[0; 8], time).unwrap();
+        let expected: Vec<u8> = (0..128).map(|idx| idx & !7).collect();
+        assert_eq!(table.datagram(idx), &expected[..]);
+        table.release(idx);
+
+        // With fewer holes the datagram can not be tracked.
+        let mut storage = [Buffer::new(vec![0; 128], vec![Contig::default(); 2])];
+        let mut table = Table::new(&mut storage[..]);
+        for offset in (8..48).step_by(16) {
+            assert_eq!(table.add(KEY, fragment(offset, true), &[0; 8], time), None);
+        }
+        assert_eq!(table.in_progress(time), 0);
+    }
+
+    #[test]
+    fn times_out() {
+        let mut storage = [buffer()];
+        let mut table = Table::new(&mut storage[..]);
+        let time = Instant::from_millis(0);
+
+        assert_eq!(table.add(KEY, fragment(8, false), &[0; 8], time), None);
+        let later = time + Table::DEFAULT_TIMEOUT;
+        assert_eq!(table.in_progress(later), 0);
+
+        // The first fragment alone does not complete the datagram anymore.
+        assert_eq!(table.add(KEY, fragment(0, true), &[0; 8], later), None);
+        assert_eq!(table.in_progress(later), 1);
+    }
+
+    #[test]
+    fn keeps_expired_first_fragment() {
+        let mut storage = [buffer(), buffer()];
+        let mut table = Table::new(&mut storage[..]);
+        let time = Instant::from_millis(0);
+        let key = Key { protocol: ip::Protocol::Ipv6Frag, ..KEY };
+
+        assert_eq!(table.add(key, fragment(0, true), &[1; 8], time), None);
+        assert_eq!(table.expired(time), None);
+
+        let later = time + Table::DEFAULT_TIMEOUT;
+        let expired = table.expired(later).expect("Reports the timed out datagram");
+        assert_eq!(expired.key, key);
+        assert_eq!(expired.protocol, ip::Protocol::Udp);
+        assert_eq!(table.first_fragment(expired.idx), &[1; 8]);
+
+        // A new datagram does not evict the first fragment while a buffer is unused.
+        let other = Key { ident: 43, ..key };
+        assert_eq!(table.add(other, fragment(8, true), &[2; 8], later), None);
+        assert_eq!(table.expired(later), Some(expired));
+
+        table.release(expired.idx);
+        assert_eq!(table.expired(later), None);
+
+        // IPv4 datagrams are not reported.
+        assert_eq!(table.add(KEY, fragment(0, true), &[1; 8], later), None);
+        assert_eq!(table.expired(later + Table::DEFAULT_TIMEOUT), None);
+    }
+
+    #[test]
+    fn keeps_first_headers() {
+        let mut storage = [buffer()];
+        let mut table = Table::new(&mut storage[..]);
+        let time = Instant::from_millis(0);
+        let key = Key { protocol: ip::Protocol::Ipv6Frag, ..KEY };
+
+        // Only the headers of the first fragment are kept, whichever fragment arrives last.
+        let last = Fragment { headers: &[2; 8], ..fragment(8, false) };
+        let first = Fragment { headers: &[1; 16], ..fragment(0, true) };
+        assert_eq!(table.add(key, last, &[4; 4], time), None);
+        let idx = table.add(key, first, &[3; 8], time).unwrap();
+        assert_eq!(table.datagram(idx), &[3, 3, 3, 3, 3, 3, 3, 3, 4, 4, 4, 4]);
+        assert_eq!(table.headers(idx), &[1; 16]);
+        table.release(idx);
+
+        // The headers must fit behind the payload received so far.
+        assert_eq!(table.add(key, fragment(48, true), &[4; 8], time), None);
+        assert_eq!(table.add(key, first, &[3; 8], time), None);
+        assert_eq!(table.in_progress(time), 0);
+    }
+}
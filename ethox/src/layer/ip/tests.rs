@@ -1,9 +1,12 @@
 use super::*;
 use crate::managed::Slice;
+use crate::storage::assembler::Contig;
 use crate::nic::{external::External, Device};
 use crate::layer::{arp, eth, ip};
+use crate::layer::hash::HashKey;
 use crate::wire::{ethernet, ip::v4, ip::v6};
-use crate::wire::ip::{Address, Cidr, Protocol, Subnet};
+use crate::wire::ip::{Address, Cidr, Ecn, Protocol, Subnet};
+use crate::time::Instant;
 use crate::wire::{Payload, PayloadMut};
 
 static PAYLOAD_BYTES: [u8; 50] =
@@ -114,6 +117,354 @@ fn simple_ipv6() {
    assert_eq!(recv, Ok(1)); 
 }
 
+#[test]
+fn reassemble_ipv4() {
+    const MAC_ADDR_SRC: ethernet::Address = ethernet::Address([0, 1, 2, 3, 4, 5]);
+    const IP_ADDR_SRC: v4::Address = v4::Address::new(10, 0, 0, 1);
+    const MAC_ADDR_DST: ethernet::Address = ethernet::Address([6, 5, 4, 3, 2, 1]);
+    const IP_ADDR_DST: v4::Address = v4::Address::new(10, 0, 0, 2);
+
+    let mut nic = External::new_send(Slice::Many(vec![vec![0; 1024]; 3]));
+
+    let mut eth = eth::Endpoint::new(MAC_ADDR_SRC);
+
+    let mut neighbors = [arp::Neighbor::default(); 1];
+    let neighbors = {
+        let mut eth_cache = arp::NeighborCache::new(&mut neighbors[..]);
+        eth_cache.fill(IP_ADDR_DST.into(), MAC_ADDR_DST, None).unwrap();
+        eth_cache
+    };
+    let mut ip = ip::Endpoint::new(Cidr::new(IP_ADDR_SRC.into(), 24),
+        ip::Routes::new(Slice::empty()),
+        neighbors);
+    let mut buffers = [ReassemblyBuffer::new(vec![0; 128], vec![Contig::default(); 128 / 16 + 1])];
+    ip.set_reassembly(Reassembly::new(&mut buffers[..]));
+
+    for _ in 0..3 {
+        let sent = nic.tx(1, eth.send(ip.send(SimpleSend {
+            dst_addr: IP_ADDR_DST.into(),
+        })));
+        assert_eq!(sent, Ok(1));
+    }
+
+    // Split the payload into fragments, arriving out of order.
+    for (idx, &(offset, len)) in [(48, 2), (0, 24), (24, 24)].iter().enumerate() {
+        let buffer = nic.get_mut(idx).unwrap();
+        let eth = ethernet::frame::new_unchecked_mut(buffer);
+        eth.set_dst_addr(MAC_ADDR_SRC);
+        eth.set_src_addr(MAC_ADDR_DST);
+        let ip = v4::packet::new_unchecked_mut(eth.payload_mut_slice());
+        ip.set_dst_addr(IP_ADDR_SRC);
+        ip.set_src_addr(IP_ADDR_DST);
+        ip.set_ident(7);
+        ip.set_more_frags(offset + len < PAYLOAD_BYTES.len());
+        ip.set_frag_offset(offset as u16);
+        ip.set_total_len(20 + len as u16);
+        ip.payload_mut_slice().copy_from_slice(&PAYLOAD_BYTES[offset..][..len]);
+        ip.fill_checksum();
+    }
+
+    nic.receive_all();
+
+    let mut received = 0;
+    for _ in 0..3 {
+        let recv = nic.rx(1, eth.recv(ip.recv_with(|frame: InPacket<_>| {
+            simple_recv(frame);
+            received += 1;
+        })));
+        assert_eq!(recv, Ok(1));
+    }
+    assert_eq!(received, 1);
+    assert_eq!(ip.reassembly().in_progress(Instant::from_millis(0)), 0);
+}
+
+#[test]
+fn fragment_ipv6() {
+    const MAC_ADDR_SRC: ethernet::Address = ethernet::Address([0, 1, 2, 3, 4, 5]);
+    const IP_ADDR_SRC: v6::Address = v6::Address::from_link_local_id(v6::InterfaceId::from_generated_ether(MAC_ADDR_SRC));
+    const MAC_ADDR_DST: ethernet::Address = ethernet::Address([6, 5, 4, 3, 2, 1]);
+    const IP_ADDR_DST: v6::Address = v6::Address::from_link_local_id(v6::InterfaceId::from_generated_ether(MAC_ADDR_DST));
+
+    let mut nic = External::new_send(Slice::Many(vec![vec![0; 1600]; 2]));
+
+    let mut eth = eth::Endpoint::new(MAC_ADDR_SRC);
+
+    let mut neighbors = [arp::Neighbor::default(); 1];
+    let neighbors = {
+        let mut eth_cache = arp::NeighborCache::new(&mut neighbors[..]);
+        eth_cache.fill(IP_ADDR_DST.into(), MAC_ADDR_DST, None).unwrap();
+        eth_cache
+    };
+    let mut ip = ip::Endpoint::new(Cidr::new(IP_ADDR_SRC.into(), 64),
+        ip::Routes::new(Slice::empty()),
+        neighbors);
+    ip.set_fragmenter(Fragmenter::new(vec![0; 4096], HashKey::from_key(0, 0)));
+
+    let sent = nic.tx(1, eth.send(ip.send_with(|raw: RawPacket<_>| {
+        let init = ip::Init {
+            source: Address::from(IP_ADDR_SRC).into(),
+            dst_addr: IP_ADDR_DST.into(),
+            payload: 2000,
+            protocol: Protocol::Unknown(0xEF),
+            dscp: 0,
+            ecn: Ecn::NotEct,
+            fragment: true,
+        };
+        let mut prepared = raw.prepare_fragmented(init)
+            .expect("Fits into the fragmenter");
+        prepared.payload_mut_slice()
+            .iter_mut()
+            .for_each(|b| *b = 0xaa);
+        prepared.send()
+            .expect("Could send the first fragment");
+    })));
+    assert_eq!(sent, Ok(1));
+    assert!(ip.fragmenter().unwrap().is_pending());
+
+    // The upper layer is not invoked while fragments are pending.
+    let sent = nic.tx(1, eth.send(ip.send_with(|_: RawPacket<_>| {
+        panic!("Fragments are sent first");
+    })));
+    assert_eq!(sent, Ok(1));
+    assert!(!ip.fragmenter().unwrap().is_pending());
+
+    let fragments: Vec<_> = (0..2).map(|idx| {
+        let buffer = nic.get(idx).unwrap();
+        let eth = ethernet::frame::new_checked(&buffer[..]).unwrap();
+        let ip = v6::packet::new_checked(eth.payload_slice()).unwrap();
+        assert_eq!(ip.next_header(), Protocol::Ipv6Frag);
+        let header = v6::fragment::Header::new_checked(ip.payload_slice()).unwrap();
+        let repr = v6::fragment::Repr::parse(&header).unwrap();
+        assert_eq!(repr.next_header, Protocol::Unknown(0xEF));
+        (repr, ip.payload_slice().len() - 8)
+    }).collect();
+
+    assert_eq!(fragments[0].0.frag_offset, 0);
+    assert!(fragments[0].0.more_frags);
+    assert_eq!(usize::from(fragments[1].0.frag_offset) * 8, fragments[0].1);
+    assert!(!fragments[1].0.more_frags);
+    assert_eq!(fragments[0].0.ident, fragments[1].0.ident);
+    assert_eq!(fragments[0].1 + fragments[1].1, 2000);
+}
+
+#[test]
+fn reassemble_ipv6() {
+    const MAC_ADDR_SRC: ethernet::Address = ethernet::Address([0, 1, 2, 3, 4, 5]);
+    const IP_ADDR_SRC: v6::Address = v6::Address::from_link_local_id(v6::InterfaceId::from_generated_ether(MAC_ADDR_SRC));
+    const MAC_ADDR_DST: ethernet::Address = ethernet::Address([6, 5, 4, 3, 2, 1]);
+    const IP_ADDR_DST: v6::Address = v6::Address::from_link_local_id(v6::InterfaceId::from_generated_ether(MAC_ADDR_DST));
+
+    let mut nic = External::new_send(Slice::Many(vec![vec![0; 1024]; 3]));
+
+    let mut eth = eth::Endpoint::new(MAC_ADDR_SRC);
+
+    let mut neighbors = [arp::Neighbor::default(); 1];
+    let neighbors = {
+        let mut eth_cache = arp::NeighborCache::new(&mut neighbors[..]);
+        eth_cache.fill(IP_ADDR_DST.into(), MAC_ADDR_DST, None).unwrap();
+        eth_cache
+    };
+    let mut ip = ip::Endpoint::new(Cidr::new(IP_ADDR_SRC.into(), 64),
+        ip::Routes::new(Slice::empty()),
+        neighbors);
+    let mut buffers = [ReassemblyBuffer::new(vec![0; 128], vec![Contig::default(); 128 / 16 + 1])];
+    ip.set_reassembly(Reassembly::new(&mut buffers[..]));
+
+    for _ in 0..3 {
+        let sent = nic.tx(1, eth.send(ip.send(SimpleSend {
+            dst_addr: IP_ADDR_DST.into(),
+        })));
+        assert_eq!(sent, Ok(1));
+    }
+
+    // An atomic fragment, followed by a datagram in two fragments arriving out of order.
+    let fragments = [(0, 50, 1), (24, 26, 2), (0, 24, 2)];
+    for (idx, &(offset, len, ident)) in fragments.iter().enumerate() {
+        let buffer = nic.get_mut(idx).unwrap();
+        // Room for the fragment header.
+        buffer.resize(14 + 40 + 8 + len, 0);
+        let eth = ethernet::frame::new_unchecked_mut(buffer);
+        eth.set_dst_addr(MAC_ADDR_SRC);
+        eth.set_src_addr(MAC_ADDR_DST);
+        let ip = v6::packet::new_unchecked_mut(eth.payload_mut_slice());
+        ip.set_dst_addr(IP_ADDR_SRC);
+        ip.set_src_addr(IP_ADDR_DST);
+        ip.set_next_header(Protocol::Ipv6Frag);
+        ip.set_payload_len(8 + len as u16);
+        let (header, payload) = ip.payload_mut_slice().split_at_mut(8);
+        let repr = v6::fragment::Repr {
+            next_header: Protocol::Unknown(0xEF),
+            frag_offset: (offset / 8) as u16,
+            more_frags: offset + len < PAYLOAD_BYTES.len(),
+            ident,
+        };
+        repr.emit(&mut v6::fragment::Header::new_unchecked(header));
+        payload.copy_from_slice(&PAYLOAD_BYTES[offset..][..len]);
+    }
+
+    nic.receive_all();
+
+    let mut received = 0;
+    for _ in 0..3 {
+        let recv = nic.rx(1, eth.recv(ip.recv_with(|frame: InPacket<_>| {
+            assert_eq!(frame.packet.repr().protocol(), Protocol::Unknown(0xEF));
+            simple_recv(frame);
+            received += 1;
+        })));
+        assert_eq!(recv, Ok(1));
+    }
+    assert_eq!(received, 2);
+    assert_eq!(ip.reassembly().in_progress(Instant::from_millis(0)), 0);
+}
+
+#[test]
+fn reassemble_ipv6_first_headers() {
+    const MAC_ADDR_SRC: ethernet::Address = ethernet::Address([0, 1, 2, 3, 4, 5]);
+    const IP_ADDR_SRC: v6::Address = v6::Address::from_link_local_id(v6::InterfaceId::from_generated_ether(MAC_ADDR_SRC));
+    const MAC_ADDR_DST: ethernet::Address = ethernet::Address([6, 5, 4, 3, 2, 1]);
+    const IP_ADDR_DST: v6::Address = v6::Address::from_link_local_id(v6::InterfaceId::from_generated_ether(MAC_ADDR_DST));
+
+    let mut nic = External::new_send(Slice::Many(vec![vec![0; 1024]; 2]));
+
+    let mut eth = eth::Endpoint::new(MAC_ADDR_SRC);
+
+    let mut neighbors = [arp::Neighbor::default(); 1];
+    let neighbors = {
+        let mut eth_cache = arp::NeighborCache::new(&mut neighbors[..]);
+        eth_cache.fill(IP_ADDR_DST.into(), MAC_ADDR_DST, None).unwrap();
+        eth_cache
+    };
+    let mut ip = ip::Endpoint::new(Cidr::new(IP_ADDR_SRC.into(), 64),
+        ip::Routes::new(Slice::empty()),
+        neighbors);
+    let mut buffers = [ReassemblyBuffer::new(vec![0; 128], vec![Contig::default(); 128 / 16 + 1])];
+    ip.set_reassembly(Reassembly::new(&mut buffers[..]));
+
+    for _ in 0..2 {
+        let sent = nic.tx(1, eth.send(ip.send(SimpleSend {
+            dst_addr: IP_ADDR_DST.into(),
+        })));
+        assert_eq!(sent, Ok(1));
+    }
+
+    // The fragments carry different Destination options, the first one arrives first.
+    const FIRST_OPTIONS: [u8; 16] = [44, 1, 0x1e, 12, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    const LAST_OPTIONS: [u8; 8] = [44, 0, 1, 4, 0, 0, 0, 0];
+    let fragments: [(usize, usize, &[u8]); 2] = [(0, 24, &FIRST_OPTIONS), (24, 26, &LAST_OPTIONS)];
+    for (idx, &(offset, len, options)) in fragments.iter().enumerate() {
+        let buffer = nic.get_mut(idx).unwrap();
+        buffer.resize(14 + 40 + options.len() + 8 + len, 0);
+        let eth = ethernet::frame::new_unchecked_mut(buffer);
+        eth.set_dst_addr(MAC_ADDR_SRC);
+        eth.set_src_addr(MAC_ADDR_DST);
+        let ip = v6::packet::new_unchecked_mut(eth.payload_mut_slice());
+        ip.set_dst_addr(IP_ADDR_SRC);
+        ip.set_src_addr(IP_ADDR_DST);
+        ip.set_next_header(Protocol::Ipv6Opts);
+        ip.set_payload_len((options.len() + 8 + len) as u16);
+        let (extension, rest) = ip.payload_mut_slice().split_at_mut(options.len());
+        extension.copy_from_slice(options);
+        let (header, payload) = rest.split_at_mut(8);
+        let repr = v6::fragment::Repr {
+            next_header: Protocol::Unknown(0xEF),
+            frag_offset: (offset / 8) as u16,
+            more_frags: offset + len < PAYLOAD_BYTES.len(),
+            ident: 1,
+        };
+        repr.emit(&mut v6::fragment::Header::new_unchecked(header));
+        payload.copy_from_slice(&PAYLOAD_BYTES[offset..][..len]);
+    }
+
+    nic.receive_all();
+
+    // The reassembled packet is rebuilt from the headers of the first fragment.
+    let mut received = 0;
+    for _ in 0..2 {
+        let recv = nic.rx(1, eth.recv(ip.recv_with(|frame: InPacket<_>| {
+            match &frame.packet {
+                IpPacket::V6(packet) => {
+                    assert_eq!(packet.upper_protocol(), Protocol::Unknown(0xEF));
+                    assert_eq!(packet.upper_offset(), 56);
+                    assert_eq!(packet.next_header(), Protocol::Ipv6Opts);
+                    assert_eq!(&packet.as_bytes()[41..56], &FIRST_OPTIONS[1..]);
+                    assert_eq!(packet.as_bytes()[40], 0xEF);
+                },
+                _ => panic!("Expected an IPv6 packet"),
+            }
+            simple_recv(frame);
+            received += 1;
+        })));
+        assert_eq!(recv, Ok(1));
+    }
+    assert_eq!(received, 1);
+    assert_eq!(ip.reassembly().in_progress(Instant::from_millis(0)), 0);
+}
+
+#[test]
+fn extension_headers_ipv6() {
+    const MAC_ADDR_SRC: ethernet::Address = ethernet::Address([0, 1, 2, 3, 4, 5]);
+    const IP_ADDR_SRC: v6::Address = v6::Address::from_link_local_id(v6::InterfaceId::from_generated_ether(MAC_ADDR_SRC));
+    const MAC_ADDR_DST: ethernet::Address = ethernet::Address([6, 5, 4, 3, 2, 1]);
+    const IP_ADDR_DST: v6::Address = v6::Address::from_link_local_id(v6::InterfaceId::from_generated_ether(MAC_ADDR_DST));
+
+    let mut nic = External::new_send(Slice::Many(vec![vec![0; 1024]; 2]));
+
+    let mut eth = eth::Endpoint::new(MAC_ADDR_SRC);
+
+    let mut neighbors = [arp::Neighbor::default(); 1];
+    let neighbors = {
+        let mut eth_cache = arp::NeighborCache::new(&mut neighbors[..]);
+        eth_cache.fill(IP_ADDR_DST.into(), MAC_ADDR_DST, None).unwrap();
+        eth_cache
+    };
+    let mut ip = ip::Endpoint::new(Cidr::new(IP_ADDR_SRC.into(), 64),
+        ip::Routes::new(Slice::empty()),
+        neighbors);
+
+    for _ in 0..2 {
+        let sent = nic.tx(1, eth.send(ip.send(SimpleSend {
+            dst_addr: IP_ADDR_DST.into(),
+        })));
+        assert_eq!(sent, Ok(1));
+    }
+
+    // A hop-by-hop header with an unknown option, to be skipped and then to discard the packet.
+    for (idx, &option) in [0x1e, 0x5e].iter().enumerate() {
+        let buffer = nic.get_mut(idx).unwrap();
+        buffer.resize(14 + 40 + 8 + PAYLOAD_BYTES.len(), 0);
+        let eth = ethernet::frame::new_unchecked_mut(buffer);
+        eth.set_dst_addr(MAC_ADDR_SRC);
+        eth.set_src_addr(MAC_ADDR_DST);
+        let ip = v6::packet::new_unchecked_mut(eth.payload_mut_slice());
+        ip.set_dst_addr(IP_ADDR_SRC);
+        ip.set_src_addr(IP_ADDR_DST);
+        ip.set_next_header(Protocol::HopByHop);
+        ip.set_payload_len(8 + PAYLOAD_BYTES.len() as u16);
+        let (header, payload) = ip.payload_mut_slice().split_at_mut(8);
+        header.copy_from_slice(&[0xEF, 0, option, 2, 0, 0, 1, 0]);
+        payload.copy_from_slice(&PAYLOAD_BYTES[..]);
+    }
+
+    nic.receive_all();
+
+    let mut received = 0;
+    for _ in 0..2 {
+        let recv = nic.rx(1, eth.recv(ip.recv_with(|frame: InPacket<_>| {
+            let repr = frame.packet.repr();
+            assert_eq!(repr.protocol(), Protocol::Unknown(0xEF));
+            assert_eq!(repr.payload_len(), PAYLOAD_BYTES.len());
+            match &frame.packet {
+                IpPacket::V6(packet) => assert_eq!(packet.upper_offset(), 48),
+                _ => panic!("Expected an IPv6 packet"),
+            }
+            simple_recv(frame);
+            received += 1;
+        })));
+        assert_eq!(recv, Ok(1));
+    }
+    assert_eq!(received, 1);
+}
+
 fn simple_recv<P: Payload>(frame: InPacket<P>) {
     assert_eq!(frame.packet.payload().as_slice(), &PAYLOAD_BYTES[..]);
 }
@@ -129,6 +480,9 @@ impl<P: PayloadMut> ip::Send<P> for SimpleSend {
             dst_addr: self.dst_addr,
             payload: PAYLOAD_BYTES.len(),
             protocol: Protocol::Unknown(0xEF),
+            dscp: 0,
+            ecn: Ecn::NotEct,
+            fragment: false,
         };
         let mut prepared = packet.prepare(init)
             .expect("Found no valid routes");
@@ -318,6 +318,25 @@ where
 
         self.0.receive(packet)
     }
+
+    fn receive_problem(&mut self, packet: ip::InPacket<P>, problem: ip::ParamProblem) {
+        if !self.1.next_pass() {
+            return;
+        }
+
+        let mut handle_mem = core::mem::MaybeUninit::uninit();
+        let loss = &mut self.1;
+
+        // Reconstruct packet with change handle.
+        let ip::InPacket { mut control, packet } = packet;
+        let control = control
+            .borrow_mut()
+            .wrap(|inner| LossyHandle::new(
+                &mut handle_mem, loss, inner));
+        let packet = ip::InPacket { control, packet, };
+
+        self.0.receive_problem(packet, problem)
+    }
 }
 
 impl<P, I> ip::Send<P> for Lossy<'_, I>
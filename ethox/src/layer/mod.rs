@@ -135,6 +135,7 @@
 
 pub mod arp;
 pub mod eth;
+pub mod hash;
 pub mod icmp;
 pub mod ip;
 pub mod loss;
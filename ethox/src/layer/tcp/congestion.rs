@@ -0,0 +1,573 @@
+//! Congestion control algorithms.
+//!
+//! The loss recovery itself (fast retransmit, the NewReno recovery point, and window inflation
+//! during fast recovery) is done by the connection. An algorithm only decides how the congestion
+//! window grows with acknowledged data and how it is reduced when a loss is detected. All
+//! algorithms are plain data without any allocation so that they can be stored within each
+//! connection. Choose one per connection with [`RawPacket::open_with`] or
+//! [`Endpoint::listen_with`].
+//!
+//! [`RawPacket::open_with`]: ../struct.RawPacket.html#method.open_with
+//! [`Endpoint::listen_with`]: ../struct.Endpoint.html#method.listen_with
+use crate::time::Instant;
+
+/// An interface for congestion window control.
+///
+/// The window is a number of bytes that may be in flight. The connection will additionally limit
+/// this by the receive window advertised by the remote.
+pub trait CongestionControl {
+    /// Setup the window for a newly established connection.
+    fn on_establish(&mut self, smss: u16, time: Instant);
+
+    /// Data was newly acknowledged outside of fast recovery.
+    fn on_ack(&mut self, ack: Ack);
+
+    /// A loss was detected by duplicate acknowledgments.
+    ///
+    /// The connection enters fast recovery afterwards, inflating the window on top of the new
+    /// congestion window.
+    fn on_loss(&mut self, loss: Loss);
+
+    /// Fast recovery ended since all data outstanding at the loss was acknowledged.
+    ///
+    /// The default implementation does nothing.
+    fn on_recovered(&mut self, _: Loss) { }
+
+    /// The remote echoed a congestion mark of the network, see [RFC3168] section 6.1.2.
+    ///
+    /// This happens at most once per window of data and without entering fast recovery. The
+    /// default implementation reacts as to a loss.
+    ///
+    /// [RFC3168]: https://tools.ietf.org/html/rfc3168#section-6.1.2
+    fn on_ecn(&mut self, loss: Loss) {
+        self.on_loss(loss)
+    }
+
+    /// The retransmission timer expired.
+    fn on_rto(&mut self, loss: Loss);
+
+    /// The connection is restarted after being idle, see [RFC5681] section 4.1.
+    ///
+    /// [RFC5681]: https://tools.ietf.org/html/rfc5681#section-4.1
+    fn on_idle(&mut self, smss: u16, time: Instant);
+
+    /// The current congestion window (cwnd) in bytes.
+    fn congestion_window(&self) -> u32;
+
+    /// The current slow start threshold (ssthresh) in bytes.
+    fn slow_start_threshold(&self) -> u32;
+}
+
+/// Information on newly acknowledged data.
+#[derive(Clone, Copy, Debug)]
+pub struct Ack {
+    /// The number of newly acknowledged bytes.
+    pub new_bytes: u32,
+
+    /// The number of bytes that are still in flight.
+    pub flight_size: u32,
+
+    /// The sender maximum segment size of the connection.
+    pub smss: u16,
+
+    /// The time at which the acknowledgment arrived.
+    pub time: Instant,
+}
+
+/// Information on a detected loss.
+#[derive(Clone, Copy, Debug)]
+pub struct Loss {
+    /// The number of bytes that were in flight.
+    pub flight_size: u32,
+
+    /// The sender maximum segment size of the connection.
+    pub smss: u16,
+
+    /// The time at which the loss was detected.
+    pub time: Instant,
+}
+
+/// One of the supported congestion control algorithms.
+///
+/// Dispatches the `CongestionControl` trait statically so that the connection state remains
+/// `Copy` and does not require any allocation.
+#[derive(Clone, Copy, Debug, Hash)]
+pub enum Congestion {
+    /// Standard Reno from RFC5681.
+    Reno(Reno),
+
+    /// CUBIC from RFC8312.
+    Cubic(Cubic),
+}
+
+/// The Reno congestion window, see [RFC5681].
+///
+/// [RFC5681]: https://tools.ietf.org/html/rfc5681
+#[derive(Clone, Copy, Debug, Hash)]
+pub struct Reno {
+    /// Decider between slow-start and congestion.
+    ///
+    /// Set to MAX initially, then updated on occurrence of congestion.
+    pub ssthresh: u32,
+
+    /// The window dictated by congestion.
+    pub congestion_window: u32,
+}
+
+/// The CUBIC congestion window, see [RFC8312].
+///
+/// Uses the recommended constants `C = 0.4` and `beta = 0.7` with fast convergence and the
+/// TCP-friendly region. All computations are done in integer arithmetic with millisecond time
+/// resolution.
+///
+/// [RFC8312]: https://tools.ietf.org/html/rfc8312
+#[derive(Clone, Copy, Debug, Hash)]
+pub struct Cubic {
+    /// Decider between slow-start and congestion.
+    pub ssthresh: u32,
+
+    /// The window dictated by congestion.
+    pub congestion_window: u32,
+
+    /// The window before the last reduction (`W_max`).
+    pub last_max: u32,
+
+    /// The start of the current congestion avoidance epoch.
+    pub epoch_start: Option<Instant>,
+
+    /// The window at which the cubic function has its plateau (`W_max` or the current window).
+    pub origin: u32,
+
+    /// Milliseconds after the epoch start until the plateau is reached (`K`).
+    pub plateau: u64,
+
+    /// The estimated window of standard TCP in the same conditions (`W_est`).
+    pub estimated: u32,
+}
+
+impl Congestion {
+    /// Reno congestion control.
+    pub fn reno() -> Self {
+        Congestion::Reno(Reno::new())
+    }
+
+    /// CUBIC congestion control.
+    pub fn cubic() -> Self {
+        Congestion::Cubic(Cubic::new())
+    }
+}
+
+impl Reno {
+    /// Create a window in initial slow start.
+    pub fn new() -> Self {
+        Reno {
+            ssthresh: u32::MAX,
+            congestion_window: 0,
+        }
+    }
+}
+
+impl Cubic {
+    /// Cubic scaling constant `C`, as a fraction.
+    const C: (u64, u64) = (4, 10);
+
+    /// The multiplicative window decrease factor `beta`, as a fraction.
+    const BETA: (u32, u32) = (7, 10);
+
+    /// Create a window in initial slow start.
+    pub fn new() -> Self {
+        Cubic {
+            ssthresh: u32::MAX,
+            congestion_window: 0,
+            last_max: 0,
+            epoch_start: None,
+            origin: 0,
+            plateau: 0,
+            estimated: 0,
+        }
+    }
+
+    /// Record the loss and return the reduced window.
+    fn reduce(&mut self, smss: u16) -> u32 {
+        let (beta_num, beta_den) = Self::BETA;
+        let cwnd = u64::from(self.congestion_window);
+
+        // Fast convergence, release bandwidth for new flows.
+        self.last_max = if self.congestion_window < self.last_max {
+            (cwnd * u64::from(beta_den + beta_num) / u64::from(2*beta_den)) as u32
+        } else {
+            self.congestion_window
+        };
+
+        self.epoch_start = None;
+        let reduced = (cwnd * u64::from(beta_num) / u64::from(beta_den)) as u32;
+        self.ssthresh = reduced.max(2*u32::from(smss));
+        self.ssthresh
+    }
+
+    /// Start a new congestion avoidance epoch.
+    fn start_epoch(&mut self, smss: u16, time: Instant) {
+        let (c_num, c_den) = Self::C;
+        self.epoch_start = Some(time);
+        self.estimated = self.congestion_window;
+
+        if self.congestion_window < self.last_max {
+            // K = cbrt((W_max - cwnd)/C) in seconds, with windows in segments.
+            let missing = u128::from(self.last_max - self.congestion_window);
+            let cubed = missing * u128::from(c_den) * 1_000_000_000
+                / u128::from(c_num * u64::from(smss.max(1)));
+            self.plateau = cube_root(cubed.min(u128::from(u64::MAX)) as u64);
+            self.origin = self.last_max;
+        } else {
+            self.plateau = 0;
+            self.origin = self.congestion_window;
+        }
+    }
+
+    /// The cubic window function `W_cubic(t)`.
+    fn window_at(&self, millis: u64, smss: u16) -> u32 {
+        let (c_num, c_den) = Self::C;
+        let offset = i128::from(millis) - i128::from(self.plateau);
+        let delta = i128::from(c_num) * i128::from(smss) * offset * offset * offset
+            / (i128::from(c_den) * 1_000_000_000);
+        let window = i128::from(self.origin) + delta;
+        window.max(0).min(i128::from(u32::MAX)) as u32
+    }
+
+    /// Window growth in congestion avoidance.
+    fn avoid_congestion(&mut self, ack: Ack) {
+        let epoch_start = match self.epoch_start {
+            Some(start) => start,
+            None => {
+                self.start_epoch(ack.smss, ack.time);
+                ack.time
+            },
+        };
+
+        let elapsed = (ack.time - epoch_start).as_millis() as u64;
+        let cwnd = u64::from(self.congestion_window.max(1));
+        // Limit the growth to 1.5 times the window per round trip.
+        let target = u64::from(self.window_at(elapsed, ack.smss))
+            .min(cwnd + cwnd/2);
+
+        let cubic = if target > cwnd {
+            let increase = (target - cwnd) * u64::from(ack.new_bytes) / cwnd;
+            cwnd + increase
+        } else {
+            cwnd
+        };
+
+        // TCP friendly region, grow at least as fast as Reno with the same beta would.
+        // alpha = 3*(1 - beta)/(1 + beta) = 9/17 for beta = 0.7.
+        let smss = u64::from(ack.smss);
+        let estimated = u64::from(self.estimated.max(1));
+        let increase = 9 * smss * u64::from(ack.new_bytes) / (17 * estimated);
+        self.estimated = (estimated + increase).min(u64::from(u32::MAX)) as u32;
+
+        let window = cubic.max(u64::from(self.estimated));
+        self.congestion_window = window.min(u64::from(u32::MAX)) as u32;
+    }
+}
+
+impl CongestionControl for Reno {
+    fn on_establish(&mut self, smss: u16, _: Instant) {
+        self.congestion_window = initial_window(smss);
+    }
+
+    fn on_ack(&mut self, ack: Ack) {
+        let smss = u32::from(ack.smss);
+        let increase = if self.congestion_window < self.ssthresh {
+            // Slow start. Avoid cwnd flooding from ack splitting by limiting to one SMSS.
+            ack.new_bytes.min(smss)
+        } else {
+            // Congestion avoidance, approximately one SMSS per RTT.
+            (smss*smss / self.congestion_window.max(1)).max(1)
+        };
+        self.congestion_window = self.congestion_window.saturating_add(increase);
+    }
+
+    fn on_loss(&mut self, loss: Loss) {
+        self.ssthresh = reduced_threshold(loss.flight_size, loss.smss);
+        self.congestion_window = self.ssthresh;
+    }
+
+    fn on_recovered(&mut self, loss: Loss) {
+        // RFC6582 option 1, avoid a burst when only few segments are in flight.
+        let smss = u32::from(loss.smss);
+        self.congestion_window = self.ssthresh.min(loss.flight_size.max(smss) + smss);
+    }
+
+    fn on_rto(&mut self, loss: Loss) {
+        self.ssthresh = reduced_threshold(loss.flight_size, loss.smss);
+        self.congestion_window = loss.smss.into();
+    }
+
+    fn on_idle(&mut self, smss: u16, _: Instant) {
+        self.congestion_window = self.congestion_window.min(initial_window(smss));
+    }
+
+    fn congestion_window(&self) -> u32 {
+        self.congestion_window
+    }
+
+    fn slow_start_threshold(&self) -> u32 {
+        self.ssthresh
+    }
+}
+
+impl CongestionControl for Cubic {
+    fn on_establish(&mut self, smss: u16, _: Instant) {
+        self.congestion_window = initial_window(smss);
+        self.epoch_start = None;
+    }
+
+    fn on_ack(&mut self, ack: Ack) {
+        if self.congestion_window < self.ssthresh {
+            let increase = ack.new_bytes.min(ack.smss.into());
+            self.congestion_window = self.congestion_window.saturating_add(increase);
+        } else {
+            self.avoid_congestion(ack);
+        }
+    }
+
+    fn on_loss(&mut self, loss: Loss) {
+        self.congestion_window = self.reduce(loss.smss);
+    }
+
+    fn on_rto(&mut self, loss: Loss) {
+        let _ = self.reduce(loss.smss);
+        self.congestion_window = loss.smss.into();
+    }
+
+    fn on_idle(&mut self, smss: u16, _: Instant) {
+        self.congestion_window = self.congestion_window.min(initial_window(smss));
+        self.epoch_start = None;
+    }
+
+    fn congestion_window(&self) -> u32 {
+        self.congestion_window
+    }
+
+    fn slow_start_threshold(&self) -> u32 {
+        self.ssthresh
+    }
+}
+
+impl CongestionControl for Congestion {
+    fn on_establish(&mut self, smss: u16, time: Instant) {
+        match self {
+            Congestion::Reno(reno) => reno.on_establish(smss, time),
+            Congestion::Cubic(cubic) => cubic.on_establish(smss, time),
+        }
+    }
+
+    fn on_ack(&mut self, ack: Ack) {
+        match self {
+            Congestion::Reno(reno) => reno.on_ack(ack),
+            Congestion::Cubic(cubic) => cubic.on_ack(ack),
+        }
+    }
+
+    fn on_loss(&mut self, loss: Loss) {
+        match self {
+            Congestion::Reno(reno) => reno.on_loss(loss),
+            Congestion::Cubic(cubic) => cubic.on_loss(loss),
+        }
+    }
+
+    fn on_recovered(&mut self, loss: Loss) {
+        match self {
+            Congestion::Reno(reno) => reno.on_recovered(loss),
+            Congestion::Cubic(cubic) => cubic.on_recovered(loss),
+        }
+    }
+
+    fn on_ecn(&mut self, loss: Loss) {
+        match self {
+            Congestion::Reno(reno) => reno.on_ecn(loss),
+            Congestion::Cubic(cubic) => cubic.on_ecn(loss),
+        }
+    }
+
+    fn on_rto(&mut self, loss: Loss) {
+        match self {
+            Congestion::Reno(reno) => reno.on_rto(loss),
+            Congestion::Cubic(cubic) => cubic.on_rto(loss),
+        }
+    }
+
+    fn on_idle(&mut self, smss: u16, time: Instant) {
+        match self {
+            Congestion::Reno(reno) => reno.on_idle(smss, time),
+            Congestion::Cubic(cubic) => cubic.on_idle(smss, time),
+        }
+    }
+
+    fn congestion_window(&self) -> u32 {
+        match self {
+            Congestion::Reno(reno) => reno.congestion_window(),
+            Congestion::Cubic(cubic) => cubic.congestion_window(),
+        }
+    }
+
+    fn slow_start_threshold(&self) -> u32 {
+        match self {
+            Congestion::Reno(reno) => reno.slow_start_threshold(),
+            Congestion::Cubic(cubic) => cubic.slow_start_threshold(),
+        }
+    }
+}
+
+impl Default for Congestion {
+    fn default() -> Self {
+        Congestion::reno()
+    }
+}
+
+impl Default for Reno {
+    fn default() -> Self {
+        Reno::new()
+    }
+}
+
+impl Default for Cubic {
+    fn default() -> Self {
+        Cubic::new()
+    }
+}
+
+/// The initial window (IW) as defined in RFC5681.
+pub fn initial_window(smss: u16) -> u32 {
+    let smss = u32::from(smss);
+    if smss > 2190 {
+        2*smss
+    } else if smss > 1095 {
+        3*smss
+    } else {
+        4*smss
+    }
+}
+
+/// The slow start threshold after a loss, as defined in RFC5681.
+fn reduced_threshold(flight_size: u32, smss: u16) -> u32 {
+    (flight_size / 2).max(2*u32::from(smss))
+}
+
+/// Integer cube root, rounded down.
+fn cube_root(value: u64) -> u64 {
+    let mut root = 0u64;
+    // Determine the result bit by bit, from the highest possible one.
+    for bit in (0..22).rev() {
+        let candidate = root | 1 << bit;
+        if matches!(candidate.checked_pow(3), Some(cube) if cube <= value) {
+            root = candidate;
+        }
+    }
+    root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MSS: u16 = 1000;
+
+    fn ack(time: Instant) -> Ack {
+        Ack {
+            new_bytes: MSS.into(),
+            flight_size: 0,
+            smss: MSS,
+            time,
+        }
+    }
+
+    #[test]
+    fn cube_roots() {
+        assert_eq!(cube_root(0), 0);
+        assert_eq!(cube_root(7), 1);
+        assert_eq!(cube_root(8), 2);
+        assert_eq!(cube_root(1_000_000_000), 1000);
+        assert_eq!(cube_root(999_999_999), 999);
+        assert_eq!(cube_root(u64::MAX), 2_642_245);
+    }
+
+    #[test]
+    fn cubic_loss() {
+        let mut cubic = Cubic::new();
+        cubic.on_establish(MSS, Instant::from_millis(0));
+        cubic.congestion_window = 100*u32::from(MSS);
+
+        cubic.on_loss(Loss {
+            flight_size: 100*u32::from(MSS),
+            smss: MSS,
+            time: Instant::from_millis(0),
+        });
+        assert_eq!(cubic.congestion_window(), 70*u32::from(MSS));
+        assert_eq!(cubic.slow_start_threshold(), 70*u32::from(MSS));
+        assert_eq!(cubic.last_max, 100*u32::from(MSS));
+
+        // Another loss before reaching the previous maximum converges faster.
+        cubic.on_loss(Loss {
+            flight_size: 70*u32::from(MSS),
+            smss: MSS,
+            time: Instant::from_millis(0),
+        });
+        assert_eq!(cubic.congestion_window(), 49*u32::from(MSS));
+        assert_eq!(cubic.last_max, 59_500);
+    }
+
+    #[test]
+    fn cubic_plateau() {
+        let mut cubic = Cubic::new();
+        cubic.on_establish(MSS, Instant::from_millis(0));
+        cubic.congestion_window = 100*u32::from(MSS);
+        cubic.on_loss(Loss {
+            flight_size: 100*u32::from(MSS),
+            smss: MSS,
+            time: Instant::from_millis(0),
+        });
+
+        // K = cbrt(30/0.4) seconds, about 4.217 seconds.
+        cubic.on_ack(ack(Instant::from_millis(0)));
+        assert_eq!(cubic.plateau, 4217);
+
+        // Approach the plateau, the window is concave and remains below the previous maximum.
+        let mut time = 0;
+        while time < 4200 {
+            time += 100;
+            for _ in 0..(cubic.congestion_window / u32::from(MSS)) / 10 {
+                cubic.on_ack(ack(Instant::from_millis(time)));
+            }
+        }
+        let cwnd = cubic.congestion_window();
+        assert!(cwnd > 90*u32::from(MSS), "Window {} grew too slowly", cwnd);
+        assert!(cwnd <= 100*u32::from(MSS), "Window {} overshot plateau", cwnd);
+
+        // Convex growth beyond the plateau.
+        while time < 8000 {
+            time += 100;
+            for _ in 0..(cubic.congestion_window / u32::from(MSS)) / 10 {
+                cubic.on_ack(ack(Instant::from_millis(time)));
+            }
+        }
+        assert!(cubic.congestion_window() > 110*u32::from(MSS));
+    }
+
+    #[test]
+    fn reno_growth() {
+        let mut reno = Reno::new();
+        reno.on_establish(MSS, Instant::from_millis(0));
+        assert_eq!(reno.congestion_window(), 4*u32::from(MSS));
+
+        reno.on_ack(ack(Instant::from_millis(0)));
+        assert_eq!(reno.congestion_window(), 5*u32::from(MSS));
+
+        reno.on_rto(Loss {
+            flight_size: 5*u32::from(MSS),
+            smss: MSS,
+            time: Instant::from_millis(0),
+        });
+        assert_eq!(reno.congestion_window(), u32::from(MSS));
+        assert_eq!(reno.slow_start_threshold(), 5*u32::from(MSS)/2);
+    }
+}
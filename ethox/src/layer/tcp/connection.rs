@@ -10,12 +10,15 @@ use core::ops::Range;
 use crate::time::{Duration, Expiration, Instant};
 use crate::wire::{ip::Address, tcp};
 
+use super::congestion::{Ack, Congestion, CongestionControl, Loss};
 use super::endpoint::{
     Entry,
     EntryKey,
     FourTuple,
     Slot,
     SlotKey};
+use super::siphash::SynCookie;
+use super::time_wait::{Arrival, TimeWait};
 
 /// The state of a connection.
 ///
@@ -34,8 +37,7 @@ pub struct Connection {
 
     /// The flow control mechanism.
     ///
-    /// Currently hard coded as TCP Reno but practically could also be an enum when we find a
-    /// suitable common interface.
+    /// NewReno loss recovery with an exchangeable congestion control algorithm.
     pub flow_control: Flow,
 
     /// The indicated receive window (rcwd) of the other side.
@@ -48,6 +50,11 @@ pub struct Connection {
     /// does not include the TCP/IP headers and options.
     pub sender_maximum_segment_size: u16,
 
+    /// The maximum segment size announced by the remote during connection startup.
+    ///
+    /// This bounds the SMSS which is further clamped to a known path MTU, see `set_path_mtu`.
+    pub remote_maximum_segment_size: u16,
+
     /// The RMSS is the size of the largest segment the receiver is willing to accept.
     ///
     /// This is the value specified in the MSS option sent by the receiver during connection
@@ -64,20 +71,32 @@ pub struct Connection {
     /// The time when the next ack must be sent.
     ///
     /// We MUST NOT wait more than 500ms before sending the ACK after receiving some new segment
-    /// bytes. However, we CAN wait shorter, see `ack_timeout`.
+    /// bytes. However, we CAN wait shorter, see `ack_policy`.
     pub ack_timer: Expiration,
 
-    /// Timeout before sending the next ACK after a new segment.
+    /// The policy for delaying acknowledgments.
+    pub ack_policy: AckPolicy,
+
+    /// The number of segments that are still acknowledged immediately.
     ///
-    /// For compliance with RFC1122 this MUST NOT be greater than 500ms but it could be smaller.
-    pub ack_timeout: Duration,
+    /// Refilled from the `ack_policy` when the connection is established and when data arrives
+    /// out of order. This avoids delaying the acks that clock a sender in slow start or recovery.
+    pub quick_acks: u8,
 
     /// When to start retransmission and/or detect a loss.
     pub retransmission_timer: Instant,
 
-    /// The duration of the retransmission timer.
+    /// The duration of the retransmission timer (RTO).
+    ///
+    /// Derived from the round-trip time estimation and doubled on each expiration of the timer.
     pub retransmission_timeout: Duration,
 
+    /// The round-trip time estimation.
+    pub round_trip: RoundTrip,
+
+    /// The state of the timestamp option.
+    pub timestamps: Timestamps,
+
     /// Timeout of no packets in either direction after which restart is used.
     ///
     /// This will only occur if no data is to be transmitted in either direction as otherwise we
@@ -87,12 +106,77 @@ pub struct Connection {
 
     /// If we are permitted to use SACKs.
     ///
-    /// This is true if the SYN packet allowed it in its options since we support it [WIP].
+    /// Set this before opening a connection to offer the option. It is cleared if the remote did
+    /// not permit it in its SYN.
     pub selective_acknowledgements: bool,
 
+    /// The data selectively acknowledged by the remote.
+    pub scoreboard: Scoreboard,
+
+    /// Disable Nagle's algorithm.
+    ///
+    /// By default, a segment smaller than the maximum segment size is held back while there is
+    /// unacknowledged data in flight (RFC896). This coalesces many small writes into fewer
+    /// segments at the cost of latency.
+    pub nodelay: bool,
+
+    /// The user shut down the sending side of the connection.
+    ///
+    /// A FIN is sent after all data that the send buffer has made available, even if the buffer
+    /// itself does not signal the end of the stream. Data can still be received until the remote
+    /// closes its side as well.
+    pub shutdown: bool,
+
+    /// When to probe the zero window of the remote next.
+    ///
+    /// Armed while the remote advertises a zero window and there is queued data but none in
+    /// flight, see RFC1122 section 4.2.2.17. Without probes a lost window update would stall the
+    /// connection forever.
+    pub persist_timer: Expiration,
+
+    /// The number of window probes sent since the window closed.
+    ///
+    /// The probe interval starts at the retransmission timeout and doubles with each probe up to
+    /// the maximum retransmission timeout.
+    pub persist_backoff: u8,
+
+    /// Probe an idle connection to detect a vanished remote.
+    ///
+    /// Disabled by default as required by RFC1122 section 4.2.3.6.
+    pub keep_alive: Option<KeepAlive>,
+
+    /// The number of keep-alive probes sent since the last segment from the remote arrived.
+    pub keep_alive_probes: u8,
+
+    /// Answer connection attempts with SYN cookies while listening.
+    ///
+    /// The listening connection then only leaves its state for a validated final ACK of the
+    /// handshake and an unanswered SYN does not reserve it.
+    pub syn_cookies: bool,
+
+    /// Accept data in the SYN of connection attempts with a valid fast open cookie (RFC7413).
+    ///
+    /// While listening, cookies are also provided to remotes that request one.
+    pub fast_open: bool,
+
+    /// The fast open cookie sent with our SYN.
+    ///
+    /// An active open carries its cached cookie, or an empty one to request a cookie. A passive
+    /// open provides the remote with a fresh cookie.
+    pub fast_open_cookie: Option<tcp::FastOpenCookie>,
+
+    /// Explicit congestion notification (RFC3168) of this connection.
+    pub ecn: ExplicitCongestion,
+
     /// Counter of duplicated acks.
     pub duplicate_ack: u8,
 
+    /// Retransmit the first unacknowledged segment with the next packet.
+    ///
+    /// Set when entering fast retransmit and on partial acknowledgements during recovery. These
+    /// retransmissions do not wait for the retransmission timer.
+    pub retransmit_pending: bool,
+
     /// The sending state.
     ///
     /// In RFC793 this is referred to as `SND`.
@@ -138,6 +222,12 @@ pub struct Send {
     /// Guaranteed to be at most 14 so that shifting the window in a `u32`/`i32` is always safe.
     pub window_scale: u8,
 
+    /// The largest scaled window the receiver has indicated so far.
+    ///
+    /// Acknowledgments below `unacked` by more than this are not acceptable. In RFC5961 this is
+    /// referred to as `MAX.SND.WND`.
+    pub max_window: u32,
+
     /// The initial sequence number.
     ///
     /// This is read-only and only kept for potentially reading it for debugging later. It
@@ -176,12 +266,166 @@ pub struct Receive {
     /// Guaranteed to be at most 14 so that shifting the window in a `u32`/`i32` is always safe.
     pub window_scale: u8,
 
+    /// The largest window offered by the receive buffer.
+    ///
+    /// An estimate of the buffer size (`RCV.BUFF`) for silly window syndrome avoidance.
+    pub max_window: u32,
+
     /// The initial receive sequence number.
     ///
     /// This is read-only and only kept for potentially reading it for debugging later. It
     /// essentially provides a way of tracking the sent data. In RFC793 this is referred to as
     /// `ISS`.
     pub initial_seq: tcp::SeqNumber,
+
+    /// Blocks of out-of-order data reported by the receive buffer.
+    ///
+    /// These are sent as SACK blocks with every acknowledgment, if negotiated.
+    pub selective: [Option<(tcp::SeqNumber, tcp::SeqNumber)>; 3],
+}
+
+/// The SACK scoreboard of the sender, see RFC6675.
+///
+/// Tracks the ranges above `SND.UNA` which the remote reported as received. Retransmissions then
+/// only need to fill the holes between them instead of resending everything after a loss.
+#[derive(Clone, Copy, Debug, Default, Hash)]
+pub struct Scoreboard {
+    /// The selectively acknowledged ranges, ordered and disjoint.
+    ///
+    /// When there are more ranges than fit, the highest ones are forgotten.
+    pub blocks: [Option<(tcp::SeqNumber, tcp::SeqNumber)>; 4],
+
+    /// The end of the highest retransmitted hole (`HighRxt`).
+    pub high_retransmit: tcp::SeqNumber,
+}
+
+/// The policy for delaying acknowledgments, see RFC1122 and RFC5681.
+///
+/// Acknowledging every second full-sized segment instead of every segment halves the number of
+/// pure acks sent by a receiving connection. Out-of-order segments, segments that fill a hole,
+/// and a FIN are always acknowledged immediately.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct AckPolicy {
+    /// Whether acknowledgments of in-order data may be delayed at all.
+    ///
+    /// When disabled every segment is acknowledged immediately (quick-ack mode).
+    pub delayed: bool,
+
+    /// Timeout before sending the next ACK after a new segment.
+    ///
+    /// For compliance with RFC1122 this MUST NOT be greater than 500ms but it could be smaller.
+    pub timeout: Duration,
+
+    /// The number of segments to acknowledge immediately after establishing the connection and
+    /// after out-of-order data.
+    pub quick_acks: u8,
+}
+
+/// The configuration of keep-alive probes, see RFC1122 section 4.2.3.6.
+///
+/// A probe is sent after the connection was idle for some time and repeated with a fixed interval.
+/// When none of the probes is answered, the connection is closed and the user is notified with a
+/// reset.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct KeepAlive {
+    /// The time without any segment from the remote before the first probe.
+    ///
+    /// RFC1122 requires this to default to no less than two hours.
+    pub idle: Duration,
+
+    /// The time between two unanswered probes.
+    pub interval: Duration,
+
+    /// The number of unanswered probes after which the connection is closed.
+    pub count: u8,
+}
+
+/// Round-trip time estimation according to RFC6298.
+///
+/// Only one segment is timed at a time and, following Karn's algorithm, no measurement is taken
+/// from segments that were retransmitted.
+#[derive(Clone, Copy, Debug, Hash)]
+pub struct RoundTrip {
+    /// The smoothed round-trip time (SRTT).
+    ///
+    /// This is `None` until the first measurement was taken.
+    pub smoothed: Option<Duration>,
+
+    /// The round-trip time variation (RTTVAR).
+    pub variance: Duration,
+
+    /// The segment currently being timed.
+    ///
+    /// Contains the sequence number whose acknowledgment finishes the measurement and the time at
+    /// which the segment was sent.
+    pub timed: Option<(tcp::SeqNumber, Instant)>,
+
+    /// The number of consecutive expirations of the retransmission timer.
+    pub backoff: u8,
+
+    /// Lower bound for the retransmission timeout.
+    ///
+    /// RFC6298 recommends 1 second.
+    pub min_timeout: Duration,
+
+    /// Upper bound for the retransmission timeout, also for exponential backoff.
+    ///
+    /// RFC6298 requires this to be at least 60 seconds.
+    pub max_timeout: Duration,
+}
+
+/// State of the timestamp option, see RFC7323.
+///
+/// Timestamps are used for round-trip time measurements on every acknowledgment and to protect
+/// against wrapped sequence numbers (PAWS) on fast connections.
+#[derive(Clone, Copy, Debug, Hash)]
+pub struct Timestamps {
+    /// If timestamps are sent on this connection.
+    ///
+    /// Set this before opening a connection to offer the option. It is cleared if the remote did
+    /// not include the option in its SYN.
+    pub enabled: bool,
+
+    /// The most recent valid timestamp value of the remote (TS.Recent).
+    pub recent: u32,
+
+    /// The time at which `recent` was last updated.
+    ///
+    /// A value older than 24 days is no longer used for PAWS.
+    pub recent_time: Instant,
+
+    /// Offset of our timestamp clock from the millisecond time, chosen per connection.
+    pub offset: u32,
+}
+
+/// The state of explicit congestion notification, see [RFC3168].
+///
+/// Routers may mark packets of an ECN-capable transport as congestion experienced (CE) instead of
+/// dropping them. The receiver echoes the mark with the ECE flag on its ACKs until the sender
+/// confirms its reaction with the CWR flag. The sender reduces its congestion window as for a
+/// loss, at most once per window of data.
+///
+/// [RFC3168]: https://tools.ietf.org/html/rfc3168
+#[derive(Clone, Copy, Debug, Default, Hash)]
+pub struct ExplicitCongestion {
+    /// If ECN is used on this connection.
+    ///
+    /// Set this before opening a connection to offer ECN in the handshake. It is cleared if the
+    /// remote does not agree. Only then are data segments sent as ECN-capable.
+    pub enabled: bool,
+
+    /// Set ECE on all our ACKs.
+    ///
+    /// A segment marked as congestion experienced arrived and the remote has not yet sent CWR.
+    pub echo: bool,
+
+    /// Set CWR on the next segment with new data.
+    ///
+    /// The congestion window was reduced in reaction to an ECE.
+    pub reduced: bool,
+
+    /// No further reduction until all data up to here was acknowledged.
+    pub recover: Option<tcp::SeqNumber>,
 }
 
 /// State enum of the state machine.
@@ -225,22 +469,35 @@ pub enum State {
     LastAck,
 }
 
-/// Models TCP Reno flow control and congestion avoidance.
+/// Models TCP NewReno flow control on top of a congestion control algorithm.
+///
+/// See [RFC5681] for fast retransmit, and [RFC6582] for the NewReno modification to fast recovery.
+/// Slow start and congestion avoidance are delegated to the chosen [`Congestion`] algorithm.
+///
+/// [RFC5681]: https://tools.ietf.org/html/rfc5681
+/// [RFC6582]: https://tools.ietf.org/html/rfc6582
+/// [`Congestion`]: congestion/enum.Congestion.html
 #[derive(Clone, Copy, Debug, Hash)]
 pub struct Flow {
-    /// Decider between slow-start and congestion.
-    ///
-    /// Set to MAX initially, then updated on occurrence of congestion.
-    pub ssthresh: u32,
+    /// The congestion control algorithm.
+    pub congestion: Congestion,
 
-    /// The window dictated by congestion.
-    pub congestion_window: u32,
+    /// The window during fast recovery.
+    ///
+    /// Starts from the reduced congestion window and is artificially inflated for each segment
+    /// that left the network, as indicated by duplicate acks.
+    pub recovery_window: u32,
 
     /// Sender side end flag to fast recover.
     ///
     /// When in fast recover, declares the sent sequent number that must be acknowledged to end
     /// fast recover. Initially set to the initial sequence number (ISS).
     pub recover: tcp::SeqNumber,
+
+    /// If we are currently in fast recovery.
+    ///
+    /// Entered on the third duplicate ack and left once all data up to `recover` was acked.
+    pub fast_recovery: bool,
 }
 
 /// Output signals of the model.
@@ -261,6 +518,17 @@ pub struct Signals {
     /// Whether the Operator could send data.
     pub may_send: bool,
 
+    /// The remote closed its side of the connection with this segment.
+    ///
+    /// Only set when the FIN was accepted without data, otherwise the receive buffer must first
+    /// accept the segment with [`Connection::set_recv_ack`].
+    ///
+    /// [`Connection::set_recv_ack`]: struct.Connection.html#method.set_recv_ack
+    pub half_closed: bool,
+
+    /// A passively opened connection was reset and should return to listening.
+    pub relisten: bool,
+
     /// Need to send some tcp answer.
     ///
     /// Since TCP must assume every packet to be potentially lost it is likely technically fine
@@ -321,6 +589,9 @@ pub struct InPacket {
 
     /// The arrival time of the packet at the nic.
     pub time: Instant,
+
+    /// If the ip header was marked as congestion experienced.
+    pub congestion_experienced: bool,
 }
 
 /// An outgoing segment.
@@ -331,6 +602,11 @@ pub struct Segment {
 
     /// Range of the data that should be included, as indexed within the (re-)transmit buffer.
     pub range: Range<usize>,
+
+    /// If the ip packet should be sent as an ECN-capable transport.
+    ///
+    /// Only set for new data, never for retransmissions, pure acknowledgments or probes.
+    pub ecn_capable: bool,
 }
 
 /// Output signals of the model.
@@ -341,6 +617,9 @@ pub struct Segment {
 pub struct OutSignals {
     pub delete: bool,
 
+    /// The connection was closed without an orderly shutdown.
+    pub reset: bool,
+
     /// A packet was selected to be generated.
     ///
     /// Some packets (ACKs or during connection closing) are only generated after the data of an
@@ -365,13 +644,24 @@ pub trait Endpoint {
 
     fn find_tuple(&mut self, tuple: FourTuple) -> Option<Entry>;
 
-    fn source_port(&mut self, addr: Address) -> Option<u16>;
+    fn source_port(&mut self, tuple: FourTuple, time: Instant) -> Option<u16>;
 
     fn listen(&mut self, ip: Address, port: u16) -> Option<SlotKey>;
 
-    fn open(&mut self, tuple: FourTuple) -> Option<SlotKey>;
+    /// Return a connection to the listening state on its local address and port.
+    ///
+    /// Returns `false` if another connection is already listening there.
+    fn relisten(&mut self, index: SlotKey) -> bool;
+
+    fn open(&mut self, tuple: FourTuple, time: Instant) -> Option<SlotKey>;
 
     fn initial_seq_num(&mut self, id: FourTuple, time: Instant) -> tcp::SeqNumber;
+
+    fn fast_open_cookie(&self, remote: Address) -> Option<tcp::FastOpenCookie>;
+
+    fn cache_fast_open_cookie(&mut self, remote: Address, cookie: tcp::FastOpenCookie);
+
+    fn arrives_time_wait(&mut self, tuple: FourTuple, segment: &tcp::Repr, time: Instant) -> Arrival;
 }
 
 /// The interface to a single active connection on an endpoint.
@@ -402,6 +692,7 @@ struct InnerRepr {
     max_seg_size: Option<u16>,
     sack_permitted: bool,
     sack_ranges:  [Option<(u32, u32)>; 3],
+    timestamp:    Option<tcp::Timestamp>,
     payload_len:  u16,
 }
 
@@ -411,22 +702,46 @@ impl Connection {
         Connection {
             current: State::Closed,
             previous: State::Closed,
-            flow_control: Flow {
-                ssthresh: 0,
-                congestion_window: 0,
-                recover: tcp::SeqNumber::default(),
-            },
+            flow_control: Flow::new(Congestion::default()),
             receive_window: 0,
             sender_maximum_segment_size: 0,
+            remote_maximum_segment_size: 0,
             receiver_maximum_segment_size: 0,
             last_ack_receive_offset: tcp::SeqNumber::default(),
             ack_timer: Expiration::Never,
-            ack_timeout: Duration::from_millis(0),
+            ack_policy: AckPolicy::quick(),
+            quick_acks: 0,
             retransmission_timer: Instant::from_millis(0),
             retransmission_timeout: Duration::from_millis(0),
+            round_trip: RoundTrip {
+                smoothed: None,
+                variance: Duration::from_millis(0),
+                timed: None,
+                backoff: 0,
+                min_timeout: Duration::from_millis(0),
+                max_timeout: Duration::from_millis(0),
+            },
+            timestamps: Timestamps {
+                enabled: false,
+                recent: 0,
+                recent_time: Instant::from_millis(0),
+                offset: 0,
+            },
             restart_timeout: Duration::from_millis(0),
             selective_acknowledgements: false,
+            scoreboard: Scoreboard::default(),
+            nodelay: false,
+            shutdown: false,
+            persist_timer: Expiration::Never,
+            persist_backoff: 0,
+            keep_alive: None,
+            keep_alive_probes: 0,
+            syn_cookies: false,
+            fast_open: false,
+            fast_open_cookie: None,
+            ecn: ExplicitCongestion::default(),
             duplicate_ack: 0,
+            retransmit_pending: false,
             send: Send {
                 unacked: tcp::SeqNumber::default(),
                 next: tcp::SeqNumber::default(),
@@ -434,6 +749,7 @@ impl Connection {
                 unsent: 0,
                 window: 0,
                 window_scale: 0,
+                max_window: 0,
                 initial_seq: tcp::SeqNumber::default(),
             },
             recv: Receive {
@@ -442,20 +758,33 @@ impl Connection {
                 last_time: Instant::from_millis(0),
                 window: 0,
                 window_scale: 0,
+                max_window: 0,
                 initial_seq: tcp::SeqNumber::default(),
+                selective: [None; 3],
             },
         }
     }
 
     /// Handle an arriving packet.
     pub fn arrives(&mut self, incoming: &InPacket, entry: EntryKey) -> Signals {
-        match self.current {
+        let mut signals = match self.current {
             State::Closed => self.arrives_closed(incoming),
             State::Listen => self.arrives_listen(incoming, entry),
             State::SynSent => self.arrives_syn_sent(incoming, entry),
-            State::Established | State::FinWait => self.arrives_established(incoming, entry),
-            _ => unimplemented!(),
+            State::SynReceived => self.arrives_syn_received(incoming, entry),
+            State::Established
+            | State::FinWait
+            | State::Closing
+            | State::TimeWait
+            | State::CloseWait
+            | State::LastAck => self.arrives_established(incoming, entry),
+        };
+
+        if let Some(answer) = signals.answer.as_mut() {
+            self.stamp(answer, incoming.time);
         }
+
+        signals
     }
 
     /// Realize the effect of opening SYN packet.
@@ -471,6 +800,10 @@ impl Connection {
         self.send.initial_seq = entry.initial_seq_num(time);
         self.send.unacked = self.send.initial_seq;
         self.send.next = self.send.initial_seq + 1;
+        self.flow_control.recover = self.send.initial_seq;
+        self.round_trip.timed = None;
+        self.round_trip.backoff = 0;
+        self.timestamps.offset = entry.timestamp_offset();
         // Schedule 'immediate' transmission.
         self.retransmission_timer = time;
 
@@ -500,6 +833,7 @@ impl Connection {
                 max_seg_size: None,
                 sack_permitted: false,
                 sack_ranges: [None; 3],
+                timestamp: None,
                 payload_len: 0,
             }.send_back(segment));
         } else {
@@ -512,6 +846,7 @@ impl Connection {
                 max_seg_size: None,
                 sack_permitted: false,
                 sack_ranges: [None; 3],
+                timestamp: None,
                 payload_len: 0,
             }.send_back(segment));
         }
@@ -523,22 +858,28 @@ impl Connection {
     fn arrives_listen(&mut self, incoming: &InPacket, mut entry: EntryKey)
         -> Signals
     {
-        // TODO: SYN cookies. Ideally, we could extend the original mechanism to support timestamp,
-        // sack, and window scale as well. Note that ts and sack require only a single flag bit in
-        // the cookie; the state for timestamp can be restored from the ts-option in the Ack answer
-        // to our Syn+Ack and we require only a flag to check if we had received a ts-option in the
-        // Syn initially; while sack also only requires a flag to indicate its negotiation state.
-        //
-        // The harder part seems to be that syn cookies require a new operation within Signals.
-
-        let InPacket { segment, from, time, } = incoming;
+        let InPacket { segment, from, time, .. } = incoming;
         let mut signals = Signals::default();
 
         if segment.flags.rst() {
             return signals;
         }
 
+        let new_four = FourTuple {
+            remote: *from,
+            remote_port: segment.src_port,
+            .. entry.four_tuple()
+        };
+
         if let Some(ack_number) = segment.ack_number { // What are you acking? A previous connection.
+            if self.syn_cookies && !segment.flags.syn() {
+                let remote_isn = segment.seq_number - 1;
+                let cookie = entry.check_syn_cookie(new_four, remote_isn, ack_number - 1, *time);
+                if let Some(cookie) = cookie {
+                    return self.arrives_syn_cookie(incoming, cookie, new_four, entry);
+                }
+            }
+
             signals.answer = Some(InnerRepr {
                 flags: tcp::Flags::RST,
                 seq_number: ack_number,
@@ -548,6 +889,7 @@ impl Connection {
                 max_seg_size: None,
                 sack_permitted: false,
                 sack_ranges: [None; 3],
+                timestamp: None,
                 payload_len: 0,
             }.send_back(segment));
             return signals;
@@ -558,39 +900,209 @@ impl Connection {
             return signals;
         }
 
-        let current_four = entry.four_tuple();
-        let new_four = FourTuple {
-            remote: *from,
-            .. current_four
+        if self.syn_cookies {
+            // Answer from a copy of the state, only the final ACK creates the connection.
+            let cookie = SynCookie::new(
+                segment.max_seg_size.unwrap_or(536),
+                segment.window_scale,
+                segment.sack_permitted);
+            let isn = entry.syn_cookie(new_four, segment.seq_number, cookie, *time);
+            let offset = entry.timestamp_offset_for(new_four);
+
+            let mut syn_received = *self;
+            syn_received.accept_syn(&cookie.syn(segment), *time, isn, offset);
+            // The cookie has no room to remember the negotiation.
+            syn_received.ecn.enabled = false;
+            // Data in the SYN is not accepted without a connection state.
+            let _ = syn_received.fast_open_syn(segment, new_four, &entry);
+            signals.answer = Some(syn_received.send_open(true, new_four));
+            return signals;
+        }
+
+        entry.set_four_tuple(new_four);
+        let isn = entry.initial_seq_num(*time);
+        self.accept_syn(segment, *time, isn, entry.timestamp_offset());
+
+        if self.fast_open_syn(segment, new_four, &entry) {
+            // Deliver the data first, the SYN-ACK is sent as soon as possible afterwards and then
+            // acknowledges everything the receive buffer has accepted. A FIN is not accepted yet.
+            self.retransmission_timer = *time;
+            signals.receive = Some(ReceivedSegment {
+                syn: false,
+                fin: false,
+                data_len: usize::from(segment.payload_len),
+                begin: segment.seq_number + 1,
+                timestamp: *time,
+            });
+            return signals;
+        }
+
+        // Retransmit the SYN-ACK when it was lost, or when it could not be sent at all.
+        self.start_rtt_sample(self.send.next, *time);
+        self.rearm_retransmission_timer(*time);
+
+        signals.answer = Some(self.send_open(true, new_four));
+        signals
+    }
+
+    /// Create the connection from the final ACK of a handshake answered with a SYN cookie.
+    fn arrives_syn_cookie(
+        &mut self,
+        incoming: &InPacket,
+        cookie: SynCookie,
+        new_four: FourTuple,
+        mut entry: EntryKey,
+    ) -> Signals {
+        let InPacket { segment, time, .. } = incoming;
+        // The SYN we answered, as far as it is encoded in the cookie.
+        let syn = tcp::Repr {
+            flags: tcp::Flags::SYN,
+            seq_number: segment.seq_number - 1,
+            ack_number: None,
+            window_len: 0,
+            payload_len: 0,
+            ..cookie.syn(segment)
         };
+
+        // UNWRAP: the ack was checked to be present and acknowledges our SYN.
+        let isn = segment.ack_number.unwrap() - 1;
         entry.set_four_tuple(new_four);
+        self.accept_syn(&syn, *time, isn, entry.timestamp_offset());
+        // There is no sample, the SYN-ACK was not timed.
+        self.round_trip.timed = None;
+
+        self.arrives_syn_received(incoming, entry)
+    }
+
+    /// Initialize the connection from an accepted SYN and move to `SynReceived`.
+    fn accept_syn(&mut self, segment: &tcp::Repr, time: Instant, isn: tcp::SeqNumber, offset: u32) {
         self.recv.next = segment.seq_number + 1;
         self.recv.initial_seq = segment.seq_number;
+        self.send.window = segment.window_len;
+        self.send.window_scale = segment.window_scale.unwrap_or(0);
+        self.send.max_window = u32::from(segment.window_len);
+        if segment.window_scale.is_none() {
+            // Scaling is only used when both sides offer it.
+            self.recv.window_scale = 0;
+        }
+
+        self.negotiate_timestamps(segment, time);
+        self.timestamps.offset = offset;
+        self.selective_acknowledgements &= segment.sack_permitted;
+        // RFC3168: an ECN-setup SYN carries both ECE and CWR.
+        self.ecn.enabled &= segment.flags.ece() && segment.flags.cwr();
+
+        // TODO: better mss
+        self.remote_maximum_segment_size = segment.max_seg_size
+            .unwrap_or(536)
+            .max(536);
+        self.sender_maximum_segment_size = self.remote_maximum_segment_size;
+        self.receiver_maximum_segment_size = self.sender_maximum_segment_size;
 
-        let isn = entry.initial_seq_num(*time);
         self.send.next = isn + 1;
         self.send.unacked = isn;
         self.send.initial_seq = isn;
+        self.flow_control.recover = isn;
+        self.round_trip.timed = None;
+        self.round_trip.backoff = 0;
 
-        signals.answer = Some(InnerRepr {
-            flags: tcp::Flags::RST,
-            seq_number: isn,
-            ack_number: Some(self.ack_all()),
-            window_len: self.recv.window,
-            window_scale: None,
-            max_seg_size: None,
-            sack_permitted: false,
-            sack_ranges: [None; 3],
-            payload_len: 0,
-        }.send_to(new_four));
+        self.change_state(State::SynReceived);
+    }
 
-        signals
+    /// Handle the fast open option of a SYN accepted while listening.
+    ///
+    /// Returns if the data of the SYN may be accepted. Otherwise, the SYN-ACK provides the remote
+    /// with a valid cookie if it requested one or sent an invalid one.
+    fn fast_open_syn(&mut self, segment: &tcp::Repr, tuple: FourTuple, entry: &EntryKey) -> bool {
+        self.fast_open_cookie = None;
+        let cookie = match segment.fast_open {
+            Some(cookie) if self.fast_open => cookie,
+            _ => return false,
+        };
+
+        let valid = entry.fast_open_cookie(tuple);
+        if cookie != valid {
+            self.fast_open_cookie = Some(valid);
+            return false;
+        }
+
+        segment.payload_len > 0
+    }
+
+    /// Wait for the ACK of our SYN in a passively opened connection.
+    fn arrives_syn_received(&mut self, incoming: &InPacket, entry: EntryKey)
+        -> Signals
+    {
+        let InPacket { segment, time, .. } = incoming;
+
+        if segment.flags.rst() {
+            if !self.recv.in_window(segment.seq_number) && segment.seq_number != self.recv.next {
+                return Signals::default();
+            }
+
+            // RFC793: a connection initiated by a passive open returns to the listen state. The
+            // endpoint must restore the listening tuple, the connection state is left as is.
+            return Signals {
+                relisten: true,
+                ..Signals::default()
+            };
+        }
+
+        if segment.flags.syn() {
+            // The remote retransmitted its SYN, we answer again.
+            if segment.seq_number == self.recv.initial_seq && segment.ack_number.is_none() {
+                return Signals {
+                    answer: Some(self.send_open(true, entry.four_tuple())),
+                    ..Signals::default()
+                };
+            }
+
+            return self.signal_reset_connection(segment, entry);
+        }
+
+        let ack = match segment.ack_number {
+            None => return Signals::default(),
+            Some(ack) => ack,
+        };
+
+        if ack <= self.send.unacked || ack > self.send.next {
+            // Not an ACK for our SYN, but we do not give up the connection yet.
+            return Signals {
+                answer: Some(InnerRepr {
+                    flags: tcp::Flags::RST,
+                    seq_number: ack,
+                    ack_number: None,
+                    window_len: 0,
+                    window_scale: None,
+                    max_seg_size: None,
+                    sack_permitted: false,
+                    sack_ranges: [None; 3],
+                    timestamp: None,
+                    payload_len: 0,
+                }.send_back(segment)),
+                ..Signals::default()
+            };
+        }
+
+        self.send.unacked = ack;
+        self.complete_rtt_sample(ack, segment.timestamp, *time);
+        self.change_state(State::Established);
+        self.flow_control.establish(self.sender_maximum_segment_size, *time);
+        self.quick_acks = self.ack_policy.quick_acks;
+        // RFC6298 (5.7): Without a measurement due to a retransmitted SYN, start with 3 seconds.
+        if self.round_trip.smoothed.is_none() && self.round_trip.backoff > 0 {
+            self.round_trip.backoff = 0;
+            self.retransmission_timeout = Duration::from_secs(3);
+        }
+
+        // The segment may already contain data and a window update.
+        self.arrives_established(incoming, entry)
     }
 
     fn arrives_syn_sent(&mut self, incoming: &InPacket, entry: EntryKey)
         -> Signals
     {
-        let InPacket { segment, from: _, time, } = incoming;
+        let InPacket { segment, time, .. } = incoming;
 
         if let Some(ack) = segment.ack_number {
             if ack <= self.send.initial_seq || ack > self.send.next {
@@ -609,6 +1121,7 @@ impl Connection {
                     max_seg_size: None,
                     sack_permitted: false,
                     sack_ranges: [None; 3],
+                    timestamp: None,
                     payload_len: 0,
                 }.send_back(segment));
                 return signals;
@@ -633,17 +1146,34 @@ impl Connection {
         self.recv.next = segment.seq_number + 1;
         self.send.window = segment.window_len;
         self.send.window_scale = segment.window_scale.unwrap_or(0);
+        // The window of a SYN is never scaled.
+        self.send.max_window = u32::from(segment.window_len);
+        self.negotiate_timestamps(segment, *time);
+        self.selective_acknowledgements &= segment.sack_permitted;
+        // RFC3168: an ECN-setup SYN-ACK carries only ECE. Simultaneous opens do not use ECN.
+        self.ecn.enabled &= segment.ack_number.is_some()
+            && segment.flags.ece()
+            && !segment.flags.cwr();
 
         // TODO: better mss
-        self.sender_maximum_segment_size = segment.max_seg_size
+        self.remote_maximum_segment_size = segment.max_seg_size
             .unwrap_or(536)
             .max(536);
+        self.sender_maximum_segment_size = self.remote_maximum_segment_size;
         self.receiver_maximum_segment_size = self.sender_maximum_segment_size;
 
         if let Some(ack) = segment.ack_number {
             self.send.unacked = ack;
+            self.complete_rtt_sample(ack, segment.timestamp, *time);
         }
 
+        // RFC7413: Data of a fast open SYN that was not acknowledged is sent again as new data.
+        self.send.next = if self.send.unacked == self.send.initial_seq {
+            self.send.initial_seq + 1
+        } else {
+            self.send.unacked
+        };
+
         // The SYN didn't actually ack our SYN. So change to SYN-RECEIVED.
         if self.send.unacked == self.send.initial_seq {
             self.change_state(State::SynReceived);
@@ -654,6 +1184,13 @@ impl Connection {
         }
 
         self.change_state(State::Established);
+        self.flow_control.establish(self.sender_maximum_segment_size, *time);
+        self.quick_acks = self.ack_policy.quick_acks;
+        // RFC6298 (5.7): Without a measurement due to a retransmitted SYN, start with 3 seconds.
+        if self.round_trip.smoothed.is_none() && self.round_trip.backoff > 0 {
+            self.round_trip.backoff = 0;
+            self.retransmission_timeout = Duration::from_secs(3);
+        }
         // The rfc would immediately ack etc. We may want to send data and that requires the
         // cooperation of io. Defer but mark as ack required immediately.
         self.ack_timer = Expiration::When(*time);
@@ -662,24 +1199,32 @@ impl Connection {
 
     fn arrives_established(&mut self, incoming: &InPacket, entry: EntryKey) -> Signals {
         // TODO: time for RTT estimation, ...
-        let InPacket { segment, from: _, time, } = incoming;
-
-        let acceptable = self.ingress_acceptable(segment);
+        let InPacket { segment, time, .. } = incoming;
 
-        if !acceptable {
-            if segment.flags.rst() {
-                return self.remote_reset_connection();
-            }
+        match self.paws_acceptable(segment, *time) {
+            Some(true) => (),
+            // Silently drop, as recommended for non-RST segments without the timestamp option.
+            None => return Signals::default(),
+            // Old duplicate segment, acknowledge what we actually expect.
+            Some(false) if segment.flags.rst() => return Signals::default(),
+            Some(false) => return self.signal_ack_all(entry.four_tuple()),
+        }
 
-            // TODO: find out why this triggers in a nice tcp connection (python -m http.server)
-            return self.signal_ack_all(entry.four_tuple());
+        if segment.flags.rst() {
+            return self.arrives_reset(segment, *time, entry);
         }
 
         if segment.flags.syn() {
-            debug_assert!(self.recv.in_window(segment.seq_number));
+            // RFC5961: any SYN, regardless of its sequence number, is challenged. A remote that
+            // really restarted answers our ACK with a RST at the exact sequence number.
+            return self.challenge_ack(*time, entry);
+        }
 
-            // This is not acceptable, reset the connection.
-            return self.signal_reset_connection(segment, entry);
+        let acceptable = self.ingress_acceptable(segment);
+
+        if !acceptable {
+            // TODO: find out why this triggers in a nice tcp connection (python -m http.server)
+            return self.signal_ack_all(entry.four_tuple());
         }
 
         let ack = match segment.ack_number {
@@ -688,32 +1233,56 @@ impl Connection {
             Some(ack) => ack,
         };
 
+        if !self.send.ack_acceptable(ack) {
+            // RFC5961: an injected segment with a guessed ack does not get its data accepted.
+            return self.challenge_ack(*time, entry);
+        }
+
+        self.recv.last_time = *time;
+        self.keep_alive_probes = 0;
+        self.update_recent_timestamp(segment, *time);
+        self.ecn_echo(incoming);
+
         match self.send.incoming_ack(ack) {
-            AckUpdate::Unsent => {
-                // That acked something we hadn't sent yet. A madlad at the other end.
-                // Ignore the packet but we ack back the previous state.
-                return self.signal_ack_all(entry.four_tuple());
+            AckUpdate::Unsent => unreachable!("Ack was checked to be acceptable"),
+            AckUpdate::Duplicate if self.is_duplicate_ack(segment) => {
+                self.update_scoreboard(segment);
+                self.duplicate_ack = self.duplicate_ack.saturating_add(1);
+                self.congestion_duplicate(ack, *time);
             },
+            // Same ack but otherwise a regular segment, possibly a window update.
             AckUpdate::Duplicate => {
-                self.duplicate_ack = self.duplicate_ack.saturating_add(1);
-                /*
-                self.flow_control.ssthresh = unimplemented!();
-                self.flow_control.congestion_window = unimplemented!();
-                */
+                self.update_scoreboard(segment);
+                self.send.window = segment.window_len;
+                self.send.max_window = self.send.max_window.max(self.send.window());
             },
             // This is a reordered packet, potentially an attack. Do nothing.
             AckUpdate::TooLow => (),
             AckUpdate::Updated { new_bytes } => {
-                // No longer in fast retransmit.
-                if self.duplicate_ack > 0 {
-                    self.flow_control.congestion_window = self.flow_control.ssthresh;
-                    self.duplicate_ack = 0;
-                }
+                self.update_scoreboard(segment);
+                self.duplicate_ack = 0;
                 self.send.window = segment.window_len;
-                self.window_update(segment, new_bytes);
+                self.send.max_window = self.send.max_window.max(self.send.window());
+                self.complete_rtt_sample(ack, segment.timestamp, *time);
+                // Restart the timer for the remaining segments in flight, RFC6298 (5.3).
+                self.rearm_retransmission_timer(*time);
+                self.congestion_ack(ack, new_bytes, *time);
             },
         }
 
+        if segment.flags.ece() {
+            self.congestion_ecn(ack, *time);
+        }
+
+        if self.current == State::LastAck && self.send.unacked == self.send.next {
+            // Our FIN was acknowledged, nothing is left of the connection.
+            self.change_state(State::Closed);
+            return Signals {
+                delete: true,
+                ..Signals::default()
+            };
+        }
+
         // URG lol
 
         let segment_ack = ReceivedSegment {
@@ -725,8 +1294,29 @@ impl Connection {
         };
 
         if segment_ack.data_len == 0 {
+            // A FIN beyond missing data must wait for its retransmission.
+            if segment_ack.fin && segment_ack.begin != self.recv.next {
+                return self.signal_ack_all(entry.four_tuple());
+            }
+
+            let was_closed = self.recv_closed();
             self.set_recv_ack(segment_ack);
-            return Signals::default();
+            return Signals {
+                half_closed: !was_closed && self.recv_closed(),
+                ..Signals::default()
+            };
+        }
+
+        if self.recv_closed() {
+            // RFC793: the remote sent its FIN already, ignore any text after it.
+            return self.signal_ack_all(entry.four_tuple());
+        }
+
+        // RFC5681: acknowledge out-of-order segments and those filling a hole immediately, so
+        // that the sender gets its duplicate acks. Continue quickly until the sender recovered.
+        if segment.seq_number != self.recv.next || self.recv.selective.iter().any(Option::is_some) {
+            self.ack_timer = Expiration::When(*time);
+            self.quick_acks = self.quick_acks.max(self.ack_policy.quick_acks);
         }
 
         // Actually accept the segment data. Note that we do not control the receive buffer
@@ -741,19 +1331,156 @@ impl Connection {
         signals
     }
 
-    /// Determine if a packet should be deemed acceptable on an open connection.
+    /// The data in the network and the congestion window that limits it.
     ///
-    /// See: https://tools.ietf.org/html/rfc793#page-40
-    fn ingress_acceptable(&self, repr: &tcp::Repr) -> bool {
-        match (repr.payload_len, self.recv.window) {
-            (0, 0) => repr.seq_number == self.recv.next,
-            (0, _) => self.recv.in_window(repr.seq_number),
-            (_, 0) => false,
-            (_, _) => self.recv.in_window(repr.seq_number)
+    /// While recovering with SACK information, this is `pipe` against the congestion window as in
+    /// RFC6675. Otherwise it is the data in flight against the window of NewReno, which is inflated
+    /// by every duplicate ack instead.
+    fn congestion_limit(&self) -> (u32, u32) {
+        if self.flow_control.fast_recovery && !self.scoreboard.is_empty() {
+            let pipe = self.scoreboard.pipe(self.send.unacked, self.send.next);
+            (pipe, self.flow_control.congestion.congestion_window())
+        } else {
+            (self.send.in_flight(), self.flow_control.window())
+        }
+    }
+
+    /// Determine if an ack for `SND.UNA` is a duplicate ack as defined in RFC5681.
+    ///
+    /// It must not carry data, not open or close the stream, not update the window, and there must
+    /// be outstanding data.
+    fn is_duplicate_ack(&self, segment: &tcp::Repr) -> bool {
+        segment.payload_len == 0
+            && !segment.flags.syn()
+            && !segment.flags.fin()
+            && segment.window_len == self.send.window
+            && self.send.in_flight() > 0
+    }
+
+    /// Count a duplicate ack towards fast retransmit, or inflate the window in fast recovery.
+    fn congestion_duplicate(&mut self, ack: tcp::SeqNumber, time: Instant) {
+        const DUPLICATE_THRESHOLD: u8 = 3;
+
+        let loss = self.loss(time);
+        let next = self.send.next;
+        let flow = &mut self.flow_control;
+
+        if flow.fast_recovery {
+            flow.inflate(loss.smss);
+        } else if self.duplicate_ack == DUPLICATE_THRESHOLD && ack > flow.recover {
+            // RFC6582: only enter fast retransmit if this does not cover data from a previous
+            // recovery. That would indicate a retransmission and not a loss.
+            flow.enter_recovery(loss, next);
+            self.retransmit_pending = true;
+            self.scoreboard.high_retransmit = self.send.unacked;
+        }
+    }
+
+    /// Track congestion marks that must be echoed to the remote, RFC3168 section 6.1.3.
+    fn ecn_echo(&mut self, incoming: &InPacket) {
+        if !self.ecn.enabled {
+            return;
+        }
+
+        if incoming.segment.flags.cwr() {
+            self.ecn.echo = false;
+        }
+
+        if incoming.congestion_experienced {
+            self.ecn.echo = true;
+            // The sender should learn of the congestion as soon as possible.
+            self.ack_timer = Expiration::When(incoming.time);
+        }
+    }
+
+    /// Reduce the congestion window for an echoed congestion mark, RFC3168 section 6.1.2.
+    ///
+    /// This happens at most once per window of data and not while recovering from a loss.
+    fn congestion_ecn(&mut self, ack: tcp::SeqNumber, time: Instant) {
+        if !self.ecn.enabled || self.flow_control.fast_recovery {
+            return;
+        }
+
+        match self.ecn.recover {
+            Some(recover) if ack < recover => return,
+            _ => (),
+        }
+
+        let loss = self.loss(time);
+        self.flow_control.congestion.on_ecn(loss);
+        self.ecn.recover = Some(self.send.next);
+        self.ecn.reduced = true;
+    }
+
+    /// Update the congestion window for newly acknowledged data.
+    fn congestion_ack(&mut self, ack: tcp::SeqNumber, new_bytes: u32, time: Instant) {
+        let loss = self.loss(time);
+        let flow = &mut self.flow_control;
+
+        if flow.fast_recovery {
+            if ack >= flow.recover {
+                flow.exit_recovery(loss);
+            } else {
+                // A partial ack, the next segment was lost as well. With SACK information the
+                // holes are instead filled as the congestion window permits.
+                flow.deflate(new_bytes, loss.smss);
+                self.retransmit_pending = self.scoreboard.is_empty();
+            }
+        } else {
+            flow.congestion.on_ack(Ack {
+                new_bytes,
+                flight_size: loss.flight_size,
+                smss: loss.smss,
+                time,
+            });
+            // Still recovering from a timeout, continue with the next unacknowledged segment.
+            if ack < flow.recover {
+                self.retransmit_pending = true;
+            }
+        }
+    }
+
+    /// Determine if a packet should be deemed acceptable on an open connection.
+    ///
+    /// See: https://tools.ietf.org/html/rfc793#page-40
+    fn ingress_acceptable(&self, repr: &tcp::Repr) -> bool {
+        match (repr.payload_len, self.recv.window) {
+            (0, 0) => repr.seq_number == self.recv.next,
+            (0, _) => self.recv.in_window(repr.seq_number),
+            (_, 0) => false,
+            (_, _) => self.recv.in_window(repr.seq_number)
                 || self.recv.in_window(repr.seq_number + repr.payload_len.into() - 1),
         }
     }
 
+    /// Handle a RST in a synchronized state, see RFC5961 section 3.2.
+    ///
+    /// Only a RST at exactly the next expected sequence number closes the connection. One that is
+    /// merely within the window is challenged, all others are dropped.
+    fn arrives_reset(&mut self, segment: &tcp::Repr, time: Instant, entry: EntryKey) -> Signals {
+        if self.current == State::TimeWait {
+            // RFC1337: a RST would assassinate the TIME-WAIT state prematurely.
+            Signals::default()
+        } else if segment.seq_number == self.recv.next {
+            self.remote_reset_connection()
+        } else if self.recv.in_window(segment.seq_number) {
+            self.challenge_ack(time, entry)
+        } else {
+            Signals::default()
+        }
+    }
+
+    /// Send a challenge ACK, within the global rate limit of RFC5961 section 7.
+    ///
+    /// The segment that caused it is dropped in any case.
+    fn challenge_ack(&mut self, time: Instant, mut entry: EntryKey) -> Signals {
+        if entry.challenge_ack(time) {
+            self.signal_ack_all(entry.four_tuple())
+        } else {
+            Signals::default()
+        }
+    }
+
     /// Close from an incoming reset.
     ///
     /// This shared logic is used by some states on receiving a packet with RST set.
@@ -784,6 +1511,7 @@ impl Connection {
             max_seg_size: None,
             sack_permitted: false,
             sack_ranges: [None; 3],
+            timestamp: None,
             payload_len: 0,
         }.send_to(entry.four_tuple()));
         signals
@@ -801,19 +1529,24 @@ impl Connection {
         Segment {
             repr: self.repr_ack_all(remote),
             range: 0..0,
+            ecn_capable: false,
         }
     }
 
     fn repr_ack_all(&mut self, remote: FourTuple) -> tcp::Repr {
+        let mut flags = tcp::Flags::default();
+        flags.set_ece(self.ecn.echo);
+
         InnerRepr {
-            flags: tcp::Flags::default(),
+            flags,
             seq_number: self.send.next,
             ack_number: Some(self.ack_all()),
             window_len: self.recv.window,
             window_scale: None,
             max_seg_size: None,
             sack_permitted: false,
-            sack_ranges: [None; 3],
+            sack_ranges: self.sack_ranges(),
+            timestamp: None,
             payload_len: 0,
         }.send_to(remote)
     }
@@ -823,17 +1556,49 @@ impl Connection {
     /// If `ack` is true then it also acknowledges received segments (i.e. this is a passive open).
     fn send_open(&mut self, ack: bool, to: FourTuple) -> tcp::Repr {
         let ack_number = if ack { Some(self.ack_all()) } else { None };
-        InnerRepr {
+        // The window of a SYN is never scaled.
+        let window = u32::from(self.recv.window) << self.recv.window_scale;
+        let window_len = u16::try_from(window).unwrap_or(u16::MAX);
+        // An answer only contains the option when the remote offered to scale as well.
+        let window_scale = if ack && self.send.window_scale == 0 && self.recv.window_scale == 0 {
+            None
+        } else {
+            Some(self.recv.window_scale)
+        };
+        let mut repr = InnerRepr {
             flags: tcp::Flags::SYN,
             seq_number: self.send.initial_seq,
             ack_number,
-            window_len: 0,
-            window_scale: Some(self.send.window_scale),
+            window_len,
+            window_scale,
             max_seg_size: None,
-            sack_permitted: false,
+            sack_permitted: self.selective_acknowledgements,
             sack_ranges: [None; 3],
+            timestamp: None,
             payload_len: 0,
-        }.send_to(to)
+        }.send_to(to);
+        repr.fast_open = self.fast_open_cookie;
+        if self.ecn.enabled {
+            repr.flags.set_ece(true);
+            repr.flags.set_cwr(!ack);
+        }
+        repr
+    }
+
+    /// Clamp the SMSS such that segments fit into the path MTU, RFC 1191 section 6.3.
+    ///
+    /// Resets the SMSS to the one announced by the remote when no path MTU is known. This has no
+    /// effect while the remote has not yet announced a segment size.
+    pub fn set_path_mtu(&mut self, mtu: Option<u16>, ip_header_len: u16) {
+        if self.remote_maximum_segment_size == 0 {
+            return;
+        }
+
+        let remote_mss = self.remote_maximum_segment_size;
+        self.sender_maximum_segment_size = match mtu {
+            Some(mtu) => remote_mss.min(mtu.saturating_sub(ip_header_len + 20)).max(1),
+            None => remote_mss,
+        };
     }
 
     /// Choose a next data segment to send.
@@ -843,11 +1608,13 @@ impl Connection {
     pub fn next_send_segment(&mut self, mut available: AvailableBytes, time: Instant, entry: EntryKey)
         -> OutSignals
     {
-        match self.current {
+        let mut signals = match self.current {
             State::Established | State::CloseWait => {
-                self.select_send_segment(available, time, entry)
-                    .map(OutSignals::segment)
-                    .unwrap_or_else(OutSignals::none)
+                let tuple = entry.four_tuple();
+                match self.select_send_segment(available, time, entry) {
+                    Some(segment) => OutSignals::segment(segment),
+                    None => self.select_keep_alive(time, tuple),
+                }
             },
             // When we have already sent our FIN, never send *new* data.
             State::FinWait | State::Closing | State::LastAck => {
@@ -864,12 +1631,18 @@ impl Connection {
             },
             State::TimeWait => self.ensure_time_wait(time, entry),
             State::SynSent | State::SynReceived => {
-                self.select_syn_retransmit(time, entry)
+                self.select_syn_retransmit(available, time, entry)
                     .map(OutSignals::segment)
                     .unwrap_or_else(OutSignals::none)
             },
             State::Listen => OutSignals::none(),
+        };
+
+        if let Some(segment) = signals.segment.as_mut() {
+            self.stamp(&mut segment.repr, time);
         }
+
+        signals
     }
 
     fn select_send_segment(&mut self, available: AvailableBytes, time: Instant, entry: EntryKey)
@@ -878,20 +1651,21 @@ impl Connection {
         // Convert the input to `u32`, our window can never be that large anyways.
         let byte_window = u32::try_from(available.total)
             .ok().unwrap_or_else(u32::max_value);
+        let sent = self.send.in_flight();
+
         // Connection restarted after idle time.
         let last_time = self.recv.last_time.max(self.send.last_time);
-        if time > last_time + self.restart_timeout {
-            self.flow_control.congestion_window = self.restart_window();
+        if sent == 0 && time > last_time + self.restart_timeout {
+            self.flow_control.congestion.on_idle(self.sender_maximum_segment_size, time);
         }
 
-        if self.duplicate_ack >= 2 {
-            // Fast retransmit?
-            //
-            // this would be a return path but just don't do anything atm.
-            return self.fast_retransmit(available, time, entry);
+        if self.retransmit_pending {
+            if let Some(segment) = self.fast_retransmit(available, time, entry.four_tuple()) {
+                return Some(segment);
+            }
         }
 
-        if self.retransmission_timer < time {
+        if sent > 0 && self.retransmission_timer < time {
             // Choose segments to retransmit, in contrast to `fast_retransmit` this may influence
             // multiple next packets.
             return self.timeout_retransmit(available, time, entry);
@@ -899,15 +1673,31 @@ impl Connection {
 
         // That's funny. Even if we have sent a FIN, the other side could decrease their window
         // size to the point where we could not send the sequence number of the FIN again.
-        let window = self.send.window();
-            // TODO: congestion flow control
-            // .min(self.flow_control.congestion_window);
-        let sent = self.send.in_flight();
+        // The congestion window limits the data in the network, not the sequence space.
+        let (pipe, congestion_window) = self.congestion_limit();
+        let window = self.send.window()
+            .min(sent.saturating_add(congestion_window.saturating_sub(pipe)));
         let max_sent = window.min(byte_window);
 
-        if sent < max_sent {
+        if self.send.window() > 0 {
+            self.persist_timer = Expiration::Never;
+            self.persist_backoff = 0;
+        } else if sent == 0 && byte_window > 0 {
+            if let Some(probe) = self.window_probe(time, entry.four_tuple()) {
+                return Some(probe);
+            }
+        }
+
+        // Fill the holes reported by SACK before sending new data, RFC6675.
+        if self.flow_control.fast_recovery && pipe < congestion_window
+            && self.scoreboard.next_hole(self.send.unacked).is_some()
+        {
+            return self.segment_retransmit(available, time, entry.four_tuple());
+        }
+
+        if sent < max_sent && self.may_send_new(sent, max_sent, byte_window, time) {
             // Send one new segment of new data.
-            let end = sent.saturating_add(self.sender_maximum_segment_size.into()).min(max_sent);
+            let end = sent.saturating_add(self.segment_size().into()).min(max_sent);
             // UNWRAP: Available was larger than `end` so these will not fail (even on 16-bit
             // platforms where the buffer may be smaller than the `u32` window). Math:
             // `sent_u32 <= end_u32 <= available_u32 <= available_usize`
@@ -929,18 +1719,34 @@ impl Connection {
             let mut repr = self.repr_ack_all(entry.four_tuple());
 
             repr.payload_len = range.len() as u16;
-            if is_fin {
-                repr.flags = tcp::Flags::FIN;
-            }
+            repr.flags.set_fin(is_fin);
+            // RFC3168: confirm the reduction of the window with the next new data.
+            repr.flags.set_cwr(self.ecn.reduced);
+            self.ecn.reduced = false;
 
             self.send.next = self.send.next + range.len() + usize::from(is_fin);
+            self.send.last_time = time;
+            self.start_rtt_sample(self.send.next, time);
+
+            // Start the timer if it is not running, RFC6298 (5.1).
+            if sent == 0 {
+                self.rearm_retransmission_timer(time);
+            }
 
             return Some(Segment {
                 repr,
                 range,
+                ecn_capable: self.ecn.enabled,
             });
         }
 
+        // All data has been sent, close our side with a segment carrying only the FIN.
+        if available.fin && sent == byte_window {
+            if let Some(segment) = self.segment_fin(sent, time, entry.four_tuple()) {
+                return Some(segment);
+            }
+        }
+
         // There is nothing to send but we may need to ack anyways.
         if self.should_ack() || Expiration::When(time) >= self.ack_timer {
             self.rearm_ack_timer(time);
@@ -950,7 +1756,137 @@ impl Connection {
         None
     }
 
-    fn select_syn_retransmit(&mut self, time: Instant, entry: EntryKey)
+    /// Send a FIN without data after all data has been sent.
+    fn segment_fin(&mut self, sent: u32, time: Instant, tuple: FourTuple) -> Option<Segment> {
+        match self.current {
+            State::Established => self.change_state(State::FinWait),
+            State::CloseWait => self.change_state(State::LastAck),
+            _ => return None,
+        }
+
+        let mut repr = self.repr_ack_all(tuple);
+        repr.flags.set_fin(true);
+
+        self.send.next += 1;
+        self.send.last_time = time;
+        self.start_rtt_sample(self.send.next, time);
+
+        if sent == 0 {
+            self.rearm_retransmission_timer(time);
+        }
+
+        // UNWRAP: `sent` is bounded by the available bytes which were converted from `usize`.
+        let start = usize::try_from(sent).unwrap();
+        Some(Segment {
+            repr,
+            range: start..start,
+            ecn_capable: false,
+        })
+    }
+
+    /// Decide if a new segment should be sent now or better be coalesced with later data.
+    ///
+    /// Implements the sender side of silly window syndrome avoidance and Nagle's algorithm, see
+    /// RFC1122 section 4.2.3.4. A segment is sent if it has the maximum size, if it contains all
+    /// queued data, or if it fills at least half of the largest window the remote ever offered.
+    /// The latter two are only sent while no data is in flight unless Nagle's algorithm is
+    /// disabled. When nothing is in flight, the override timeout also sends a smaller segment.
+    fn may_send_new(&self, sent: u32, max_sent: u32, byte_window: u32, time: Instant) -> bool {
+        // The override timeout, between 0.1 and 1 second as recommended.
+        const SWS_OVERRIDE: Duration = Duration::from_millis(200);
+
+        let len = max_sent - sent;
+        if len >= u32::from(self.segment_size()) {
+            return true;
+        }
+
+        if sent > 0 && !self.nodelay {
+            return false;
+        }
+
+        max_sent == byte_window
+            || len >= self.send.max_window / 2
+            || (sent == 0 && time >= self.send.last_time + SWS_OVERRIDE)
+    }
+
+    /// Run the persist timer while the remote window is closed.
+    ///
+    /// The probe carries no data and the sequence number just before the first unacknowledged
+    /// byte, like a keep-alive. It is never acceptable and the remote must answer with an ACK
+    /// containing its current window. Unlike a probe with one byte of data we never have to
+    /// retransmit anything beyond the window when the probe itself is lost.
+    fn window_probe(&mut self, time: Instant, tuple: FourTuple) -> Option<Segment> {
+        match self.persist_timer {
+            Expiration::Never => {
+                self.persist_timer = Expiration::When(time + self.persist_timeout());
+                return None;
+            },
+            Expiration::When(expires) if expires > time => return None,
+            Expiration::When(_) => (),
+        }
+
+        self.persist_backoff = self.persist_backoff.saturating_add(1);
+        self.persist_timer = Expiration::When(time + self.persist_timeout());
+
+        let mut repr = self.repr_ack_all(tuple);
+        repr.seq_number = self.send.unacked - 1;
+        Some(Segment {
+            repr,
+            range: 0..0,
+            ecn_capable: false,
+        })
+    }
+
+    /// Probe the remote when the connection was idle for too long.
+    ///
+    /// Probes look like window probes, the remote must answer them with an ACK. When that does not
+    /// happen after the configured number of probes, the connection is closed.
+    fn select_keep_alive(&mut self, time: Instant, tuple: FourTuple) -> OutSignals {
+        let keep_alive = match self.keep_alive {
+            Some(keep_alive) => keep_alive,
+            None => return OutSignals::none(),
+        };
+
+        // Data in flight is already covered by the retransmission timer.
+        if self.send.in_flight() > 0 {
+            return OutSignals::none();
+        }
+
+        let probes = self.keep_alive_probes;
+        let next_probe = self.recv.last_time
+            + keep_alive.idle
+            + keep_alive.interval*u32::from(probes);
+        if time < next_probe {
+            return OutSignals::none();
+        }
+
+        if probes >= keep_alive.count {
+            self.change_state(State::Closed);
+            return OutSignals {
+                delete: true,
+                reset: true,
+                segment: None,
+            };
+        }
+
+        self.keep_alive_probes += 1;
+        let mut repr = self.repr_ack_all(tuple);
+        repr.seq_number = self.send.next - 1;
+        OutSignals::segment(Segment {
+            repr,
+            range: 0..0,
+            ecn_capable: false,
+        })
+    }
+
+    /// The current interval between two window probes.
+    fn persist_timeout(&self) -> Duration {
+        let factor = 1_u32 << self.persist_backoff.min(16);
+        (self.retransmission_timeout*factor)
+            .min(self.round_trip.max_timeout)
+    }
+
+    fn select_syn_retransmit(&mut self, available: AvailableBytes, time: Instant, entry: EntryKey)
         -> Option<Segment>
     {
         if self.retransmission_timer > time {
@@ -963,28 +1899,63 @@ impl Connection {
             _ => unreachable!(),
         };
 
+        let first = self.round_trip.timed.is_none() && self.round_trip.backoff == 0;
+        // RFC7413: Only the first SYN carries data, with a cookie of the remote. Retransmissions
+        // leave it for after the handshake. The maximum segment size of the remote is not known
+        // yet so assume the default of 536 bytes.
+        let data_len = match self.fast_open_cookie {
+            Some(cookie) if first && !ack && !cookie.is_request()
+                => available.total.min(536),
+            _ => 0,
+        };
+
+        if !ack {
+            self.send.next = self.send.initial_seq + 1 + data_len;
+        }
+
+        if first {
+            // The first transmission of an actively opened connection.
+            self.start_rtt_sample(self.send.next, time);
+        } else {
+            self.back_off();
+        }
+
         self.rearm_retransmission_timer(time);
+        let mut repr = self.send_open(ack, entry.four_tuple());
+        // UNWRAP: bounded by the default segment size.
+        repr.payload_len = u16::try_from(data_len).unwrap();
         Some(Segment {
-            repr: self.send_open(ack, entry.four_tuple()),
-            range: 0..0,
+            repr,
+            range: 0..data_len,
+            ecn_capable: false,
         })
     }
 
-    fn fast_retransmit(&mut self, available: AvailableBytes, _: Instant, entry: EntryKey)
+    fn fast_retransmit(&mut self, available: AvailableBytes, time: Instant, tuple: FourTuple)
         -> Option<Segment>
     {
-        // TODO: flow control, adjust window
-        self.segment_retransmit(available, entry.four_tuple())
+        // The window has already been adjusted when the loss was detected.
+        self.retransmit_pending = false;
+        self.segment_retransmit(available, time, tuple)
     }
 
     fn timeout_retransmit(&mut self, available: AvailableBytes, time: Instant, entry: EntryKey)
         -> Option<Segment>
     {
+        let loss = self.loss(time);
+        self.flow_control.timeout(loss, self.send.next);
+        self.duplicate_ack = 0;
+        self.retransmit_pending = false;
+        // The remote may have discarded selectively acknowledged data (RFC2018, section 8).
+        self.scoreboard = Scoreboard::default();
+        self.back_off();
         self.rearm_retransmission_timer(time);
-        self.segment_retransmit(available, entry.four_tuple())
+        self.segment_retransmit(available, time, entry.four_tuple())
     }
 
-    fn segment_retransmit(&mut self, available: AvailableBytes, tuple: FourTuple) -> Option<Segment> {
+    fn segment_retransmit(&mut self, available: AvailableBytes, time: Instant, tuple: FourTuple)
+        -> Option<Segment>
+    {
         // See: https://tools.ietf.org/html/rfc5681#section-3.2
         // Retransmit the first unacknowledged segment. We can however also retransmit as much
         // bytes as we'd like starting at the first unacked segment. This is more efficient if that
@@ -999,25 +1970,49 @@ impl Connection {
             return None;
         }
 
-        let to_send = self.send.window()
-            .min(u32::from(self.sender_maximum_segment_size))
-            .min(byte_window);
+        // With SACK information only the holes need to be resent, RFC6675.
+        let (begin, end) = if self.scoreboard.is_empty() {
+            (self.send.unacked, self.send.next)
+        } else {
+            self.scoreboard.next_hole(self.send.unacked)?
+        };
+
+        // UNWRAP: the hole lies within the data in flight which fits into `u32`.
+        let offset = u32::try_from(begin - self.send.unacked).unwrap();
+        let hole = u32::try_from(end - begin).unwrap();
 
-        if to_send == 0 {
+        // Holes are only filled as the congestion window permits but the first retransmission
+        // after a loss or timeout is sent regardless, we only replace a segment deemed lost.
+        let to_send = self.send.window().saturating_sub(offset)
+            .min(u32::from(self.segment_size()))
+            .min(byte_window.saturating_sub(offset))
+            .min(hole);
+
+        // Only our FIN is outstanding, which carries no data.
+        let fin_only = available.fin && offset == byte_window && in_flight - 1 == byte_window;
+        if to_send == 0 && !fin_only {
             return None;
         }
 
-        let range = 0..usize::try_from(to_send).unwrap();
+        // Karn's algorithm, the ack would be ambiguous.
+        self.round_trip.timed = None;
+        self.send.last_time = time;
+        self.scoreboard.high_retransmit = begin + to_send as usize;
+
+        // UNWRAP: bounded by `byte_window` which was converted from `usize`.
+        let start = usize::try_from(offset).unwrap();
+        let range = start..start + usize::try_from(to_send).unwrap();
         let is_fin = available.fin && range.end == available.total;
 
         let mut repr = self.repr_ack_all(tuple);
         repr.flags.set_fin(is_fin);
-        repr.seq_number = self.send.unacked;
+        repr.seq_number = begin;
         repr.payload_len = to_send as u16;
 
         Some(Segment {
             repr,
             range,
+            ecn_capable: false,
         })
     }
 
@@ -1029,29 +2024,70 @@ impl Connection {
         Some(self.segment_ack_all(tuple))
     }
 
-    fn ensure_time_wait(&mut self, time: Instant, entry: EntryKey) -> OutSignals {
-        match self.ensure_closed_ack(entry.four_tuple()) {
-            Some(segment) => OutSignals {
-                segment: Some(segment),
-                delete: false,
-            },
-            None => OutSignals {
-                delete: time >= self.retransmission_timer,
-                segment: None,
-            },
+    fn ensure_time_wait(&mut self, time: Instant, mut entry: EntryKey) -> OutSignals {
+        let tuple = entry.four_tuple();
+        if let Some(segment) = self.ensure_closed_ack(tuple) {
+            return OutSignals::segment(segment);
+        }
+
+        let expired = time >= self.retransmission_timer;
+        let parked = !expired && entry.park_time_wait(self.time_wait_entry(tuple), time);
+        OutSignals {
+            delete: expired || parked,
+            ..OutSignals::default()
         }
     }
 
-    fn window_update(&mut self, _segment: &tcp::Repr, new_bytes: u32) {
-        let flow = &mut self.flow_control;
-        if self.duplicate_ack > 0 {
-            flow.congestion_window = flow.ssthresh;
-        } else if flow.congestion_window <= flow.ssthresh {
-            flow.congestion_window = flow.congestion_window.saturating_mul(2);
+    /// The state of this connection that needs to be kept during TIME-WAIT.
+    fn time_wait_entry(&self, tuple: FourTuple) -> TimeWait {
+        let recent_timestamp = if self.timestamps.enabled {
+            Some(self.timestamps.recent)
         } else {
-            // https://tools.ietf.org/html/rfc5681, avoid cwnd flooding from ack splitting.
-            let update = u32::from(self.sender_maximum_segment_size).min(new_bytes);
-            flow.congestion_window = flow.congestion_window.saturating_add(update);
+            None
+        };
+
+        TimeWait::new(
+            tuple,
+            self.send.next,
+            self.recv.next,
+            recent_timestamp,
+            self.timestamps.offset,
+            self.retransmission_timer)
+    }
+
+    /// Check if the remote has closed its side of the connection.
+    ///
+    /// This is the case once its FIN has been accepted, no more data will arrive afterwards.
+    pub fn recv_closed(&self) -> bool {
+        matches!(self.current,
+            State::CloseWait | State::Closing | State::LastAck | State::TimeWait)
+    }
+
+    /// Close the connection immediately, the ABORT call of RFC793.
+    ///
+    /// Returns the RST to send to the remote if it might still consider the connection open. The
+    /// connection is `Closed` afterwards and should be deleted.
+    pub(crate) fn abort(&mut self, tuple: FourTuple) -> Option<tcp::Repr> {
+        let previous = self.current;
+        self.change_state(State::Closed);
+
+        match previous {
+            State::SynReceived
+            | State::Established
+            | State::FinWait
+            | State::CloseWait => Some(InnerRepr {
+                flags: tcp::Flags::RST,
+                seq_number: self.send.next,
+                ack_number: None,
+                window_len: 0,
+                window_scale: None,
+                max_seg_size: None,
+                sack_permitted: false,
+                sack_ranges: [None; 3],
+                timestamp: None,
+                payload_len: 0,
+            }.send_to(tuple)),
+            _ => None,
         }
     }
 
@@ -1087,8 +2123,20 @@ impl Connection {
             _ => (),
         }
 
-        self.recv.next = end;
-        let new_timer = Expiration::When(meta.timestamp + self.ack_timeout);
+        if end > self.recv.next {
+            // The data now occupies part of the advertised window.
+            self.recv.consume_window(end - self.recv.next);
+            self.recv.next = end;
+        }
+
+        // Segments without data or flags are not acknowledged at all, that would never end.
+        if meta.sequence_len() == 0 {
+            return;
+        }
+
+        // A FIN is acknowledged without delay.
+        let delay = if meta.fin { Duration::from_millis(0) } else { self.ack_policy.timeout };
+        let new_timer = Expiration::When(meta.timestamp + delay);
         self.ack_timer = self.ack_timer.min(new_timer);
     }
 
@@ -1099,7 +2147,7 @@ impl Connection {
     pub fn get_send_ack(&self) -> tcp::SeqNumber {
         match self.current {
             // If our SYN has not been acked, advance beyond the SYN.
-            State::SynSent => self.send.unacked + 1,
+            State::SynSent | State::SynReceived => self.send.unacked + 1,
             // Don't include our FIN even if it has already been acked.
             State::FinWait | State::Closing | State::TimeWait | State::LastAck
                 if self.send.unacked == self.send.next
@@ -1115,22 +2163,34 @@ impl Connection {
     /// Sending a packet with the current received state catches the former up to the latter
     /// counter.
     fn ack_all(&mut self) -> tcp::SeqNumber {
+        if self.recv.acked < self.recv.next {
+            self.quick_acks = self.quick_acks.saturating_sub(1);
+        }
         self.recv.acked = self.recv.next;
         self.ack_timer = Expiration::Never;
         self.recv.next
     }
 
-    /// Determine whether to send an ACK.
+    /// Determine whether to send an ACK right now.
     ///
-    /// This is currently always true when there is any sequence space to ack but that may change
-    /// for delayed acks.
+    /// When acknowledgments are delayed, we wait for a second full-sized segment (RFC5681) or the
+    /// expiration of the `ack_timer` (RFC1122). Otherwise any new sequence space is acked.
     fn should_ack(&self) -> bool {
-        self.recv.acked < self.recv.next
+        if self.recv.acked >= self.recv.next {
+            return false;
+        }
+
+        if !self.ack_policy.delayed || self.quick_acks > 0 {
+            return true;
+        }
+
+        let unacked = self.recv.next - self.recv.acked;
+        unacked >= 2*usize::from(self.receiver_maximum_segment_size)
     }
 
     fn rearm_ack_timer(&mut self, time: Instant) {
         self.ack_timer = match self.ack_timer {
-            Expiration::When(_) => Expiration::When(time + self.ack_timeout),
+            Expiration::When(_) => Expiration::When(time + self.ack_policy.timeout),
             Expiration::Never => Expiration::Never,
         }
     }
@@ -1139,14 +2199,433 @@ impl Connection {
         self.retransmission_timer = time + self.retransmission_timeout;
     }
 
+    /// Time a segment ending at `end` if no other measurement is in progress.
+    fn start_rtt_sample(&mut self, end: tcp::SeqNumber, time: Instant) {
+        if self.round_trip.timed.is_none() {
+            self.round_trip.timed = Some((end, time));
+        }
+    }
+
+    /// Finish the measurement if the timed segment was acknowledged.
+    ///
+    /// With timestamps, every acknowledgment of new data is a measurement (RTTM).
+    fn complete_rtt_sample(&mut self, ack: tcp::SeqNumber, timestamp: Option<tcp::Timestamp>, time: Instant) {
+        if let Some(rtt) = self.timestamp_rtt(timestamp, time) {
+            self.round_trip.timed = None;
+            self.round_trip.backoff = 0;
+            self.round_trip.sample(rtt);
+            self.retransmission_timeout = self.round_trip.timeout();
+            return;
+        }
+
+        match self.round_trip.timed {
+            Some((end, sent)) if ack >= end => {
+                self.round_trip.timed = None;
+                self.round_trip.backoff = 0;
+                self.round_trip.sample(time - sent);
+                self.retransmission_timeout = self.round_trip.timeout();
+            },
+            _ => (),
+        }
+    }
+
+    /// The round-trip time measured with the echoed timestamp of an acknowledgment.
+    fn timestamp_rtt(&self, timestamp: Option<tcp::Timestamp>, time: Instant) -> Option<Duration> {
+        if !self.timestamps.enabled {
+            return None;
+        }
+
+        // An echo of zero is not a measurement, the remote may not have received any timestamp.
+        let echo = timestamp?.tsecr;
+        if echo == 0 {
+            return None;
+        }
+
+        let millis = self.timestamp_value(time).wrapping_sub(echo);
+        // The echo is from the future, not a valid measurement.
+        if millis > i32::MAX as u32 {
+            return None;
+        }
+
+        Some(Duration::from_millis(millis.into()))
+    }
+
+    /// Our timestamp clock, with millisecond ticks.
+    fn timestamp_value(&self, time: Instant) -> u32 {
+        (time.millis() as u32).wrapping_add(self.timestamps.offset)
+    }
+
+    /// Record the SACK blocks of an incoming acknowledgment.
+    fn update_scoreboard(&mut self, segment: &tcp::Repr) {
+        if self.selective_acknowledgements {
+            self.scoreboard.update(self.send.unacked, self.send.next, &segment.sack_ranges);
+        }
+    }
+
+    /// The SACK blocks to include in an outgoing acknowledgment.
+    ///
+    /// Blocks which have since been cumulatively acknowledged are left out.
+    fn sack_ranges(&self) -> [Option<(u32, u32)>; 3] {
+        let mut ranges = [None; 3];
+        if !self.selective_acknowledgements {
+            return ranges;
+        }
+
+        let next = self.recv.next;
+        ranges.iter_mut()
+            .zip(self.recv.selective.iter())
+            .for_each(|(range, block)| *range = block
+                .filter(|&(_, right)| right > next)
+                .map(|(left, right)| (left.0 as u32, right.0 as u32)));
+        ranges
+    }
+
+    /// Add the timestamp option to an outgoing segment if it was negotiated.
+    fn stamp(&self, repr: &mut tcp::Repr, time: Instant) {
+        // RFC7323: A RST does not need to carry the option.
+        if !self.timestamps.enabled || repr.flags.rst() {
+            return;
+        }
+
+        repr.timestamp = Some(tcp::Timestamp {
+            tsval: self.timestamp_value(time),
+            tsecr: self.timestamps.recent,
+        });
+    }
+
+    /// Negotiate the option from an incoming SYN.
+    fn negotiate_timestamps(&mut self, segment: &tcp::Repr, time: Instant) {
+        match segment.timestamp {
+            Some(timestamp) if self.timestamps.enabled => {
+                self.timestamps.recent = timestamp.tsval;
+                self.timestamps.recent_time = time;
+            },
+            _ => self.timestamps.enabled = false,
+        }
+    }
+
+    /// Check the segment against PAWS, RFC7323 section 5.3.
+    ///
+    /// Returns `None` if the segment should have included a timestamp but did not.
+    fn paws_acceptable(&self, segment: &tcp::Repr, time: Instant) -> Option<bool> {
+        /// After 24 days the recent timestamp is assumed to be invalid (RFC7323 5.5).
+        const RECENT_VALIDITY: Duration = Duration::from_secs(24*24*60*60);
+
+        if !self.timestamps.enabled {
+            return Some(true);
+        }
+
+        let timestamp = match segment.timestamp {
+            Some(timestamp) => timestamp,
+            None if segment.flags.rst() => return Some(true),
+            None => return None,
+        };
+
+        if time > self.timestamps.recent_time + RECENT_VALIDITY {
+            return Some(true);
+        }
+
+        Some(timestamp.tsval.wrapping_sub(self.timestamps.recent) as i32 >= 0)
+    }
+
+    /// Update TS.Recent from an acceptable segment, RFC7323 section 4.3.
+    fn update_recent_timestamp(&mut self, segment: &tcp::Repr, time: Instant) {
+        let timestamp = match segment.timestamp {
+            Some(timestamp) if self.timestamps.enabled => timestamp,
+            _ => return,
+        };
+
+        // Only for segments that cover the left edge of the window we last acknowledged.
+        if segment.seq_number <= self.recv.acked {
+            self.timestamps.recent = timestamp.tsval;
+            self.timestamps.recent_time = time;
+        }
+    }
+
+    /// The maximum amount of data in one segment.
+    ///
+    /// The segment size advertised by the remote does not include the TCP options, so deduct the
+    /// space for the timestamp option and SACK blocks (with padding).
+    fn segment_size(&self) -> u16 {
+        let mut options = 0;
+        if self.timestamps.enabled {
+            options += 10;
+        }
+
+        let blocks = self.sack_ranges().iter().filter(|range| range.is_some()).count() as u16;
+        if blocks > 0 {
+            options += 2 + 8*blocks;
+        }
+
+        if options % 4 != 0 {
+            options += 4 - options % 4;
+        }
+
+        self.sender_maximum_segment_size.saturating_sub(options).max(1)
+    }
+
+    /// Exponential backoff when the retransmission timer expired, RFC6298 (5.5).
+    fn back_off(&mut self) {
+        self.round_trip.timed = None;
+        self.round_trip.backoff = self.round_trip.backoff.saturating_add(1);
+        self.retransmission_timeout = (2*self.retransmission_timeout)
+            .min(self.round_trip.max_timeout);
+    }
+
     pub(crate) fn change_state(&mut self, new: State) {
         self.previous = self.current;
         self.current = new;
     }
 
-    /// RFC5681 restart window.
-    fn restart_window(&self) -> u32 {
-        self.flow_control.congestion_window.min(self.send.window.into())
+    /// Describe the current flight for the congestion control.
+    fn loss(&self, time: Instant) -> Loss {
+        Loss {
+            flight_size: self.send.in_flight(),
+            smss: self.sender_maximum_segment_size,
+            time,
+        }
+    }
+}
+
+impl Scoreboard {
+    /// Check if the remote selectively acknowledged any data.
+    pub fn is_empty(&self) -> bool {
+        self.blocks[0].is_none()
+    }
+
+    /// Merge newly reported blocks and forget everything below `unacked`.
+    ///
+    /// Blocks that are not within the data in flight are ignored, this includes D-SACK blocks.
+    fn update(&mut self, unacked: tcp::SeqNumber, next: tcp::SeqNumber, reported: &[Option<(u32, u32)>; 3]) {
+        let reported = reported.iter()
+            .filter_map(|block| *block)
+            .map(|(left, right)| (tcp::SeqNumber(left as i32), tcp::SeqNumber(right as i32)))
+            .filter(|&(left, right)| left < right && unacked < right && right <= next);
+
+        let mut all = [(tcp::SeqNumber::default(), tcp::SeqNumber::default()); 7];
+        let mut count = 0;
+        for (slot, block) in all.iter_mut().zip(self.blocks.iter().filter_map(|block| *block).chain(reported)) {
+            *slot = block;
+            count += 1;
+        }
+
+        // UNWRAP: all sequence numbers are within the window and thus comparable.
+        let all = &mut all[..count];
+        all.sort_unstable_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        self.blocks = [None; 4];
+        let mut len = 0;
+        for &(left, right) in all.iter() {
+            if right <= unacked {
+                continue;
+            }
+
+            let left = if left < unacked { unacked } else { left };
+            if let Some(Some(last)) = self.blocks[..len].last_mut() {
+                if left <= last.1 {
+                    if right > last.1 {
+                        last.1 = right;
+                    }
+                    continue;
+                }
+            }
+
+            if len == self.blocks.len() {
+                break;
+            }
+
+            self.blocks[len] = Some((left, right));
+            len += 1;
+        }
+    }
+
+    /// Estimate the data still in the network, `pipe` of RFC6675.
+    ///
+    /// This is the data in flight without the selectively acknowledged data and the holes deemed
+    /// lost, except those that were already retransmitted.
+    fn pipe(&self, unacked: tcp::SeqNumber, next: tcp::SeqNumber) -> u32 {
+        let mut from = if self.high_retransmit > unacked {
+            self.high_retransmit
+        } else {
+            unacked
+        };
+
+        let mut left_network = 0;
+        for &(left, right) in self.blocks.iter().flatten() {
+            // A hole not yet retransmitted.
+            if from < left {
+                left_network += left - from;
+            }
+            if from < right {
+                from = right;
+            }
+            left_network += right - left;
+        }
+
+        // UNWRAP: all blocks lie within the data in flight which fits into `u32`.
+        u32::try_from((next - unacked).saturating_sub(left_network)).unwrap()
+    }
+
+    /// Find the first hole that has not yet been retransmitted.
+    ///
+    /// Only holes below the highest selectively acknowledged sequence number are deemed lost.
+    fn next_hole(&self, unacked: tcp::SeqNumber) -> Option<(tcp::SeqNumber, tcp::SeqNumber)> {
+        let mut from = if self.high_retransmit > unacked {
+            self.high_retransmit
+        } else {
+            unacked
+        };
+
+        for &(left, right) in self.blocks.iter().flatten() {
+            if from < left {
+                return Some((from, left));
+            }
+            if from < right {
+                from = right;
+            }
+        }
+
+        None
+    }
+}
+
+impl AckPolicy {
+    /// Delay acknowledgments, as recommended by RFC1122 and RFC5681.
+    ///
+    /// Acks every second full-sized segment or after 200ms, with a few quick acks to speed up
+    /// the slow start of the remote.
+    pub fn delayed() -> Self {
+        AckPolicy {
+            delayed: true,
+            timeout: Duration::from_millis(200),
+            quick_acks: 8,
+        }
+    }
+
+    /// Acknowledge every segment immediately.
+    pub fn quick() -> Self {
+        AckPolicy {
+            delayed: false,
+            timeout: Duration::from_millis(0),
+            quick_acks: 0,
+        }
+    }
+}
+
+impl Default for AckPolicy {
+    fn default() -> Self {
+        AckPolicy::delayed()
+    }
+}
+
+impl Default for KeepAlive {
+    /// The conventional values: two hours idle time, then nine probes every 75 seconds.
+    fn default() -> Self {
+        KeepAlive {
+            idle: Duration::from_secs(2*60*60),
+            interval: Duration::from_secs(75),
+            count: 9,
+        }
+    }
+}
+
+impl Flow {
+    /// Create the flow control with some congestion control algorithm.
+    pub fn new(congestion: Congestion) -> Self {
+        Flow {
+            congestion,
+            recovery_window: 0,
+            recover: tcp::SeqNumber::default(),
+            fast_recovery: false,
+        }
+    }
+
+    /// The current window dictated by congestion.
+    pub fn window(&self) -> u32 {
+        if self.fast_recovery {
+            self.recovery_window
+        } else {
+            self.congestion.congestion_window()
+        }
+    }
+
+    /// Setup the window for a newly established connection.
+    fn establish(&mut self, smss: u16, time: Instant) {
+        self.congestion.on_establish(smss, time);
+        self.fast_recovery = false;
+    }
+
+    /// Enter fast recovery on the third duplicate ack.
+    ///
+    /// `next` is the highest sequence number sent so far.
+    fn enter_recovery(&mut self, loss: Loss, next: tcp::SeqNumber) {
+        self.congestion.on_loss(loss);
+        self.recovery_window = self.congestion.congestion_window()
+            .saturating_add(3*u32::from(loss.smss));
+        self.recover = next;
+        self.fast_recovery = true;
+    }
+
+    /// Artificially inflate the window for each additional duplicate ack.
+    ///
+    /// Each such ack signals that a segment has left the network.
+    fn inflate(&mut self, smss: u16) {
+        self.recovery_window = self.recovery_window.saturating_add(smss.into());
+    }
+
+    /// Deflate the window on a partial ack in fast recovery.
+    fn deflate(&mut self, new_bytes: u32, smss: u16) {
+        let smss = u32::from(smss);
+        self.recovery_window = self.recovery_window.saturating_sub(new_bytes);
+        if new_bytes >= smss {
+            self.recovery_window = self.recovery_window.saturating_add(smss);
+        }
+    }
+
+    /// Leave fast recovery with a full acknowledgment.
+    fn exit_recovery(&mut self, loss: Loss) {
+        self.congestion.on_recovered(loss);
+        self.fast_recovery = false;
+    }
+
+    /// Collapse the window to the loss window after a retransmission timeout.
+    fn timeout(&mut self, loss: Loss, next: tcp::SeqNumber) {
+        self.congestion.on_rto(loss);
+        self.recover = next;
+        self.fast_recovery = false;
+    }
+}
+
+impl RoundTrip {
+    /// The clock granularity (G), our timestamps have millisecond resolution.
+    const GRANULARITY: Duration = Duration::from_millis(1);
+
+    /// Update the estimators with a new measurement.
+    pub fn sample(&mut self, rtt: Duration) {
+        match self.smoothed {
+            None => {
+                self.smoothed = Some(rtt);
+                self.variance = rtt / 2;
+            },
+            Some(smoothed) => {
+                let deviation = smoothed.max(rtt) - smoothed.min(rtt);
+                self.variance = (3*self.variance + deviation) / 4;
+                self.smoothed = Some((7*smoothed + rtt) / 8);
+            },
+        }
+    }
+
+    /// The retransmission timeout derived from the current estimation.
+    ///
+    /// Without any measurements this is the minimum timeout.
+    pub fn timeout(&self) -> Duration {
+        let smoothed = match self.smoothed {
+            None => return self.min_timeout,
+            Some(smoothed) => smoothed,
+        };
+
+        let timeout = smoothed + Self::GRANULARITY.max(4*self.variance);
+        timeout.max(self.min_timeout).min(self.max_timeout)
     }
 }
 
@@ -1155,19 +2634,50 @@ impl Receive {
         self.next.contains_in_window(seq, self.window.into())
     }
 
-    /// Setup the window based on an incoming (unscaled) window field.
-    pub fn update_window(&mut self, window: usize) {
+    /// Setup the window based on the (unscaled) free space of the receive buffer.
+    ///
+    /// Avoids the silly window syndrome (RFC1122 section 4.2.3.3). The window is only opened
+    /// further when that offers at least a full segment of `mss` bytes or half the buffer.
+    pub fn update_window(&mut self, window: usize, mss: u16) {
         let max = u32::from(u16::max_value()) << self.window_scale;
         let capped = u32::try_from(window)
             .unwrap_or_else(|_| u32::max_value())
             .min(max);
-        let scaled_down = (capped >> self.window_scale)
-            + u32::from(capped % (1 << self.window_scale) != 0);
+        self.max_window = self.max_window.max(capped);
+
+        let current = u32::from(self.window) << self.window_scale;
+        let threshold = (self.max_window / 2).min(u32::from(mss));
+        if capped > current && capped - current < threshold {
+            return;
+        }
+
+        self.set_window(capped);
+    }
+
+    /// Shrink the window by the sequence space that was just received.
+    fn consume_window(&mut self, len: usize) {
+        let current = u32::from(self.window) << self.window_scale;
+        let len = u32::try_from(len).unwrap_or(u32::MAX);
+        self.set_window(current.saturating_sub(len));
+    }
+
+    fn set_window(&mut self, window: u32) {
+        let remainder = window & ((1 << self.window_scale) - 1);
+        let scaled_down = (window >> self.window_scale) + u32::from(remainder != 0);
         self.window = u16::try_from(scaled_down).unwrap();
     }
 }
 
 impl Send {
+    /// Check if an ack is in the range accepted by RFC5961 section 5.2.
+    ///
+    /// That is `SND.UNA - MAX.SND.WND <= SEG.ACK <= SND.NXT`.
+    fn ack_acceptable(&self, ack: tcp::SeqNumber) -> bool {
+        let max_window = usize::try_from(self.max_window).unwrap_or(usize::MAX)
+            .min(i32::MAX as usize);
+        ack <= self.next && ack >= self.unacked - max_window
+    }
+
     fn incoming_ack(&mut self, seq: tcp::SeqNumber) -> AckUpdate {
         if seq < self.unacked {
             AckUpdate::TooLow
@@ -1206,13 +2716,22 @@ impl ReceivedSegment {
     /// Only ack part of the segment until some sequence point.
     ///
     /// Takes care of removing the FIN flag if the acked part does not cover every data byte until
-    /// that point.
+    /// that point. The result ends at `ack` instead, as the receive buffer may not have accepted
+    /// all data of the segment or may have completed earlier out-of-order data with it. A segment
+    /// entirely beyond `ack` becomes empty.
     pub fn acked_until(&self, ack: tcp::SeqNumber) -> Self {
+        let fin = self.fin && ack + 1 >= self.sequence_end();
+        if fin {
+            return ReceivedSegment { fin, ..*self };
+        }
+
+        let begin = if ack < self.begin { ack } else { self.begin };
+        let syn = self.syn && begin == self.begin;
         ReceivedSegment {
-            syn: self.syn,
-            fin: self.fin && ack + 1 >= self.sequence_end(),
-            begin: self.begin,
-            data_len: self.data_len,
+            syn,
+            fin,
+            begin,
+            data_len: (ack - begin).saturating_sub(usize::from(syn)),
             timestamp: self.timestamp,
         }
     }
@@ -1248,7 +2767,7 @@ impl OutSignals {
     pub fn segment(segment: Segment) -> Self {
         OutSignals {
             segment: Some(segment),
-            delete: false,
+            ..OutSignals::default()
         }
     }
 }
@@ -1300,7 +2819,19 @@ impl<'a> Operator<'a> {
 
     pub(crate) fn arrives(&mut self, incoming: &InPacket) -> Signals {
         let (entry_key, connection) = self.entry().into_key_value();
-        connection.arrives(incoming, entry_key)
+        let mut signals = connection.arrives(incoming, entry_key);
+
+        if signals.relisten && !self.endpoint.relisten(self.connection_key) {
+            // Someone else is listening already, so this connection is simply reset.
+            signals = self.connection_mut().remote_reset_connection();
+        }
+
+        signals
+    }
+
+    pub(crate) fn abort(&mut self) -> Option<tcp::Repr> {
+        let (entry_key, connection) = self.entry().into_key_value();
+        connection.abort(entry_key.four_tuple())
     }
 
     pub(crate) fn next_send_segment(&mut self, available: AvailableBytes, time: Instant)
@@ -1358,6 +2889,8 @@ impl InnerRepr {
             max_seg_size: self.max_seg_size,
             sack_permitted: self.sack_permitted,
             sack_ranges: self.sack_ranges,
+            timestamp: self.timestamp,
+            fast_open: None,
             payload_len: self.payload_len,
         }
     }
@@ -1365,11 +2898,15 @@ impl InnerRepr {
 
 #[cfg(test)]
 mod tests {
-    use crate::layer::tcp::endpoint::{EntryKey, FourTuple, PortMap};
+    use crate::layer::tcp::endpoint::{ChallengeAcks, EntryKey, FourTuple, PortMap};
     use crate::layer::tcp::IsnGenerator;
-    use crate::time::Instant;
+    use crate::layer::tcp::time_wait::Table as TimeWaitTable;
+    use crate::managed::Slice;
+    use crate::time::{Duration, Expiration, Instant};
     use crate::wire::ip::Address;
-    use super::{AvailableBytes, Connection};
+    use crate::wire::tcp;
+    use crate::layer::tcp::congestion::CongestionControl;
+    use super::{AckPolicy, AvailableBytes, Connection, InPacket, KeepAlive, OutSignals, Segment, State};
 
     struct NoRemap;
 
@@ -1379,6 +2916,139 @@ mod tests {
         }
     }
 
+    /// A connection with its endpoint data, to drive it through its states.
+    struct Harness {
+        connection: Connection,
+        isn: IsnGenerator,
+        challenge_acks: ChallengeAcks,
+        time_wait: TimeWaitTable<'static>,
+        no_remap: NoRemap,
+        four: FourTuple,
+    }
+
+    const MSS: u16 = 1000;
+
+    impl Harness {
+        fn new() -> Self {
+            let mut connection = Connection::zeroed();
+            connection.retransmission_timeout = Duration::from_secs(1);
+            connection.round_trip.min_timeout = Duration::from_secs(1);
+            connection.round_trip.max_timeout = Duration::from_secs(60);
+            connection.restart_timeout = Duration::from_secs(30);
+            Harness {
+                connection,
+                isn: IsnGenerator::from_key(0, 0),
+                challenge_acks: ChallengeAcks::new(ChallengeAcks::DEFAULT_LIMIT),
+                time_wait: TimeWaitTable::new(Slice::empty()),
+                no_remap: NoRemap,
+                four: FourTuple {
+                    local: Address::v4(192, 0, 10, 1),
+                    remote: Address::v4(192, 0, 10, 2),
+                    local_port: 80,
+                    remote_port: 80,
+                },
+            }
+        }
+
+        /// Actively open the connection and answer with a SYN-ACK.
+        fn established(window: u16) -> Self {
+            Harness::established_after(window, Instant::from_millis(0))
+        }
+
+        /// Actively open the connection at time zero, the SYN-ACK arrives at `time`.
+        fn established_after(window: u16, time: Instant) -> Self {
+            Harness::new().establish(window, time, false)
+        }
+
+        /// Open a connection that negotiated selective acknowledgments.
+        fn established_sack(window: u16) -> Self {
+            let mut harness = Harness::new();
+            harness.connection.selective_acknowledgements = true;
+            harness.establish(window, Instant::from_millis(0), true)
+        }
+
+        fn establish(mut self, window: u16, time: Instant, sack_permitted: bool) -> Self {
+            let entry = EntryKey::fake(&mut self.no_remap, &self.isn, &mut self.challenge_acks, &mut self.time_wait, &mut self.four);
+            assert!(self.connection.open(Instant::from_millis(0), entry).is_ok());
+            let syn = self.send(0, Instant::from_millis(0)).expect("Sends a SYN");
+            assert_eq!(syn.repr.sack_permitted, self.connection.selective_acknowledgements);
+
+            let mut syn_ack = self.segment(tcp::SeqNumber(1000), Some(self.connection.send.next));
+            syn_ack.flags = tcp::Flags::SYN;
+            syn_ack.window_len = window;
+            syn_ack.max_seg_size = Some(MSS);
+            syn_ack.sack_permitted = sack_permitted;
+            let signals = self.arrive(syn_ack, time);
+            assert!(signals.answer.is_none());
+            assert_eq!(self.connection.current, State::Established);
+            self
+        }
+
+        /// A segment from the remote, without data.
+        fn segment(&self, seq_number: tcp::SeqNumber, ack_number: Option<tcp::SeqNumber>) -> tcp::Repr {
+            tcp::Repr {
+                src_port: self.four.remote_port,
+                dst_port: self.four.local_port,
+                flags: tcp::Flags::default(),
+                seq_number,
+                ack_number,
+                window_len: self.connection.send.window,
+                window_scale: None,
+                max_seg_size: None,
+                sack_permitted: false,
+                sack_ranges: [None; 3],
+                timestamp: None,
+                fast_open: None,
+                payload_len: 0,
+            }
+        }
+
+        /// An ack from the remote for some sequence number.
+        fn ack(&self, ack: tcp::SeqNumber) -> tcp::Repr {
+            self.segment(self.connection.recv.next, Some(ack))
+        }
+
+        fn arrive(&mut self, segment: tcp::Repr, time: Instant) -> super::Signals {
+            self.arrive_ecn(segment, time, false)
+        }
+
+        /// A segment whose ip packet may have been marked as congestion experienced.
+        fn arrive_ecn(&mut self, segment: tcp::Repr, time: Instant, congestion_experienced: bool)
+            -> super::Signals
+        {
+            let incoming = InPacket {
+                segment,
+                from: self.four.remote,
+                time,
+                congestion_experienced,
+            };
+            let entry = EntryKey::fake(&mut self.no_remap, &self.isn, &mut self.challenge_acks, &mut self.time_wait, &mut self.four);
+            self.connection.arrives(&incoming, entry)
+        }
+
+        fn send(&mut self, total: usize, time: Instant) -> Option<Segment> {
+            self.send_signals(total, time).segment
+        }
+
+        fn send_signals(&mut self, total: usize, time: Instant) -> OutSignals {
+            let entry = EntryKey::fake(&mut self.no_remap, &self.isn, &mut self.challenge_acks, &mut self.time_wait, &mut self.four);
+            let available = AvailableBytes { fin: false, total };
+            self.connection.next_send_segment(available, time, entry)
+        }
+
+        /// Send data segments until none are produced, return the number of bytes sent.
+        fn send_all(&mut self, total: usize, time: Instant) -> usize {
+            let mut sent = 0;
+            while let Some(segment) = self.send(total, time) {
+                if segment.range.is_empty() {
+                    break;
+                }
+                sent += segment.range.len();
+            }
+            sent
+        }
+    }
+
     fn simple_connection() -> Connection {
         Connection::zeroed()
     }
@@ -1387,6 +3057,8 @@ mod tests {
     fn resent_syn() {
         let mut connection = simple_connection();
         let isn = IsnGenerator::from_key(0, 0);
+        let mut challenge_acks = ChallengeAcks::new(ChallengeAcks::DEFAULT_LIMIT);
+        let mut time_wait = TimeWaitTable::new(Slice::empty());
         let mut no_remap = NoRemap;
         let mut four = FourTuple {
             local: Address::v4(192, 0, 10, 1),
@@ -1398,11 +3070,699 @@ mod tests {
         let time_start = Instant::from_secs(0);
         let time_resend = Instant::from_secs(3);
 
-        let entry = EntryKey::fake(&mut no_remap, &isn, &mut four);
+        let entry = EntryKey::fake(&mut no_remap, &isn, &mut challenge_acks, &mut time_wait, &mut four);
         assert!(connection.open(time_start, entry).is_ok());
 
-        let entry = EntryKey::fake(&mut no_remap, &isn, &mut four);
+        let entry = EntryKey::fake(&mut no_remap, &isn, &mut challenge_acks, &mut time_wait, &mut four);
         let available = AvailableBytes { fin: false, total: 0 };
         let _resent = connection.next_send_segment(available, time_resend, entry);
     }
+
+    #[test]
+    fn slow_start_limits_flight() {
+        let mut harness = Harness::established(u16::max_value());
+        let time = Instant::from_millis(10);
+        let initial = harness.connection.send.unacked;
+
+        // Only the initial window is sent, even though the receiver allows more.
+        assert_eq!(harness.send_all(100_000, time), 4*usize::from(MSS));
+        assert_eq!(harness.connection.flow_control.window(), 4*u32::from(MSS));
+
+        // Each ack grows the window by at most one segment.
+        let signals = harness.arrive(harness.ack(initial + 2*usize::from(MSS)), time);
+        assert!(signals.answer.is_none());
+        assert_eq!(harness.connection.flow_control.window(), 5*u32::from(MSS));
+        assert_eq!(harness.send_all(100_000, time), 3*usize::from(MSS));
+    }
+
+    #[test]
+    fn fast_retransmit_and_recovery() {
+        let mut harness = Harness::established(u16::max_value());
+        let time = Instant::from_millis(10);
+        let initial = harness.connection.send.unacked;
+        assert_eq!(harness.send_all(100_000, time), 4*usize::from(MSS));
+
+        // First segment arrived, the second one got lost.
+        let first = initial + usize::from(MSS);
+        let _ = harness.arrive(harness.ack(first), time);
+        let _ = harness.send_all(100_000, time);
+        let highest = harness.connection.send.next;
+
+        for _ in 0..2 {
+            let _ = harness.arrive(harness.ack(first), time);
+            assert_eq!(harness.send_all(100_000, time), 0);
+        }
+
+        let _ = harness.arrive(harness.ack(first), time);
+        assert!(harness.connection.flow_control.fast_recovery);
+        let flow = harness.connection.flow_control;
+        // Half of the five segments in flight, plus the three that left the network.
+        assert_eq!(flow.congestion.slow_start_threshold(), 5*u32::from(MSS)/2);
+        assert_eq!(flow.window(), 11*u32::from(MSS)/2);
+        assert_eq!(flow.recover, highest);
+
+        let retransmit = harness.send(100_000, time).expect("Fast retransmit");
+        assert_eq!(retransmit.repr.seq_number, first);
+        assert_eq!(retransmit.range, 0..usize::from(MSS));
+
+        // Partial ack, retransmit the next hole immediately.
+        let second = first + usize::from(MSS);
+        let _ = harness.arrive(harness.ack(second), time);
+        assert!(harness.connection.flow_control.fast_recovery);
+        let retransmit = harness.send(100_000, time).expect("Partial ack retransmit");
+        assert_eq!(retransmit.repr.seq_number, second);
+
+        // A full ack ends the recovery and deflates the window.
+        let _ = harness.arrive(harness.ack(highest), time);
+        let flow = harness.connection.flow_control;
+        assert!(!flow.fast_recovery);
+        assert_eq!(flow.window(), 2*u32::from(MSS));
+    }
+
+    #[test]
+    fn explicit_congestion_notification() {
+        let mut harness = Harness::new();
+        harness.connection.ecn.enabled = true;
+        let time = Instant::from_millis(0);
+
+        let entry = EntryKey::fake(&mut harness.no_remap, &harness.isn, &mut harness.challenge_acks, &mut harness.time_wait, &mut harness.four);
+        assert!(harness.connection.open(time, entry).is_ok());
+        let syn = harness.send(0, time).expect("Sends a SYN");
+        assert!(syn.repr.flags.ece() && syn.repr.flags.cwr());
+        assert!(!syn.ecn_capable);
+
+        let mut syn_ack = harness.segment(tcp::SeqNumber(1000), Some(harness.connection.send.next));
+        syn_ack.flags = tcp::Flags::SYN;
+        syn_ack.flags.set_ece(true);
+        syn_ack.window_len = u16::MAX;
+        syn_ack.max_seg_size = Some(MSS);
+        let _ = harness.arrive(syn_ack, time);
+        assert_eq!(harness.connection.current, State::Established);
+        assert!(harness.connection.ecn.enabled);
+
+        // New data is sent as ECN-capable.
+        let initial = harness.connection.send.unacked;
+        let first = harness.send(100_000, time).expect("Sends data");
+        assert!(first.ecn_capable);
+        assert!(!first.repr.flags.ece() && !first.repr.flags.cwr());
+        let _ = harness.send_all(100_000, time);
+        let window = harness.connection.flow_control.window();
+
+        // A congestion mark is echoed until the remote confirms with CWR.
+        let _ = harness.arrive_ecn(harness.ack(initial), time, true);
+        assert!(harness.connection.ecn.echo);
+        let mut cwr = harness.ack(initial);
+        cwr.flags.set_cwr(true);
+        let _ = harness.arrive(cwr, time);
+        assert!(!harness.connection.ecn.echo);
+
+        // The echo reduces the window once and the next new data confirms with CWR.
+        let mut ece = harness.ack(initial + usize::from(MSS));
+        ece.flags.set_ece(true);
+        let _ = harness.arrive(ece, time);
+        assert!(harness.connection.flow_control.window() < window);
+        assert!(!harness.connection.flow_control.fast_recovery);
+        let threshold = harness.connection.flow_control.congestion.slow_start_threshold();
+
+        // Not reduced again within the same window of data.
+        let mut ece = harness.ack(initial + 2*usize::from(MSS));
+        ece.flags.set_ece(true);
+        let _ = harness.arrive(ece, time);
+        assert_eq!(harness.connection.flow_control.congestion.slow_start_threshold(), threshold);
+
+        let _ = harness.arrive(harness.ack(harness.connection.send.next), time);
+        let confirm = harness.send(100_000, time).expect("Sends data");
+        assert!(confirm.repr.flags.cwr());
+        assert!(confirm.ecn_capable);
+        if let Some(next) = harness.send(100_000, time) {
+            assert!(!next.repr.flags.cwr());
+        }
+    }
+
+    #[test]
+    fn reset_is_challenged() {
+        let mut harness = Harness::established(u16::MAX);
+        harness.connection.recv.window = 1000;
+        let time = Instant::from_millis(10);
+        let next = harness.connection.recv.next;
+
+        // Far outside of the window, dropped silently.
+        let mut rst = harness.segment(next + 5000, None);
+        rst.flags = tcp::Flags::RST;
+        let signals = harness.arrive(rst, time);
+        assert!(signals.answer.is_none() && !signals.reset);
+
+        // Within the window, answered by an ack.
+        let mut rst = harness.segment(next + 10, None);
+        rst.flags = tcp::Flags::RST;
+        let signals = harness.arrive(rst, time);
+        let answer = signals.answer.expect("Sends a challenge ACK");
+        assert_eq!(answer.ack_number, Some(next));
+        assert!(!answer.flags.rst());
+        assert!(!signals.reset && !signals.delete);
+        assert_eq!(harness.connection.current, State::Established);
+
+        // Exactly at the next sequence number.
+        let mut rst = harness.segment(next, None);
+        rst.flags = tcp::Flags::RST;
+        let signals = harness.arrive(rst, time);
+        assert!(signals.reset && signals.delete);
+        assert_eq!(harness.connection.current, State::Closed);
+    }
+
+    #[test]
+    fn remote_half_close() {
+        let mut harness = Harness::established(u16::MAX);
+        let next = harness.connection.recv.next;
+
+        // A FIN beyond missing data is not accepted.
+        let mut fin = harness.segment(next + 10, Some(harness.connection.send.next));
+        fin.flags = tcp::Flags::FIN;
+        let signals = harness.arrive(fin, Instant::from_millis(10));
+        assert!(!signals.half_closed && signals.answer.is_some());
+        assert_eq!(harness.connection.current, State::Established);
+
+        let mut fin = harness.ack(harness.connection.send.next);
+        fin.flags = tcp::Flags::FIN;
+        let signals = harness.arrive(fin, Instant::from_millis(20));
+        assert!(signals.half_closed);
+        assert!(harness.connection.recv_closed());
+        assert_eq!(harness.connection.current, State::CloseWait);
+
+        // Text after the FIN is ignored, but we may continue sending.
+        let mut data = harness.ack(harness.connection.send.next);
+        data.payload_len = 10;
+        let signals = harness.arrive(data, Instant::from_millis(30));
+        assert!(signals.receive.is_none() && !signals.half_closed);
+        assert_eq!(harness.send_all(1000, Instant::from_millis(40)), 1000);
+    }
+
+    #[test]
+    fn time_wait_ignores_reset() {
+        let mut harness = Harness::established(u16::MAX);
+        harness.connection.change_state(State::TimeWait);
+        let next = harness.connection.recv.next;
+
+        let mut rst = harness.segment(next, None);
+        rst.flags = tcp::Flags::RST;
+        let signals = harness.arrive(rst, Instant::from_millis(10));
+        assert!(signals.answer.is_none() && !signals.reset && !signals.delete);
+        assert_eq!(harness.connection.current, State::TimeWait);
+    }
+
+    #[test]
+    fn syn_is_challenged() {
+        let mut harness = Harness::established(u16::MAX);
+        harness.connection.recv.window = 1000;
+        let time = Instant::from_millis(10);
+        let next = harness.connection.recv.next;
+
+        for seq in [next, next + 10, next + 5000].iter() {
+            let mut syn = harness.segment(*seq, None);
+            syn.flags = tcp::Flags::SYN;
+            let signals = harness.arrive(syn, time);
+            let answer = signals.answer.expect("Sends a challenge ACK");
+            assert_eq!(answer.ack_number, Some(next));
+            assert!(!answer.flags.rst() && !answer.flags.syn());
+            assert!(!signals.reset && !signals.delete);
+        }
+
+        assert_eq!(harness.connection.current, State::Established);
+    }
+
+    #[test]
+    fn data_with_invalid_ack_is_challenged() {
+        let mut harness = Harness::established(1000);
+        harness.connection.recv.window = 1000;
+        let time = Instant::from_millis(10);
+        let next = harness.connection.recv.next;
+        let unacked = harness.connection.send.unacked;
+
+        // Acknowledges data long before anything we could still have in flight.
+        let mut data = harness.segment(next, Some(unacked - 5000));
+        data.payload_len = 100;
+        let signals = harness.arrive(data, time);
+        assert!(signals.receive.is_none());
+        assert_eq!(signals.answer.expect("Sends a challenge ACK").ack_number, Some(next));
+
+        // Acknowledges data that was never sent.
+        let mut data = harness.segment(next, Some(unacked + 5000));
+        data.payload_len = 100;
+        let signals = harness.arrive(data, time);
+        assert!(signals.receive.is_none());
+        assert!(signals.answer.is_some());
+
+        // An old ack within the window the remote has indicated, the data is accepted.
+        let mut data = harness.segment(next, Some(unacked - 500));
+        data.payload_len = 100;
+        let signals = harness.arrive(data, time);
+        assert!(signals.receive.is_some());
+    }
+
+    #[test]
+    fn challenge_acks_are_limited() {
+        let mut harness = Harness::established(u16::MAX);
+        harness.connection.recv.window = 1000;
+        harness.challenge_acks = ChallengeAcks::new(2);
+        let next = harness.connection.recv.next;
+
+        let challenged = |harness: &mut Harness, time| {
+            let mut rst = harness.segment(next + 10, None);
+            rst.flags = tcp::Flags::RST;
+            harness.arrive(rst, time).answer.is_some()
+        };
+
+        // Between one and two per second.
+        let time = Instant::from_secs(1);
+        let sent = (0..4).filter(|_| challenged(&mut harness, time)).count();
+        assert!((1..=2).contains(&sent), "Sent {} challenge ACKs", sent);
+        assert!(challenged(&mut harness, Instant::from_secs(2)));
+
+        harness.challenge_acks = ChallengeAcks::new(0);
+        assert!(!challenged(&mut harness, Instant::from_secs(3)));
+    }
+
+    #[test]
+    fn timeout_collapses_window() {
+        let mut harness = Harness::established(u16::max_value());
+        let time = Instant::from_millis(10);
+        let initial = harness.connection.send.unacked;
+        assert_eq!(harness.send_all(100_000, time), 4*usize::from(MSS));
+
+        let later = Instant::from_secs(5);
+        let retransmit = harness.send(100_000, later).expect("Timeout retransmit");
+        assert_eq!(retransmit.repr.seq_number, initial);
+
+        let flow = harness.connection.flow_control;
+        assert_eq!(flow.window(), u32::from(MSS));
+        assert_eq!(flow.congestion.slow_start_threshold(), 2*u32::from(MSS));
+        // The window is still filled by the unacknowledged segments.
+        assert!(harness.send(100_000, later).is_none());
+    }
+
+    #[test]
+    fn round_trip_estimation() {
+        let mut harness = Harness::established_after(u16::max_value(), Instant::from_millis(100));
+        let round_trip = harness.connection.round_trip;
+        assert_eq!(round_trip.smoothed, Some(Duration::from_millis(100)));
+        assert_eq!(round_trip.variance, Duration::from_millis(50));
+        // Clamped to the minimum timeout.
+        assert_eq!(harness.connection.retransmission_timeout, Duration::from_secs(1));
+
+        let initial = harness.connection.send.unacked;
+        let time = Instant::from_millis(200);
+        assert!(harness.send_all(usize::from(MSS), time) > 0);
+        let _ = harness.arrive(harness.ack(initial + usize::from(MSS)), Instant::from_millis(500));
+
+        let round_trip = harness.connection.round_trip;
+        assert_eq!(round_trip.smoothed, Some(Duration::from_millis(125)));
+        assert_eq!(round_trip.variance, Duration::from_micros(87_500));
+        assert!(round_trip.timed.is_none());
+    }
+
+    #[test]
+    fn retransmission_backoff() {
+        let mut harness = Harness::established(u16::max_value());
+        let initial = harness.connection.send.unacked;
+        assert!(harness.send_all(usize::from(MSS), Instant::from_millis(10)) > 0);
+
+        // Each expiration doubles the timeout.
+        let mut time = Instant::from_millis(10);
+        for &timeout in &[2, 4, 8] {
+            time = harness.connection.retransmission_timer + Duration::from_millis(1);
+            assert!(harness.send(usize::from(MSS), time).is_some());
+            assert_eq!(harness.connection.retransmission_timeout, Duration::from_secs(timeout));
+            assert_eq!(harness.connection.retransmission_timer, time + Duration::from_secs(timeout));
+        }
+
+        // Karn's algorithm: the ack of a retransmitted segment is no measurement.
+        let smoothed = harness.connection.round_trip.smoothed;
+        let _ = harness.arrive(harness.ack(initial + usize::from(MSS)), time);
+        assert_eq!(harness.connection.round_trip.smoothed, smoothed);
+        assert_eq!(harness.connection.retransmission_timeout, Duration::from_secs(8));
+
+        // New data is timed again and resets the backoff.
+        let acked = harness.connection.send.next;
+        assert!(harness.send_all(2*usize::from(MSS), time) > 0);
+        let later = time + Duration::from_millis(200);
+        let _ = harness.arrive(harness.ack(acked + usize::from(MSS)), later);
+        assert_eq!(harness.connection.round_trip.backoff, 0);
+        assert_eq!(harness.connection.retransmission_timeout, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn timestamps() {
+        let mut harness = Harness::new();
+        harness.connection.timestamps.enabled = true;
+        let entry = EntryKey::fake(&mut harness.no_remap, &harness.isn, &mut harness.challenge_acks, &mut harness.time_wait, &mut harness.four);
+        assert!(harness.connection.open(Instant::from_millis(0), entry).is_ok());
+
+        let syn = harness.send(0, Instant::from_millis(0)).expect("Initial SYN");
+        let offered = syn.repr.timestamp.expect("Timestamp offered in SYN");
+        assert_eq!(offered.tsecr, 0);
+
+        let mut syn_ack = harness.segment(tcp::SeqNumber(1000), Some(harness.connection.send.next));
+        syn_ack.flags = tcp::Flags::SYN;
+        syn_ack.window_len = u16::max_value();
+        syn_ack.max_seg_size = Some(MSS);
+        syn_ack.timestamp = Some(tcp::Timestamp { tsval: 500, tsecr: offered.tsval });
+        let _ = harness.arrive(syn_ack, Instant::from_millis(100));
+        assert!(harness.connection.timestamps.enabled);
+        assert_eq!(harness.connection.timestamps.recent, 500);
+        assert_eq!(harness.connection.round_trip.smoothed, Some(Duration::from_millis(100)));
+
+        // Data segments echo the timestamp and leave space for the option.
+        let initial = harness.connection.send.unacked;
+        let segment = harness.send(usize::from(MSS), Instant::from_millis(200)).expect("Data");
+        assert_eq!(segment.range, 0..usize::from(MSS - 12));
+        let stamp = segment.repr.timestamp.expect("Timestamp on data");
+        assert_eq!(stamp.tsecr, 500);
+
+        // Every ack with an echo is a measurement.
+        let mut ack = harness.ack(initial + usize::from(MSS - 12));
+        ack.timestamp = Some(tcp::Timestamp { tsval: 550, tsecr: stamp.tsval });
+        let _ = harness.arrive(ack, Instant::from_millis(250));
+        assert_eq!(harness.connection.timestamps.recent, 550);
+        assert_eq!(harness.connection.round_trip.smoothed, Some(Duration::from_micros(93_750)));
+
+        // PAWS: an old duplicate is answered with an ack and ignored.
+        let mut old = harness.ack(initial + usize::from(MSS - 12));
+        old.timestamp = Some(tcp::Timestamp { tsval: 400, tsecr: stamp.tsval });
+        old.payload_len = 100;
+        let signals = harness.arrive(old, Instant::from_millis(300));
+        assert!(signals.receive.is_none());
+        assert!(signals.answer.is_some());
+        assert_eq!(harness.connection.timestamps.recent, 550);
+
+        // Segments without the negotiated option are dropped.
+        let mut missing = harness.ack(initial + usize::from(MSS - 12));
+        missing.payload_len = 100;
+        let signals = harness.arrive(missing, Instant::from_millis(300));
+        assert!(signals.receive.is_none());
+        assert!(signals.answer.is_none());
+    }
+
+    #[test]
+    fn sack_blocks() {
+        let mut harness = Harness::established_sack(u16::max_value());
+        assert!(harness.connection.selective_acknowledgements);
+        let time = Instant::from_millis(10);
+        let next = harness.connection.recv.next;
+
+        // The first block was completed in the meantime and is not reported.
+        harness.connection.recv.selective = [
+            Some((next + 500, next + 1000)),
+            Some((next + 100, next + 200)),
+            Some((next - 100, next)),
+        ];
+        harness.connection.ack_timer = Expiration::When(time);
+        let ack = harness.send(0, time).expect("Sends an ack");
+        assert_eq!(ack.repr.sack_ranges, [
+            Some(((next + 500).0 as u32, (next + 1000).0 as u32)),
+            Some(((next + 100).0 as u32, (next + 200).0 as u32)),
+            None,
+        ]);
+        // The blocks take up option space.
+        assert_eq!(harness.connection.segment_size(), MSS - 20);
+
+        // Not used when the remote did not permit it.
+        let mut harness = Harness::new();
+        harness.connection.selective_acknowledgements = true;
+        let mut harness = harness.establish(u16::max_value(), Instant::from_millis(0), false);
+        assert!(!harness.connection.selective_acknowledgements);
+        harness.connection.recv.selective = [Some((next + 100, next + 200)), None, None];
+        harness.connection.ack_timer = Expiration::When(time);
+        let ack = harness.send(0, time).expect("Sends an ack");
+        assert_eq!(ack.repr.sack_ranges, [None; 3]);
+    }
+
+    #[test]
+    fn selective_retransmission() {
+        let mut harness = Harness::established_sack(u16::max_value());
+        let time = Instant::from_millis(10);
+        let initial = harness.connection.send.unacked;
+        let mss = usize::from(MSS);
+        let at = |offset: usize| initial + offset*mss;
+        let block = |left: usize, right: usize| Some((at(left).0 as u32, at(right).0 as u32));
+
+        assert_eq!(harness.send_all(100_000, time), 4*mss);
+        let _ = harness.arrive(harness.ack(at(1)), time);
+        assert_eq!(harness.send_all(100_000, time), 2*mss);
+        assert_eq!(harness.connection.send.next, at(6));
+
+        // The segments at 1 and 3 were lost, everything else arrived.
+        let mut dup = harness.ack(at(1));
+        dup.sack_ranges = [block(2, 3), None, None];
+        let _ = harness.arrive(dup, time);
+        dup.sack_ranges = [block(4, 5), block(2, 3), None];
+        let _ = harness.arrive(dup, time);
+        dup.sack_ranges = [block(4, 6), block(2, 3), None];
+        let _ = harness.arrive(dup, time);
+        assert!(harness.connection.flow_control.fast_recovery);
+        assert_eq!(harness.connection.scoreboard.blocks, [
+            Some((at(2), at(3))),
+            Some((at(4), at(6))),
+            None,
+            None,
+        ]);
+
+        let retransmit = harness.send(100_000, time).expect("Fast retransmit");
+        assert_eq!(retransmit.repr.seq_number, at(1));
+        assert_eq!(retransmit.range, 0..mss);
+
+        // The second hole is filled before any new data.
+        let retransmit = harness.send(100_000, time).expect("Fill the second hole");
+        assert_eq!(retransmit.repr.seq_number, at(3));
+        assert_eq!(retransmit.range, 2*mss..3*mss);
+
+        // Partial ack, but the remaining hole was already retransmitted.
+        let mut partial = harness.ack(at(3));
+        partial.sack_ranges = [block(4, 6), None, None];
+        let _ = harness.arrive(partial, time);
+        assert!(harness.connection.flow_control.fast_recovery);
+        assert_eq!(harness.connection.scoreboard.blocks[0], Some((at(4), at(6))));
+        if let Some(segment) = harness.send(100_000, time) {
+            assert!(segment.repr.seq_number >= at(6), "Resent selectively acked data");
+        }
+
+        let _ = harness.arrive(harness.ack(at(6)), time);
+        assert!(!harness.connection.flow_control.fast_recovery);
+        assert!(harness.connection.scoreboard.is_empty());
+    }
+
+    #[test]
+    fn selective_recovery_paced() {
+        let mut harness = Harness::established_sack(u16::max_value());
+        let time = Instant::from_millis(10);
+        let initial = harness.connection.send.unacked;
+        let mss = usize::from(MSS);
+        let at = |offset: usize| initial + offset*mss;
+        let block = |left: usize, right: usize| Some((at(left).0 as u32, at(right).0 as u32));
+
+        // Grow the window until eight segments are in flight.
+        assert_eq!(harness.send_all(100_000, time), 4*mss);
+        for acked in 1..=4 {
+            let _ = harness.arrive(harness.ack(at(acked)), time);
+            assert_eq!(harness.send_all(100_000, time), 2*mss);
+        }
+        assert_eq!(harness.connection.send.next, at(12));
+
+        // The segments at 4 and 6 were lost, everything else arrives in order.
+        let mut dup = harness.ack(at(4));
+        dup.sack_ranges = [block(5, 6), None, None];
+        let _ = harness.arrive(dup, time);
+        dup.sack_ranges = [block(7, 8), block(5, 6), None];
+        let _ = harness.arrive(dup, time);
+        dup.sack_ranges = [block(7, 9), block(5, 6), None];
+        let _ = harness.arrive(dup, time);
+        assert!(harness.connection.flow_control.fast_recovery);
+        let congestion_window = harness.connection.flow_control.congestion.congestion_window();
+        assert_eq!(congestion_window, 4*u32::from(MSS));
+
+        let retransmit = harness.send(100_000, time).expect("Fast retransmit");
+        assert_eq!(retransmit.repr.seq_number, at(4));
+
+        // The retransmission and the three segments after the highest SACK fill the window.
+        let (pipe, _) = harness.connection.congestion_limit();
+        assert_eq!(pipe, congestion_window);
+        assert!(harness.send(100_000, time).is_none(), "Second hole exceeds the window");
+
+        // Another segment left the network, now the second hole may be filled.
+        dup.sack_ranges = [block(7, 10), block(5, 6), None];
+        let _ = harness.arrive(dup, time);
+        let retransmit = harness.send(100_000, time).expect("Fill the second hole");
+        assert_eq!(retransmit.repr.seq_number, at(6));
+        assert!(harness.send(100_000, time).is_none(), "Window is full again");
+
+        // No holes are left, further room in the window is used for new data.
+        dup.sack_ranges = [block(7, 11), block(5, 6), None];
+        let _ = harness.arrive(dup, time);
+        let segment = harness.send(100_000, time).expect("Sends new data");
+        assert_eq!(segment.repr.seq_number, at(12));
+        assert!(harness.send(100_000, time).is_none(), "Window is full again");
+    }
+
+    #[test]
+    fn delayed_ack() {
+        let mut harness = Harness::established(u16::max_value());
+        harness.connection.ack_policy = AckPolicy::delayed();
+        harness.connection.quick_acks = 0;
+        harness.connection.recv.window = u16::max_value();
+        let mss = usize::from(MSS);
+        let start = harness.connection.recv.next;
+
+        // A buffer that accepts all data up to `acked`.
+        let receive = |harness: &mut Harness, seq: tcp::SeqNumber, acked: tcp::SeqNumber, time: Instant| {
+            let mut segment = harness.ack(harness.connection.send.next);
+            segment.seq_number = seq;
+            segment.payload_len = MSS;
+            let received = harness.arrive(segment, time).receive.expect("Accepts the data");
+            harness.connection.set_recv_ack(received.acked_until(acked));
+        };
+
+        let time = Instant::from_millis(10);
+        assert!(harness.send(0, time).is_some(), "Acks the SYN immediately");
+        receive(&mut harness, start, start + mss, time);
+        assert!(harness.send(0, time).is_none());
+        // Every second full-sized segment is acked.
+        receive(&mut harness, start + mss, start + 2*mss, time);
+        let ack = harness.send(0, time).expect("Acks the second segment");
+        assert_eq!(ack.repr.ack_number, Some(start + 2*mss));
+
+        // Otherwise the timer expires.
+        receive(&mut harness, start + 2*mss, start + 3*mss, time);
+        assert!(harness.send(0, Instant::from_millis(100)).is_none());
+        let ack = harness.send(0, Instant::from_millis(210)).expect("Acks after the timeout");
+        assert_eq!(ack.repr.ack_number, Some(start + 3*mss));
+
+        // Out-of-order data is acked immediately and enters quick-ack mode.
+        let time = Instant::from_millis(300);
+        receive(&mut harness, start + 4*mss, start + 3*mss, time);
+        let ack = harness.send(0, time).expect("Duplicate ack");
+        assert_eq!(ack.repr.ack_number, Some(start + 3*mss));
+        assert_eq!(harness.connection.recv.next, start + 3*mss);
+        receive(&mut harness, start + 3*mss, start + 5*mss, time);
+        let ack = harness.send(0, time).expect("Acks the filled hole");
+        assert_eq!(ack.repr.ack_number, Some(start + 5*mss));
+        receive(&mut harness, start + 5*mss, start + 6*mss, time);
+        assert!(harness.send(0, time).is_some(), "Quick ack");
+
+        // No delay at all in quick-ack mode.
+        harness.connection.ack_policy = AckPolicy::quick();
+        harness.connection.quick_acks = 0;
+        receive(&mut harness, start + 6*mss, start + 7*mss, time);
+        assert!(harness.send(0, time).is_some());
+    }
+
+    #[test]
+    fn nagle() {
+        let mut harness = Harness::established(u16::max_value());
+        let time = Instant::from_millis(10);
+        let initial = harness.connection.send.unacked;
+
+        // Nothing in flight, a small segment is sent right away.
+        let segment = harness.send(100, time).expect("Sends small segment");
+        assert_eq!(segment.range, 0..100);
+
+        // Held back until the previous data was acked.
+        assert!(harness.send(150, time).is_none());
+        let _ = harness.arrive(harness.ack(initial + 100), time);
+        let segment = harness.send(50, time).expect("Sends after ack");
+        assert_eq!(segment.range, 0..50);
+
+        // But full-sized segments are never held back.
+        let segment = harness.send(50 + usize::from(MSS), time).expect("Sends full segment");
+        assert_eq!(segment.range, 50..50 + usize::from(MSS));
+
+        harness.connection.nodelay = true;
+        let segment = harness.send(1250, time).expect("Sends with nodelay");
+        assert_eq!(segment.range, 1050..1250);
+    }
+
+    #[test]
+    fn send_window_sws() {
+        let mut harness = Harness::established(1500);
+        let time = Instant::from_millis(10);
+        let initial = harness.connection.send.unacked;
+        assert_eq!(harness.send_all(1000, time), 1000);
+
+        // At least half of the largest window is worth sending.
+        let mut ack = harness.ack(initial + 1000);
+        ack.window_len = 800;
+        let _ = harness.arrive(ack, time);
+        let segment = harness.send(5000, time).expect("Sends half the window");
+        assert_eq!(segment.range, 0..800);
+
+        // A smaller window is only used after the override timeout.
+        let mut ack = harness.ack(initial + 1800);
+        ack.window_len = 600;
+        let _ = harness.arrive(ack, time);
+        assert!(harness.send(5000, time).is_none());
+        let later = time + Duration::from_millis(200);
+        let segment = harness.send(5000, later).expect("Sends after the override");
+        assert_eq!(segment.range, 0..600);
+    }
+
+    #[test]
+    fn receive_window_sws() {
+        let mut harness = Harness::new();
+        let recv = &mut harness.connection.recv;
+        recv.update_window(10_000, MSS);
+        assert_eq!(recv.window, 10_000);
+
+        // Data arrived and occupies the buffer.
+        recv.consume_window(6000);
+        assert_eq!(recv.window, 4000);
+
+        // The reader freed some space, but not enough to be useful to the sender.
+        recv.update_window(4500, MSS);
+        assert_eq!(recv.window, 4000);
+        recv.update_window(5000, MSS);
+        assert_eq!(recv.window, 5000);
+
+        // A small buffer is still opened when half of it is free.
+        let mut harness = Harness::new();
+        let recv = &mut harness.connection.recv;
+        recv.update_window(600, MSS);
+        recv.consume_window(600);
+        recv.update_window(200, MSS);
+        assert_eq!(recv.window, 0);
+        recv.update_window(300, MSS);
+        assert_eq!(recv.window, 300);
+    }
+
+    #[test]
+    fn keep_alive() {
+        let mut harness = Harness::established(1000);
+        harness.connection.keep_alive = Some(KeepAlive {
+            idle: Duration::from_secs(10),
+            interval: Duration::from_secs(1),
+            count: 2,
+        });
+
+        assert!(harness.send(0, Instant::from_millis(0)).is_some(), "Acks the SYN");
+        assert!(harness.send(0, Instant::from_millis(9_000)).is_none());
+
+        let probe = harness.send(0, Instant::from_millis(10_000))
+            .expect("Probes the idle connection");
+        assert_eq!(probe.range.len(), 0);
+        assert_eq!(probe.repr.seq_number, harness.connection.send.next - 1);
+        assert!(harness.send(0, Instant::from_millis(10_500)).is_none());
+
+        // An answer restarts the idle time.
+        let ack = harness.ack(harness.connection.send.next);
+        let _ = harness.arrive(ack, Instant::from_millis(10_100));
+        assert_eq!(harness.connection.keep_alive_probes, 0);
+        assert!(harness.send(0, Instant::from_millis(20_000)).is_none());
+
+        // Unanswered probes close the connection.
+        assert!(harness.send(0, Instant::from_millis(20_100)).is_some());
+        assert!(harness.send(0, Instant::from_millis(20_600)).is_none());
+        assert!(harness.send(0, Instant::from_millis(21_100)).is_some());
+        let signals = harness.send_signals(0, Instant::from_millis(22_100));
+        assert!(signals.segment.is_none());
+        assert!(signals.delete);
+        assert!(signals.reset);
+        assert_eq!(harness.connection.current, State::Closed);
+    }
 }
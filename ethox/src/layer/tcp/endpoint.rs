@@ -9,26 +9,46 @@
 //! Selective ACKs: https://tools.ietf.org/html/rfc2018
 //! RST handling specifically: https://www.snellman.net/blog/archive/2016-02-01-tcp-rst/
 //!     OS comparison in particular
+use core::convert::TryFrom;
+use core::ops::RangeInclusive;
+
 use crate::layer::ip;
-use crate::managed::{Map, SlotMap, slotmap::Key};
-use crate::wire::{ip::Address, tcp::SeqNumber, tcp::Packet as TcpPacket};
+use crate::managed::{Map, Slice, SlotMap, slotmap::Key};
+use crate::wire::{ip::Address, tcp, tcp::FastOpenCookie, tcp::SeqNumber, tcp::Packet as TcpPacket};
 use crate::wire::PayloadMut;
 use crate::time::{Duration, Expiration, Instant};
 
+use super::congestion::Congestion;
 use super::connection::{
+    AckPolicy,
     Connection,
+    ExplicitCongestion,
     Flow,
+    KeepAlive,
+    RoundTrip,
+    Scoreboard,
     Send,
+    Timestamps,
     State,
     Receive};
 use super::packet::{In, Raw};
-use super::siphash::IsnGenerator;
+use super::siphash::{IsnGenerator, SynCookie};
+use super::time_wait::{Arrival, Table as TimeWaitTable, TimeWait};
 
 /// Handles TCP connection states.
 pub struct Endpoint<'a> {
     ports: Map<'a, FourTuple, Key>,
     states: SlotMap<'a, Slot>,
     isn_generator: IsnGenerator,
+    ack_policy: AckPolicy,
+    syn_cookies: bool,
+    fast_open: bool,
+    fast_open_cookies: Option<Map<'a, Address, FastOpenCookie>>,
+    ecn: bool,
+    challenge_acks: ChallengeAcks,
+    time_wait: TimeWaitTable<'a>,
+    ephemeral_ports: RangeInclusive<u16>,
+    next_ephemeral: u16,
 }
 
 /// The TCP connection identifier, with four components.
@@ -121,6 +141,8 @@ pub struct Entry<'a> {
     key: SlotKey,
     ports: &'a mut dyn PortMap,
     isn: &'a IsnGenerator,
+    challenge_acks: &'a mut ChallengeAcks,
+    time_wait: &'a mut dyn TimeWaitMap,
     slot: &'a mut Slot,
 }
 
@@ -131,9 +153,28 @@ pub struct Entry<'a> {
 pub struct EntryKey<'a> {
     ports: &'a mut dyn PortMap,
     isn: &'a IsnGenerator,
+    challenge_acks: &'a mut ChallengeAcks,
+    time_wait: &'a mut dyn TimeWaitMap,
     key_in_slot: &'a mut FourTuple,
 }
 
+/// The global limit of challenge ACKs, see [RFC5961] section 7.
+///
+/// The number of challenge ACKs within each second is randomized around the configured limit.
+/// A fixed global budget would allow an off-path attacker to count the challenge ACKs sent to
+/// other connections and thereby infer their sequence numbers (CVE-2016-5696).
+///
+/// [RFC5961]: https://tools.ietf.org/html/rfc5961#section-7
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ChallengeAcks {
+    /// The average number of challenge ACKs per second.
+    limit: u32,
+    /// The second of the current budget.
+    second: Option<i64>,
+    /// The challenge ACKs still allowed within the current second.
+    remaining: u32,
+}
+
 /// Provides remapping a `SlotKey` under a different four tuple.
 ///
 /// Erases the lifetime from the underlying `Map` itself.
@@ -142,6 +183,14 @@ pub(crate) trait PortMap {
     fn remap(&mut self, old: FourTuple, new: FourTuple);
 }
 
+/// Provides parking a connection in the TIME-WAIT side table.
+///
+/// Erases the lifetime from the underlying `TimeWaitTable` itself.
+pub(crate) trait TimeWaitMap {
+    /// Returns `false` if the table is full.
+    fn park(&mut self, entry: TimeWait, time: Instant) -> bool;
+}
+
 impl Endpoint<'_> {
     /// Returns a reference to the slot containing the corresponding connection.
     ///
@@ -169,6 +218,8 @@ impl Endpoint<'_> {
             key: SlotKey { key: index.key },
             ports: &mut self.ports,
             isn: &mut self.isn_generator,
+            challenge_acks: &mut self.challenge_acks,
+            time_wait: &mut self.time_wait,
             slot,
         })
     }
@@ -210,7 +261,19 @@ impl Endpoint<'_> {
     ///
     /// The source address is chosen during the first send operation on the create connection.
     /// Returns the key to use to inspect or modify the connection state and parameters.
-    fn listen(&mut self, ip: Address, port: u16)
+    pub fn listen(&mut self, ip: Address, port: u16)
+        -> Option<SlotKey>
+    {
+        self.listen_with(ip, port, Congestion::default())
+    }
+
+    /// Opens a new port for listening, with a chosen congestion control.
+    ///
+    /// The accepted connection will use the congestion control algorithm. See [`listen`] for
+    /// details.
+    ///
+    /// [`listen`]: #method.listen
+    pub fn listen_with(&mut self, ip: Address, port: u16, congestion: Congestion)
         -> Option<SlotKey>
     {
         let key = FourTuple {
@@ -223,15 +286,57 @@ impl Endpoint<'_> {
 
         let (key, state) = self.create_state(key)?;
         state.connection.current = State::Listen;
+        state.connection.flow_control = Flow::new(congestion);
         Some(key)
     }
 
+    /// Return a passively opened connection to listening.
+    ///
+    /// The configuration of the endpoint is applied anew while the congestion control algorithm
+    /// and the options set on the slot are kept.
+    fn relisten(&mut self, index: SlotKey) -> bool {
+        let fresh = self.create_connection();
+
+        let slot = match self.states.get_mut(index.key) {
+            Some(slot) => slot,
+            None => return false,
+        };
+
+        let listening = FourTuple {
+            remote: Address::Unspecified,
+            remote_port: 0,
+            .. slot.addr
+        };
+
+        if self.ports.get(&listening).is_some() {
+            return false;
+        }
+
+        let previous = slot.connection;
+        slot.connection = Connection {
+            current: State::Listen,
+            flow_control: Flow::new(previous.flow_control.congestion),
+            nodelay: previous.nodelay,
+            keep_alive: previous.keep_alive,
+            .. fresh
+        };
+
+        self.ports.remap(slot.addr, listening);
+        slot.addr = listening;
+        true
+    }
+
     /// Actively try to connect to a remote TCP.
     ///
-    /// This is not public as the caller controls the complete tuple.
-    fn open(&mut self, tuple: FourTuple)
+    /// This is not public as the caller controls the complete tuple. The tuple may not be reused
+    /// while it is still in TIME-WAIT, just as if it had kept its slot.
+    fn open(&mut self, tuple: FourTuple, time: Instant)
         -> Option<SlotKey>
     {
+        if self.time_wait.lookup(tuple, time).is_some() {
+            return None;
+        }
+
         let (key, _) = self.create_state(tuple)?;
         // Don't set to open yet, only after having sent the packet.
         Some(key)
@@ -272,22 +377,49 @@ impl Endpoint<'_> {
         Connection {
             current: State::Closed,
             previous: State::Closed,
-            flow_control: Flow {
-                congestion_window: 0,
-                ssthresh: u32::max_value(),
-                recover: SeqNumber::default(),
-            },
+            flow_control: Flow::new(Congestion::default()),
             receive_window: 0,
             sender_maximum_segment_size: 0,
+            remote_maximum_segment_size: 0,
             receiver_maximum_segment_size: 0,
             last_ack_receive_offset: SeqNumber::default(),
             ack_timer: Expiration::Never,
-            ack_timeout: Duration::from_millis(500),
+            ack_policy: self.ack_policy,
+            quick_acks: 0,
             retransmission_timer: Instant::from_millis(0),
-            retransmission_timeout: Duration::from_millis(3000),
+            retransmission_timeout: Duration::from_secs(1),
+            round_trip: RoundTrip {
+                smoothed: None,
+                variance: Duration::from_millis(0),
+                timed: None,
+                backoff: 0,
+                min_timeout: Duration::from_secs(1),
+                max_timeout: Duration::from_secs(60),
+            },
+            timestamps: Timestamps {
+                enabled: true,
+                recent: 0,
+                recent_time: Instant::from_millis(0),
+                offset: 0,
+            },
             restart_timeout: Duration::from_millis(30000),
-            selective_acknowledgements: false,
+            selective_acknowledgements: true,
+            scoreboard: Scoreboard::default(),
+            nodelay: false,
+            shutdown: false,
+            persist_timer: Expiration::Never,
+            persist_backoff: 0,
+            keep_alive: None,
+            keep_alive_probes: 0,
+            syn_cookies: self.syn_cookies,
+            fast_open: self.fast_open,
+            fast_open_cookie: None,
+            ecn: ExplicitCongestion {
+                enabled: self.ecn,
+                .. ExplicitCongestion::default()
+            },
             duplicate_ack: 0,
+            retransmit_pending: false,
             send: Send {
                 unacked: SeqNumber::default(),
                 next: SeqNumber::default(),
@@ -295,6 +427,7 @@ impl Endpoint<'_> {
                 unsent: 0,
                 window: 0,
                 window_scale: 0,
+                max_window: 0,
                 initial_seq: SeqNumber::default(),
             },
             recv: Receive {
@@ -303,7 +436,9 @@ impl Endpoint<'_> {
                 last_time: Instant::from_millis(0),
                 window: 0,
                 window_scale: 0,
+                max_window: 0,
                 initial_seq: SeqNumber::default(),
+                selective: [None; 3],
             },
         }
     }
@@ -323,12 +458,30 @@ impl Slot {
     }
 
     /// Returns a reference to the connection contained in the slot.
-    pub(crate) fn connection(&self) -> &Connection {
+    ///
+    /// Can be used to monitor the connection state, such as the round-trip time estimation.
+    pub fn connection(&self) -> &Connection {
         &self.connection
     }
+
+    /// Disable or re-enable Nagle's algorithm on the connection.
+    ///
+    /// With `nodelay` small segments are sent immediately, even when data is still in flight.
+    pub fn set_nodelay(&mut self, nodelay: bool) {
+        self.connection.nodelay = nodelay;
+    }
+
+    /// Enable or disable keep-alive probes on the connection.
+    pub fn set_keep_alive(&mut self, keep_alive: Option<KeepAlive>) {
+        self.connection.keep_alive = keep_alive;
+        self.connection.keep_alive_probes = 0;
+    }
 }
 
 impl<'ep> Endpoint<'ep> {
+    /// The dynamic port range assigned by IANA, RFC6335.
+    pub const DEFAULT_EPHEMERAL_PORTS: RangeInclusive<u16> = 49152..=65535;
+
     /// Create a new endpoint.
     ///
     /// The map and states are **not** cleared.
@@ -341,9 +494,136 @@ impl<'ep> Endpoint<'ep> {
             ports,
             states,
             isn_generator,
+            ack_policy: AckPolicy::default(),
+            syn_cookies: false,
+            fast_open: false,
+            fast_open_cookies: None,
+            ecn: false,
+            challenge_acks: ChallengeAcks::new(ChallengeAcks::DEFAULT_LIMIT),
+            time_wait: TimeWaitTable::new(Slice::empty()),
+            ephemeral_ports: Self::DEFAULT_EPHEMERAL_PORTS,
+            next_ephemeral: 0,
         }
     }
 
+    /// Get the policy for delaying acknowledgments of new connections.
+    pub fn ack_policy(&self) -> AckPolicy {
+        self.ack_policy
+    }
+
+    /// Change how new connections delay their acknowledgments.
+    ///
+    /// Already existing connections are not affected, modify their state directly instead. Use
+    /// [`AckPolicy::quick`] to acknowledge every segment immediately.
+    ///
+    /// [`AckPolicy::quick`]: struct.AckPolicy.html#method.quick
+    pub fn set_ack_policy(&mut self, policy: AckPolicy) {
+        self.ack_policy = policy;
+    }
+
+    /// Check if new listening connections answer with SYN cookies.
+    pub fn syn_cookies(&self) -> bool {
+        self.syn_cookies
+    }
+
+    /// Enable or disable SYN cookies for new listening connections.
+    ///
+    /// With SYN cookies a listening connection answers connection attempts without reserving its
+    /// slot. The parameters of the handshake are encoded in the initial sequence number instead
+    /// and the connection is only established for an authentic final ACK. This prevents a flood of
+    /// SYNs from occupying the listening connection. The maximum segment size of the remote is
+    /// rounded down to a few common values and lost SYN-ACKs are not retransmitted.
+    pub fn set_syn_cookies(&mut self, enabled: bool) {
+        self.syn_cookies = enabled;
+    }
+
+    /// Check if new listening connections accept fast open.
+    pub fn fast_open(&self) -> bool {
+        self.fast_open
+    }
+
+    /// Enable or disable fast open (RFC7413) for new listening connections.
+    ///
+    /// Remotes that request it are provided with a cookie, derived from their address with the
+    /// keyed generator of initial sequence numbers. Data in a SYN is delivered to the receive
+    /// buffer when it carries a valid cookie. Such data may be duplicated by the network, only
+    /// enable this for idempotent requests.
+    pub fn set_fast_open(&mut self, enabled: bool) {
+        self.fast_open = enabled;
+    }
+
+    /// Use a cache of fast open cookies for active opens.
+    ///
+    /// New connections request a cookie from remotes without a cached one. With a cookie the data
+    /// of the first write is sent in the SYN, up to the default segment size. Cookies provided by
+    /// a remote are not cached when the map is full.
+    pub fn set_fast_open_cache(&mut self, cookies: Map<'ep, Address, FastOpenCookie>) {
+        self.fast_open_cookies = Some(cookies);
+    }
+
+    /// Check if new connections offer or accept explicit congestion notification.
+    pub fn ecn(&self) -> bool {
+        self.ecn
+    }
+
+    /// Enable or disable explicit congestion notification (RFC3168) for new connections.
+    ///
+    /// It is only used when both sides agree during the handshake. Then data segments are sent as
+    /// ECN-capable and a congestion mark reported by the remote reduces the congestion window
+    /// without waiting for a loss.
+    pub fn set_ecn(&mut self, enabled: bool) {
+        self.ecn = enabled;
+    }
+
+    /// Get the average number of challenge ACKs sent per second.
+    pub fn challenge_ack_limit(&self) -> u32 {
+        self.challenge_acks.limit
+    }
+
+    /// Change the global limit of challenge ACKs (RFC5961).
+    ///
+    /// Connections answer a RST or SYN that is within the window but not exactly expected, and
+    /// segments acknowledging data far outside the send window, with an ACK instead of accepting
+    /// them. A legitimate remote then resends a valid RST while a blind attacker can not. The
+    /// number of such ACKs across all connections is randomly chosen each second between half and
+    /// one and a half times this limit. A limit of `0` disables challenge ACKs.
+    pub fn set_challenge_ack_limit(&mut self, per_second: u32) {
+        self.challenge_acks = ChallengeAcks::new(per_second);
+    }
+
+    /// Use a side table for connections in TIME-WAIT.
+    ///
+    /// A connection that finished its active close is moved into the table and its slot is freed
+    /// right away. Without a table, or when it is full, the connection occupies its slot until the
+    /// end of the TIME-WAIT period.
+    pub fn set_time_wait_table(&mut self, table: TimeWaitTable<'ep>) {
+        self.time_wait = table;
+    }
+
+    /// Get the side table of connections in TIME-WAIT.
+    pub fn time_wait_table(&self) -> &TimeWaitTable<'ep> {
+        &self.time_wait
+    }
+
+    /// Get the range of local ports chosen for active opens.
+    pub fn ephemeral_ports(&self) -> RangeInclusive<u16> {
+        self.ephemeral_ports.clone()
+    }
+
+    /// Change the range of local ports chosen for active opens without an explicit port.
+    ///
+    /// Ports are selected in a pseudo-random order that differs for each remote endpoint, as in
+    /// algorithm 3 of RFC6056. The default is the IANA dynamic range `49152..=65535`. A larger
+    /// range makes it less likely that all ports towards a remote are in use and harder for an
+    /// attacker to guess the port of a connection.
+    ///
+    /// ## Panics
+    /// This method panics if the range is empty or contains port `0`.
+    pub fn set_ephemeral_ports(&mut self, ports: RangeInclusive<u16>) {
+        assert!(*ports.start() > 0 && ports.start() <= ports.end(), "Invalid port range");
+        self.ephemeral_ports = ports;
+    }
+
     /// Create a TCP receiver using this endpoint.
     pub fn recv<H>(&mut self, handler: H) -> Receiver<'_, 'ep, H> {
         Receiver { endpoint: self.borrow(), handler }
@@ -365,6 +645,8 @@ impl<'a> Entry<'a> {
         let entry_key = EntryKey {
             ports: self.ports,
             isn: self.isn,
+            challenge_acks: self.challenge_acks,
+            time_wait: self.time_wait,
             key_in_slot: &mut self.slot.addr,
         };
 
@@ -394,6 +676,57 @@ impl EntryKey<'_> {
         *self.key_in_slot
     }
 
+    /// Generate an offset for the timestamp clock of the connection.
+    ///
+    /// RFC7323 recommends a random offset per connection so that the timestamps do not reveal the
+    /// system clock. This uses the keyed hash of the reversed tuple which is independent from the
+    /// initial sequence number.
+    pub fn timestamp_offset(&self) -> u32 {
+        self.timestamp_offset_for(*self.key_in_slot)
+    }
+
+    /// Generate the timestamp clock offset of a connection with another tuple.
+    pub fn timestamp_offset_for(&self, tuple: FourTuple) -> u32 {
+        let reversed = FourTuple {
+            local: tuple.remote,
+            remote: tuple.local,
+            local_port: tuple.remote_port,
+            remote_port: tuple.local_port,
+        };
+        self.isn.get_isn(reversed, Instant::from_millis(0)).0 as u32
+    }
+
+    /// Generate the initial sequence number for a passive open with a SYN cookie.
+    pub(crate) fn syn_cookie(&self, tuple: FourTuple, remote_isn: SeqNumber, cookie: SynCookie, time: Instant)
+        -> SeqNumber
+    {
+        self.isn.syn_cookie(tuple, remote_isn, cookie, time)
+    }
+
+    /// Generate the fast open cookie for the remote of a connection tuple.
+    pub(crate) fn fast_open_cookie(&self, tuple: FourTuple) -> FastOpenCookie {
+        self.isn.fast_open_cookie(tuple.local, tuple.remote)
+    }
+
+    /// Validate a SYN cookie and recover its connection parameters.
+    pub(crate) fn check_syn_cookie(&self, tuple: FourTuple, remote_isn: SeqNumber, isn: SeqNumber, time: Instant)
+        -> Option<SynCookie>
+    {
+        self.isn.check_syn_cookie(tuple, remote_isn, isn, time)
+    }
+
+    /// Check if a challenge ACK may be sent now, consuming one from the global limit.
+    pub(crate) fn challenge_ack(&mut self, time: Instant) -> bool {
+        self.challenge_acks.consume(self.isn, time)
+    }
+
+    /// Move the connection into the TIME-WAIT side table.
+    ///
+    /// Returns `false` if this is not possible and the connection must keep its slot.
+    pub(crate) fn park_time_wait(&mut self, entry: TimeWait, time: Instant) -> bool {
+        self.time_wait.park(entry, time)
+    }
+
     /// Move the connection state to a new connection tuple.
     ///
     /// # Panics
@@ -409,9 +742,47 @@ impl<'a> EntryKey<'a> {
     pub(crate) fn fake(
         ports: &'a mut dyn PortMap,
         isn: &'a IsnGenerator,
+        challenge_acks: &'a mut ChallengeAcks,
+        time_wait: &'a mut dyn TimeWaitMap,
         key_in_slot: &'a mut FourTuple,
     ) -> EntryKey<'a> {
-        EntryKey { ports, isn, key_in_slot, }
+        EntryKey { ports, isn, challenge_acks, time_wait, key_in_slot, }
+    }
+}
+
+impl ChallengeAcks {
+    /// The default average limit per second.
+    pub(crate) const DEFAULT_LIMIT: u32 = 1000;
+
+    pub(crate) fn new(limit: u32) -> Self {
+        ChallengeAcks {
+            limit,
+            second: None,
+            remaining: 0,
+        }
+    }
+
+    fn consume(&mut self, isn: &IsnGenerator, time: Instant) -> bool {
+        if self.limit == 0 {
+            return false;
+        }
+
+        let second = time.secs();
+        if self.second != Some(second) {
+            let jitter = isn.random(second as u64) % u64::from(self.limit);
+            // Never overflows, at most 1.5 times a u32.
+            let budget = u64::from(self.limit / 2) + jitter;
+            self.second = Some(second);
+            self.remaining = u32::try_from(budget).unwrap_or(u32::MAX).max(1);
+        }
+
+        match self.remaining.checked_sub(1) {
+            Some(remaining) => {
+                self.remaining = remaining;
+                true
+            },
+            None => false,
+        }
     }
 }
 
@@ -454,22 +825,85 @@ impl super::connection::Endpoint for Endpoint<'_> {
         }
     }
 
-    fn source_port(&mut self, _: Address) -> Option<u16> {
-        // FIXME: find a suitable source port....
-        Some(80)
+    fn source_port(&mut self, tuple: FourTuple, time: Instant) -> Option<u16> {
+        let first = *self.ephemeral_ports.start();
+        let count = u32::from(*self.ephemeral_ports.end() - first) + 1;
+        let offset = self.isn_generator.port_offset(tuple);
+
+        for _ in 0..count {
+            let index = offset.wrapping_add(self.next_ephemeral.into()) % u64::from(count);
+            self.next_ephemeral = self.next_ephemeral.wrapping_add(1);
+            // Can't overflow, the index is smaller than the size of the range.
+            let local_port = first + index as u16;
+
+            let candidate = FourTuple { local_port, ..tuple };
+            let listening = FourTuple {
+                remote: Address::Unspecified,
+                remote_port: 0,
+                ..candidate
+            };
+
+            let in_use = self.ports.get(&candidate).is_some()
+                || self.ports.get(&listening).is_some()
+                || self.time_wait.lookup(candidate, time).is_some();
+
+            if !in_use {
+                return Some(local_port);
+            }
+        }
+
+        None
     }
 
     fn listen(&mut self, ip: Address, port: u16) -> Option<SlotKey> {
         Endpoint::listen(self, ip, port)
     }
 
-    fn open(&mut self, tuple: FourTuple) -> Option<SlotKey> {
-        Endpoint::open(self, tuple)
+    fn relisten(&mut self, index: SlotKey) -> bool {
+        Endpoint::relisten(self, index)
+    }
+
+    fn open(&mut self, tuple: FourTuple, time: Instant) -> Option<SlotKey> {
+        Endpoint::open(self, tuple, time)
     }
 
     fn initial_seq_num(&mut self, id: FourTuple, time: Instant) -> SeqNumber {
         Endpoint::initial_seq_num(self, id, time)
     }
+
+    fn fast_open_cookie(&self, remote: Address) -> Option<FastOpenCookie> {
+        let cookies = self.fast_open_cookies.as_ref()?;
+        Some(cookies.get(&remote).copied().unwrap_or_else(FastOpenCookie::request))
+    }
+
+    fn arrives_time_wait(&mut self, tuple: FourTuple, segment: &tcp::Repr, time: Instant) -> Arrival {
+        // An open connection for the tuple takes precedence.
+        if self.ports.get(&tuple).is_some() {
+            return Arrival::Deliver;
+        }
+
+        self.time_wait.arrives(tuple, segment, time)
+    }
+
+    fn cache_fast_open_cookie(&mut self, remote: Address, cookie: FastOpenCookie) {
+        let cookies = match self.fast_open_cookies.as_mut() {
+            Some(cookies) => cookies,
+            None => return,
+        };
+
+        match cookies.entry(remote).occupied() {
+            Some(mut entry) => *entry.get_mut() = cookie,
+            None => if let Some(entry) = cookies.entry(remote).vacant() {
+                entry.insert(cookie);
+            },
+        }
+    }
+}
+
+impl TimeWaitMap for TimeWaitTable<'_> {
+    fn park(&mut self, entry: TimeWait, time: Instant) -> bool {
+        self.insert(entry, time)
+    }
 }
 
 impl PortMap for Map<'_, FourTuple, Key> {
@@ -6,7 +6,9 @@ use core::borrow::{Borrow, BorrowMut};
 use core::convert::TryFrom;
 
 use crate::alloc::vec::Vec;
+use crate::managed::Slice;
 use crate::wire::tcp::SeqNumber;
+use crate::storage::RingBuffer;
 use crate::storage::assembler::{Assembler, Contig};
 
 use super::{AvailableBytes, ReceivedSegment, RecvBuf, SendBuf};
@@ -56,6 +58,58 @@ pub struct RecvInto<B> {
     mark: usize,
     /// Assembler since we can easily buffer.
     asm: Assembler<[Contig; 4]>,
+    /// The sequence number of the most recent segment that arrived out of order.
+    recent: Option<SeqNumber>,
+}
+
+/// A sender streaming from a fixed ring of bytes.
+///
+/// New data is appended at the end of the ring while acknowledged data is removed from its front,
+/// freeing space for more data. A connection can thus send an unbounded stream with constant
+/// memory.
+///
+/// ```
+/// # use ethox::layer::tcp::io::RingSend;
+/// let mut storage = [0; 1024];
+/// let mut sender = RingSend::new(&mut storage[..]);
+/// assert_eq!(sender.write(b"Hello, world!"), 13);
+/// assert_eq!(sender.window(), 1024 - 13);
+/// ```
+pub struct RingSend<'a> {
+    /// Unacknowledged and unsent data, in sequence order.
+    ring: RingBuffer<'a, u8>,
+    /// Indicate that all data has been put into the buffer.
+    fin: bool,
+    /// The tcp sequence number corresponding to the front of the ring.
+    at: Option<SeqNumber>,
+}
+
+/// A receiver reassembling into a fixed ring of bytes.
+///
+/// Completely received data is at the front of the ring and can be read in order, which makes room
+/// for new data. Segments that arrived out of order are placed into the free space of the ring and
+/// tracked by an `Assembler` until the missing data arrives.
+///
+/// ```
+/// # use ethox::layer::tcp::io::RingRecv;
+/// let mut storage = [0; 1024];
+/// let mut receiver = RingRecv::new(&mut storage[..]);
+/// let mut buffer = [0; 16];
+/// assert_eq!(receiver.read(&mut buffer), 0);
+/// ```
+pub struct RingRecv<'a> {
+    /// Completed data in the allocated part, out of order data in the unallocated part.
+    ring: RingBuffer<'a, u8>,
+    /// The highest fully complete sequence number.
+    complete: Option<SeqNumber>,
+    /// Assembler for data following `complete`.
+    asm: Assembler<[Contig; 4]>,
+    /// The sequence number of the most recent segment that arrived out of order.
+    recent: Option<SeqNumber>,
+    /// The sequence number of the FIN, if it has arrived.
+    fin: Option<SeqNumber>,
+    /// If the FIN was reached by the completed data.
+    finished: bool,
 }
 
 impl<Buffer: Borrow<[u8]>> SendFrom<Buffer> {
@@ -250,6 +304,7 @@ impl<Buffer: BorrowMut<[u8]>> RecvInto<Buffer> {
             complete: None,
             mark: 0,
             asm: Assembler::new([Contig::default(); 4]),
+            recent: None,
         }
     }
 
@@ -325,6 +380,107 @@ impl RecvInto<Vec<u8>> {
     }
 }
 
+impl<'a> RingSend<'a> {
+    /// Create a sender with an empty ring.
+    pub fn new<S>(storage: S) -> Self
+        where S: Into<Slice<'a, u8>>,
+    {
+        RingSend {
+            ring: RingBuffer::new(storage),
+            fin: false,
+            at: None,
+        }
+    }
+
+    /// Append data to the stream.
+    ///
+    /// Returns the number of bytes that fit into the free space of the ring. Nothing is written
+    /// after [`fin`] has been called since the stream has already been closed.
+    ///
+    /// [`fin`]: #method.fin
+    pub fn write(&mut self, data: &[u8]) -> usize {
+        if self.fin {
+            return 0;
+        }
+
+        self.ring.enqueue_slice(data)
+    }
+
+    /// Indicate that no more data will be added.
+    ///
+    /// Any following [`write`] is refused.
+    ///
+    /// [`write`]: #method.write
+    pub fn fin(&mut self) {
+        self.fin = true;
+    }
+
+    /// The number of bytes that can be written.
+    pub fn window(&self) -> usize {
+        self.ring.window()
+    }
+
+    /// The number of bytes not yet acknowledged by the other TCP.
+    ///
+    /// This includes data that has not been sent at all.
+    pub fn buffered(&self) -> usize {
+        self.ring.len()
+    }
+
+    /// Get a reference to the underlying ring.
+    pub fn get_ref(&self) -> &RingBuffer<'a, u8> {
+        &self.ring
+    }
+}
+
+impl<'a> RingRecv<'a> {
+    /// Create a receiver with an empty ring.
+    pub fn new<S>(storage: S) -> Self
+        where S: Into<Slice<'a, u8>>,
+    {
+        RingRecv {
+            ring: RingBuffer::new(storage),
+            complete: None,
+            asm: Assembler::new([Contig::default(); 4]),
+            recent: None,
+            fin: None,
+            finished: false,
+        }
+    }
+
+    /// Read completely received data, making room for new data.
+    ///
+    /// Returns the number of bytes read.
+    pub fn read(&mut self, buffer: &mut [u8]) -> usize {
+        self.ring.dequeue_slice(buffer)
+    }
+
+    /// The number of bytes that can be read.
+    pub fn readable(&self) -> usize {
+        self.ring.len()
+    }
+
+    /// Check if the remote closed the stream and all data before its FIN has been received.
+    ///
+    /// The stream ended once this is set and there is nothing left to read.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Get a reference to the underlying ring.
+    pub fn get_ref(&self) -> &RingBuffer<'a, u8> {
+        &self.ring
+    }
+
+    /// Get a mutable reference to the underlying ring.
+    ///
+    /// Use this to dequeue received data without copying. Enqueueing data into the ring is not
+    /// correct as it would be mixed with the received stream.
+    pub fn get_mut(&mut self) -> &mut RingBuffer<'a, u8> {
+        &mut self.ring
+    }
+}
+
 impl SendBuf for Empty {
     fn available(&self) -> AvailableBytes {
         AvailableBytes {
@@ -385,6 +541,84 @@ impl<B: Borrow<[u8]>> SendBuf for SendFrom<B> {
     }
 }
 
+impl SendBuf for RingSend<'_> {
+    fn available(&self) -> AvailableBytes {
+        AvailableBytes {
+            total: self.ring.len(),
+            fin: self.fin,
+        }
+    }
+
+    fn fill(&mut self, buf: &mut [u8], begin: SeqNumber) {
+        let front = self.at.expect("Fill must not be called before isn indication");
+        let read = self.ring.read_allocated(begin - front, buf);
+        assert_eq!(read, buf.len(), "Fill must only request buffered data");
+    }
+
+    fn ack(&mut self, ack: SeqNumber) {
+        let previous = *self.at.get_or_insert(ack);
+        self.ring.dequeue_allocated(ack - previous);
+        self.at = Some(ack);
+    }
+}
+
+impl RecvBuf for RingRecv<'_> {
+    fn receive(&mut self, data: &[u8], segment: ReceivedSegment) {
+        let complete = *self.complete.get_or_insert(segment.data_begin());
+        let begin = segment.data_begin();
+
+        // Cut away anything we have already completed.
+        let (relative, data) = if begin > complete {
+            (begin - complete, data)
+        } else {
+            (0, data.get(complete - begin..).unwrap_or(&[]))
+        };
+
+        let window = self.ring.window();
+        let length = window.saturating_sub(relative).min(data.len());
+        if length == data.len() && segment.fin {
+            self.fin = Some(complete + relative + length);
+        }
+
+        // UNWRAP: Incoming data is bounded by tcp sizes, and the offset by our window.
+        let relative_u32 = u32::try_from(relative).unwrap();
+        let length_u32 = u32::try_from(length).unwrap();
+
+        // Try to add it to the reassembly buffer.
+        let new_data = match self.asm.add(relative_u32, length_u32) {
+            Err(_) => return,
+            // `new` bounded by the window which is a valid `usize`.
+            Ok(new) => new as usize,
+        };
+
+        self.ring.write_unallocated(relative, &data[..length]);
+        self.ring.enqueue_unallocated(new_data);
+
+        let complete = complete + new_data;
+        self.complete = Some(complete);
+        if self.fin == Some(complete) && !self.finished {
+            self.finished = true;
+            self.complete = Some(complete + 1);
+        }
+
+        if relative > 0 && length > 0 {
+            self.recent = Some(begin);
+        }
+    }
+
+    fn ack(&mut self) -> SeqNumber {
+        self.complete.expect("Must not be called before any isn indication")
+    }
+
+    fn window(&self) -> usize {
+        self.ring.window()
+    }
+
+    fn selective_acks(&self) -> [Option<(SeqNumber, SeqNumber)>; 3] {
+        selective_acks(&self.asm, self.complete, self.recent)
+    }
+}
+
 impl<B: BorrowMut<[u8]>> RecvBuf for RecvInto<B> {
     fn receive(&mut self, mut data: &[u8], segment: ReceivedSegment) {
         let begin = self.complete.get_or_insert(segment.begin);
@@ -420,6 +654,10 @@ impl<B: BorrowMut<[u8]>> RecvBuf for RecvInto<B> {
         *begin += usize::from(segment.syn);
         *begin += new_data;
         *begin += usize::from(new_data == segment.data_len && segment.fin);
+
+        if relative > 0 && length > 0 {
+            self.recent = Some(segment.begin);
+        }
     }
 
     fn ack(&mut self) -> SeqNumber {
@@ -429,4 +667,147 @@ impl<B: BorrowMut<[u8]>> RecvBuf for RecvInto<B> {
     fn window(&self) -> usize {
         self.buffer.borrow()[self.mark..].len()
     }
+
+    fn selective_acks(&self) -> [Option<(SeqNumber, SeqNumber)>; 3] {
+        selective_acks(&self.asm, self.complete, self.recent)
+    }
+}
+
+/// Report the ranges of an assembler following the completed sequence number as SACK blocks.
+fn selective_acks(
+    asm: &Assembler<[Contig; 4]>,
+    complete: Option<SeqNumber>,
+    recent: Option<SeqNumber>,
+) -> [Option<(SeqNumber, SeqNumber)>; 3] {
+    let mut blocks = [None; 3];
+    let complete = match complete {
+        Some(complete) => complete,
+        None => return blocks,
+    };
+
+    // All contiguous data at the front has already been completed, every range is a block.
+    let ranges = || asm.iter()
+        .map(|(left, right)| (complete + left as usize, complete + right as usize));
+
+    // RFC2018: the first block must contain the most recently received segment.
+    let first = recent.and_then(|recent| ranges()
+        .find(|&(left, right)| left <= recent && recent < right));
+
+    let others = ranges().filter(|&block| Some(block) != first);
+    first.into_iter()
+        .chain(others)
+        .zip(blocks.iter_mut())
+        .for_each(|(block, slot)| *slot = Some(block));
+
+    blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::time::Instant;
+    use crate::wire::tcp::SeqNumber;
+    use super::{ReceivedSegment, RecvBuf, RecvInto, RingRecv, RingSend, SendBuf};
+
+    fn segment(begin: i32, data_len: usize) -> ReceivedSegment {
+        ReceivedSegment {
+            syn: false,
+            fin: false,
+            data_len,
+            begin: SeqNumber(begin),
+            timestamp: Instant::from_millis(0),
+        }
+    }
+
+    #[test]
+    fn selective_acks() {
+        let mut recv = RecvInto::new([0; 64]);
+        assert_eq!(recv.selective_acks(), [None; 3]);
+
+        recv.receive(&[0; 10], segment(100, 10));
+        assert_eq!(recv.ack(), SeqNumber(110));
+        assert_eq!(recv.selective_acks(), [None; 3]);
+
+        recv.receive(&[0; 10], segment(120, 10));
+        recv.receive(&[0; 5], segment(140, 5));
+        // The most recent segment is reported first.
+        assert_eq!(recv.selective_acks(), [
+            Some((SeqNumber(140), SeqNumber(145))),
+            Some((SeqNumber(120), SeqNumber(130))),
+            None,
+        ]);
+
+        recv.receive(&[0; 5], segment(130, 5));
+        assert_eq!(recv.selective_acks(), [
+            Some((SeqNumber(120), SeqNumber(135))),
+            Some((SeqNumber(140), SeqNumber(145))),
+            None,
+        ]);
+
+        recv.receive(&[0; 10], segment(110, 10));
+        assert_eq!(recv.ack(), SeqNumber(135));
+        assert_eq!(recv.selective_acks(), [
+            Some((SeqNumber(140), SeqNumber(145))),
+            None,
+            None,
+        ]);
+    }
+
+    #[test]
+    fn ring_send() {
+        let mut storage = [0; 8];
+        let mut send = RingSend::new(&mut storage[..]);
+        send.ack(SeqNumber(100));
+        assert_eq!(send.write(b"abcdef"), 6);
+        assert_eq!(send.available().total, 6);
+
+        let mut buf = [0; 4];
+        send.fill(&mut buf, SeqNumber(102));
+        assert_eq!(&buf, b"cdef");
+
+        // Acknowledged data makes room for more, which wraps around.
+        send.ack(SeqNumber(105));
+        assert_eq!(send.buffered(), 1);
+        assert_eq!(send.write(b"ghijklmn"), 7);
+        assert_eq!(send.window(), 0);
+
+        let mut buf = [0; 8];
+        send.fill(&mut buf, SeqNumber(105));
+        assert_eq!(&buf, b"fghijklm");
+
+        // No more data is accepted once the stream was closed.
+        send.ack(SeqNumber(110));
+        send.fin();
+        assert_eq!(send.write(b"op"), 0);
+        assert_eq!(send.buffered(), 3);
+    }
+
+    #[test]
+    fn ring_recv() {
+        let mut storage = [0; 8];
+        let mut recv = RingRecv::new(&mut storage[..]);
+
+        recv.receive(b"abcdef", segment(100, 6));
+        assert_eq!(recv.ack(), SeqNumber(106));
+        assert_eq!(recv.window(), 2);
+
+        let mut buf = [0; 4];
+        assert_eq!(recv.read(&mut buf), 4);
+        assert_eq!(&buf, b"abcd");
+
+        // Out of order data is kept in the free space, wrapping around the ring.
+        let mut fin = segment(109, 3);
+        fin.fin = true;
+        recv.receive(b"jkl", fin);
+        assert_eq!(recv.ack(), SeqNumber(106));
+        assert_eq!(recv.selective_acks()[0], Some((SeqNumber(109), SeqNumber(112))));
+
+        recv.receive(b"ghi", segment(106, 3));
+        assert_eq!(recv.ack(), SeqNumber(113));
+        assert!(recv.is_finished());
+        assert_eq!(recv.readable(), 8);
+
+        let mut buf = [0; 8];
+        assert_eq!(recv.read(&mut buf), 8);
+        assert_eq!(&buf, b"efghijkl");
+    }
 }
@@ -49,10 +49,11 @@
 //! [`Raw`]: struct.RawPacket.html
 //! [`Endpoint`]: struct.Endpoint.html
 //!
-//! The stack does not *currently* allow sending any data in a SYN packet as these are rarely
-//! accepted. They are incompatible with SYN-cookies and otherwise a security and stability risk.
-//! As such, they would be mostly useless. However, unlike many other implementations, the default
-//! client will already begin sending data in the same packet that acknowledges the reverse SYN.
+//! By default no data is sent in a SYN packet as these are rarely accepted. Unlike many other
+//! implementations, the default client will already begin sending data in the same packet that
+//! acknowledges the reverse SYN. Where the round-trip of the handshake matters, fast open
+//! (RFC7413) may be enabled on the [`Endpoint`] with a cache of cookies. The first write of a
+//! connection to a remote with a cached cookie then sends the data in the SYN itself.
 //!
 //! ## Accepting connections
 //!
@@ -63,7 +64,26 @@
 //! connection attempt. The handler of the then produced [`Open`] packet needs to create more
 //! reserved connection states.
 //!
+//! The [`Server`] does exactly this. It keeps a bounded backlog of preallocated buffers and
+//! re-arms a listening state in each send phase for as long as an entry of the backlog is idle.
+//!
+//! Under a flood of connection attempts the listening state would be occupied by half-open
+//! connections. Enable SYN cookies on the [`Endpoint`] to only establish a connection once the
+//! remote has completed the handshake.
+//!
+//! Listening connections accept data in a SYN only when fast open is enabled and the SYN carries
+//! a valid cookie. The data is then delivered in an [`Open`] packet before the SYN-ACK is sent.
+//!
 //! [`Open`]: struct.Open.html
+//! [`Server`]: struct.Server.html
+//!
+//! ## Streams
+//!
+//! With the `std` feature, the [`stream`] module provides connections implementing `Read` and
+//! `Write` on ring buffers. Its `Stack` owns the device and the endpoints and pumps packets until
+//! an operation can complete, either blocking or returning `WouldBlock`.
+//!
+//! [`stream`]: stream/index.html
 //!
 //! ## Deviations
 //!
@@ -78,9 +98,14 @@
 //! document did not ever reset `dACK`, the duplicate ack counter. We reset it whenever an ACK is
 //! not a duplicate ack. Kind of obvious.
 //!
-//! The congestion control is (TBD). Currently likely NewReno but Westwood+ might be an option
-//! since the target environment is high-throughput networks (currently). If your environment is
-//! different, please provide a pull request containing an implementation.
+//! Loss recovery is NewReno, that is fast retransmit from RFC5681 with the fast recovery
+//! modification of RFC6582. The growth of the congestion window is chosen per connection from the
+//! algorithms in [`congestion`], currently Reno and CUBIC. The number of bytes in flight is bounded
+//! by the minimum of the congestion window and the receiver's advertised window. If your
+//! environment requires another algorithm, please provide a pull request containing an
+//! implementation.
+//!
+//! [`congestion`]: congestion/index.html
 //!
 //! An incoming packet in Closed state is simply dropped if it had RST set.  Packets with RST
 //! should *never* be answered with a packet with RST but the only specified answers would set that
@@ -93,22 +118,36 @@
 //! A listening socket is designed to accept all connection request. That is not necessarily true
 //! and we want to be indistinguishable from a closed socket else.
 //!
-//! Data sent in SYN packet is ignored for now but that may change in the future. If the connection
+//! Data sent in a SYN packet is ignored unless accepted with a fast open cookie. If the connection
 //! was initiated actively then there is virtually no difference for the handling of contained
 //! segment data within the library.
 use crate::wire::PayloadMut;
 
+pub mod congestion;
 mod connection;
 mod endpoint;
 pub mod io;
 mod packet;
 mod socket;
+#[cfg(feature = "std")]
+pub mod stream;
 
 mod siphash;
+#[cfg(test)]
+mod tests;
+mod time_wait;
 
 pub use connection::{
+    AckPolicy,
     AvailableBytes,
-    ReceivedSegment};
+    Connection,
+    ExplicitCongestion,
+    Flow,
+    KeepAlive,
+    ReceivedSegment,
+    RoundTrip,
+    Scoreboard,
+    State};
 
 pub use endpoint::{
     FourTuple,
@@ -127,7 +166,12 @@ pub use packet::{
     UserSignals};
 
 pub use socket::{
-    Client};
+    Client,
+    Server};
+
+pub use time_wait::{
+    Table as TimeWaitTable,
+    TimeWait};
 
 // publically exposed for initialization.
 pub use siphash::IsnGenerator;
@@ -3,11 +3,14 @@
 //! The interface differs from other layers in that the `In` packet has many different variants it
 //! represents, depending on the state of the underlying connection.
 use crate::layer;
+use crate::time::Expiration;
 use crate::wire::{Payload, PayloadMut};
 use crate::wire::{ip, tcp};
 
-use super::connection::{AvailableBytes, Endpoint, InPacket, Operator, OutSignals, ReceivedSegment, Segment, Signals};
-use super::endpoint::{FourTuple, SlotKey};
+use super::congestion::Congestion;
+use super::connection::{AvailableBytes, Endpoint, Flow, InPacket, Operator, OutSignals, ReceivedSegment, Segment, Signals, State};
+use super::endpoint::{FourTuple, Slot, SlotKey};
+use super::time_wait::Arrival;
 
 /// An incoming tcp packet.
 ///
@@ -36,6 +39,11 @@ pub enum In<'a, P: PayloadMut> {
     /// want to retro-actively open a new, due some funny port-knocking business or w/e. Your hacks
     /// stay your own, and keep the bugs you find along the way.
     Stray(Stray<'a, P>),
+
+    /// A packet for a connection that only lingers in the TIME-WAIT side table.
+    ///
+    /// It has already been answered or dropped, as appropriate.
+    Lingering(Lingering<'a>),
 }
 
 /// A user defined (re-)transmission buffer.
@@ -76,6 +84,16 @@ pub trait RecvBuf {
     /// Shrinking the window size without having accepted new data is allowed but strongly
     /// discouraged.
     fn window(&self) -> usize;
+
+    /// Report received data beyond the highest completed sequence number.
+    ///
+    /// These blocks are selectively acknowledged (SACK, RFC2018) so that the remote only needs to
+    /// retransmit the missing segments. Each block is the sequence number of its first byte and
+    /// the one following its last byte. The first block should contain the most recently received
+    /// segment. The default implementation does not report any block.
+    fn selective_acks(&self) -> [Option<(tcp::SeqNumber, tcp::SeqNumber)>; 3] {
+        [None; 3]
+    }
 }
 
 /// Informational signals to the user.
@@ -91,8 +109,10 @@ pub struct UserSignals {
     /// The tcp data stream was closed by the remote end.
     ///
     /// The actual connection may still be half-open until our side closes the connection as well.
+    /// A FIN carried by a segment with data is only accepted once it was read, see
+    /// [`Open::read`], which updates this flag for the packet.
     ///
-    /// WIP: this is not implemented yet and always `false`.
+    /// [`Open::read`]: struct.Open.html#method.read
     pub half_closed: bool,
 
     /// There is new data to be read.
@@ -100,7 +120,8 @@ pub struct UserSignals {
 
     /// A listening socket returned to its listen state.
     ///
-    /// WIP: this is not implemented yet and always `false`.
+    /// The connection attempt it had accepted was reset by the remote before it was established.
+    /// The connection key remains valid and refers to the listening connection again.
     pub relisten: bool,
 }
 
@@ -141,6 +162,12 @@ pub struct Closing<'a> {
     signals: UserSignals,
 }
 
+/// A packet handled by the TIME-WAIT side table.
+pub struct Lingering<'a> {
+    #[allow(dead_code)] // This attribute exists for parity with other message structs.
+    endpoint: &'a mut dyn Endpoint,
+}
+
 /// A connection was closed by a remote packet.
 ///
 /// Similar to a `Stray` packet but we retain which connection was closed.
@@ -199,17 +226,8 @@ impl<'a, P: PayloadMut> Unhandled<'a, P> {
     fn try_open(
         endpoint: &'a mut dyn Endpoint,
         tcp: tcp::Packet<layer::ip::IpPacket<'a, P>>,
+        connection: FourTuple,
     ) -> Self {
-        let tcp_repr = tcp.repr();
-        let ip_repr = tcp.inner().repr();
-
-        let connection = FourTuple {
-            local: ip_repr.dst_addr(),
-            local_port: tcp_repr.dst_port,
-            remote: ip_repr.src_addr(),
-            remote_port: tcp_repr.src_port,
-        };
-
         match Operator::from_tuple(endpoint, connection) {
             Ok(operator) => Unhandled::Open {
                 operator,
@@ -230,7 +248,27 @@ impl<'a, P: PayloadMut> In<'a, P> {
         ip_control: layer::ip::Controller<'a>,
         tcp: tcp::Packet<layer::ip::IpPacket<'a, P>>,
     ) -> Result<Self, crate::layer::Error> {
-        let (mut operator, tcp) = match Unhandled::try_open(endpoint, tcp) {
+        let tcp_repr = tcp.repr();
+        let ip_repr = tcp.inner().repr();
+        let time = ip_control.info().timestamp();
+
+        let connection = FourTuple {
+            local: ip_repr.dst_addr(),
+            local_port: tcp_repr.dst_port,
+            remote: ip_repr.src_addr(),
+            remote_port: tcp_repr.src_port,
+        };
+
+        match endpoint.arrives_time_wait(connection, &tcp_repr, time) {
+            Arrival::Deliver => (),
+            Arrival::Drop => return Ok(In::Lingering(Lingering { endpoint })),
+            Arrival::Answer(answer) => {
+                control_answer(tcp, answer, ip_control)?;
+                return Ok(In::Lingering(Lingering { endpoint }));
+            },
+        }
+
+        let (mut operator, tcp) = match Unhandled::try_open(endpoint, tcp, connection) {
             Unhandled::Open { operator, tcp } => (operator, tcp),
             Unhandled::Closed { endpoint, tcp } => {
                 return Ok(In::Stray(Stray {
@@ -241,17 +279,29 @@ impl<'a, P: PayloadMut> In<'a, P> {
             }
         };
 
-        let from = tcp.inner().repr().src_addr();
-        let time = ip_control.info().timestamp();
+        let from = ip_repr.src_addr();
+        let congestion_experienced = tcp.inner().repr().ecn() == ip::Ecn::Ce;
         let in_packet = InPacket {
             segment: tcp.repr(),
             from,
             time,
+            congestion_experienced,
         };
 
         let mut signals = operator.arrives(&in_packet);
         let user = UserSignals::new(&signals);
 
+        // Remember a fast open cookie that the remote provided on our request.
+        let segment = &in_packet.segment;
+        let requested = operator.connection().fast_open_cookie.is_some()
+            && segment.flags.syn()
+            && segment.ack_number.is_some();
+        match segment.fast_open {
+            Some(cookie) if requested && !cookie.is_request()
+                => operator.endpoint.cache_fast_open_cookie(from, cookie),
+            _ => (),
+        }
+
         // Deleting the connection nothing to be sent.
         if signals.delete && signals.answer.is_none() {
             let previous = operator.connection_key;
@@ -310,6 +360,7 @@ impl<'a, P: PayloadMut> In<'a, P> {
             In::Closing(closing) => Some(closing.key()),
             In::Closed(closed) => Some(closed.key()),
             In::Stray(_) => None,
+            In::Lingering(_) => None,
         }
     }
 
@@ -321,6 +372,7 @@ impl<'a, P: PayloadMut> In<'a, P> {
             In::Closing(closing) => closing.signals,
             In::Closed(_) => UserSignals::default(),
             In::Stray(_) => UserSignals::default(),
+            In::Lingering(_) => UserSignals::default(),
         }
     }
 }
@@ -331,18 +383,84 @@ impl<'a, P: PayloadMut> Open<'a, P> {
         self.operator.connection_key
     }
 
+    /// Get a descriptor for state changes that would usually send a signal to the user.
+    pub fn user_signals(&self) -> UserSignals {
+        self.signals
+    }
+
     /// Receive data contained in the TCP segment.
     pub fn read(&mut self, with: &mut impl RecvBuf) {
         let connection = self.operator.connection_mut();
-        connection.recv.update_window(with.window());
 
         if let OpenPacket::In { tcp, segment } = &self.packet {
+            let was_closed = connection.recv_closed();
             with.receive(tcp.payload_slice(), *segment);
             let progress = segment.acked_until(with.ack());
             connection.set_recv_ack(progress);
+            connection.recv.selective = with.selective_acks();
+            self.signals.half_closed |= !was_closed && connection.recv_closed();
+        }
+
+        let mss = connection.receiver_maximum_segment_size;
+        let was_zero = connection.recv.window == 0;
+        connection.recv.update_window(with.window(), mss);
+
+        // The remote only probes a zero window slowly, announce its reopening right away.
+        let receiving = matches!(connection.current, State::Established | State::FinWait);
+        if was_zero && connection.recv.window > 0 && receiving {
+            connection.ack_timer = Expiration::When(self.ip.info().timestamp());
         }
     }
 
+    /// Close the sending side of the connection.
+    ///
+    /// Our FIN is sent by the next `write`, after all data that the send buffer has available at
+    /// that point. Receiving continues until the remote closes its side as well.
+    pub fn shutdown_write(&mut self) {
+        self.operator.connection_mut().shutdown = true;
+    }
+
+    /// Reset the connection and delete it immediately.
+    ///
+    /// The remote is sent a RST if it might still consider the connection open, in place of any
+    /// incoming packet. All data that has not been acknowledged yet is lost.
+    pub fn abort(self) -> Result<Closing<'a>, crate::layer::Error> {
+        let Open { ip, mut operator, signals, packet, } = self;
+        let payload: &'a mut P = match packet {
+            OpenPacket::In { tcp, .. } | OpenPacket::Control { tcp }
+                => tcp.into_inner().into_inner().into_inner(),
+            OpenPacket::Out { raw } => raw,
+        };
+
+        let result = match operator.abort() {
+            Some(repr) => {
+                let raw_ip = layer::ip::RawPacket {
+                    control: ip,
+                    payload,
+                };
+
+                prepare(raw_ip, &mut operator, repr, ip::Ecn::NotEct).and_then(|mut out_ip| {
+                    let ip_repr = out_ip.repr();
+                    let mut tcp = tcp::Packet::new_unchecked(out_ip.payload_mut_slice(), repr);
+                    tcp.fill_checksum(ip_repr.src_addr(), ip_repr.dst_addr());
+                    out_ip.send()
+                })
+            },
+            None => Ok(()),
+        };
+
+        // The connection is gone even if the RST could not be sent.
+        let previous = operator.key();
+        let endpoint = operator.delete();
+        result?;
+
+        Ok(Closing {
+            endpoint,
+            previous,
+            signals,
+        })
+    }
+
     /// Try to send parts of the available data.
     ///
     /// If the method succeeds returns a view on the packet being sent. Else, it will return a
@@ -358,24 +476,40 @@ impl<'a, P: PayloadMut> Open<'a, P> {
         };
 
         let tcp_seq = operator.connection().get_send_ack();
-        with.ack(tcp_seq);
-        let available = with.available();
+        // A passive open may still return to listening, with a new sequence space for the next
+        // connection. Only bind the send buffer once the connection is established.
+        match operator.connection().current {
+            State::Listen | State::SynReceived => (),
+            _ => with.ack(tcp_seq),
+        }
+        let mut available = with.available();
+        available.fin |= operator.connection().shutdown;
         let time = ip.info().timestamp();
 
+        let remote = operator.four_tuple().remote;
+        let ip_header_len = match remote {
+            ip::Address::Ipv6(_) => 40,
+            _ => 20,
+        };
+        operator.connection_mut().set_path_mtu(ip.path_mtu(remote), ip_header_len);
+
         let signals = operator.next_send_segment(available, time);
         user.update(&signals);
 
-        if let Some(Segment { repr, range }) = signals.segment {
+        if let Some(Segment { repr, range, ecn_capable }) = signals.segment {
             let raw_ip = layer::ip::RawPacket {
                 control: ip,
                 payload,
             };
 
-            let mut out_ip = prepare(raw_ip, &mut operator, repr)?;
+            let ecn = if ecn_capable { ip::Ecn::Ect0 } else { ip::Ecn::NotEct };
+            let mut out_ip = prepare(raw_ip, &mut operator, repr, ecn)?;
 
             let ip_repr = out_ip.repr();
             let mut tcp = tcp::Packet::new_unchecked(out_ip.payload_mut_slice(), repr);
-            with.fill(tcp.payload_mut_slice(), tcp_seq + range.start);
+            if !range.is_empty() {
+                with.fill(tcp.payload_mut_slice(), tcp_seq + range.start);
+            }
             tcp.fill_checksum(ip_repr.src_addr(), ip_repr.dst_addr());
 
             out_ip.send()?;
@@ -400,24 +534,63 @@ impl<'a, P: PayloadMut> Open<'a, P> {
 
 impl<'a, P: PayloadMut> Raw<'a, P> {
     /// Create a new connection.
+    ///
+    /// When the endpoint has a fast open cookie of the remote cached, the first write on the
+    /// returned packet sends the initial data of the `SendBuf` in the SYN. See
+    /// [`Endpoint::set_fast_open_cache`].
+    ///
+    /// [`Endpoint::set_fast_open_cache`]: struct.Endpoint.html#method.set_fast_open_cache
     pub fn open(self, addr: ip::Address, port: u16) -> Result<Open<'a, P>, crate::layer::Error> {
-        let local = self.source(addr)?;
-        let local_port = self.endpoint.source_port(local)
-            .ok_or(crate::layer::Error::Exhausted)?;
+        self.open_with(addr, port, Congestion::default())
+    }
+
+    /// Create a new connection with a chosen congestion control.
+    pub fn open_with(self, addr: ip::Address, port: u16, congestion: Congestion)
+        -> Result<Open<'a, P>, crate::layer::Error>
+    {
+        self.open_from(ip::Address::Unspecified, 0, addr, port, congestion)
+    }
 
-        let new = FourTuple {
+    /// Create a new connection from a chosen local address and port.
+    ///
+    /// An unspecified local address is replaced by a suitable address of the ip layer and a local
+    /// port of `0` by an ephemeral port of the endpoint, see [`Endpoint::set_ephemeral_ports`].
+    /// Fails with `Exhausted` if the complete tuple is already in use or still in TIME-WAIT.
+    ///
+    /// [`Endpoint::set_ephemeral_ports`]: struct.Endpoint.html#method.set_ephemeral_ports
+    pub fn open_from(
+        self,
+        local: ip::Address,
+        local_port: u16,
+        addr: ip::Address,
+        port: u16,
+        congestion: Congestion,
+    ) -> Result<Open<'a, P>, crate::layer::Error> {
+        let local = match local {
+            ip::Address::Unspecified => self.source(addr)?,
+            local => local,
+        };
+
+        let time = self.ip.control.info().timestamp();
+        let mut new = FourTuple {
             local,
             local_port,
             remote: addr,
             remote_port: port,
         };
 
-        let mut operator = match self.endpoint.open(new) {
+        if local_port == 0 {
+            new.local_port = self.endpoint.source_port(new, time)
+                .ok_or(crate::layer::Error::Exhausted)?;
+        }
+
+        let mut operator = match self.endpoint.open(new, time) {
             None => return Err(crate::layer::Error::Exhausted),
             Some(key) => Operator::new(self.endpoint, key).unwrap(),
         };
 
-        let time = self.ip.control.info().timestamp();
+        operator.connection_mut().flow_control = Flow::new(congestion);
+        operator.connection_mut().fast_open_cookie = operator.endpoint.fast_open_cookie(addr);
         assert!(operator.open(time).is_ok());
 
         let layer::ip::RawPacket {
@@ -433,6 +606,24 @@ impl<'a, P: PayloadMut> Raw<'a, P> {
         })
     }
 
+    /// Open a new port for listening, without using the packet.
+    ///
+    /// The address must be the exact local address that the connection attempts are sent to. See
+    /// [`Endpoint::listen`] for details.
+    ///
+    /// [`Endpoint::listen`]: struct.Endpoint.html#method.listen
+    pub fn listen(&mut self, addr: ip::Address, port: u16) -> Result<SlotKey, crate::layer::Error> {
+        self.endpoint.listen(addr, port)
+            .ok_or(crate::layer::Error::Exhausted)
+    }
+
+    /// Get the slot of an existing connection.
+    ///
+    /// Returns `None` if the connection no longer exists.
+    pub fn get(&self, key: SlotKey) -> Option<&Slot> {
+        self.endpoint.get(key)
+    }
+
     /// Attach to an existing connection.
     ///
     /// If successful, this return an `Open` packet with which you can send data on the connection.
@@ -530,13 +721,13 @@ impl UserSignals {
         UserSignals {
             reset: signals.reset,
             data: signals.receive.is_some(),
-            half_closed: false,
-            relisten: false,
+            half_closed: signals.half_closed,
+            relisten: signals.relisten,
         }
     }
 
-    fn update(&mut self, _signals: &OutSignals) {
-        // TODO: anything to set?
+    fn update(&mut self, signals: &OutSignals) {
+        self.reset |= signals.reset;
     }
 }
 
@@ -562,6 +753,9 @@ fn control_answer<'a, P: PayloadMut>(
         dst_addr: ip_repr.src_addr(),
         protocol: ip::Protocol::Tcp,
         payload: ip_payload_len,
+        dscp: 0,
+        ecn: ip::Ecn::NotEct,
+        fragment: false,
     })?.into_incoming();
 
     // FIXME: make initialization nicer.
@@ -578,6 +772,7 @@ fn prepare<'a, P: PayloadMut>(
     packet: layer::ip::RawPacket<'a, P>,
     operator: &mut Operator,
     repr: tcp::Repr,
+    ecn: ip::Ecn,
 ) -> Result<layer::ip::OutPacket<'a, P>, crate::layer::Error> {
 
     let tuple = operator.four_tuple();
@@ -586,6 +781,9 @@ fn prepare<'a, P: PayloadMut>(
         source: layer::ip::Source::Exact(tuple.local),
         protocol: ip::Protocol::Tcp,
         payload: repr.header_len() + usize::from(repr.payload_len),
+        dscp: 0,
+        ecn,
+        fragment: false,
     })?;
 
     let layer::ip::InPacket { control, mut packet } = init_ip.into_incoming();
@@ -1,12 +1,16 @@
 //! Initial sequence number generation, as recommended by rfc6528.
 //!
 //! Uses a keyed cryptographic hash function (SipHash-2-4) instead of appending the secret key to
-//! the four tuple for hashing. That should be better anyways. Hash function SipHash-2-4 from:
+//! the four tuple for hashing. That should be better anyways. The hash function is shared with
+//! other layers, see [`HashKey`].
 //!
-//! > SipHash: a fast short-input PRFJean-Philippe Aumasson1and Daniel J. Bernstein
+//! [`HashKey`]: ../hash/struct.HashKey.html
+use core::fmt;
+
 use super::endpoint::FourTuple;
+use crate::layer::hash::HashKey;
 use crate::time::Instant;
-use crate::wire::{ip::Address, ip::v6, tcp::SeqNumber};
+use crate::wire::{ip::Address, tcp::FastOpenCookie, tcp::Repr, tcp::SeqNumber};
 
 /// An initial sequence number generator based on SipHash-2-4.
 ///
@@ -16,59 +20,36 @@ use crate::wire::{ip::Address, ip::v6, tcp::SeqNumber};
 ///
 /// > ISN = M + SipHash-2-4(secretkey, localip, localport, remoteip, remoteport)
 ///
-/// The security of 2-4 might be better than what is required for some usecases and in some cases a
-/// SipHash-1-3 might instead be adequate. If this is indeed the case for Your use then You are
-/// invited to provide a PR introducing such a switch of hash function internally.
+/// See [`HashKey`] for the choice of hash function.
 ///
-/// Parameters that are unlikely to be accepted: 
-/// * SipHash-4-8, the conservative proposed variant for cryptographic MAC, is twice as expensive
-///   to compute and unlikely to have a practical advantage. Note that any attacker is highly limited
-///   in modifications to the hash input and a collision (second pre-image) is not her goal.
-/// * SipHash-0-x, there exist key recovery attacks and it only has marginal extra 
+/// [`HashKey`]: ../hash/struct.HashKey.html
 pub struct IsnGenerator {
-    keys: (u64, u64),
+    key: HashKey,
 }
 
-// Yes, that's the initial values, as ASCII text.
-const IV: [&[u8; 8]; 4] = [
-    b"somepseu",
-    b"dorandom",
-    b"lygenera",
-    b"tedbytes"];
-
-struct State {
-    v0: u64,
-    v1: u64,
-    v2: u64,
-    v3: u64,
+/// Connection parameters of a passive open that are encoded in a SYN cookie.
+///
+/// The maximum segment size is rounded down to one of a few common values. Timestamps need not be
+/// encoded as the final ACK contains the option if and only if they were negotiated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct SynCookie {
+    /// The maximum segment size of the remote.
+    pub max_seg_size: u16,
+    /// The window scale offered by the remote.
+    pub window_scale: Option<u8>,
+    /// If the remote permitted selective acknowledgements.
+    pub sack_permitted: bool,
 }
 
 impl IsnGenerator {
     /// Create a generator by deriving a key from the standard `RandomState`.
     ///
-    /// This is done by individually hashing the numbers `0u64` and `1u64` each with the same
-    /// hasher created from a new instance of `RandomState`. The two output tags are then used as
-    /// the internal key state.
+    /// See [`HashKey::from_std_hash`] for details.
+    ///
+    /// [`HashKey::from_std_hash`]: ../hash/struct.HashKey.html#method.from_std_hash
     #[cfg(feature = "std")]
     pub fn from_std_hash() -> Self {
-        use std::hash::{Hasher, BuildHasher};
-        use std::collections::hash_map::RandomState;
-
-        let hash = RandomState::new().build_hasher();
-        let x0 = {
-            let mut hash = hash.clone();
-            hash.write_u64(0);
-            hash.finish()
-        };
-        let x1 = {
-            let mut hash = hash.clone();
-            hash.write_u64(1);
-            hash.finish()
-        };
-
-        IsnGenerator {
-            keys: (x0, x1),
-        }
+        IsnGenerator { key: HashKey::from_std_hash(), }
     }
 
     /// Create a generator with some pre-defined secret key.
@@ -76,16 +57,13 @@ impl IsnGenerator {
     /// Really, create the key with some cryptographic random means or derive them from some other
     /// key with a key derivation function.
     pub fn from_secret_key_bytes(bytes: [u8; 16]) -> Self {
-        use core::convert::TryInto;
-        let a = u64::from_le_bytes(bytes[..8].try_into().unwrap());
-        let b = u64::from_le_bytes(bytes[8..].try_into().unwrap());
-        IsnGenerator { keys: (a, b), }
+        IsnGenerator { key: HashKey::from_secret_key_bytes(bytes), }
     }
 
     /// Create a generator with a pre-defined key.
     #[cfg(test)]
     pub fn from_key(a: u64, b: u64) -> Self {
-        IsnGenerator { keys: (a, b), }
+        IsnGenerator { key: HashKey::from_key(a, b), }
     }
 
     /// Get the initial sequence number for a connection.
@@ -98,163 +76,189 @@ impl IsnGenerator {
     /// connection pair (i.e. the Invalid state). This may be statically checked in the future
     /// through some other connection representation.
     pub fn get_isn(&self, connection: FourTuple, time: Instant) -> SeqNumber {
-        let mut state = State::init(self.keys.0, self.keys.1);
-
-        let num = match (connection.local, connection.remote) {
-            (Address::Ipv4(here), Address::Ipv4(there)) => {
-                let m = u64::from(here.to_network_integer())
-                    | u64::from(there.to_network_integer()) << 32;
-                let p = u64::from(connection.local_port)
-                    | u64::from(connection.remote_port) << 16
-                    // Message length = 12
-                    | 12_u64 << 56;
-                state.absorb(m);
-                state.absorb(p);
-                state.finalize()
-            },
-            (Address::Ipv6(here), Address::Ipv6(there)) => {
-                let (m0, m1) = Self::ipv6_to_messages(here);
-                let (m2, m3) = Self::ipv6_to_messages(there);
-                let p = u64::from(connection.local_port)
-                    | u64::from(connection.remote_port) << 16
-                    // Message length = 20
-                    | 20_u64 << 56;
-                state.absorb(m0);
-                state.absorb(m1);
-                state.absorb(m2);
-                state.absorb(m3);
-                state.absorb(p);
-                state.finalize()
-            },
-            // Don't even know how we could get here, but maybe with mapped addresses.
-            (Address::Ipv4(here), Address::Ipv6(there)) => {
-                let m0 = u64::from(here.to_network_integer())
-                    | u64::from(connection.local_port) << 32
-                    | u64::from(connection.remote_port) << 48;
-                let (m1, m2) = Self::ipv6_to_messages(there);
-                // Message length = 16
-                let p = 16_u64 << 56;
-                state.absorb(m0);
-                state.absorb(m1);
-                state.absorb(m2);
-                state.absorb(p);
-                state.finalize()
-            },
-            (Address::Ipv6(here), Address::Ipv4(there)) => {
-                let (m0, m1) = Self::ipv6_to_messages(here);
-                let m2 = u64::from(there.to_network_integer())
-                    | u64::from(connection.local_port) << 32
-                    | u64::from(connection.remote_port) << 48;
-                // Message length = 16
-                let p = 16_u64 << 56;
-                state.absorb(m0);
-                state.absorb(m1);
-                state.absorb(m2);
-                state.absorb(p);
-                state.finalize()
-            },
-            // FIXME: this really shouldn't be hit. We should introdce a good enum for Ip addresses
-            // to guarantee this statically.
-            _ => panic!("Should not be called, four tuple needs to be concrete ip addresses"),
-        };
-
+        let num = self.hash_tuple(connection, None);
         SeqNumber(num as i32) + (time.millis()/4000) as usize
     }
 
-    fn ipv6_to_messages(addr: v6::Address) -> (u64, u64) {
-        let v6::Address([a, b, c, d, e, f, g, h, i, j, k, l, m, n, o, p]) = addr;
-        let m0 = u64::from_be_bytes([a, b, c, d, e, f, g, h]);
-        let m1 = u64::from_be_bytes([i, j, k, l, m, n, o, p]);
-        (m0, m1)
+    /// Get the initial sequence number of a passive open that encodes a SYN cookie.
+    ///
+    /// The cookie also authenticates the initial sequence number of the remote. It remains valid
+    /// for at least one and at most two periods of 64 seconds, see [`check_syn_cookie`].
+    ///
+    /// [`check_syn_cookie`]: #method.check_syn_cookie
+    pub(crate) fn syn_cookie(
+        &self,
+        connection: FourTuple,
+        remote_isn: SeqNumber,
+        cookie: SynCookie,
+        time: Instant,
+    ) -> SeqNumber {
+        let counter = Self::cookie_counter(time);
+        let params = cookie.encode();
+        let mac = self.cookie_mac(connection, remote_isn, counter, params);
+        SeqNumber((counter << 27 | params << 20 | mac) as i32)
     }
-}
 
-impl State {
-    const SIP_C: usize = 2;
-    const SIP_D: usize = 4;
+    /// Validate the SYN cookie returned in the final ACK of a handshake.
+    ///
+    /// Returns the connection parameters encoded in the cookie if it is authentic and not expired.
+    pub(crate) fn check_syn_cookie(
+        &self,
+        connection: FourTuple,
+        remote_isn: SeqNumber,
+        isn: SeqNumber,
+        time: Instant,
+    ) -> Option<SynCookie> {
+        let isn = isn.0 as u32;
+        let counter = isn >> 27;
+        let params = (isn >> 20) & 0x7f;
 
-    fn init(k0: u64, k1: u64) -> Self {
-        State {
-            v0: u64::from_be_bytes(*IV[0]) ^ k0,
-            v1: u64::from_be_bytes(*IV[1]) ^ k1,
-            v2: u64::from_be_bytes(*IV[2]) ^ k0,
-            v3: u64::from_be_bytes(*IV[3]) ^ k1,
+        let age = Self::cookie_counter(time).wrapping_sub(counter) & 0x1f;
+        if age > 1 {
+            return None;
         }
+
+        if self.cookie_mac(connection, remote_isn, counter, params) != isn & 0xf_ffff {
+            return None;
+        }
+
+        Some(SynCookie::decode(params))
     }
 
-    fn round(&mut self) {
-        self.v0 = self.v0.wrapping_add(self.v1);
-        self.v1 = self.v1.rotate_left(13);
-        self.v1 ^= self.v0;
-        self.v0 = self.v0.rotate_left(32);
-        self.v2 = self.v2.wrapping_add(self.v3);
-        self.v3 = self.v3.rotate_left(16);
-        self.v3 ^= self.v2;
-        self.v0 = self.v0.wrapping_add(self.v3);
-        self.v3 = self.v3.rotate_left(21);
-        self.v3 ^= self.v0;
-        self.v2 = self.v2.wrapping_add(self.v1);
-        self.v1 = self.v1.rotate_left(17);
-        self.v1 ^= self.v2;
-        self.v2 = self.v2.rotate_left(32);
+    /// Get the fast open cookie of a remote address.
+    ///
+    /// RFC7413 recommends a MAC of the remote address only, which does not depend on the ports or
+    /// the time of the connection attempt. The local address is included to separate cookies of
+    /// different interfaces. Cookies remain valid until the secret key is updated.
+    pub(crate) fn fast_open_cookie(&self, local: Address, remote: Address) -> FastOpenCookie {
+        let tuple = FourTuple {
+            local,
+            remote,
+            local_port: 0,
+            remote_port: 0,
+        };
+        // Distinct from the SYN cookie prefix whose upper bits are always zero.
+        let mac = self.hash_tuple(tuple, Some(u64::from_le_bytes(*b"fastopen")));
+        // UNWRAP: eight bytes is a valid cookie length.
+        FastOpenCookie::new(&mac.to_be_bytes()).unwrap()
     }
 
-    /// Process a single portion of the message.
+    /// The offset of ephemeral ports towards a remote, algorithm 3 of RFC6056.
     ///
-    /// Note that all users need to manually add absorbing the length in the last block. This is
-    /// slightly easier to read since it arranges the input to only have 8-btye blocks in all cases
-    /// which separates the length block completely and makes it a constant.
-    fn absorb(&mut self, m: u64) {
-        self.v3 ^= m;
-        (0..Self::SIP_C).for_each(|_| self.round());
-        self.v0 ^= m;
+    /// The local port of the tuple is ignored. Each remote endpoint sees a separate sequence of
+    /// local ports so that ports chosen for one remote reveal nothing about those of another.
+    pub(crate) fn port_offset(&self, connection: FourTuple) -> u64 {
+        let tuple = FourTuple {
+            local_port: 0,
+            ..connection
+        };
+        self.hash_tuple(tuple, Some(u64::from_le_bytes(*b"ephemera")))
     }
 
-    /// Do the finalization rounds.
-    fn finalize(mut self) -> u64 {
-        self.v2 ^= 0xff;
-        (0..Self::SIP_D).for_each(|_| self.round());
-        self.v0 ^ self.v1 ^ self.v2 ^ self.v3
+    /// A keyed pseudo-random number derived from a counter, unrelated to any connection.
+    pub(crate) fn random(&self, counter: u64) -> u64 {
+        self.key.random(counter)
+    }
+
+    /// The 5-bit counter of 64 second periods.
+    fn cookie_counter(time: Instant) -> u32 {
+        (time.secs() / 64) as u32 & 0x1f
+    }
+
+    /// The 20-bit authentication of the cookie.
+    fn cookie_mac(&self, connection: FourTuple, remote_isn: SeqNumber, counter: u32, params: u32)
+        -> u32
+    {
+        let extra = u64::from(remote_isn.0 as u32)
+            | u64::from(counter) << 32
+            | u64::from(params) << 40;
+        (self.hash_tuple(connection, Some(extra)) & 0xf_ffff) as u32
+    }
+
+    /// Hash the connection tuple, optionally prefixed with another message block.
+    fn hash_tuple(&self, connection: FourTuple, extra: Option<u64>) -> u64 {
+        self.key.hash_addresses(
+            (connection.local, connection.local_port),
+            (connection.remote, connection.remote_port),
+            extra)
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use core::fmt;
-    use super::*;
+impl fmt::Debug for IsnGenerator {
+    // Never print the secret key.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IsnGenerator").finish()
+    }
+}
 
-    struct DebugState<'a>(&'a State);
+impl SynCookie {
+    /// The representable maximum segment sizes.
+    const MSS: [u16; 4] = [536, 1220, 1440, 1460];
 
-    impl fmt::Debug for DebugState<'_> {
-        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-            write!(f, "{:x} ", self.0.v0)?;
-            write!(f, "{:x} ", self.0.v1)?;
-            write!(f, "{:x} ", self.0.v2)?;
-            write!(f, "{:x} ", self.0.v3)
+    /// Create the cookie parameters, the segment size is rounded down.
+    pub(crate) fn new(max_seg_size: u16, window_scale: Option<u8>, sack_permitted: bool) -> Self {
+        let idx = Self::MSS.iter().rposition(|&mss| mss <= max_seg_size).unwrap_or(0);
+        SynCookie {
+            max_seg_size: Self::MSS[idx],
+            window_scale: window_scale.map(|scale| scale.min(14)),
+            sack_permitted,
         }
     }
 
-    impl super::State {
-        fn debug(&self) -> DebugState {
-            DebugState(self)
+    /// Replace the options of a SYN with the parameters of the cookie.
+    pub(crate) fn syn(self, segment: &Repr) -> Repr {
+        Repr {
+            window_scale: self.window_scale,
+            max_seg_size: Some(self.max_seg_size),
+            sack_permitted: self.sack_permitted,
+            ..*segment
         }
     }
 
-    /// See the paper–Appendix A
+    fn encode(self) -> u32 {
+        let mss = Self::MSS.iter().position(|&mss| mss == self.max_seg_size).unwrap_or(0) as u32;
+        let scale = self.window_scale.map_or(15, u32::from);
+        mss << 5 | scale << 1 | u32::from(self.sack_permitted)
+    }
+
+    fn decode(params: u32) -> Self {
+        let scale = ((params >> 1) & 0xf) as u8;
+        SynCookie {
+            max_seg_size: Self::MSS[(params >> 5) as usize & 0x3],
+            window_scale: if scale == 15 { None } else { Some(scale) },
+            sack_permitted: params & 1 != 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
     #[test]
-    fn manual_test_vectors() {
-        let k0 = u64::from_le_bytes(0x0001020304050607_u64.to_be_bytes());
-        let k1 = u64::from_le_bytes(0x08090a0b0c0d0e0f_u64.to_be_bytes());
+    fn syn_cookie() {
+        let generator = IsnGenerator::from_key(0, 0);
+        let tuple = FourTuple {
+            local: crate::wire::ip::v4::Address::new(127, 0, 0, 1).into(),
+            remote: crate::wire::ip::v4::Address::new(127, 0, 0, 2).into(),
+            local_port: 80,
+            remote_port: 4000,
+        };
+        let remote_isn = SeqNumber(1000);
+        let cookie = SynCookie::new(1400, Some(7), true);
+        assert_eq!(cookie.max_seg_size, 1220);
 
-        let mut state = State::init(k0, k1);
-        println!("{:?}", state.debug());
-        let m0 = u64::from_le_bytes(0x0001020304050607_u64.to_be_bytes());
-        state.absorb(m0);
-        println!("{:?}", state.debug());
-        let m1 = u64::from_le_bytes(0x08090a0b0c0d0e0f_u64.to_be_bytes());
-        state.absorb(m1);
-        println!("{:?}", state.debug());
+        let time = Instant::from_secs(100);
+        let isn = generator.syn_cookie(tuple, remote_isn, cookie, time);
+        assert_eq!(generator.check_syn_cookie(tuple, remote_isn, isn, time), Some(cookie));
+        assert_eq!(generator.check_syn_cookie(tuple, remote_isn, isn, time + crate::time::Duration::from_secs(64)),
+            Some(cookie));
 
-        assert_eq!(state.finalize(), 0xa129ca6149be45e5);
+        // Expired, forged or for a different connection.
+        assert_eq!(generator.check_syn_cookie(tuple, remote_isn, isn, Instant::from_secs(300)), None);
+        assert_eq!(generator.check_syn_cookie(tuple, remote_isn, isn + 1, time), None);
+        assert_eq!(generator.check_syn_cookie(tuple, remote_isn + 1, isn, time), None);
+        let other = FourTuple { remote_port: 4001, ..tuple };
+        assert_eq!(generator.check_syn_cookie(other, remote_isn, isn, time), None);
     }
 }
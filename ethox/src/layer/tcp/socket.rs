@@ -3,7 +3,8 @@
 //! An actual socket layer requires allocation all buffers and depends on a few details in the
 //! layer below and these do not (that was not the end goal but some may be added in the future),
 //! but it tries to give a slightly more familiar interface.
-use super::{InPacket, RawPacket, Recv, RecvBuf, Send, SendBuf, SlotKey};
+use super::{InPacket, RawPacket, Recv, RecvBuf, Send, SendBuf, SlotKey, State};
+use crate::managed::Slice;
 use crate::wire::{ip::Address, PayloadMut};
 
 /// A tcp handler for a client (actively opened connection).
@@ -16,28 +17,33 @@ use crate::wire::{ip::Address, PayloadMut};
 ///
 /// ## Things that do not work yet
 ///
-/// There should be a `bind` constructor but currently the local address can only be deduced
-/// automatically.
-///
 /// Error handling is also suboptimal and mostly close the connection.
 ///
 /// [`tcp::Send`]: ../trait.Send.html
 /// [`tcp::Recv`]: ../trait.Recv.html
 pub struct Client<R, S> {
     state: ClientState,
+    shutdown: bool,
     recv: R,
     send: S,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 enum ClientState {
+    Idle,
     Uninstantiated {
+        local: Address,
+        local_port: u16,
         remote: Address,
         remote_port: u16,
     },
     InStack {
         key: SlotKey,
     },
+    /// Reset the connection with the next packet.
+    Aborting {
+        key: SlotKey,
+    },
     Finished,
 }
 
@@ -47,17 +53,54 @@ where
     S: SendBuf,
 {
     /// Create a client connecting to a remote on some automatically derived local address.
+    ///
+    /// The local port is an ephemeral port chosen by the endpoint.
     pub fn new(
         remote: Address,
         remote_port: u16,
         recv: R,
         send: S,
+    ) -> Self {
+        Client::bind(Address::Unspecified, 0, remote, remote_port, recv, send)
+    }
+
+    /// Create a client connecting to a remote from an explicit local address and port.
+    ///
+    /// An unspecified address or a port of `0` are chosen automatically, as in [`new`]. The
+    /// connection is opened on the first send. While the tuple is still in use by another
+    /// connection or in TIME-WAIT, opening is retried on every send.
+    ///
+    /// [`new`]: #method.new
+    pub fn bind(
+        local: Address,
+        local_port: u16,
+        remote: Address,
+        remote_port: u16,
+        recv: R,
+        send: S,
     ) -> Self {
         Client {
             state: ClientState::Uninstantiated {
+                local,
+                local_port,
                 remote,
                 remote_port,
             },
+            shutdown: false,
+            recv,
+            send,
+        }
+    }
+
+    /// Create an idle client for the backlog of a [`Server`].
+    ///
+    /// The client will not open a connection by itself, it only gets assigned one by the server.
+    ///
+    /// [`Server`]: struct.Server.html
+    pub fn from_buffers(recv: R, send: S) -> Self {
+        Client {
+            state: ClientState::Idle,
+            shutdown: false,
             recv,
             send,
         }
@@ -95,6 +138,28 @@ impl<R, S> Client<R, S> {
         &mut self.send
     }
 
+    /// Close the sending side of the connection.
+    ///
+    /// A FIN is sent after all data of the send buffer, even if the buffer itself does not end
+    /// the stream. Data is still received until the remote closes its side as well.
+    pub fn shutdown_write(&mut self) {
+        self.shutdown = true;
+    }
+
+    /// Reset the connection.
+    ///
+    /// The RST is sent with the next packet of the connection, in the receive or send path. A
+    /// connection that was not opened yet is closed immediately.
+    pub fn abort(&mut self) {
+        self.state = match self.state {
+            ClientState::InStack { key } | ClientState::Aborting { key }
+                => ClientState::Aborting { key },
+            ClientState::Idle => ClientState::Idle,
+            ClientState::Uninstantiated { .. } | ClientState::Finished
+                => ClientState::Finished,
+        };
+    }
+
     /// Check if the connection was closed.
     pub fn is_closed(&self) -> bool {
         match self.state {
@@ -103,6 +168,11 @@ impl<R, S> Client<R, S> {
         }
     }
 
+    /// Check if the client is not associated with any connection.
+    pub fn is_idle(&self) -> bool {
+        matches!(self.state, ClientState::Idle)
+    }
+
     /// Get the key of the active connection.
     ///
     /// The key can be used to manually attach to the connection during rx and tx operations or to
@@ -112,7 +182,7 @@ impl<R, S> Client<R, S> {
     /// terminated.
     pub fn connection_key(&self) -> Option<SlotKey> {
         match self.state {
-            ClientState::InStack { key } => Some(key),
+            ClientState::InStack { key } | ClientState::Aborting { key } => Some(key),
             _ => None,
         }
     }
@@ -127,7 +197,7 @@ where
     fn receive(&mut self, packet: InPacket<P>) {
         let key = match self.state {
             // We really need to send first.
-            ClientState::InStack { key } => key,
+            ClientState::InStack { key } | ClientState::Aborting { key } => key,
             _ => return,
         };
 
@@ -137,13 +207,22 @@ where
         }
 
         match packet {
-            InPacket::Stray(_) | InPacket::Sending(_) => (),
+            InPacket::Stray(_) | InPacket::Sending(_) | InPacket::Lingering(_) => (),
             InPacket::Closed(_) | InPacket::Closing(_) => {
                 self.state = ClientState::Finished;
             },
+            InPacket::Open(open) if self.state == (ClientState::Aborting { key }) => {
+                let _ = open.abort();
+                self.state = ClientState::Finished;
+            },
             InPacket::Open(mut open) => {
                 open.read(&mut self.recv);
-                let _ = open.write(&mut self.send);
+                if self.shutdown {
+                    open.shutdown_write();
+                }
+                if let Ok(Err(_closing)) = open.write(&mut self.send) {
+                    self.state = ClientState::Finished;
+                }
             },
         }
     }
@@ -156,9 +235,9 @@ where
     P: PayloadMut,
 {
     fn send(&mut self, packet: RawPacket<P>) {
-        let open = match self.state {
-            ClientState::Uninstantiated { remote, remote_port } => {
-                match packet.open(remote, remote_port) {
+        let mut open = match self.state {
+            ClientState::Uninstantiated { local, local_port, remote, remote_port } => {
+                match packet.open_from(local, local_port, remote, remote_port, Default::default()) {
                     Ok(open) => {
                         self.state = ClientState::InStack { key: open.key() };
                         open
@@ -179,10 +258,188 @@ where
                     Err(_) => return self.state = ClientState::Finished,
                 }
             },
-            ClientState::Finished => return,
+            ClientState::Aborting { key } => {
+                if let Ok(open) = packet.attach(key) {
+                    let _ = open.abort();
+                }
+                return self.state = ClientState::Finished;
+            },
+            ClientState::Idle | ClientState::Finished => return,
         };
 
+        // Picks up space freed by the user, the window update is sent if necessary.
+        open.read(&mut self.recv);
+
+        if self.shutdown {
+            open.shutdown_write();
+        }
+
         // TODO: error handling.
-        let _ = open.write(&mut self.send);
+        if let Ok(Err(_closing)) = open.write(&mut self.send) {
+            // The connection was deleted, e.g. after unanswered keep-alive probes.
+            self.state = ClientState::Finished;
+        }
+    }
+}
+
+/// A tcp handler for a server (passively opened connections).
+///
+/// Manages a bounded backlog of clients, each with their own buffers. One idle entry of the
+/// backlog is always kept listening on the local port. When a connection attempt arrives that
+/// entry becomes the accepted connection and the next idle entry is armed as the listener during
+/// the next send phase. Arming a listener uses up the raw packet as it must advertise the window of
+/// the receive buffer in the SYN-ACK. Other raw packets are distributed to the accepted connections
+/// in a round robin fashion.
+///
+/// Finished connections remain in the backlog, with their buffers, until the entry is explicitly
+/// [`release`]d. Only then can it accept another connection.
+///
+/// [`release`]: #method.release
+pub struct Server<'a, R, S> {
+    local: Address,
+    port: u16,
+    listener: Option<usize>,
+    next: usize,
+    backlog: Slice<'a, Client<R, S>>,
+}
+
+impl<'a, R, S> Server<'a, R, S> {
+    /// Create a server listening on a local address and port.
+    ///
+    /// The address must be the exact address that the connection attempts are sent to. All
+    /// entries of the backlog are reset to be idle.
+    pub fn new(local: Address, port: u16, mut backlog: Slice<'a, Client<R, S>>) -> Self {
+        for client in backlog.iter_mut() {
+            client.state = ClientState::Idle;
+        }
+
+        Server {
+            local,
+            port,
+            listener: None,
+            next: 0,
+            backlog,
+        }
+    }
+
+    /// Get the entries of the backlog.
+    ///
+    /// The listening entry has a connection key as well but is neither idle nor closed.
+    pub fn backlog(&self) -> &[Client<R, S>] {
+        &self.backlog
+    }
+
+    /// Get mutable references to the entries of the backlog.
+    pub fn backlog_mut(&mut self) -> &mut [Client<R, S>] {
+        &mut self.backlog
+    }
+
+    /// Check if there is a listening entry in the backlog.
+    pub fn is_listening(&self) -> bool {
+        self.listener.is_some()
+    }
+
+    /// Make a finished entry available for new connections.
+    ///
+    /// Returns `false` if the entry does not exist or its connection is not closed yet. The buffers
+    /// of the entry are not modified, clear them before releasing when necessary.
+    pub fn release(&mut self, idx: usize) -> bool {
+        match self.backlog.get_mut(idx) {
+            Some(client) if client.is_closed() => {
+                client.state = ClientState::Idle;
+                client.shutdown = false;
+                true
+            },
+            _ => false,
+        }
+    }
+
+    /// Forget the listener if it has accepted a connection or was removed.
+    fn update_listener<P: PayloadMut>(&mut self, packet: &RawPacket<P>) {
+        let idx = match self.listener {
+            Some(idx) => idx,
+            None => return,
+        };
+
+        let listening = self.backlog[idx].connection_key()
+            .and_then(|key| packet.get(key))
+            .map(|slot| slot.connection().current == State::Listen)
+            .unwrap_or(false);
+
+        if !listening {
+            self.listener = None;
+        }
+    }
+}
+
+impl<R, S, P> Recv<P> for &'_ mut Server<'_, R, S>
+where
+    R: RecvBuf,
+    S: SendBuf,
+    P: PayloadMut,
+{
+    fn receive(&mut self, packet: InPacket<P>) {
+        let key = match packet.key() {
+            Some(key) => key,
+            None => return,
+        };
+
+        let idx = self.backlog.iter()
+            .position(|client| client.connection_key() == Some(key));
+
+        if let Some(idx) = idx {
+            // The accepted connection attempt was reset, the entry is the listener again.
+            if packet.user_signals().relisten {
+                self.listener = Some(idx);
+            }
+
+            let mut client = &mut self.backlog[idx];
+            client.receive(packet);
+        }
+    }
+}
+
+impl<R, S, P> Send<P> for &'_ mut Server<'_, R, S>
+where
+    R: RecvBuf,
+    S: SendBuf,
+    P: PayloadMut,
+{
+    fn send(&mut self, mut packet: RawPacket<P>) {
+        self.update_listener(&packet);
+
+        if self.listener.is_none() {
+            if let Some(idx) = self.backlog.iter().position(Client::is_idle) {
+                let key = match packet.listen(self.local, self.port) {
+                    Ok(key) => key,
+                    // TODO: error handling.
+                    Err(_) => return,
+                };
+
+                let client = &mut self.backlog[idx];
+                client.state = ClientState::InStack { key };
+                self.listener = Some(idx);
+
+                // Advertise the receive window in the answer to the connection attempt.
+                if let Ok(mut open) = packet.attach(key) {
+                    open.read(&mut client.recv);
+                }
+
+                return;
+            }
+        }
+
+        let len = self.backlog.len();
+        let listener = self.listener;
+        let next = (0..len)
+            .map(|offset| (self.next + offset) % len)
+            .filter(|&idx| Some(idx) != listener)
+            .find(|&idx| self.backlog[idx].connection_key().is_some());
+
+        if let Some(idx) = next {
+            self.next = idx + 1;
+            let mut client = &mut self.backlog[idx];
+            client.send(packet);
+        }
     }
 }
@@ -0,0 +1,566 @@
+//! Streams implementing `std::io::Read` and `std::io::Write`.
+//!
+//! Everything else in this layer is driven by callbacks of the network device. Code written
+//! against the standard io traits instead expects calls to `read` and `write` to make progress on
+//! their own. The [`Stack`] bridges the two by owning the device and the endpoints of all layers
+//! and pumping packets until an operation on a [`TcpStream`] can complete.
+//!
+//! The buffers of a stream are ring buffers provided by the caller, so that a stream uses constant
+//! memory no matter how much data passes through it. A stream is non-blocking or blocking. In
+//! non-blocking mode an operation that can not make progress returns an error of kind
+//! `WouldBlock` after pumping all packets that were immediately available. In blocking mode the
+//! stack keeps polling the device, yielding the thread in between, until the operation completes.
+//! There are no timeouts, the stream only fails when its connection is closed.
+//!
+//! [`Stack`]: struct.Stack.html
+//! [`TcpStream`]: struct.TcpStream.html
+use std::io;
+use std::thread;
+
+use super::io::{RingRecv, RingSend};
+use super::{Client, InPacket, RawPacket, Recv, Send, Server, SlotKey};
+use crate::layer::{eth, ip, tcp, Error};
+use crate::managed::Slice;
+use crate::nic::Device;
+use crate::wire::{ip::Address, PayloadMut};
+
+/// A tcp connection with ring buffers, read and written through a [`Stack`].
+///
+/// The stream itself only holds the buffers and connection state. It is attached to the stack
+/// that pumps its packets in [`Stack::io`], which returns a handle implementing `Read` and
+/// `Write`.
+///
+/// [`Stack`]: struct.Stack.html
+/// [`Stack::io`]: struct.Stack.html#method.io
+pub struct TcpStream<'r> {
+    socket: Socket<'r>,
+    nonblocking: bool,
+    shutdown: bool,
+    /// The remote closed its half, possibly with a FIN that the receive buffer never saw.
+    eof: bool,
+}
+
+enum Socket<'r> {
+    Connect(Client<RingRecv<'r>, RingSend<'r>>),
+    Accept(Server<'r, RingRecv<'r>, RingSend<'r>>),
+}
+
+/// Owns a device and the endpoints of all layers for driving streams.
+///
+/// ## Example
+///
+/// Connecting to a remote and writing a request.
+///
+/// ```no_run
+/// # fn request<D>(stack: &mut ethox::layer::tcp::stream::Stack<D>)
+/// # where D: ethox::nic::Device, D::Handle: Sized, D::Payload: ethox::wire::PayloadMut + Sized,
+/// # {
+/// use std::io::{Read, Write};
+/// use ethox::layer::tcp::stream::TcpStream;
+/// use ethox::wire::ip::Address;
+///
+/// let mut stream = TcpStream::connect(
+///     Address::v4(192, 168, 0, 1), 80,
+///     vec![0; 1 << 16],
+///     vec![0; 1 << 12]);
+///
+/// let mut io = stack.io(&mut stream);
+/// io.write_all(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+/// io.flush().unwrap();
+///
+/// let mut response = Vec::new();
+/// io.read_to_end(&mut response).unwrap();
+/// # }
+/// ```
+pub struct Stack<'a, D> {
+    nic: D,
+    eth: eth::Endpoint<'a>,
+    ip: ip::Endpoint<'a>,
+    tcp: tcp::Endpoint<'a>,
+    batch: usize,
+}
+
+/// A stream attached to the stack that drives it.
+///
+/// Implements `Read` and `Write` by pumping the packets of the stack.
+pub struct Io<'s, 'a, 'r, D> {
+    stack: &'s mut Stack<'a, D>,
+    stream: &'s mut TcpStream<'r>,
+}
+
+impl<'r> TcpStream<'r> {
+    /// Create a stream connecting to a remote on an automatically chosen local address and port.
+    ///
+    /// The connection is opened when the stream is first polled.
+    pub fn connect<R, S>(remote: Address, remote_port: u16, recv: R, send: S) -> Self
+    where
+        R: Into<Slice<'r, u8>>,
+        S: Into<Slice<'r, u8>>,
+    {
+        let client = Client::new(remote, remote_port, RingRecv::new(recv), RingSend::new(send));
+        TcpStream::from_socket(Socket::Connect(client))
+    }
+
+    /// Create a stream accepting a single connection on a local address and port.
+    ///
+    /// The address must be the exact address that the connection attempt is sent to. Reads and
+    /// writes wait until a remote has connected.
+    pub fn accept<R, S>(local: Address, port: u16, recv: R, send: S) -> Self
+    where
+        R: Into<Slice<'r, u8>>,
+        S: Into<Slice<'r, u8>>,
+    {
+        let client = Client::from_buffers(RingRecv::new(recv), RingSend::new(send));
+        let server = Server::new(local, port, Slice::One(client));
+        TcpStream::from_socket(Socket::Accept(server))
+    }
+
+    fn from_socket(socket: Socket<'r>) -> Self {
+        TcpStream {
+            socket,
+            nonblocking: false,
+            shutdown: false,
+            eof: false,
+        }
+    }
+
+    /// Choose if operations return `WouldBlock` instead of waiting for the network.
+    pub fn set_nonblocking(&mut self, nonblocking: bool) {
+        self.nonblocking = nonblocking;
+    }
+
+    /// Check if the stream is in non-blocking mode.
+    pub fn is_nonblocking(&self) -> bool {
+        self.nonblocking
+    }
+
+    /// Close the writing half of the stream.
+    ///
+    /// A FIN is sent after all data that has been written. Further writes fail with
+    /// `BrokenPipe` while data can still be read until the remote closes its half.
+    pub fn shutdown_write(&mut self) {
+        self.shutdown = true;
+        self.client_mut().send_mut().fin();
+    }
+
+    /// Reset the connection with its next packet.
+    pub fn abort(&mut self) {
+        self.client_mut().abort();
+    }
+
+    /// Check if the connection was closed.
+    pub fn is_closed(&self) -> bool {
+        self.client().is_closed()
+    }
+
+    /// Get the key of the connection in the tcp endpoint.
+    ///
+    /// This is `None` until the connection was opened, or while waiting for a remote.
+    pub fn connection_key(&self) -> Option<SlotKey> {
+        match &self.socket {
+            Socket::Connect(client) => client.connection_key(),
+            Socket::Accept(server) if server.is_listening() => None,
+            Socket::Accept(server) => server.backlog()[0].connection_key(),
+        }
+    }
+
+    /// Get a reference to the client holding the buffers.
+    pub fn client(&self) -> &Client<RingRecv<'r>, RingSend<'r>> {
+        match &self.socket {
+            Socket::Connect(client) => client,
+            Socket::Accept(server) => &server.backlog()[0],
+        }
+    }
+
+    /// Get a mutable reference to the client holding the buffers.
+    pub fn client_mut(&mut self) -> &mut Client<RingRecv<'r>, RingSend<'r>> {
+        match &mut self.socket {
+            Socket::Connect(client) => client,
+            Socket::Accept(server) => &mut server.backlog_mut()[0],
+        }
+    }
+
+    fn try_read(&mut self, buf: &mut [u8]) -> Option<io::Result<usize>> {
+        let eof = self.eof;
+        let client = self.client_mut();
+        let recv = client.recv_mut();
+        if buf.is_empty() || recv.readable() > 0 || recv.is_finished() || eof {
+            Some(Ok(recv.read(buf)))
+        } else if client.is_closed() {
+            Some(Err(io::ErrorKind::ConnectionReset.into()))
+        } else {
+            None
+        }
+    }
+
+    fn try_write(&mut self, buf: &[u8]) -> Option<io::Result<usize>> {
+        if self.shutdown || self.is_closed() {
+            return Some(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+
+        match self.client_mut().send_mut().write(buf) {
+            0 if !buf.is_empty() => None,
+            written => Some(Ok(written)),
+        }
+    }
+
+    fn try_flush(&mut self) -> Option<io::Result<()>> {
+        if self.client().send().buffered() == 0 {
+            Some(Ok(()))
+        } else if self.is_closed() {
+            Some(Err(io::ErrorKind::BrokenPipe.into()))
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a, D> Stack<'a, D>
+where
+    D: Device,
+    D::Handle: Sized,
+    D::Payload: PayloadMut + Sized,
+{
+    /// Assemble a stack from a device and the endpoints of each layer.
+    pub fn new(
+        nic: D,
+        eth: eth::Endpoint<'a>,
+        ip: ip::Endpoint<'a>,
+        tcp: tcp::Endpoint<'a>,
+    ) -> Self {
+        Stack {
+            nic,
+            eth,
+            ip,
+            tcp,
+            batch: 16,
+        }
+    }
+
+    /// Set the maximum number of packets received and sent in one poll.
+    ///
+    /// The default is `16`.
+    pub fn set_batch(&mut self, batch: usize) {
+        self.batch = batch;
+    }
+
+    /// Get a reference to the device.
+    pub fn nic(&self) -> &D {
+        &self.nic
+    }
+
+    /// Get a mutable reference to the device.
+    pub fn nic_mut(&mut self) -> &mut D {
+        &mut self.nic
+    }
+
+    /// Get a mutable reference to the ip endpoint, to change routes or addresses.
+    pub fn ip_mut(&mut self) -> &mut ip::Endpoint<'a> {
+        &mut self.ip
+    }
+
+    /// Get a reference to the tcp endpoint.
+    pub fn tcp(&self) -> &tcp::Endpoint<'a> {
+        &self.tcp
+    }
+
+    /// Get a mutable reference to the tcp endpoint, to change its configuration.
+    pub fn tcp_mut(&mut self) -> &mut tcp::Endpoint<'a> {
+        &mut self.tcp
+    }
+
+    /// Attach a stream for reading and writing.
+    pub fn io<'s, 'r>(&'s mut self, stream: &'s mut TcpStream<'r>) -> Io<'s, 'a, 'r, D> {
+        Io {
+            stack: self,
+            stream,
+        }
+    }
+
+    /// Receive and send one batch of packets of a stream.
+    ///
+    /// Returns `true` if any packet was received or sent. Packets of other connections are
+    /// processed by the tcp endpoint but their data is not read.
+    pub fn poll(&mut self, stream: &mut TcpStream) -> io::Result<bool> {
+        let Stack { nic, eth, ip, tcp, batch } = self;
+        let received = nic.rx(*batch, eth.recv(ip.recv(tcp.recv(&mut *stream))))
+            .map_err(io_error)?;
+        let sent = nic.tx(*batch, eth.send(ip.send(tcp.send(&mut *stream))))
+            .map_err(io_error)?;
+        Ok(received + sent > 0)
+    }
+
+    /// Read from a stream, waiting for data unless it is non-blocking.
+    ///
+    /// Returns `0` once the remote has closed its half and all data was read.
+    pub fn read(&mut self, stream: &mut TcpStream, buf: &mut [u8]) -> io::Result<usize> {
+        self.drive(stream, |stream| stream.try_read(buf))
+    }
+
+    /// Write to a stream, waiting for space in its buffer unless it is non-blocking.
+    ///
+    /// The data is sent with the following polls of the stack.
+    pub fn write(&mut self, stream: &mut TcpStream, buf: &[u8]) -> io::Result<usize> {
+        self.drive(stream, |stream| stream.try_write(buf))
+    }
+
+    /// Wait until all written data of a stream was acknowledged by the remote.
+    pub fn flush(&mut self, stream: &mut TcpStream) -> io::Result<()> {
+        self.drive(stream, TcpStream::try_flush)
+    }
+
+    fn drive<'r, T>(
+        &mut self,
+        stream: &mut TcpStream<'r>,
+        mut op: impl FnMut(&mut TcpStream<'r>) -> Option<io::Result<T>>,
+    ) -> io::Result<T> {
+        loop {
+            if let Some(result) = op(stream) {
+                return result;
+            }
+
+            if !self.poll(stream)? {
+                if stream.nonblocking {
+                    return Err(io::ErrorKind::WouldBlock.into());
+                }
+
+                thread::yield_now();
+            }
+        }
+    }
+}
+
+impl<D> io::Read for Io<'_, '_, '_, D>
+where
+    D: Device,
+    D::Handle: Sized,
+    D::Payload: PayloadMut + Sized,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stack.read(self.stream, buf)
+    }
+}
+
+impl<D> io::Write for Io<'_, '_, '_, D>
+where
+    D: Device,
+    D::Handle: Sized,
+    D::Payload: PayloadMut + Sized,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stack.write(self.stream, buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stack.flush(self.stream)
+    }
+}
+
+impl<P: PayloadMut> Recv<P> for &'_ mut TcpStream<'_> {
+    fn receive(&mut self, packet: InPacket<P>) {
+        if packet.key().is_some() && packet.key() == self.connection_key() {
+            self.eof |= packet.user_signals().half_closed;
+        }
+
+        match &mut self.socket {
+            Socket::Connect(client) => {
+                let mut client = &mut *client;
+                client.receive(packet)
+            },
+            Socket::Accept(server) => {
+                let mut server = &mut *server;
+                server.receive(packet)
+            },
+        }
+    }
+}
+
+impl<P: PayloadMut> Send<P> for &'_ mut TcpStream<'_> {
+    fn send(&mut self, packet: RawPacket<P>) {
+        match &mut self.socket {
+            Socket::Connect(client) => {
+                let mut client = &mut *client;
+                client.send(packet)
+            },
+            Socket::Accept(server) => {
+                let mut server = &mut *server;
+                server.send(packet)
+            },
+        }
+    }
+}
+
+fn io_error(err: Error) -> io::Error {
+    let msg = match err {
+        Error::Illegal => "operation not permitted by the device",
+        Error::BadSize => "packet does not fit the device buffer",
+        Error::Unreachable => "no route to the destination",
+        Error::Exhausted => "not enough resources on the device",
+    };
+    io::Error::other(msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::io::{ErrorKind, Read, Write};
+    use std::rc::Rc;
+
+    use super::{Stack, TcpStream};
+    use crate::layer::{arp, eth, ip, tcp, Result};
+    use crate::managed::{List, Map, Slice, SlotMap};
+    use crate::nic::{self, common::{EnqueueFlag, PacketInfo}, Capabilities, Device, Personality};
+    use crate::time::{Duration, Instant};
+    use crate::wire::{ethernet, ip::v4, ip::Cidr};
+
+    const MAC_ADDR_A: ethernet::Address = ethernet::Address([0, 1, 2, 3, 4, 5]);
+    const IP_ADDR_A: v4::Address = v4::Address::new(10, 0, 0, 1);
+    const MAC_ADDR_B: ethernet::Address = ethernet::Address([6, 5, 4, 3, 2, 1]);
+    const IP_ADDR_B: v4::Address = v4::Address::new(10, 0, 0, 2);
+
+    type Queue = Rc<RefCell<VecDeque<Vec<u8>>>>;
+
+    /// One end of a lossless link between two stacks.
+    struct Pipe {
+        incoming: Queue,
+        outgoing: Queue,
+        info: PacketInfo,
+    }
+
+    impl Device for Pipe {
+        type Handle = EnqueueFlag;
+        type Payload = Vec<u8>;
+
+        fn personality(&self) -> Personality {
+            Personality::baseline()
+        }
+
+        fn tx(&mut self, max: usize, mut sender: impl nic::Send<Self::Handle, Self::Payload>)
+            -> Result<usize>
+        {
+            for count in 0..max {
+                let mut payload = vec![0; 1514];
+                let mut flag = EnqueueFlag::set_true(self.info);
+                sender.send(nic::Packet { handle: &mut flag, payload: &mut payload });
+                if !flag.was_sent() {
+                    return Ok(count);
+                }
+                self.outgoing.borrow_mut().push_back(payload);
+            }
+            Ok(max)
+        }
+
+        fn rx(&mut self, max: usize, mut receptor: impl nic::Recv<Self::Handle, Self::Payload>)
+            -> Result<usize>
+        {
+            for count in 0..max {
+                let mut payload = match self.incoming.borrow_mut().pop_front() {
+                    Some(payload) => payload,
+                    None => return Ok(count),
+                };
+                let mut flag = EnqueueFlag::set_true(self.info);
+                receptor.receive(nic::Packet { handle: &mut flag, payload: &mut payload });
+                if flag.was_sent() {
+                    self.outgoing.borrow_mut().push_back(payload);
+                }
+            }
+            Ok(max)
+        }
+    }
+
+    fn stack(nic: Pipe, mac: ethernet::Address, addr: v4::Address, other: (ethernet::Address, v4::Address))
+        -> Stack<'static, Pipe>
+    {
+        let mut neighbors = arp::NeighborCache::new(vec![arp::Neighbor::default(); 1]);
+        neighbors.fill(other.1.into(), other.0, None).unwrap();
+        Stack::new(
+            nic,
+            eth::Endpoint::new(mac),
+            ip::Endpoint::new(Cidr::new(addr.into(), 24), ip::Routes::new(Slice::empty()), neighbors),
+            tcp::Endpoint::new(
+                Map::Pairs(List::new(Slice::Many(vec![Default::default(); 2]))),
+                SlotMap::new(Slice::Many(vec![Default::default(); 2]), Slice::Many(vec![Default::default(); 2])),
+                tcp::IsnGenerator::from_key(0, 0)))
+    }
+
+    fn linked() -> (Stack<'static, Pipe>, Stack<'static, Pipe>) {
+        let info = PacketInfo {
+            timestamp: Instant::from_millis(0),
+            capabilities: Capabilities::no_support(),
+        };
+        let (a_to_b, b_to_a) = (Queue::default(), Queue::default());
+        let a = Pipe { incoming: b_to_a.clone(), outgoing: a_to_b.clone(), info };
+        let b = Pipe { incoming: a_to_b, outgoing: b_to_a, info };
+        (stack(a, MAC_ADDR_A, IP_ADDR_A, (MAC_ADDR_B, IP_ADDR_B)),
+         stack(b, MAC_ADDR_B, IP_ADDR_B, (MAC_ADDR_A, IP_ADDR_A)))
+    }
+
+    /// Let both stacks exchange packets while time passes.
+    fn pump(
+        (stack_a, a): (&mut Stack<'static, Pipe>, &mut TcpStream),
+        (stack_b, b): (&mut Stack<'static, Pipe>, &mut TcpStream),
+    ) {
+        for _ in 0..16 {
+            for stack in [&mut *stack_a, &mut *stack_b].iter_mut() {
+                let info = &mut stack.nic_mut().info;
+                info.timestamp += Duration::from_millis(50);
+            }
+            stack_a.poll(a).unwrap();
+            stack_b.poll(b).unwrap();
+        }
+    }
+
+    #[test]
+    fn nonblocking() {
+        let (mut stack_a, mut stack_b) = linked();
+        let mut a = TcpStream::connect(IP_ADDR_B.into(), 80, vec![0; 64], vec![0; 64]);
+        let mut b = TcpStream::accept(IP_ADDR_B.into(), 80, vec![0; 64], vec![0; 64]);
+        a.set_nonblocking(true);
+        b.set_nonblocking(true);
+
+        let mut buf = [0; 64];
+        // Nothing arrived yet, but the server starts listening.
+        let would_block = stack_b.io(&mut b).read(&mut buf).unwrap_err();
+        assert_eq!(would_block.kind(), ErrorKind::WouldBlock);
+        assert!(b.connection_key().is_none());
+
+        // More than the ring holds at once.
+        let message: Vec<u8> = (0..100).collect();
+        assert_eq!(stack_a.io(&mut a).write(&message).unwrap(), 64);
+        pump((&mut stack_a, &mut a), (&mut stack_b, &mut b));
+        assert!(b.connection_key().is_some());
+        assert_eq!(stack_a.io(&mut a).write(&message[64..]).unwrap(), 36);
+        assert_eq!(stack_b.io(&mut b).read(&mut buf).unwrap(), 64);
+        assert_eq!(&buf[..], &message[..64]);
+
+        pump((&mut stack_a, &mut a), (&mut stack_b, &mut b));
+        stack_a.io(&mut a).flush().unwrap();
+        assert_eq!(stack_b.io(&mut b).read(&mut buf).unwrap(), 36);
+        assert_eq!(&buf[..36], &message[64..]);
+    }
+
+    #[test]
+    fn blocking_until_closed() {
+        let (mut stack_a, mut stack_b) = linked();
+        let mut a = TcpStream::connect(IP_ADDR_B.into(), 80, vec![0; 64], vec![0; 64]);
+        let mut b = TcpStream::accept(IP_ADDR_B.into(), 80, vec![0; 64], vec![0; 64]);
+        stack_b.poll(&mut b).unwrap();
+
+        // The buffer of the stream has space, the write does not need to wait.
+        stack_a.io(&mut a).write_all(b"Hello, world!").unwrap();
+        a.shutdown_write();
+        let broken = stack_a.io(&mut a).write(b"!").unwrap_err();
+        assert_eq!(broken.kind(), ErrorKind::BrokenPipe);
+        pump((&mut stack_a, &mut a), (&mut stack_b, &mut b));
+
+        // All data and the FIN already arrived, reading to the end completes.
+        let mut received = Vec::new();
+        stack_b.io(&mut b).read_to_end(&mut received).unwrap();
+        assert_eq!(received, b"Hello, world!");
+
+        b.shutdown_write();
+        pump((&mut stack_a, &mut a), (&mut stack_b, &mut b));
+        assert_eq!(stack_a.io(&mut a).read(&mut [0; 8]).unwrap(), 0);
+    }
+}
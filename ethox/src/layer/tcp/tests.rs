@@ -5,3 +5,835 @@
 //! top of tcp and test against other implementations. Due to the abundance of options and allowed
 //! implementation specific behaviour it has proven quite hard to conduct this as a black-box test.
 //! Hence, see also the example binary for tcp echo.
+//!
+//! The tests in here drive a `Client` through a full stack on top of an external nic. The remote
+//! is played by hand: each sent frame is parsed from the nic buffer and the answers are crafted
+//! into the same buffer before it is received.
+use crate::managed::{List, Map, Slice, SlotMap};
+use crate::nic::{external::External, Device};
+use crate::layer::{arp, eth, ip, tcp};
+use crate::time::Instant;
+use crate::wire::{ethernet, Checksum, PayloadMut};
+use crate::wire::ip::{v4, Cidr, Ecn, Protocol};
+use crate::wire::tcp::{Checksum as TcpChecksum, FastOpenCookie, Flags, Packet, Repr, SeqNumber};
+
+const MAC_ADDR_HOST: ethernet::Address = ethernet::Address([0, 1, 2, 3, 4, 5]);
+const IP_ADDR_HOST: v4::Address = v4::Address::new(127, 0, 0, 1);
+const MAC_ADDR_REMOTE: ethernet::Address = ethernet::Address([6, 5, 4, 3, 2, 1]);
+const IP_ADDR_REMOTE: v4::Address = v4::Address::new(127, 0, 0, 2);
+const REMOTE_PORT: u16 = 80;
+const REMOTE_ISN: SeqNumber = SeqNumber(1000);
+
+type Client = tcp::Client<tcp::io::Sink, tcp::io::SendFrom<Vec<u8>>>;
+type Server<'a> = tcp::Server<'a, tcp::io::Sink, tcp::io::SendFrom<Vec<u8>>>;
+
+/// The host stack on top of a nic with a single buffer.
+struct Stack<'a> {
+    nic: External<Slice<'static, Vec<u8>>>,
+    eth: eth::Endpoint<'a>,
+    ip: ip::Endpoint<'a>,
+    tcp: tcp::Endpoint<'a>,
+}
+
+impl<'a> Stack<'a> {
+    /// Create a host stack that knows the remote and has room for `connections`.
+    fn new(
+        neighbors: &'a mut [arp::Neighbor],
+        routes: &'a mut [ip::Route],
+        connections: usize,
+    ) -> Self {
+        let mut eth_cache = arp::NeighborCache::new(neighbors);
+        eth_cache.fill(IP_ADDR_REMOTE.into(), MAC_ADDR_REMOTE, None).unwrap();
+
+        Stack {
+            nic: External::new_send(Slice::One(vec![0; 1514])),
+            eth: eth::Endpoint::new(MAC_ADDR_HOST),
+            ip: ip::Endpoint::new(Cidr::new(IP_ADDR_HOST.into(), 24),
+                ip::Routes::new(routes),
+                eth_cache),
+            tcp: tcp::Endpoint::new(
+                Map::Pairs(List::new(Slice::Many(vec![Default::default(); connections]))),
+                SlotMap::new(
+                    Slice::Many(vec![Default::default(); connections]),
+                    Slice::Many(vec![Default::default(); connections])),
+                tcp::IsnGenerator::from_key(0, 0)),
+        }
+    }
+
+    /// Let the client send one packet and parse the emitted segment.
+    fn transmit(&mut self, client: &mut Client, time: Instant) -> Option<Repr> {
+        self.transmit_with(client, time)
+    }
+
+    /// Let any handler send one packet and parse the emitted segment.
+    fn transmit_with(&mut self, handler: impl tcp::Send<Vec<u8>>, time: Instant) -> Option<Repr> {
+        self.nic.set_current_time(time);
+        self.nic.send_all();
+        let sent = self.nic.tx(1, self.eth.send(self.ip.send(self.tcp.send(handler))));
+        if sent != Ok(1) {
+            return None;
+        }
+
+        Some(self.last_segment())
+    }
+
+    /// Parse the segment currently in the nic buffer.
+    ///
+    /// After receiving, this is the answer that was written in the receive path.
+    fn last_segment(&self) -> Repr {
+        let buffer = self.nic.get(0).unwrap();
+        let frame = ethernet::frame::new_checked(buffer).unwrap();
+        assert_eq!(frame.dst_addr(), MAC_ADDR_REMOTE);
+        let packet = v4::packet::new_checked(frame.payload_slice()).unwrap();
+        let ip_repr = v4::Repr::parse(packet, Checksum::Manual).unwrap();
+        assert_eq!(ip_repr.dst_addr, IP_ADDR_REMOTE);
+        assert_eq!(ip_repr.protocol, Protocol::Tcp);
+        let checksum = TcpChecksum::Manual {
+            src_addr: ip_repr.src_addr.into(),
+            dst_addr: ip_repr.dst_addr.into(),
+        };
+        Repr::parse(&packet.payload_slice(), checksum).unwrap()
+    }
+
+    /// Send all packets the client wants to send at this time.
+    fn transmit_all(&mut self, client: &mut Client, time: Instant) -> Vec<Repr> {
+        let mut segments = Vec::new();
+        while let Some(segment) = self.transmit(client, time) {
+            segments.push(segment);
+        }
+        segments
+    }
+
+    /// Deliver a segment of the remote to the client.
+    fn receive(&mut self, client: &mut Client, segment: Repr, time: Instant) {
+        self.receive_with(Reader(client), segment, time)
+    }
+
+    /// Deliver a segment of the remote to any handler.
+    fn receive_with(&mut self, handler: impl tcp::Recv<Vec<u8>>, segment: Repr, time: Instant) {
+        let buffer = self.nic.get_mut(0).unwrap();
+        // The previous frame may have been shorter than the segment.
+        buffer.resize(1514, 0);
+        remote_frame(buffer, segment);
+        self.nic.set_current_time(time);
+        self.nic.receive_all();
+        let recv = self.nic.rx(1, self.eth.recv(self.ip.recv(self.tcp.recv(handler))));
+        assert_eq!(recv, Ok(1));
+    }
+}
+
+/// Only reads incoming segments into the client.
+///
+/// The external nic can not send in its receive path. Segments written there would count as sent
+/// but get lost, so all sending happens in `Stack::transmit` instead.
+struct Reader<'a>(&'a mut Client);
+
+impl<P: PayloadMut> tcp::Recv<P> for Reader<'_> {
+    fn receive(&mut self, packet: tcp::InPacket<P>) {
+        if let tcp::InPacket::Open(mut open) = packet {
+            open.read(self.0.recv_mut());
+        }
+    }
+}
+
+/// Only reads incoming segments into the accepted connections of a server.
+///
+/// All other packets are passed to the server, for it to track closed connections.
+struct ServerReader<'a, 'b>(&'a mut Server<'b>);
+
+impl<P: PayloadMut> tcp::Recv<P> for ServerReader<'_, '_> {
+    fn receive(&mut self, packet: tcp::InPacket<P>) {
+        match packet {
+            tcp::InPacket::Open(mut open) => {
+                let key = open.key();
+                let client = self.0.backlog_mut().iter_mut()
+                    .find(|client| client.connection_key() == Some(key))
+                    .expect("Open packet for an unknown connection");
+                open.read(client.recv_mut());
+            },
+            other => (&mut *self.0).receive(other),
+        }
+    }
+}
+
+/// Write a complete frame from the remote into the buffer.
+fn remote_frame(buffer: &mut [u8], segment: Repr) {
+    let eth_repr = ethernet::Repr {
+        src_addr: MAC_ADDR_REMOTE,
+        dst_addr: MAC_ADDR_HOST,
+        ethertype: ethernet::EtherType::Ipv4,
+    };
+    let ip_repr = v4::Repr {
+        src_addr: IP_ADDR_REMOTE,
+        dst_addr: IP_ADDR_HOST,
+        protocol: Protocol::Tcp,
+        payload_len: segment.buffer_len(),
+        hop_limit: 64,
+        dscp: 0,
+        ecn: Ecn::NotEct,
+    };
+
+    let frame = ethernet::frame::new_unchecked_mut(buffer);
+    eth_repr.emit(frame);
+    let packet = v4::packet::new_unchecked_mut(frame.payload_mut_slice());
+    ip_repr.emit(packet, Checksum::Manual);
+    let payload = packet.payload_mut_slice();
+    segment.emit(Packet::new_unchecked(&mut *payload, segment));
+    Packet::new_unchecked(payload, segment)
+        .fill_checksum(IP_ADDR_REMOTE.into(), IP_ADDR_HOST.into());
+}
+
+/// A segment of the remote without data.
+fn remote_segment(answer: &Repr, ack: SeqNumber, window: u16) -> Repr {
+    Repr {
+        src_port: answer.dst_port,
+        dst_port: answer.src_port,
+        flags: Flags::default(),
+        seq_number: REMOTE_ISN + 1,
+        ack_number: Some(ack),
+        window_len: window,
+        window_scale: None,
+        max_seg_size: None,
+        sack_permitted: false,
+        sack_ranges: [None; 3],
+        timestamp: None,
+        fast_open: None,
+        payload_len: 0,
+    }
+}
+
+#[test]
+fn zero_window_probe() {
+    let mut neighbors = [arp::Neighbor::default(); 1];
+    let mut routes = [ip::Route::unspecified(); 1];
+
+    let mut stack = Stack::new(&mut neighbors[..], &mut routes[..], 1);
+
+    let mut client = Client::new(IP_ADDR_REMOTE.into(), REMOTE_PORT,
+        tcp::io::Sink::default(),
+        tcp::io::SendFrom::once(vec![0; 2144]));
+
+    let syn = stack.transmit(&mut client, Instant::from_millis(0))
+        .expect("Sends a SYN");
+    assert!(syn.flags.syn());
+    let data_start = syn.seq_number + 1;
+
+    let mut syn_ack = remote_segment(&syn, data_start, 1072);
+    syn_ack.flags = Flags::SYN;
+    syn_ack.seq_number = REMOTE_ISN;
+    syn_ack.max_seg_size = Some(536);
+    stack.receive(&mut client, syn_ack, Instant::from_millis(10));
+
+    // The window limits the data in flight.
+    let flight = stack.transmit_all(&mut client, Instant::from_millis(10));
+    let sent: usize = flight.iter().map(|segment| usize::from(segment.payload_len)).sum();
+    assert_eq!(sent, 1072);
+
+    // All data is acknowledged but the remote has not read any of it yet.
+    let closed = remote_segment(&syn, data_start + 1072, 0);
+    stack.receive(&mut client, closed, Instant::from_millis(20));
+    assert!(stack.transmit(&mut client, Instant::from_millis(20)).is_none());
+
+    // The window update of the remote gets lost. Nothing is sent until the persist timer expires.
+    assert!(stack.transmit(&mut client, Instant::from_millis(500)).is_none());
+
+    let probe = stack.transmit(&mut client, Instant::from_millis(1100))
+        .expect("Probes the zero window");
+    assert_eq!(probe.payload_len, 0);
+    assert_eq!(probe.seq_number, data_start + 1071);
+    assert!(stack.transmit(&mut client, Instant::from_millis(1100)).is_none());
+
+    // The window is still closed, the next probe is backed off.
+    stack.receive(&mut client, closed, Instant::from_millis(1110));
+    assert!(stack.transmit(&mut client, Instant::from_millis(2500)).is_none());
+    let probe = stack.transmit(&mut client, Instant::from_millis(3200))
+        .expect("Probes the zero window again");
+    assert_eq!(probe.payload_len, 0);
+
+    // The answer to the probe opens the window again and the transmission resumes.
+    let open = remote_segment(&syn, data_start + 1072, 1072);
+    stack.receive(&mut client, open, Instant::from_millis(3210));
+    let flight = stack.transmit_all(&mut client, Instant::from_millis(3210));
+    assert_eq!(flight[0].seq_number, data_start + 1072);
+    let sent: usize = flight.iter().map(|segment| usize::from(segment.payload_len)).sum();
+    assert_eq!(sent, 1072);
+}
+
+#[test]
+fn server_backlog() {
+    const LOCAL_PORT: u16 = 80;
+
+    let mut neighbors = [arp::Neighbor::default(); 1];
+    let mut routes = [ip::Route::unspecified(); 1];
+
+    let mut stack = Stack::new(&mut neighbors[..], &mut routes[..], 4);
+
+    let backlog = (0..2)
+        .map(|_| Client::from_buffers(
+            tcp::io::Sink::default(),
+            tcp::io::SendFrom::once(vec![0; 536])))
+        .collect();
+    let mut server = Server::new(IP_ADDR_HOST.into(), LOCAL_PORT, Slice::Many(backlog));
+
+    // Arming the listener does not send anything.
+    assert!(stack.transmit_with(&mut server, Instant::from_millis(0)).is_none());
+    assert!(server.is_listening());
+    assert!(!server.backlog()[0].is_idle());
+    assert!(server.backlog()[1].is_idle());
+
+    let syn = |src_port| Repr {
+        src_port,
+        dst_port: LOCAL_PORT,
+        flags: Flags::SYN,
+        seq_number: REMOTE_ISN,
+        ack_number: None,
+        window_len: 1072,
+        window_scale: None,
+        max_seg_size: Some(536),
+        sack_permitted: false,
+        sack_ranges: [None; 3],
+        timestamp: None,
+        fast_open: None,
+        payload_len: 0,
+    };
+
+    // The answer in the receive path is lost, the SYN-ACK is sent again after a timeout.
+    stack.receive_with(ServerReader(&mut server), syn(4000), Instant::from_millis(10));
+    // The next idle entry listens for the next connection attempt.
+    assert!(stack.transmit_with(&mut server, Instant::from_millis(10)).is_none());
+    assert!(server.is_listening());
+    assert!(!server.backlog()[1].is_idle());
+
+    let syn_ack = stack.transmit_with(&mut server, Instant::from_millis(1100))
+        .expect("Retransmits the SYN-ACK");
+    assert!(syn_ack.flags.syn());
+    assert_eq!(syn_ack.dst_port, 4000);
+    assert_eq!(syn_ack.ack_number, Some(REMOTE_ISN + 1));
+    assert!(syn_ack.window_len > 0);
+
+    // Completing the handshake establishes the connection which sends its data.
+    let ack = remote_segment(&syn_ack, syn_ack.seq_number + 1, 1072);
+    stack.receive_with(ServerReader(&mut server), ack, Instant::from_millis(1110));
+    let first = server.backlog()[0].connection_key().unwrap();
+    assert_eq!(stack.tcp.get(first).unwrap().connection().current, tcp::State::Established);
+
+    let data = stack.transmit_with(&mut server, Instant::from_millis(1110))
+        .expect("Sends data on the accepted connection");
+    assert_eq!(data.dst_port, 4000);
+    assert_eq!(data.seq_number, syn_ack.seq_number + 1);
+    assert_eq!(data.payload_len, 536);
+
+    // A second client is accepted while the first connection is active.
+    stack.receive_with(ServerReader(&mut server), syn(4001), Instant::from_millis(1120));
+    let second = server.backlog()[1].connection_key().unwrap();
+    assert_eq!(stack.tcp.get(second).unwrap().connection().current, tcp::State::SynReceived);
+    assert!(stack.transmit_with(&mut server, Instant::from_millis(1120)).is_none());
+    assert!(!server.is_listening());
+
+    // The remote closes the first connection as well. After the time wait the entry is finished
+    // and can be reused afterwards.
+    assert!(data.flags.fin());
+    let fin_end = data.seq_number + 536 + 1;
+    let mut fin = remote_segment(&syn_ack, fin_end, 1072);
+    fin.flags = Flags::FIN;
+    stack.receive_with(ServerReader(&mut server), fin, Instant::from_millis(1130));
+    assert_eq!(stack.tcp.get(first).unwrap().connection().current, tcp::State::TimeWait);
+    for _ in 0..4 {
+        let _ = stack.transmit_with(&mut server, Instant::from_secs(60));
+    }
+
+    assert!(server.backlog()[0].is_closed());
+    assert!(!server.release(1));
+    assert!(server.release(0));
+
+    assert!(stack.transmit_with(&mut server, Instant::from_millis(1140)).is_none());
+    assert!(server.is_listening());
+    assert!(!server.backlog()[0].is_idle());
+}
+
+#[test]
+fn fin_without_data() {
+    let mut neighbors = [arp::Neighbor::default(); 1];
+    let mut routes = [ip::Route::unspecified(); 1];
+
+    let mut stack = Stack::new(&mut neighbors[..], &mut routes[..], 1);
+
+    let mut client = Client::new(IP_ADDR_REMOTE.into(), REMOTE_PORT,
+        tcp::io::Sink::default(),
+        tcp::io::SendFrom::once(vec![]));
+
+    let syn = stack.transmit(&mut client, Instant::from_millis(0))
+        .expect("Sends a SYN");
+    let fin_seq = syn.seq_number + 1;
+
+    let mut syn_ack = remote_segment(&syn, fin_seq, 1072);
+    syn_ack.flags = Flags::SYN;
+    syn_ack.seq_number = REMOTE_ISN;
+    stack.receive(&mut client, syn_ack, Instant::from_millis(10));
+
+    let fin = stack.transmit(&mut client, Instant::from_millis(10))
+        .expect("Sends a FIN");
+    assert!(fin.flags.fin());
+    assert_eq!(fin.seq_number, fin_seq);
+    assert_eq!(fin.payload_len, 0);
+    assert!(stack.transmit(&mut client, Instant::from_millis(10)).is_none());
+
+    // The FIN is lost and retransmitted.
+    let again = stack.transmit(&mut client, Instant::from_millis(3100))
+        .expect("Retransmits the FIN");
+    assert!(again.flags.fin());
+    assert_eq!(again.seq_number, fin_seq);
+    assert_eq!(again.payload_len, 0);
+}
+
+#[test]
+fn syn_cookies() {
+    const LOCAL_PORT: u16 = 80;
+
+    let mut neighbors = [arp::Neighbor::default(); 1];
+    let mut routes = [ip::Route::unspecified(); 1];
+
+    let mut stack = Stack::new(&mut neighbors[..], &mut routes[..], 1);
+    stack.tcp.set_syn_cookies(true);
+
+    let backlog = Client::from_buffers(
+        tcp::io::Sink::default(),
+        tcp::io::SendFrom::once(vec![0; 536]));
+    let mut server = Server::new(IP_ADDR_HOST.into(), LOCAL_PORT, Slice::One(backlog));
+    assert!(stack.transmit_with(&mut server, Instant::from_millis(0)).is_none());
+    let key = server.backlog()[0].connection_key().unwrap();
+
+    let syn = |src_port| Repr {
+        src_port,
+        dst_port: LOCAL_PORT,
+        flags: Flags::SYN,
+        seq_number: REMOTE_ISN,
+        ack_number: None,
+        window_len: 1072,
+        window_scale: Some(2),
+        max_seg_size: Some(1460),
+        sack_permitted: true,
+        sack_ranges: [None; 3],
+        timestamp: None,
+        fast_open: None,
+        payload_len: 0,
+    };
+
+    // Many connection attempts are answered, none of them occupies the single slot.
+    let mut answers = Vec::new();
+    for src_port in 4000..4010 {
+        stack.receive_with(ServerReader(&mut server), syn(src_port), Instant::from_millis(10));
+        let syn_ack = stack.last_segment();
+        assert!(syn_ack.flags.syn());
+        assert_eq!(syn_ack.ack_number, Some(REMOTE_ISN + 1));
+        assert!(syn_ack.sack_permitted);
+        answers.push(syn_ack);
+    }
+    assert_eq!(stack.tcp.get(key).unwrap().connection().current, tcp::State::Listen);
+
+    // A forged cookie is reset.
+    let forged = remote_segment(&answers[0], answers[0].seq_number + 2, 1072);
+    stack.receive_with(ServerReader(&mut server), forged, Instant::from_millis(20));
+    assert!(stack.last_segment().flags.rst());
+    assert_eq!(stack.tcp.get(key).unwrap().connection().current, tcp::State::Listen);
+
+    // An expired cookie is reset as well.
+    let late = remote_segment(&answers[1], answers[1].seq_number + 1, 1072);
+    stack.receive_with(ServerReader(&mut server), late, Instant::from_secs(200));
+    assert!(stack.last_segment().flags.rst());
+
+    // The final ACK of a valid handshake creates the connection with the encoded parameters.
+    let ack = remote_segment(&answers[5], answers[5].seq_number + 1, 1072);
+    stack.receive_with(ServerReader(&mut server), ack, Instant::from_millis(30));
+    let slot = stack.tcp.get(key).unwrap();
+    assert_eq!(slot.four_tuple().remote_port, 4005);
+    let connection = slot.connection();
+    assert_eq!(connection.current, tcp::State::Established);
+    assert_eq!(connection.sender_maximum_segment_size, 1460);
+    assert!(connection.selective_acknowledgements);
+
+    let data = stack.transmit_with(&mut server, Instant::from_millis(30))
+        .expect("Sends data on the connection");
+    assert_eq!(data.dst_port, 4005);
+    assert_eq!(data.seq_number, answers[5].seq_number + 1);
+    assert_eq!(data.payload_len, 536);
+}
+
+#[test]
+fn fast_open_client() {
+    let mut neighbors = [arp::Neighbor::default(); 1];
+    let mut routes = [ip::Route::unspecified(); 1];
+
+    let mut stack = Stack::new(&mut neighbors[..], &mut routes[..], 2);
+    stack.tcp.set_fast_open_cache(Map::Pairs(List::new(Slice::One(Default::default()))));
+    let cookie = FastOpenCookie::new(&[1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+
+    // Without a cached cookie the SYN requests one.
+    let mut client = Client::new(IP_ADDR_REMOTE.into(), REMOTE_PORT,
+        tcp::io::Sink::default(),
+        tcp::io::SendFrom::once(vec![0; 100]));
+    let syn = stack.transmit(&mut client, Instant::from_millis(0))
+        .expect("Sends a SYN");
+    assert_eq!(syn.fast_open, Some(FastOpenCookie::request()));
+    assert_eq!(syn.payload_len, 0);
+
+    let mut syn_ack = remote_segment(&syn, syn.seq_number + 1, 1072);
+    syn_ack.flags = Flags::SYN;
+    syn_ack.seq_number = REMOTE_ISN;
+    syn_ack.fast_open = Some(cookie);
+    stack.receive(&mut client, syn_ack, Instant::from_millis(10));
+
+    // The next connection to the remote sends its data with the cached cookie.
+    let mut client = Client::new(IP_ADDR_REMOTE.into(), REMOTE_PORT + 1,
+        tcp::io::Sink::default(),
+        tcp::io::SendFrom::once(vec![0; 100]));
+    let syn = stack.transmit(&mut client, Instant::from_millis(20))
+        .expect("Sends a SYN with data");
+    assert!(syn.flags.syn());
+    assert_eq!(syn.fast_open, Some(cookie));
+    assert_eq!(syn.payload_len, 100);
+
+    // The remote only acknowledges the SYN, so the data is sent again.
+    let mut syn_ack = remote_segment(&syn, syn.seq_number + 1, 1072);
+    syn_ack.flags = Flags::SYN;
+    syn_ack.seq_number = REMOTE_ISN;
+    stack.receive(&mut client, syn_ack, Instant::from_millis(30));
+
+    let data = stack.transmit(&mut client, Instant::from_millis(30))
+        .expect("Sends the data after the handshake");
+    assert_eq!(data.seq_number, syn.seq_number + 1);
+    assert_eq!(data.payload_len, 100);
+}
+
+#[test]
+fn fast_open_server() {
+    const LOCAL_PORT: u16 = 80;
+
+    let mut neighbors = [arp::Neighbor::default(); 1];
+    let mut routes = [ip::Route::unspecified(); 1];
+
+    let mut stack = Stack::new(&mut neighbors[..], &mut routes[..], 4);
+    stack.tcp.set_fast_open(true);
+
+    let backlog = (0..3)
+        .map(|_| Client::from_buffers(
+            tcp::io::Sink::default(),
+            tcp::io::SendFrom::once(vec![])))
+        .collect();
+    let mut server = Server::new(IP_ADDR_HOST.into(), LOCAL_PORT, Slice::Many(backlog));
+    assert!(stack.transmit_with(&mut server, Instant::from_millis(0)).is_none());
+
+    let syn = |src_port, fast_open, payload_len| Repr {
+        src_port,
+        dst_port: LOCAL_PORT,
+        flags: Flags::SYN,
+        seq_number: REMOTE_ISN,
+        ack_number: None,
+        window_len: 1072,
+        window_scale: None,
+        max_seg_size: Some(536),
+        sack_permitted: false,
+        sack_ranges: [None; 3],
+        timestamp: None,
+        fast_open,
+        payload_len,
+    };
+
+    // A cookie request is answered with a cookie.
+    stack.receive_with(ServerReader(&mut server), syn(4000, Some(FastOpenCookie::request()), 0),
+        Instant::from_millis(10));
+    let syn_ack = stack.last_segment();
+    assert_eq!(syn_ack.ack_number, Some(REMOTE_ISN + 1));
+    let cookie = syn_ack.fast_open.expect("Provides a cookie");
+    assert!(!cookie.is_request());
+    assert!(stack.transmit_with(&mut server, Instant::from_millis(10)).is_none());
+
+    // Data with an invalid cookie is not acknowledged but a valid cookie is provided.
+    let forged = FastOpenCookie::new(&[0; 8]).unwrap();
+    stack.receive_with(ServerReader(&mut server), syn(4001, Some(forged), 100),
+        Instant::from_millis(20));
+    let syn_ack = stack.last_segment();
+    assert_eq!(syn_ack.ack_number, Some(REMOTE_ISN + 1));
+    assert_eq!(syn_ack.fast_open, Some(cookie));
+    assert!(stack.transmit_with(&mut server, Instant::from_millis(20)).is_none());
+
+    // Data with a valid cookie is delivered and then acknowledged by the SYN-ACK.
+    stack.receive_with(ServerReader(&mut server), syn(4002, Some(cookie), 100),
+        Instant::from_millis(30));
+    // The server tries each of its connections in turn.
+    let syn_ack = (0..3)
+        .filter_map(|_| stack.transmit_with(&mut server, Instant::from_millis(30)))
+        .find(|segment| segment.dst_port == 4002)
+        .expect("Sends the SYN-ACK");
+    assert!(syn_ack.flags.syn());
+    assert_eq!(syn_ack.ack_number, Some(REMOTE_ISN + 101));
+    assert_eq!(syn_ack.fast_open, None);
+}
+
+#[test]
+fn path_mtu_clamps_segments() {
+    let mut neighbors = [arp::Neighbor::default(); 1];
+    let mut routes = [ip::Route::unspecified(); 1];
+    let mut path_mtu = [ip::PathMtu::default(); 1];
+
+    let mut stack = Stack::new(&mut neighbors[..], &mut routes[..], 1);
+
+    // Some router on the path reported a smaller mtu.
+    let mut cache = ip::PathMtuCache::new(&mut path_mtu[..]);
+    cache.update(IP_ADDR_REMOTE.into(), 1000, Instant::from_millis(0));
+    stack.ip.set_path_mtu_cache(cache);
+
+    let mut client = Client::new(IP_ADDR_REMOTE.into(), REMOTE_PORT,
+        tcp::io::Sink::default(),
+        tcp::io::SendFrom::once(vec![0; 2000]));
+
+    let syn = stack.transmit(&mut client, Instant::from_millis(0))
+        .expect("Sends a SYN");
+    let data_start = syn.seq_number + 1;
+
+    let mut syn_ack = remote_segment(&syn, data_start, 8000);
+    syn_ack.flags = Flags::SYN;
+    syn_ack.seq_number = REMOTE_ISN;
+    syn_ack.max_seg_size = Some(1460);
+    stack.receive(&mut client, syn_ack, Instant::from_millis(10));
+
+    let data = stack.transmit(&mut client, Instant::from_millis(10))
+        .expect("Sends data");
+    assert_eq!(data.payload_len, 1000 - 40);
+
+    // Once the learned mtu expires, the announced segment size is used again.
+    let acked = remote_segment(&syn, data_start + 960, 8000);
+    stack.receive(&mut client, acked, Instant::from_secs(700));
+    let data = stack.transmit(&mut client, Instant::from_secs(700))
+        .expect("Sends the remaining data");
+    assert_eq!(data.seq_number, data_start + 960);
+    assert_eq!(data.payload_len, 1040);
+}
+
+#[test]
+fn time_wait_side_table() {
+    let mut neighbors = [arp::Neighbor::default(); 1];
+    let mut routes = [ip::Route::unspecified(); 1];
+
+    let mut stack = Stack::new(&mut neighbors[..], &mut routes[..], 1);
+    stack.tcp.set_time_wait_table(tcp::TimeWaitTable::new(Slice::One(Default::default())));
+
+    let mut client = Client::new(IP_ADDR_REMOTE.into(), REMOTE_PORT,
+        tcp::io::Sink::default(),
+        tcp::io::SendFrom::once(vec![0; 100]));
+
+    let syn = stack.transmit(&mut client, Instant::from_millis(0))
+        .expect("Sends a SYN");
+    let key = client.connection_key().unwrap();
+    let data_start = syn.seq_number + 1;
+
+    let mut syn_ack = remote_segment(&syn, data_start, 1072);
+    syn_ack.flags = Flags::SYN;
+    syn_ack.seq_number = REMOTE_ISN;
+    syn_ack.max_seg_size = Some(536);
+    stack.receive(&mut client, syn_ack, Instant::from_millis(10));
+
+    let data = stack.transmit(&mut client, Instant::from_millis(10))
+        .expect("Sends the data");
+    assert!(data.flags.fin());
+
+    // The remote acknowledges everything and closes as well.
+    let mut fin = remote_segment(&syn, data_start + 101, 1072);
+    fin.flags = Flags::FIN;
+    stack.receive(&mut client, fin, Instant::from_millis(20));
+    assert_eq!(stack.tcp.get(key).unwrap().connection().current, tcp::State::TimeWait);
+    let ack = stack.transmit(&mut client, Instant::from_millis(20))
+        .expect("Acknowledges the FIN");
+    assert_eq!(ack.ack_number, Some(REMOTE_ISN + 2));
+
+    // The connection moves to the side table and frees its slot.
+    assert!(stack.transmit(&mut client, Instant::from_millis(30)).is_none());
+    assert!(stack.tcp.get(key).is_none());
+    let tuple = tcp::FourTuple {
+        local: IP_ADDR_HOST.into(),
+        local_port: syn.src_port,
+        remote: IP_ADDR_REMOTE.into(),
+        remote_port: REMOTE_PORT,
+    };
+    assert!(stack.tcp.time_wait_table().lookup(tuple, Instant::from_millis(30)).is_some());
+
+    // A RST does not end the TIME-WAIT state early and is not answered.
+    let mut rst = remote_segment(&syn, data_start + 101, 0);
+    rst.flags = Flags::RST;
+    rst.seq_number = REMOTE_ISN + 2;
+    stack.receive(&mut client, rst, Instant::from_millis(40));
+    let frame = ethernet::frame::new_checked(stack.nic.get(0).unwrap()).unwrap();
+    assert_eq!(frame.dst_addr(), MAC_ADDR_HOST);
+    assert!(stack.tcp.time_wait_table().lookup(tuple, Instant::from_millis(40)).is_some());
+
+    // A retransmitted FIN is acknowledged from the side table.
+    stack.receive(&mut client, fin, Instant::from_millis(50));
+    let ack = stack.last_segment();
+    assert_eq!(ack.seq_number, data_start + 101);
+    assert_eq!(ack.ack_number, Some(REMOTE_ISN + 2));
+    assert!(!ack.flags.rst());
+
+    // The tuple can not be opened again before the TIME-WAIT period ends.
+    let mut again = Client::bind(
+        IP_ADDR_HOST.into(), syn.src_port,
+        IP_ADDR_REMOTE.into(), REMOTE_PORT,
+        tcp::io::Sink::default(),
+        tcp::io::SendFrom::once(vec![0; 100]));
+    assert!(stack.transmit(&mut again, Instant::from_millis(60)).is_none());
+    assert!(again.connection_key().is_none());
+    assert!(stack.tcp.time_wait_table().lookup(tuple, Instant::from_millis(60)).is_some());
+}
+
+#[test]
+fn ephemeral_ports() {
+    let mut neighbors = [arp::Neighbor::default(); 1];
+    let mut routes = [ip::Route::unspecified(); 1];
+
+    let mut stack = Stack::new(&mut neighbors[..], &mut routes[..], 4);
+    stack.tcp.set_ephemeral_ports(50000..=50001);
+
+    let client = || Client::new(IP_ADDR_REMOTE.into(), REMOTE_PORT,
+        tcp::io::Sink::default(),
+        tcp::io::SendFrom::once(vec![0; 100]));
+
+    let (mut first, mut second, mut third) = (client(), client(), client());
+    let first = stack.transmit(&mut first, Instant::from_millis(0))
+        .expect("Sends a SYN");
+    let second = stack.transmit(&mut second, Instant::from_millis(0))
+        .expect("Sends a SYN");
+    assert!(stack.tcp.ephemeral_ports().contains(&first.src_port));
+    assert!(stack.tcp.ephemeral_ports().contains(&second.src_port));
+    assert_ne!(first.src_port, second.src_port);
+
+    // All ports towards the remote are in use, the client waits for one to become free.
+    assert!(stack.transmit(&mut third, Instant::from_millis(0)).is_none());
+    assert!(!third.is_closed());
+
+    // An explicitly bound client uses its port, unless the tuple is taken.
+    let mut bound = Client::bind(IP_ADDR_HOST.into(), 4000, IP_ADDR_REMOTE.into(), REMOTE_PORT,
+        tcp::io::Sink::default(),
+        tcp::io::SendFrom::once(vec![0; 100]));
+    let syn = stack.transmit(&mut bound, Instant::from_millis(0))
+        .expect("Sends a SYN");
+    assert_eq!(syn.src_port, 4000);
+
+    let mut conflict = Client::bind(IP_ADDR_HOST.into(), first.src_port, IP_ADDR_REMOTE.into(), REMOTE_PORT,
+        tcp::io::Sink::default(),
+        tcp::io::SendFrom::once(vec![0; 100]));
+    assert!(stack.transmit(&mut conflict, Instant::from_millis(0)).is_none());
+}
+
+#[test]
+fn server_relisten() {
+    const LOCAL_PORT: u16 = 80;
+
+    let mut neighbors = [arp::Neighbor::default(); 1];
+    let mut routes = [ip::Route::unspecified(); 1];
+
+    let mut stack = Stack::new(&mut neighbors[..], &mut routes[..], 2);
+
+    let backlog = vec![Client::from_buffers(
+        tcp::io::Sink::default(),
+        tcp::io::SendFrom::once(vec![0; 536]))];
+    let mut server = Server::new(IP_ADDR_HOST.into(), LOCAL_PORT, Slice::Many(backlog));
+    assert!(stack.transmit_with(&mut server, Instant::from_millis(0)).is_none());
+    let key = server.backlog()[0].connection_key().unwrap();
+
+    let syn = |src_port| Repr {
+        src_port,
+        dst_port: LOCAL_PORT,
+        flags: Flags::SYN,
+        seq_number: REMOTE_ISN,
+        ack_number: None,
+        window_len: 1072,
+        window_scale: None,
+        max_seg_size: Some(536),
+        sack_permitted: false,
+        sack_ranges: [None; 3],
+        timestamp: None,
+        fast_open: None,
+        payload_len: 0,
+    };
+
+    stack.receive_with(ServerReader(&mut server), syn(4000), Instant::from_millis(10));
+    assert!(stack.transmit_with(&mut server, Instant::from_millis(10)).is_none());
+    assert!(!server.is_listening());
+
+    // The remote gives up on the attempt, the entry returns to listening.
+    let mut rst = syn(4000);
+    rst.flags = Flags::RST;
+    rst.seq_number = REMOTE_ISN + 1;
+    stack.receive_with(&mut server, rst, Instant::from_millis(20));
+    assert!(server.is_listening());
+    let slot = stack.tcp.get(key).unwrap();
+    assert_eq!(slot.connection().current, tcp::State::Listen);
+    assert_eq!(slot.four_tuple().remote_port, 0);
+
+    // And accepts the next attempt.
+    assert!(stack.transmit_with(&mut server, Instant::from_millis(20)).is_none());
+    stack.receive_with(ServerReader(&mut server), syn(4001), Instant::from_millis(30));
+    let slot = stack.tcp.get(key).unwrap();
+    assert_eq!(slot.connection().current, tcp::State::SynReceived);
+    assert_eq!(slot.four_tuple().remote_port, 4001);
+}
+
+#[test]
+fn client_shutdown_and_abort() {
+    let mut neighbors = [arp::Neighbor::default(); 1];
+    let mut routes = [ip::Route::unspecified(); 1];
+
+    let mut stack = Stack::new(&mut neighbors[..], &mut routes[..], 2);
+
+    // The send buffer never ends the stream by itself.
+    let mut client = Client::new(IP_ADDR_REMOTE.into(), REMOTE_PORT,
+        tcp::io::Sink::default(),
+        tcp::io::SendFrom::new(vec![0; 100]));
+    let syn = stack.transmit(&mut client, Instant::from_millis(0))
+        .expect("Sends a SYN");
+    let key = client.connection_key().unwrap();
+    let mut syn_ack = remote_segment(&syn, syn.seq_number + 1, 1072);
+    syn_ack.flags = Flags::SYN;
+    syn_ack.seq_number = REMOTE_ISN;
+    stack.receive(&mut client, syn_ack, Instant::from_millis(10));
+
+    let data = stack.transmit(&mut client, Instant::from_millis(10))
+        .expect("Sends the data");
+    assert_eq!(data.payload_len, 100);
+    assert!(!data.flags.fin());
+
+    client.shutdown_write();
+    let fin = stack.transmit(&mut client, Instant::from_millis(10))
+        .expect("Sends the FIN");
+    assert!(fin.flags.fin());
+    assert_eq!(fin.seq_number, data.seq_number + 100);
+
+    // The remote acknowledges everything but keeps sending.
+    let ack = remote_segment(&syn, fin.seq_number + 1, 1072);
+    stack.receive(&mut client, ack, Instant::from_millis(20));
+    let mut more = remote_segment(&syn, fin.seq_number + 1, 1072);
+    more.payload_len = 50;
+    stack.receive(&mut client, more, Instant::from_millis(30));
+    let connection = stack.tcp.get(key).unwrap().connection();
+    assert_eq!(connection.current, tcp::State::FinWait);
+    assert_eq!(connection.recv.next, REMOTE_ISN + 51);
+
+    // A second connection is reset before it is established.
+    let mut other = Client::new(IP_ADDR_REMOTE.into(), REMOTE_PORT + 1,
+        tcp::io::Sink::default(),
+        tcp::io::SendFrom::new(vec![0; 100]));
+    let syn = stack.transmit(&mut other, Instant::from_millis(40))
+        .expect("Sends a SYN");
+    let key = other.connection_key().unwrap();
+    let mut syn_ack = remote_segment(&syn, syn.seq_number + 1, 1072);
+    syn_ack.flags = Flags::SYN;
+    syn_ack.seq_number = REMOTE_ISN;
+    stack.receive(&mut other, syn_ack, Instant::from_millis(50));
+
+    other.abort();
+    let rst = stack.transmit(&mut other, Instant::from_millis(50))
+        .expect("Sends a RST");
+    assert!(rst.flags.rst());
+    assert_eq!(rst.seq_number, syn.seq_number + 1);
+    assert!(other.is_closed());
+    assert!(stack.tcp.get(key).is_none());
+}
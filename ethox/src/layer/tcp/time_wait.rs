@@ -0,0 +1,306 @@
+// Connections in TIME-WAIT only need to answer retransmitted FINs and to keep the tuple from being
+// reused too early. A full `Slot` is wasteful for that, so the few values needed are moved into
+// a compact side table and the slot is freed immediately.
+use crate::managed::Slice;
+use crate::time::{Expiration, Instant};
+use crate::wire::tcp;
+
+use super::endpoint::FourTuple;
+
+/// The remains of a connection in TIME-WAIT.
+///
+/// The entry is invalid and considered free when its remote address is unspecified, which is also
+/// the default value.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct TimeWait {
+    tuple: FourTuple,
+    expires_at: Expiration,
+    send_next: tcp::SeqNumber,
+    recv_next: tcp::SeqNumber,
+    recent_timestamp: Option<u32>,
+    timestamp_offset: u32,
+}
+
+/// A table of connections in TIME-WAIT backed by a preallocated slice.
+///
+/// # Examples
+///
+/// On systems without heap, use:
+///
+/// ```rust
+/// use ethox::layer::tcp::{TimeWait, TimeWaitTable};
+///
+/// let mut time_wait_storage = [TimeWait::default(); 16];
+/// let mut time_wait_table = TimeWaitTable::new(&mut time_wait_storage[..]);
+/// ```
+///
+/// ## Details
+///
+/// While in the table, a RST for the tuple is ignored as recommended by RFC 1337. A new SYN for
+/// the tuple is accepted by a listening connection when its timestamp or otherwise its sequence
+/// number is larger than that of the old connection, as in RFC 6191. Retransmitted FINs and all
+/// other segments with data are acknowledged. When the table is full, connections stay in their
+/// slot for the whole TIME-WAIT period instead.
+#[derive(Debug)]
+pub struct Table<'a> {
+    entries: Slice<'a, TimeWait>,
+}
+
+/// How a segment for a tuple that may be in TIME-WAIT is handled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Arrival {
+    /// The tuple is not in TIME-WAIT (anymore), deliver it to connections as usual.
+    Deliver,
+    /// Drop the segment without an answer.
+    Drop,
+    /// Answer the segment with this acknowledgment.
+    Answer(tcp::Repr),
+}
+
+impl TimeWait {
+    /// Create the entry from the final state of a connection.
+    pub(crate) fn new(
+        tuple: FourTuple,
+        send_next: tcp::SeqNumber,
+        recv_next: tcp::SeqNumber,
+        recent_timestamp: Option<u32>,
+        timestamp_offset: u32,
+        expires_at: Instant,
+    ) -> Self {
+        TimeWait {
+            tuple,
+            expires_at: Expiration::When(expires_at),
+            send_next,
+            recv_next,
+            recent_timestamp,
+            timestamp_offset,
+        }
+    }
+
+    /// The connection tuple that may not yet be reused.
+    pub fn four_tuple(&self) -> FourTuple {
+        self.tuple
+    }
+
+    /// The end of the TIME-WAIT period.
+    pub fn expires_at(&self) -> Expiration {
+        self.expires_at
+    }
+
+    fn is_valid(&self, time: Instant) -> bool {
+        !self.tuple.remote.is_unspecified() && self.expires_at > Expiration::When(time)
+    }
+
+    /// Check if a SYN is from a new incarnation of the connection, RFC 6191 section 2.
+    fn is_newer(&self, segment: &tcp::Repr) -> bool {
+        match (segment.timestamp, self.recent_timestamp) {
+            (Some(timestamp), Some(recent)) => (timestamp.tsval.wrapping_sub(recent) as i32) > 0,
+            _ => segment.seq_number > self.recv_next,
+        }
+    }
+
+    fn arrives(&self, segment: &tcp::Repr, time: Instant) -> Arrival {
+        let flags = segment.flags;
+        if flags.rst() {
+            // RFC 1337: a RST would assassinate the TIME-WAIT state prematurely.
+            Arrival::Drop
+        } else if flags.syn() && segment.ack_number.is_none() && self.is_newer(segment) {
+            Arrival::Deliver
+        } else if flags.syn() || flags.fin() || segment.payload_len > 0 {
+            Arrival::Answer(self.ack(time))
+        } else {
+            // Never answer a bare ACK, that could start an endless exchange.
+            Arrival::Drop
+        }
+    }
+
+    /// The acknowledgment of the final state, with timestamps if they were negotiated.
+    fn ack(&self, time: Instant) -> tcp::Repr {
+        let timestamp = self.recent_timestamp.map(|recent| tcp::Timestamp {
+            tsval: (time.millis() as u32).wrapping_add(self.timestamp_offset),
+            tsecr: recent,
+        });
+
+        tcp::Repr {
+            src_port: self.tuple.local_port,
+            dst_port: self.tuple.remote_port,
+            flags: tcp::Flags::default(),
+            seq_number: self.send_next,
+            ack_number: Some(self.recv_next),
+            window_len: 0,
+            window_scale: None,
+            max_seg_size: None,
+            sack_permitted: false,
+            sack_ranges: [None; 3],
+            timestamp,
+            fast_open: None,
+            payload_len: 0,
+        }
+    }
+}
+
+impl<'a> Table<'a> {
+    /// Create a table with all entries in the storage cleared.
+    pub fn new<T>(storage: T) -> Self
+        where T: Into<Slice<'a, TimeWait>>
+    {
+        let mut entries = storage.into();
+        for entry in entries.iter_mut() {
+            *entry = TimeWait::default();
+        }
+        Table { entries }
+    }
+
+    /// Find the entry of a tuple that is still in TIME-WAIT.
+    pub fn lookup(&self, tuple: FourTuple, time: Instant) -> Option<&TimeWait> {
+        self.entries.iter()
+            .find(|entry| entry.tuple == tuple && entry.is_valid(time))
+    }
+
+    /// Record a connection in TIME-WAIT.
+    ///
+    /// Returns `false` if there is no free entry, then the connection must keep its slot.
+    pub fn insert(&mut self, entry: TimeWait, time: Instant) -> bool {
+        let slot = self.entries.iter()
+            .position(|old| old.tuple == entry.tuple)
+            .or_else(|| self.entries.iter().position(|old| !old.is_valid(time)));
+
+        match slot {
+            Some(slot) => {
+                self.entries[slot] = entry;
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Remove the entry of a tuple, allowing its immediate reuse.
+    pub fn remove(&mut self, tuple: FourTuple) {
+        for entry in self.entries.iter_mut().filter(|entry| entry.tuple == tuple) {
+            *entry = TimeWait::default();
+        }
+    }
+
+    /// Decide on a segment arriving for a tuple.
+    ///
+    /// The entry is removed when a new incarnation of the connection is accepted.
+    pub(crate) fn arrives(&mut self, tuple: FourTuple, segment: &tcp::Repr, time: Instant)
+        -> Arrival
+    {
+        let arrival = match self.lookup(tuple, time) {
+            Some(entry) => entry.arrives(segment, time),
+            None => return Arrival::Deliver,
+        };
+
+        if arrival == Arrival::Deliver {
+            self.remove(tuple);
+        }
+
+        arrival
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::time::Duration;
+    use crate::wire::ip::Address;
+
+    const TUPLE: FourTuple = FourTuple {
+        local: Address::v4(192, 0, 10, 1),
+        remote: Address::v4(192, 0, 10, 2),
+        local_port: 80,
+        remote_port: 4000,
+    };
+
+    fn entry(recent_timestamp: Option<u32>) -> TimeWait {
+        TimeWait::new(
+            TUPLE,
+            tcp::SeqNumber(100),
+            tcp::SeqNumber(5000),
+            recent_timestamp,
+            0,
+            Instant::from_secs(2))
+    }
+
+    fn segment(flags: tcp::Flags, seq_number: tcp::SeqNumber) -> tcp::Repr {
+        tcp::Repr {
+            src_port: TUPLE.remote_port,
+            dst_port: TUPLE.local_port,
+            flags,
+            seq_number,
+            ack_number: None,
+            window_len: 1000,
+            window_scale: None,
+            max_seg_size: None,
+            sack_permitted: false,
+            sack_ranges: [None; 3],
+            timestamp: None,
+            fast_open: None,
+            payload_len: 0,
+        }
+    }
+
+    #[test]
+    fn protects_and_expires() {
+        let mut storage = [TimeWait::default(); 1];
+        let mut table = Table::new(&mut storage[..]);
+        let time = Instant::from_secs(0);
+        assert!(table.insert(entry(None), time));
+
+        // RFC 1337, no assassination.
+        let rst = segment(tcp::Flags::RST, tcp::SeqNumber(5000));
+        assert_eq!(table.arrives(TUPLE, &rst, time), Arrival::Drop);
+
+        // A retransmitted FIN is acknowledged again.
+        let fin = segment(tcp::Flags::FIN, tcp::SeqNumber(4999));
+        match table.arrives(TUPLE, &fin, time) {
+            Arrival::Answer(ack) => {
+                assert_eq!(ack.seq_number, tcp::SeqNumber(100));
+                assert_eq!(ack.ack_number, Some(tcp::SeqNumber(5000)));
+            },
+            other => panic!("Unexpected {:?}", other),
+        }
+
+        // Another connection does not fit until the entry expired.
+        let other = TimeWait::new(
+            FourTuple { remote_port: 4001, ..TUPLE },
+            tcp::SeqNumber(0),
+            tcp::SeqNumber(0),
+            None,
+            0,
+            Instant::from_secs(4));
+        assert!(!table.insert(other, time));
+        let later = time + Duration::from_secs(2);
+        assert_eq!(table.arrives(TUPLE, &rst, later), Arrival::Deliver);
+        assert!(table.insert(other, later));
+    }
+
+    #[test]
+    fn accepts_newer_syn() {
+        let mut storage = [TimeWait::default(); 1];
+        let mut table = Table::new(&mut storage[..]);
+        let time = Instant::from_secs(0);
+
+        assert!(table.insert(entry(None), time));
+        let old = segment(tcp::Flags::SYN, tcp::SeqNumber(4000));
+        assert!(matches!(table.arrives(TUPLE, &old, time), Arrival::Answer(_)));
+        let new = segment(tcp::Flags::SYN, tcp::SeqNumber(6000));
+        assert_eq!(table.arrives(TUPLE, &new, time), Arrival::Deliver);
+        assert!(table.lookup(TUPLE, time).is_none());
+
+        // With timestamps the sequence number does not matter.
+        assert!(table.insert(entry(Some(1000)), time));
+        let mut old = segment(tcp::Flags::SYN, tcp::SeqNumber(6000));
+        old.timestamp = Some(tcp::Timestamp { tsval: 900, tsecr: 0 });
+        match table.arrives(TUPLE, &old, time) {
+            // The answer carries the negotiated timestamp option.
+            Arrival::Answer(ack) => assert_eq!(ack.timestamp,
+                Some(tcp::Timestamp { tsval: 0, tsecr: 1000 })),
+            other => panic!("Unexpected {:?}", other),
+        }
+        let mut new = segment(tcp::Flags::SYN, tcp::SeqNumber(4000));
+        new.timestamp = Some(tcp::Timestamp { tsval: 1100, tsecr: 0 });
+        assert_eq!(table.arrives(TUPLE, &new, time), Arrival::Deliver);
+    }
+}
@@ -20,6 +20,7 @@ pub use endpoint::{
 
 pub use packet::{
     Controller,
+    Fragmented,
     Init,
     Packet,
     RawPacket,
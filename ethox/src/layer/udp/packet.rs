@@ -3,7 +3,7 @@ use core::convert::TryFrom;
 use crate::nic::Info;
 use crate::layer::{Error, Result, ip};
 use crate::wire::{Payload, PayloadMut};
-use crate::wire::{udp, ip::Address, ip::Protocol};
+use crate::wire::{udp, ip::Address, ip::Ecn, ip::Protocol};
 
 /// An incoming UDP packet.
 pub struct Packet<'a, P: Payload> {
@@ -21,6 +21,15 @@ pub struct RawPacket<'a, P: Payload> {
     pub payload: &'a mut P,
 }
 
+/// An outgoing UDP datagram that is split into ip fragments if it exceeds the MTU.
+///
+/// The header is written and the checksum computed when the datagram is sent.
+#[must_use = "You need to call `send` explicitely on a Fragmented, otherwise no packet is sent."]
+pub struct Fragmented<'a, P: Payload> {
+    inner: ip::Fragmented<'a, P>,
+    repr: udp::Repr,
+}
+
 /// A reference to the endpoint of layers below (phy + eth + ip + udp).
 ///
 /// This is not really useful on its own but should instead be used either within a `Packet` or a
@@ -88,6 +97,14 @@ impl<'a> Controller<'a> {
             inner: self.inner.borrow_mut(),
         }
     }
+
+    /// Get the learned path MTU towards a destination.
+    ///
+    /// Datagrams whose ip packet exceeds this size will be dropped on the path. This is `None`
+    /// when no smaller value than the link MTU has been reported.
+    pub fn path_mtu(&self, dst_addr: Address) -> Option<u16> {
+        self.inner.path_mtu(dst_addr)
+    }
 }
 
 impl<'a, P: Payload> Packet<'a, P> {
@@ -135,6 +152,28 @@ impl<'a, P: Payload> Packet<'a, P> {
     }
 }
 
+impl<'a, P: PayloadMut> Fragmented<'a, P> {
+    /// A mutable slice containing the payload of the datagram.
+    pub fn payload_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.inner.payload_mut_slice()[8..]
+    }
+
+    /// Called last after having initialized the payload.
+    ///
+    /// Finalizes the datagram and sends its first fragment.
+    pub fn send(mut self) -> Result<()> {
+        let ip_repr = self.inner.repr();
+        // The device can not fill the checksum over several fragments.
+        let checksum = udp::Checksum::Manual {
+            src_addr: ip_repr.src_addr(),
+            dst_addr: ip_repr.dst_addr(),
+        };
+        let packet = udp::packet::new_unchecked_mut(self.inner.payload_mut_slice());
+        self.repr.emit(packet, checksum);
+        self.inner.send()
+    }
+}
+
 impl<'a, P: Payload + PayloadMut> RawPacket<'a, P> {
     /// Get the hardware info for that packet.
     pub fn info(&self) -> &dyn Info {
@@ -157,6 +196,9 @@ impl<'a, P: Payload + PayloadMut> RawPacket<'a, P> {
             dst_addr: init.dst_addr,
             protocol: Protocol::Udp,
             payload: packet_len,
+            dscp: 0,
+            ecn: Ecn::NotEct,
+            fragment: false,
         };
 
         let prepared = lower.prepare(lower_init)?;
@@ -171,6 +213,46 @@ impl<'a, P: Payload + PayloadMut> RawPacket<'a, P> {
             packet: udp::Packet::new_unchecked(packet, repr),
         })
     }
+
+    /// Initialize a datagram that may exceed the MTU.
+    ///
+    /// The payload is buffered in the [`Fragmenter`] of the ip endpoint and sent as fragments when
+    /// it does not fit into a single packet. Prefer `prepare` for payloads known to fit, it avoids
+    /// copying the payload.
+    ///
+    /// [`Fragmenter`]: ../ip/struct.Fragmenter.html
+    pub fn prepare_fragmented(self, init: Init) -> Result<Fragmented<'a, P>> {
+        let lower = ip::RawPacket {
+            control: self.control.inner,
+            payload: self.payload,
+        };
+
+        let packet_len = init.payload
+            .checked_add(8)
+            .ok_or(Error::BadSize)?;
+
+        let repr = udp::Repr {
+            src_port: init.src_port,
+            dst_port: init.dst_port,
+            length: u16::try_from(packet_len)
+                .map_err(|_| Error::BadSize)?,
+        };
+
+        let lower_init = ip::Init {
+            source: init.source,
+            dst_addr: init.dst_addr,
+            protocol: Protocol::Udp,
+            payload: packet_len,
+            dscp: 0,
+            ecn: Ecn::NotEct,
+            fragment: true,
+        };
+
+        Ok(Fragmented {
+            inner: lower.prepare_fragmented(lower_init)?,
+            repr,
+        })
+    }
 }
 
 impl Init {
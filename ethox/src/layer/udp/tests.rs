@@ -1,6 +1,10 @@
+use core::mem;
+
 use crate::managed::Slice;
-use crate::nic::{external::External, Device};
+use crate::storage::assembler::Contig;
+use crate::nic::{external::External, loopback::Loopback, Device};
 use crate::layer::{arp, eth, ip, udp};
+use crate::layer::hash::HashKey;
 use crate::wire::{ethernet, Payload, PayloadMut};
 use crate::wire::ip::{v4, Cidr, Subnet};
 
@@ -83,3 +87,62 @@ fn simple() {
         udp.recv_with(simple_recv))));
    assert_eq!(recv, Ok(1)); 
 }
+
+#[test]
+fn fragmented() {
+    let mut nic = Loopback::<Vec<u8>>::new(Slice::Many(vec![vec![0; 1600]; 4]));
+
+    let mut eth = eth::Endpoint::new(MAC_ADDR_SRC);
+
+    let mut neighbors = [arp::Neighbor::default(); 1];
+    let neighbors = {
+        let mut eth_cache = arp::NeighborCache::new(&mut neighbors[..]);
+        eth_cache.fill(IP_ADDR_SRC.into(), MAC_ADDR_SRC, None).unwrap();
+        eth_cache
+    };
+    let mut ip = ip::Endpoint::new(Cidr::new(IP_ADDR_SRC.into(), 24),
+        ip::Routes::new(Slice::empty()),
+        neighbors);
+    // Send to ourselves, reassembling the fragments again.
+    ip.set_fragmenter(ip::Fragmenter::new(vec![0; 4096], HashKey::from_key(0, 0)));
+    let mut buffers = [ip::ReassemblyBuffer::new(
+        vec![0; 4096],
+        vec![Contig::default(); 4096 / 16 + 1])];
+    ip.set_reassembly(ip::Reassembly::new(&mut buffers[..]));
+
+    let mut udp = udp::Endpoint::new(80);
+
+    let payload: Vec<u8> = (0..3000).map(|i| i as u8).collect();
+    let mut pending = true;
+    let sent = nic.tx(4, eth.send(ip.send(udp.send_with(|raw: udp::RawPacket<_>| {
+        if !mem::replace(&mut pending, false) {
+            return;
+        }
+
+        let init = udp::Init {
+            source: ip::Source::Exact(IP_ADDR_SRC.into()),
+            src_port: 80,
+            dst_addr: IP_ADDR_SRC.into(),
+            dst_port: 80,
+            payload: payload.len(),
+        };
+        let mut prepared = raw.prepare_fragmented(init)
+            .expect("Fits into the fragmenter");
+        prepared
+            .payload_mut_slice()
+            .copy_from_slice(&payload);
+        prepared.send()
+            .expect("Could send the first fragment");
+    }))));
+    // Three fragments of 1480, 1480 and 48 bytes.
+    assert_eq!(sent, Ok(3));
+    assert!(!ip.fragmenter().unwrap().is_pending());
+
+    let mut received = 0;
+    let recv = nic.rx(4, eth.recv(ip.recv(udp.recv_with(|packet: udp::Packet<_>| {
+        assert_eq!(packet.packet.payload().as_slice(), &payload[..]);
+        received += 1;
+    }))));
+    assert_eq!(recv, Ok(3));
+    assert_eq!(received, 1);
+}
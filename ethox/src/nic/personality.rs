@@ -1,5 +1,5 @@
 // FIXME: make most of these methods `const` as soon as possible.
-use crate::wire::{ip, udp, tcp, Checksum};
+use crate::wire::{ip, icmpv6, udp, tcp, Checksum};
 
 /// A general description of a device.
 ///
@@ -20,6 +20,7 @@ pub struct Personality {
 pub struct Capabilities {
     ipv4: Protocol,
     icmpv4: Protocol,
+    icmpv6: Icmpv6,
     udp: Udp,
     tcp: Tcp,
 }
@@ -34,6 +35,17 @@ pub struct Protocol {
     receive: Checksum,
 }
 
+/// A specialized instance of `Protocol` for ICMPv6.
+///
+/// Its checksum includes the ip pseudo header, unlike the one of ICMPv4. As for the transport
+/// protocols the checksum descriptors are thus created for a specific ip header.
+///
+/// It is possible to create one `From` a `Protocol` instance as `Icmpv6` is a specialization.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Icmpv6 {
+    inner: Protocol,
+}
+
 /// A specialized instance of `Protocol`.
 ///
 /// This is a different instance since the checksumming behaviour of network cards is much more
@@ -89,6 +101,7 @@ impl Capabilities {
         Capabilities {
             ipv4: Protocol::no_support(),
             icmpv4: Protocol::no_support(),
+            icmpv6: Icmpv6::no_support(),
             udp: Udp::no_support(),
             tcp: Tcp::no_support(),
         }
@@ -99,6 +112,16 @@ impl Capabilities {
         &self.icmpv4
     }
 
+    /// Check ICMPv6 support descriptor.
+    pub fn icmpv6(&self) -> &Icmpv6 {
+        &self.icmpv6
+    }
+
+    /// Mutably get ICMPv6 support descriptor.
+    pub fn icmpv6_mut(&mut self) -> &mut Icmpv6 {
+        &mut self.icmpv6
+    }
+
     /// Check IPv4 support descriptor.
     pub fn ipv4(&self) -> &Protocol {
         &self.ipv4
@@ -174,6 +197,45 @@ impl Protocol {
     }
 }
 
+impl Icmpv6 {
+    /// Create an ICMPv6 descriptor with no supported features.
+    pub fn no_support() -> Self {
+        Icmpv6 {
+            inner: Protocol::no_support(),
+        }
+    }
+
+    /// Create the `icmpv6::Checksum` instance necessary for sending a message.
+    ///
+    /// The enum `icmpv6::Checksum` controls when and how the checksum is filled in by the `wire`
+    /// portion of the library. This creates an instance which corresponds to the requirements of
+    /// the nic.
+    pub fn tx_checksum(&self, ip: ip::Repr) -> icmpv6::Checksum {
+        match self.inner.tx_checksum() {
+            Checksum::Manual => icmpv6::Checksum::Manual {
+                src_addr: ip.src_addr(),
+                dst_addr: ip.dst_addr(),
+            },
+            Checksum::Ignored => icmpv6::Checksum::Ignored,
+        }
+    }
+
+    /// Create the `icmpv6::Checksum` instance necessary for receiving a message.
+    ///
+    /// The enum `icmpv6::Checksum` controls when and how the checksum is filled in by the `wire`
+    /// portion of the library. This creates an instance which corresponds to the requirements of
+    /// the nic.
+    pub fn rx_checksum(&self, ip: ip::Repr) -> icmpv6::Checksum {
+        match self.inner.rx_checksum() {
+            Checksum::Manual => icmpv6::Checksum::Manual {
+                src_addr: ip.src_addr(),
+                dst_addr: ip.dst_addr(),
+            },
+            Checksum::Ignored => icmpv6::Checksum::Ignored,
+        }
+    }
+}
+
 impl Udp {
     /// Create a UDP descriptor with no supported features.
     pub fn no_support() -> Self {
@@ -252,6 +314,15 @@ impl Tcp {
     }
 }
 
+/// `Protocol` may be a simplified version in the future.
+impl From<Protocol> for Icmpv6 {
+    fn from(inner: Protocol) -> Self {
+        Icmpv6 {
+            inner,
+        }
+    }
+}
+
 /// `Protocol` may be a simplified version in the future.
 impl From<Protocol> for Udp {
     fn from(inner: Protocol) -> Self {
@@ -195,7 +195,12 @@ mod field {
     pub(crate) const ECHO_IDENT: Field = 4..6;
     pub(crate) const ECHO_SEQNO: Field = 6..8;
 
+    pub(crate) const NEXT_HOP_MTU: Field = 6..8;
+
     pub(crate) const HEADER_END: usize = 8;
+
+    // The ip header of a quoted datagram without options.
+    pub(crate) const IPV4_MIN_HEADER_LEN: usize = 20;
 }
 
 impl icmpv4 {
@@ -279,6 +284,17 @@ impl icmpv4 {
         NetworkEndian::read_u16(&self.0[field::ECHO_SEQNO])
     }
 
+    /// Return the next-hop MTU field (for fragmentation required messages, RFC 1191).
+    ///
+    /// This is zero when sent by routers that predate path MTU discovery.
+    ///
+    /// # Panics
+    /// This function may panic if this packet is not a destination unreachable packet.
+    #[inline]
+    pub fn next_hop_mtu(&self) -> u16 {
+        NetworkEndian::read_u16(&self.0[field::NEXT_HOP_MTU])
+    }
+
     /// Return the header length.
     /// The result depends on the value of the message type field.
     pub fn header_len(&self) -> usize {
@@ -336,6 +352,15 @@ impl icmpv4 {
         NetworkEndian::write_u16(&mut self.0[field::ECHO_SEQNO], value);
     }
 
+    /// Set the next-hop MTU field (for fragmentation required messages, RFC 1191).
+    ///
+    /// # Panics
+    /// This function may panic if this packet is not a destination unreachable packet.
+    #[inline]
+    pub fn set_next_hop_mtu(&mut self, value: u16) {
+        NetworkEndian::write_u16(&mut self.0[field::NEXT_HOP_MTU], value);
+    }
+
     /// Compute and fill in the header checksum.
     pub fn fill_checksum(&mut self) {
         self.set_checksum(0);
@@ -421,6 +446,13 @@ impl<T: PayloadMut> Packet<T> {
 }
 
 impl<T> Packet<T> {
+    /// Get an immutable reference to the whole buffer.
+    ///
+    /// Useful if the buffer is some other packet encapsulation.
+    pub fn get_ref(&self) -> &T {
+        &self.buffer
+    }
+
     /// Return the raw underlying buffer.
     pub fn into_inner(self) -> T {
         self.buffer
@@ -493,12 +525,25 @@ impl Repr {
             },
 
             (Message::DstUnreachable, code) => {
-                let ip_packet = v4::Packet::new_checked(packet.payload_slice(), checksum)?;
+                // The quoted datagram is usually truncated, so its total length can not be
+                // checked against the buffer as for a received ip packet.
+                let quoted = packet.payload_slice();
+                if quoted.len() < field::IPV4_MIN_HEADER_LEN { return Err(Error::Truncated) }
+
+                let ip_packet = v4::packet::new_unchecked(quoted);
+                let header_len = usize::from(ip_packet.header_len());
+                let total_len = usize::from(ip_packet.total_len());
+                if ip_packet.version() != 4 { return Err(Error::Malformed) }
+                if header_len < field::IPV4_MIN_HEADER_LEN || header_len > total_len {
+                    return Err(Error::Malformed)
+                }
 
-                let payload = ip_packet.payload_slice();
                 // RFC 792 requires exactly eight bytes to be returned.
                 // We allow more, since there isn't a reason not to, but require at least eight.
-                if payload.len() < 8 { return Err(Error::Truncated) }
+                if quoted.len() < header_len + 8 { return Err(Error::Truncated) }
+                if checksum.manual() && !ip_packet.verify_checksum() {
+                    return Err(Error::WrongChecksum)
+                }
 
                 Ok(Repr::DstUnreachable {
                     reason: DstUnreachable::from(code),
@@ -506,8 +551,10 @@ impl Repr {
                         src_addr: ip_packet.src_addr(),
                         dst_addr: ip_packet.dst_addr(),
                         protocol: ip_packet.protocol(),
-                        payload_len: payload.len(),
+                        payload_len: total_len - header_len,
                         hop_limit: ip_packet.hop_limit(),
+                        dscp: ip_packet.dscp(),
+                        ecn: ip_packet.ecn().into(),
                     },
                 })
             }
@@ -704,4 +751,34 @@ mod test {
         assert_eq!(Packet::new_checked(&bytes[..4], Checksum::Ignored), Err(Error::Truncated));
         Packet::new_checked(&bytes[..], Checksum::Ignored).unwrap();
     }
+
+    #[test]
+    fn test_frag_required_truncated() {
+        let repr = Repr::DstUnreachable {
+            reason: DstUnreachable::FragRequired,
+            header: v4::Repr {
+                src_addr: v4::Address::new(10, 0, 0, 1),
+                dst_addr: v4::Address::new(10, 0, 0, 2),
+                protocol: crate::wire::ip::Protocol::Tcp,
+                payload_len: 1480,
+                hop_limit: 64,
+                dscp: 0,
+                ecn: crate::wire::ip::Ecn::NotEct,
+            },
+        };
+
+        // Only the header and eight bytes of the original datagram are quoted.
+        let mut bytes = vec![0; repr.buffer_len()];
+        let packet = icmpv4::new_unchecked_mut(&mut bytes);
+        repr.emit(packet, Checksum::Manual);
+        packet.set_next_hop_mtu(1400);
+        packet.fill_checksum();
+
+        assert_eq!(packet.next_hop_mtu(), 1400);
+        assert_eq!(Repr::parse(packet, Checksum::Manual), Ok(repr));
+
+        let len = bytes.len();
+        let packet = icmpv4::new_unchecked(&bytes[..len - 1]);
+        assert_eq!(Repr::parse(packet, Checksum::Ignored), Err(Error::Truncated));
+    }
 }
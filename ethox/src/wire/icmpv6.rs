@@ -1,11 +1,10 @@
-use core::{cmp, fmt};
+use core::{fmt, ops};
 use byteorder::{ByteOrder, NetworkEndian};
 
-use {Error, Result};
-use phy::ChecksumCapabilities;
+use crate::wire::{ip, ip::v6, Error, Result, Payload, PayloadMut};
+use crate::wire::pretty_print::{PrettyPrint, PrettyIndent};
+
 use super::ip::checksum;
-use super::{IpAddress, IpProtocol, Ipv6Packet, Ipv6Repr};
-use super::{MldRepr, NdiscRepr};
 
 enum_with_unknown! {
     /// Internet protocol control message type.
@@ -54,11 +53,9 @@ impl Message {
     ///
     /// [NDISC]: https://tools.ietf.org/html/rfc4861
     pub fn is_ndisc(&self) -> bool {
-        match *self {
+        matches!(self,
             Message::RouterSolicit | Message::RouterAdvert | Message::NeighborSolicit |
-            Message::NeighborAdvert | Message::Redirect => true,
-            _ => false,
-        }
+            Message::NeighborAdvert | Message::Redirect)
     }
 
     /// Return a boolean value indicating if the given message type
@@ -66,30 +63,27 @@ impl Message {
     ///
     /// [MLD]: https://tools.ietf.org/html/rfc3810
     pub fn is_mld(&self) -> bool {
-        match *self {
-            Message::MldQuery | Message::MldReport => true,
-            _ => false,
-        }
+        matches!(self, Message::MldQuery | Message::MldReport)
     }
 }
 
 impl fmt::Display for Message {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            &Message::DstUnreachable  => write!(f, "destination unreachable"),
-            &Message::PktTooBig       => write!(f, "packet too big"),
-            &Message::TimeExceeded    => write!(f, "time exceeded"),
-            &Message::ParamProblem    => write!(f, "parameter problem"),
-            &Message::EchoReply       => write!(f, "echo reply"),
-            &Message::EchoRequest     => write!(f, "echo request"),
-            &Message::RouterSolicit   => write!(f, "router solicitation"),
-            &Message::RouterAdvert    => write!(f, "router advertisement"),
-            &Message::NeighborSolicit => write!(f, "neighbor solicitation"),
-            &Message::NeighborAdvert  => write!(f, "neighbor advert"),
-            &Message::Redirect        => write!(f, "redirect"),
-            &Message::MldQuery        => write!(f, "multicast listener query"),
-            &Message::MldReport       => write!(f, "multicast listener report"),
-            &Message::Unknown(id)     => write!(f, "{}", id)
+            Message::DstUnreachable  => write!(f, "destination unreachable"),
+            Message::PktTooBig       => write!(f, "packet too big"),
+            Message::TimeExceeded    => write!(f, "time exceeded"),
+            Message::ParamProblem    => write!(f, "parameter problem"),
+            Message::EchoReply       => write!(f, "echo reply"),
+            Message::EchoRequest     => write!(f, "echo request"),
+            Message::RouterSolicit   => write!(f, "router solicitation"),
+            Message::RouterAdvert    => write!(f, "router advertisement"),
+            Message::NeighborSolicit => write!(f, "neighbor solicitation"),
+            Message::NeighborAdvert  => write!(f, "neighbor advert"),
+            Message::Redirect        => write!(f, "redirect"),
+            Message::MldQuery        => write!(f, "multicast listener query"),
+            Message::MldReport       => write!(f, "multicast listener report"),
+            Message::Unknown(id)     => write!(f, "{}", id)
         }
     }
 }
@@ -117,21 +111,21 @@ enum_with_unknown! {
 impl fmt::Display for DstUnreachable {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            &DstUnreachable::NoRoute =>
+            DstUnreachable::NoRoute =>
                 write!(f, "no route to destination"),
-            &DstUnreachable::AdminProhibit =>
+            DstUnreachable::AdminProhibit =>
                 write!(f, "communication with destination administratively prohibited"),
-            &DstUnreachable::BeyondScope =>
+            DstUnreachable::BeyondScope =>
                 write!(f, "beyond scope of source address"),
-            &DstUnreachable::AddrUnreachable =>
+            DstUnreachable::AddrUnreachable =>
                 write!(f, "address unreachable"),
-            &DstUnreachable::PortUnreachable =>
+            DstUnreachable::PortUnreachable =>
                 write!(f, "port unreachable"),
-            &DstUnreachable::FailedPolicy =>
+            DstUnreachable::FailedPolicy =>
                 write!(f, "source address failed ingress/egress policy"),
-            &DstUnreachable::RejectRoute =>
+            DstUnreachable::RejectRoute =>
                 write!(f, "reject route to destination"),
-            &DstUnreachable::Unknown(id) =>
+            DstUnreachable::Unknown(id) =>
                 write!(f, "{}", id)
         }
     }
@@ -152,13 +146,13 @@ enum_with_unknown! {
 impl fmt::Display for ParamProblem {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            &ParamProblem::ErroneousHdrField  =>
-                write!(f, "erroneous header field."),
-            &ParamProblem::UnrecognizedNxtHdr =>
-                write!(f, "unrecognized next header type."),
-            &ParamProblem::UnrecognizedOption =>
-                write!(f, "unrecognized IPv6 option."),
-            &ParamProblem::Unknown(id) =>
+            ParamProblem::ErroneousHdrField  =>
+                write!(f, "erroneous header field"),
+            ParamProblem::UnrecognizedNxtHdr =>
+                write!(f, "unrecognized next header type"),
+            ParamProblem::UnrecognizedOption =>
+                write!(f, "unrecognized IPv6 option"),
+            ParamProblem::Unknown(id) =>
                 write!(f, "{}", id)
         }
     }
@@ -177,11 +171,11 @@ enum_with_unknown! {
 impl fmt::Display for TimeExceeded {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            &TimeExceeded::HopLimitExceeded =>
+            TimeExceeded::HopLimitExceeded =>
                 write!(f, "hop limit exceeded in transit"),
-            &TimeExceeded::FragReassemExceeded =>
+            TimeExceeded::FragReassemExceeded =>
                 write!(f, "fragment reassembly time exceeded"),
-            &TimeExceeded::Unknown(id) =>
+            TimeExceeded::Unknown(id) =>
                 write!(f, "{}", id)
         }
     }
@@ -189,249 +183,175 @@ impl fmt::Display for TimeExceeded {
 
 /// A read/write wrapper around an Internet Control Message Protocol version 6 packet buffer.
 #[derive(Debug, PartialEq, Clone)]
-pub struct Packet<T: AsRef<[u8]>> {
-    pub(super) buffer: T
+pub struct Packet<T> {
+    buffer: T,
+    repr: Repr,
+}
+
+byte_wrapper! {
+    #[derive(Debug, PartialEq, Eq)]
+    pub struct icmpv6([u8]);
 }
 
-// Ranges and constants describing key boundaries in the ICMPv6 header.
-pub(super) mod field {
-    use wire::field::*;
+mod field {
+    use crate::wire::field::Field;
 
     // ICMPv6: See https://tools.ietf.org/html/rfc4443
-    pub const TYPE:              usize = 0;
-    pub const CODE:              usize = 1;
-    pub const CHECKSUM:          Field = 2..4;
-
-    pub const UNUSED:            Field = 4..8;
-    pub const MTU:               Field = 4..8;
-    pub const POINTER:           Field = 4..8;
-    pub const ECHO_IDENT:        Field = 4..6;
-    pub const ECHO_SEQNO:        Field = 6..8;
-
-    pub const HEADER_END:        usize = 8;
-
-    // NDISC: See https://tools.ietf.org/html/rfc4861
-    // Router Advertisement message offsets
-    pub const CUR_HOP_LIMIT:     usize = 4;
-    pub const ROUTER_FLAGS:      usize = 5;
-    pub const ROUTER_LT:         Field = 6..8;
-    pub const REACHABLE_TM:      Field = 8..12;
-    pub const RETRANS_TM:        Field = 12..16;
-
-    // Neighbor Solicitation message offsets
-    pub const TARGET_ADDR:       Field = 8..24;
-
-    // Neighbor Advertisement message offsets
-    pub const NEIGH_FLAGS:       usize = 4;
-
-    // Redirected Header message offsets
-    pub const DEST_ADDR:         Field = 24..40;
-
-    // MLD:
-    //   - https://tools.ietf.org/html/rfc3810
-    //   - https://tools.ietf.org/html/rfc3810
-    // Multicast Listener Query message
-    pub const MAX_RESP_CODE:     Field = 4..6;
-    pub const QUERY_RESV:        Field = 6..8;
-    pub const QUERY_MCAST_ADDR:  Field = 8..24;
-    pub const SQRV:              usize = 24;
-    pub const QQIC:              usize = 25;
-    pub const QUERY_NUM_SRCS:    Field = 26..28;
-
-    // Multicast Listener Report Message
-    pub const RECORD_RESV:       Field = 4..6;
-    pub const NR_MCAST_RCRDS:    Field = 6..8;
-
-    // Multicast Address Record Offsets
-    pub const RECORD_TYPE:       usize = 0;
-    pub const AUX_DATA_LEN:      usize = 1;
-    pub const RECORD_NUM_SRCS:   Field = 2..4;
-    pub const RECORD_MCAST_ADDR: Field = 4..20;
+    pub(crate) const TYPE:       usize = 0;
+    pub(crate) const CODE:       usize = 1;
+    pub(crate) const CHECKSUM:   Field = 2..4;
+
+    pub(crate) const UNUSED:     Field = 4..8;
+    pub(crate) const MTU:        Field = 4..8;
+    pub(crate) const POINTER:    Field = 4..8;
+
+    pub(crate) const ECHO_IDENT: Field = 4..6;
+    pub(crate) const ECHO_SEQNO: Field = 6..8;
+
+    pub(crate) const HEADER_END: usize = 8;
+
+    // The fixed header of a quoted datagram.
+    pub(crate) const IPV6_HEADER_LEN: usize = 40;
+
+    // An error message must fit into the minimum IPv6 MTU of 1280 octets, RFC 4443 section 2.4.
+    pub(crate) const MAX_ERROR_LEN: usize = 1280 - IPV6_HEADER_LEN;
 }
 
-impl<T: AsRef<[u8]>> Packet<T> {
+impl icmpv6 {
     /// Imbue a raw octet buffer with ICMPv6 packet structure.
-    pub fn new_unchecked(buffer: T) -> Packet<T> {
-        Packet { buffer }
+    pub fn new_unchecked(buffer: &[u8]) -> &icmpv6 {
+        Self::__from_macro_new_unchecked(buffer)
+    }
+
+    /// Imbue a mutable octet buffer with ICMPv6 packet structure.
+    pub fn new_unchecked_mut(buffer: &mut [u8]) -> &mut icmpv6 {
+        Self::__from_macro_new_unchecked_mut(buffer)
     }
 
     /// Shorthand for a combination of [new_unchecked] and [check_len].
     ///
     /// [new_unchecked]: #method.new_unchecked
     /// [check_len]: #method.check_len
-    pub fn new_checked(buffer: T) -> Result<Packet<T>> {
-        let packet = Self::new_unchecked(buffer);
+    pub fn new_checked(data: &[u8]) -> Result<&icmpv6> {
+        let packet = Self::new_unchecked(data);
         packet.check_len()?;
         Ok(packet)
     }
 
+    /// Unwrap the packet as a raw byte slice.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Unwrap the packet as a mutable raw byte slice.
+    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+
     /// Ensure that no accessor method will panic if called.
     /// Returns `Err(Error::Truncated)` if the buffer is too short.
     pub fn check_len(&self) -> Result<()> {
-        let len = self.buffer.as_ref().len();
-        if len < field::HEADER_END {
+        if self.0.len() < field::HEADER_END {
             Err(Error::Truncated)
         } else {
-            if len < self.header_len() {
-                Err(Error::Truncated)
-            } else {
-                Ok(())
-            }
+            Ok(())
         }
     }
 
-    /// Consume the packet, returning the underlying buffer.
-    pub fn into_inner(self) -> T {
-        self.buffer
-    }
-
     /// Return the message type field.
     #[inline]
     pub fn msg_type(&self) -> Message {
-        let data = self.buffer.as_ref();
-        Message::from(data[field::TYPE])
+        Message::from(self.0[field::TYPE])
     }
 
     /// Return the message code field.
     #[inline]
     pub fn msg_code(&self) -> u8 {
-        let data = self.buffer.as_ref();
-        data[field::CODE]
+        self.0[field::CODE]
     }
 
     /// Return the checksum field.
     #[inline]
     pub fn checksum(&self) -> u16 {
-        let data = self.buffer.as_ref();
-        NetworkEndian::read_u16(&data[field::CHECKSUM])
+        NetworkEndian::read_u16(&self.0[field::CHECKSUM])
     }
 
     /// Return the identifier field (for echo request and reply packets).
+    ///
+    /// # Panics
+    /// This function may panic if this packet is not an echo request or reply packet.
     #[inline]
     pub fn echo_ident(&self) -> u16 {
-        let data = self.buffer.as_ref();
-        NetworkEndian::read_u16(&data[field::ECHO_IDENT])
+        NetworkEndian::read_u16(&self.0[field::ECHO_IDENT])
     }
 
     /// Return the sequence number field (for echo request and reply packets).
+    ///
+    /// # Panics
+    /// This function may panic if this packet is not an echo request or reply packet.
     #[inline]
     pub fn echo_seq_no(&self) -> u16 {
-        let data = self.buffer.as_ref();
-        NetworkEndian::read_u16(&data[field::ECHO_SEQNO])
+        NetworkEndian::read_u16(&self.0[field::ECHO_SEQNO])
     }
 
     /// Return the MTU field (for packet too big messages).
+    ///
+    /// # Panics
+    /// This function may panic if this packet is not a packet too big message.
     #[inline]
     pub fn pkt_too_big_mtu(&self) -> u32 {
-        let data = self.buffer.as_ref();
-        NetworkEndian::read_u32(&data[field::MTU])
+        NetworkEndian::read_u32(&self.0[field::MTU])
     }
 
     /// Return the pointer field (for parameter problem messages).
+    ///
+    /// # Panics
+    /// This function may panic if this packet is not a parameter problem message.
     #[inline]
     pub fn param_problem_ptr(&self) -> u32 {
-        let data = self.buffer.as_ref();
-        NetworkEndian::read_u32(&data[field::POINTER])
+        NetworkEndian::read_u32(&self.0[field::POINTER])
     }
 
-
-    /// Return the header length. The result depends on the value of
-    /// the message type field.
+    /// Return the header length.
+    ///
+    /// All messages specified in RFC 4443 have a header of the same length.
     pub fn header_len(&self) -> usize {
-        match self.msg_type() {
-            Message::DstUnreachable  => field::UNUSED.end,
-            Message::PktTooBig       => field::MTU.end,
-            Message::TimeExceeded    => field::UNUSED.end,
-            Message::ParamProblem    => field::POINTER.end,
-            Message::EchoRequest     => field::ECHO_SEQNO.end,
-            Message::EchoReply       => field::ECHO_SEQNO.end,
-            Message::RouterSolicit   => field::UNUSED.end,
-            Message::RouterAdvert    => field::RETRANS_TM.end,
-            Message::NeighborSolicit => field::TARGET_ADDR.end,
-            Message::NeighborAdvert  => field::TARGET_ADDR.end,
-            Message::Redirect        => field::DEST_ADDR.end,
-            Message::MldQuery        => field::QUERY_NUM_SRCS.end,
-            Message::MldReport       => field::NR_MCAST_RCRDS.end,
-            // For packets that are not included in RFC 4443, do not
-            // include the last 32 bits of the ICMPv6 header in
-            // `header_bytes`. This must be done so that these bytes
-            // can be accessed in the `payload`.
-            _ => field::CHECKSUM.end
-        }
+        field::HEADER_END
     }
 
-    /// Validate the header checksum.
+    /// Validate the checksum, including the pseudo header of the ip addresses.
+    ///
+    /// # Panics
+    /// This function panics unless `src_addr` and `dst_addr` are both IPv6 addresses.
     ///
     /// # Fuzzing
     /// This function always returns `true` when fuzzing.
-    pub fn verify_checksum(&self, src_addr: &IpAddress, dst_addr: &IpAddress) -> bool {
+    pub fn verify_checksum(&self, src_addr: ip::Address, dst_addr: ip::Address) -> bool {
         if cfg!(fuzzing) { return true }
 
-        let data = self.buffer.as_ref();
         checksum::combine(&[
-            checksum::pseudo_header(src_addr, dst_addr, IpProtocol::Icmpv6,
-                                    data.len() as u32),
-            checksum::data(data)
+            checksum::pseudo_header(&src_addr, &dst_addr, ip::Protocol::Icmpv6,
+                                    self.0.len() as u32),
+            checksum::data(&self.0)
         ]) == !0
     }
-}
-
-impl<'a, T: AsRef<[u8]> + ?Sized> Packet<&'a T> {
-    /// Return a pointer to the type-specific data.
-    #[inline]
-    pub fn payload(&self) -> &'a [u8] {
-        let data = self.buffer.as_ref();
-        &data[self.header_len()..]
-    }
-}
 
-impl<T: AsRef<[u8]> + AsMut<[u8]>> Packet<T> {
     /// Set the message type field.
     #[inline]
     pub fn set_msg_type(&mut self, value: Message) {
-        let data = self.buffer.as_mut();
-        data[field::TYPE] = value.into()
+        self.0[field::TYPE] = value.into();
     }
 
     /// Set the message code field.
     #[inline]
     pub fn set_msg_code(&mut self, value: u8) {
-        let data = self.buffer.as_mut();
-        data[field::CODE] = value
-    }
-
-    /// Clear any reserved fields in the message header.
-    ///
-    /// # Panics
-    /// This function panics if the message type has not been set.
-    /// See [set_msg_type].
-    ///
-    /// [set_msg_type]: #method.set_msg_type
-    #[inline]
-    pub fn clear_reserved(&mut self) {
-        match self.msg_type() {
-            Message::RouterSolicit | Message::NeighborSolicit |
-            Message::NeighborAdvert | Message::Redirect => {
-                let data = self.buffer.as_mut();
-                NetworkEndian::write_u32(&mut data[field::UNUSED], 0);
-            },
-            Message::MldQuery => {
-                let data = self.buffer.as_mut();
-                NetworkEndian::write_u16(&mut data[field::QUERY_RESV], 0);
-                data[field::SQRV] = data[field::SQRV] & 0xf;
-            },
-            Message::MldReport => {
-                let data = self.buffer.as_mut();
-                NetworkEndian::write_u16(&mut data[field::RECORD_RESV], 0);
-            }
-            ty => panic!("Message type `{}` does not have any reserved fields.", ty),
-        }
+        self.0[field::CODE] = value;
     }
 
+    /// Set the checksum field.
     #[inline]
     pub fn set_checksum(&mut self, value: u16) {
-        let data = self.buffer.as_mut();
-        NetworkEndian::write_u16(&mut data[field::CHECKSUM], value)
+        NetworkEndian::write_u16(&mut self.0[field::CHECKSUM], value);
     }
 
     /// Set the identifier field (for echo request and reply packets).
@@ -440,8 +360,7 @@ impl<T: AsRef<[u8]> + AsMut<[u8]>> Packet<T> {
     /// This function may panic if this packet is not an echo request or reply packet.
     #[inline]
     pub fn set_echo_ident(&mut self, value: u16) {
-        let data = self.buffer.as_mut();
-        NetworkEndian::write_u16(&mut data[field::ECHO_IDENT], value)
+        NetworkEndian::write_u16(&mut self.0[field::ECHO_IDENT], value);
     }
 
     /// Set the sequence number field (for echo request and reply packets).
@@ -450,18 +369,16 @@ impl<T: AsRef<[u8]> + AsMut<[u8]>> Packet<T> {
     /// This function may panic if this packet is not an echo request or reply packet.
     #[inline]
     pub fn set_echo_seq_no(&mut self, value: u16) {
-        let data = self.buffer.as_mut();
-        NetworkEndian::write_u16(&mut data[field::ECHO_SEQNO], value)
+        NetworkEndian::write_u16(&mut self.0[field::ECHO_SEQNO], value);
     }
 
     /// Set the MTU field (for packet too big messages).
     ///
     /// # Panics
-    /// This function may panic if this packet is not an packet too big packet.
+    /// This function may panic if this packet is not a packet too big message.
     #[inline]
     pub fn set_pkt_too_big_mtu(&mut self, value: u32) {
-        let data = self.buffer.as_mut();
-        NetworkEndian::write_u32(&mut data[field::MTU], value)
+        NetworkEndian::write_u32(&mut self.0[field::MTU], value);
     }
 
     /// Set the pointer field (for parameter problem messages).
@@ -470,271 +387,431 @@ impl<T: AsRef<[u8]> + AsMut<[u8]>> Packet<T> {
     /// This function may panic if this packet is not a parameter problem message.
     #[inline]
     pub fn set_param_problem_ptr(&mut self, value: u32) {
-        let data = self.buffer.as_mut();
-        NetworkEndian::write_u32(&mut data[field::POINTER], value)
+        NetworkEndian::write_u32(&mut self.0[field::POINTER], value);
     }
 
-    /// Compute and fill in the header checksum.
-    pub fn fill_checksum(&mut self, src_addr: &IpAddress, dst_addr: &IpAddress) {
+    /// Compute and fill in the checksum, including the pseudo header of the ip addresses.
+    ///
+    /// # Panics
+    /// This function panics unless `src_addr` and `dst_addr` are both IPv6 addresses.
+    pub fn fill_checksum(&mut self, src_addr: ip::Address, dst_addr: ip::Address) {
         self.set_checksum(0);
-        let checksum = {
-            let data = self.buffer.as_ref();
-            !checksum::combine(&[
-                checksum::pseudo_header(src_addr, dst_addr, IpProtocol::Icmpv6,
-                                        data.len() as u32),
-                checksum::data(data)
-            ])
-        };
-        self.set_checksum(checksum)
+        let checksum = !checksum::combine(&[
+            checksum::pseudo_header(&src_addr, &dst_addr, ip::Protocol::Icmpv6,
+                                    self.0.len() as u32),
+            checksum::data(&self.0)
+        ]);
+        self.set_checksum(checksum);
     }
 
-    /// Return a mutable pointer to the type-specific data.
-    #[inline]
-    pub fn payload_mut(&mut self) -> &mut [u8] {
-        let range = self.header_len()..;
-        let data = self.buffer.as_mut();
-        &mut data[range]
+    /// Return the payload as a byte slice.
+    pub fn payload_slice(&self) -> &[u8] {
+        &self.0[field::HEADER_END..]
+    }
+
+    /// Return the payload as a mutable byte slice.
+    pub fn payload_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.0[field::HEADER_END..]
     }
 }
 
-impl<T: AsRef<[u8]>> AsRef<[u8]> for Packet<T> {
+impl AsRef<[u8]> for icmpv6 {
     fn as_ref(&self) -> &[u8] {
-        self.buffer.as_ref()
+        &self.0
     }
 }
 
+impl AsMut<[u8]> for icmpv6 {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+impl<T: Payload> Packet<T> {
+    /// Shorthand for a combination of [new_unchecked] and [check_len].
+    ///
+    /// [new_unchecked]: #method.new_unchecked
+    /// [check_len]: #method.check_len
+    pub fn new_checked(buffer: T, checksum: Checksum) -> Result<Packet<T>> {
+        let repr = {
+            let packet = icmpv6::new_checked(buffer.payload())?;
+            Repr::parse(packet, checksum)?
+        };
+        Ok(Packet {
+            buffer,
+            repr,
+        })
+    }
+
+    /// Get an immutable reference to the whole buffer.
+    ///
+    /// Useful if the buffer is some other packet encapsulation.
+    pub fn get_ref(&self) -> &T {
+        &self.buffer
+    }
+
+    /// Get the repr of the packet header.
+    pub fn repr(&self) -> Repr {
+        self.repr
+    }
+
+    /// Create a new packet without checking the representation.
+    ///
+    /// Misuse may lead to panics from out-of-bounds access or other subtle inconsistencies. Since
+    /// the representation might not represent the actual content in the payload, this also might
+    /// mean that seemingly inconsistent values are returned. The usage is still memory safe
+    /// though.
+    pub fn new_unchecked(buffer: T, repr: Repr) -> Self {
+        Packet {
+            buffer,
+            repr,
+        }
+    }
+}
+
+impl<T: PayloadMut> Packet<T> {
+    pub fn payload_mut_slice(&mut self) -> &mut [u8] {
+        icmpv6::new_unchecked_mut(self.buffer.payload_mut())
+            .payload_mut_slice()
+    }
+
+    /// Recalculate the checksum if necessary.
+    ///
+    /// Note that the checksum test can be elided even in a checked parse of the ipv6 frame. This
+    /// provides in opportunity to recalculate it if necessary even though the header structure is
+    /// not otherwise mutably accessible while in `Packet` representation.
+    pub fn fill_checksum(&mut self, checksum: Checksum) {
+        if let Checksum::Manual { src_addr, dst_addr } = checksum {
+            icmpv6::new_unchecked_mut(self.buffer.payload_mut())
+                .fill_checksum(src_addr, dst_addr)
+        }
+    }
+}
+
+impl<T> Packet<T> {
+    /// Return the raw underlying buffer.
+    pub fn into_inner(self) -> T {
+        self.buffer
+    }
+}
+
+impl<T: Payload> ops::Deref for Packet<T> {
+    type Target = icmpv6;
+
+    fn deref(&self) -> &icmpv6 {
+        // We checked the length at construction.
+        icmpv6::new_unchecked(self.buffer.payload())
+    }
+}
+
+/// The checksum behaviour of ICMPv6.
+///
+/// In contrast to ICMPv4 the checksum covers a pseudo header of the ip layer (RFC 4443 section
+/// 2.3), thus the addresses are required to compute it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Checksum {
+    /// Always fill the checksum and check if it exists.
+    ///
+    /// Note that both ip addresses must be IPv6 addresses.
+    Manual {
+        /// The ip source address.
+        src_addr: ip::Address,
+        /// The ip destination address.
+        dst_addr: ip::Address,
+    },
+
+    /// Never inspect the checksum.
+    ///
+    /// This assumes that some layer below has already performed the necessary checks.
+    Ignored,
+}
+
 /// A high-level representation of an Internet Control Message Protocol version 6 packet header.
+///
+/// Error messages quote the header of the invoking packet, their `payload_len` is the one of the
+/// original packet. The quoted data is usually truncated so that the message fits into the
+/// minimum IPv6 MTU.
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
-pub enum Repr<'a> {
+pub enum Repr {
+    EchoRequest {
+        ident:  u16,
+        seq_no: u16,
+        payload: usize,
+    },
+    EchoReply {
+        ident:  u16,
+        seq_no: u16,
+        payload: usize,
+    },
     DstUnreachable {
         reason: DstUnreachable,
-        header: Ipv6Repr,
-        data:   &'a [u8]
+        header: v6::Repr,
     },
     PktTooBig {
-        mtu: u32,
-        header: Ipv6Repr,
-        data:   &'a [u8]
+        mtu:    u32,
+        header: v6::Repr,
     },
     TimeExceeded {
         reason: TimeExceeded,
-        header: Ipv6Repr,
-        data:   &'a [u8]
+        header: v6::Repr,
     },
     ParamProblem {
         reason:  ParamProblem,
         pointer: u32,
-        header:  Ipv6Repr,
-        data:    &'a [u8]
-    },
-    EchoRequest {
-        ident:  u16,
-        seq_no: u16,
-        data:   &'a [u8]
+        header:  v6::Repr,
     },
-    EchoReply {
-        ident:  u16,
-        seq_no: u16,
-        data:   &'a [u8]
-    },
-    Ndisc(NdiscRepr<'a>),
-    Mld(MldRepr<'a>),
     #[doc(hidden)]
     __Nonexhaustive
 }
 
-impl<'a> Repr<'a> {
+impl Repr {
+    /// Get the echo reply request if this is an echo request.
+    pub fn echo_reply(self) -> Option<Repr> {
+        match self {
+            Repr::EchoRequest { ident, seq_no, payload, } =>
+                Some(Repr::EchoReply { ident, seq_no, payload, }),
+            _ => None,
+        }
+    }
+
     /// Parse an Internet Control Message Protocol version 6 packet and return
     /// a high-level representation.
-    pub fn parse<T>(src_addr: &IpAddress, dst_addr: &IpAddress,
-                    packet: &Packet<&'a T>, checksum_caps: &ChecksumCapabilities)
-                   -> Result<Repr<'a>>
-                where T: AsRef<[u8]> + ?Sized {
-        fn create_packet_from_payload<'a, T>(packet: &Packet<&'a T>)
-                                            -> Result<(&'a [u8], Ipv6Repr)>
-                where T: AsRef<[u8]> + ?Sized {
-            let ip_packet = Ipv6Packet::new_checked(packet.payload())?;
-
-            let payload = &packet.payload()[ip_packet.header_len() as usize..];
-            if payload.len() < 8 { return Err(Error::Truncated) }
-            let repr = Ipv6Repr {
-                src_addr: ip_packet.src_addr(),
-                dst_addr: ip_packet.dst_addr(),
-                next_header: ip_packet.next_header(),
-                payload_len: payload.len(),
-                hop_limit: ip_packet.hop_limit()
-            };
-            Ok((payload, repr))
-        }
+    pub fn parse(packet: &icmpv6, checksum: Checksum)
+        -> Result<Repr>
+    {
         // Valid checksum is expected.
-        if checksum_caps.icmpv6.rx() && !packet.verify_checksum(src_addr, dst_addr) {
-            return Err(Error::Checksum)
+        if let Checksum::Manual { src_addr, dst_addr } = checksum {
+            if !packet.verify_checksum(src_addr, dst_addr) { return Err(Error::WrongChecksum) }
         }
 
         match (packet.msg_type(), packet.msg_code()) {
+            (Message::EchoRequest, 0) => {
+                Ok(Repr::EchoRequest {
+                    ident:  packet.echo_ident(),
+                    seq_no: packet.echo_seq_no(),
+                    payload: packet.payload_slice().len(),
+                })
+            },
+
+            (Message::EchoReply, 0) => {
+                Ok(Repr::EchoReply {
+                    ident:  packet.echo_ident(),
+                    seq_no: packet.echo_seq_no(),
+                    payload: packet.payload_slice().len(),
+                })
+            },
+
             (Message::DstUnreachable, code) => {
-                let (payload, repr) = create_packet_from_payload(packet)?;
                 Ok(Repr::DstUnreachable {
                     reason: DstUnreachable::from(code),
-                    header: repr,
-                    data: payload
+                    header: parse_quoted(packet)?,
                 })
             },
+
             (Message::PktTooBig, 0) => {
-                let (payload, repr) = create_packet_from_payload(packet)?;
                 Ok(Repr::PktTooBig {
                     mtu: packet.pkt_too_big_mtu(),
-                    header: repr,
-                    data: payload
+                    header: parse_quoted(packet)?,
                 })
             },
+
             (Message::TimeExceeded, code) => {
-                let (payload, repr) = create_packet_from_payload(packet)?;
                 Ok(Repr::TimeExceeded {
                     reason: TimeExceeded::from(code),
-                    header: repr,
-                    data: payload
+                    header: parse_quoted(packet)?,
                 })
             },
+
             (Message::ParamProblem, code) => {
-                let (payload, repr) = create_packet_from_payload(packet)?;
                 Ok(Repr::ParamProblem {
                     reason: ParamProblem::from(code),
                     pointer: packet.param_problem_ptr(),
-                    header: repr,
-                    data: payload
-                })
-            },
-            (Message::EchoRequest, 0) => {
-                Ok(Repr::EchoRequest {
-                    ident:  packet.echo_ident(),
-                    seq_no: packet.echo_seq_no(),
-                    data:   packet.payload()
+                    header: parse_quoted(packet)?,
                 })
             },
-            (Message::EchoReply, 0) => {
-                Ok(Repr::EchoReply {
-                    ident:  packet.echo_ident(),
-                    seq_no: packet.echo_seq_no(),
-                    data:   packet.payload()
-                })
-            },
-            (msg_type, 0) if msg_type.is_ndisc() => {
-                NdiscRepr::parse(packet).map(|repr| Repr::Ndisc(repr))
-            },
-            (msg_type, 0) if msg_type.is_mld() => {
-                MldRepr::parse(packet).map(|repr| Repr::Mld(repr))
-            },
-            _ => Err(Error::Unrecognized)
+
+            // Unknown types are not as specified in the standard and iana registry.
+            (Message::Unknown(_), _) => Err(Error::Unrecognized),
+            // Others are just not supported (yet), such as neighbor discovery.
+            _ => Err(Error::Unsupported),
         }
     }
 
     /// Return the length of a packet that will be emitted from this high-level representation.
+    ///
+    /// For error messages this quotes as much of the invoking packet as fits into the minimum
+    /// IPv6 MTU. Only the quoted header is emitted, the caller fills in the rest.
     pub fn buffer_len(&self) -> usize {
         match self {
-            &Repr::DstUnreachable { header, data, .. } | &Repr::PktTooBig { header, data, .. } |
-            &Repr::TimeExceeded { header, data, .. } | &Repr::ParamProblem { header, data, .. } => {
-                field::UNUSED.end + header.buffer_len() + data.len()
-            }
-            &Repr::EchoRequest { data, .. } |
-            &Repr::EchoReply { data, .. } => {
-                field::ECHO_SEQNO.end + data.len()
-            },
-            &Repr::Ndisc(ndisc) => {
-                ndisc.buffer_len()
+            Repr::EchoRequest { payload, .. } |
+            Repr::EchoReply { payload, .. } => {
+                field::HEADER_END + payload
             },
-            &Repr::Mld(mld) => {
-                mld.buffer_len()
+            Repr::DstUnreachable { header, .. } |
+            Repr::PktTooBig { header, .. } |
+            Repr::TimeExceeded { header, .. } |
+            Repr::ParamProblem { header, .. } => {
+                let quoted = header.buffer_len() + header.payload_len;
+                field::HEADER_END + quoted.min(field::MAX_ERROR_LEN - field::HEADER_END)
             },
-            &Repr::__Nonexhaustive => unreachable!()
+            Repr::__Nonexhaustive => unreachable!()
         }
     }
 
     /// Emit a high-level representation into an Internet Control Message Protocol version 6
     /// packet.
-    pub fn emit<T>(&self, src_addr: &IpAddress, dst_addr: &IpAddress,
-                   packet: &mut Packet<&mut T>, checksum_caps: &ChecksumCapabilities)
-            where T: AsRef<[u8]> + AsMut<[u8]> + ?Sized {
-        fn emit_contained_packet(buffer: &mut [u8], header: Ipv6Repr, data: &[u8]) {
-            let mut ip_packet = Ipv6Packet::new_unchecked(buffer);
-            header.emit(&mut ip_packet);
-            let payload = &mut ip_packet.into_inner()[header.buffer_len()..];
-            payload.copy_from_slice(&data[..]);
-        }
+    pub fn emit(&self, packet: &mut icmpv6, checksum: Checksum) {
+        packet.set_msg_code(0);
+        match *self {
+            Repr::EchoRequest { ident, seq_no, payload: _ } => {
+                packet.set_msg_type(Message::EchoRequest);
+                packet.set_echo_ident(ident);
+                packet.set_echo_seq_no(seq_no);
+            },
 
-        match self {
-            &Repr::DstUnreachable { reason, header, data } => {
+            Repr::EchoReply { ident, seq_no, payload: _ } => {
+                packet.set_msg_type(Message::EchoReply);
+                packet.set_echo_ident(ident);
+                packet.set_echo_seq_no(seq_no);
+            },
+
+            Repr::DstUnreachable { reason, header } => {
                 packet.set_msg_type(Message::DstUnreachable);
                 packet.set_msg_code(reason.into());
-
-                emit_contained_packet(packet.payload_mut(), header, &data);
+                NetworkEndian::write_u32(&mut packet.0[field::UNUSED], 0);
+                emit_quoted(packet, header);
             },
 
-            &Repr::PktTooBig { mtu, header, data } => {
+            Repr::PktTooBig { mtu, header } => {
                 packet.set_msg_type(Message::PktTooBig);
-                packet.set_msg_code(0);
                 packet.set_pkt_too_big_mtu(mtu);
-
-                emit_contained_packet(packet.payload_mut(), header, &data);
+                emit_quoted(packet, header);
             },
 
-            &Repr::TimeExceeded { reason, header, data } => {
+            Repr::TimeExceeded { reason, header } => {
                 packet.set_msg_type(Message::TimeExceeded);
                 packet.set_msg_code(reason.into());
-
-                emit_contained_packet(packet.payload_mut(), header, &data);
+                NetworkEndian::write_u32(&mut packet.0[field::UNUSED], 0);
+                emit_quoted(packet, header);
             },
 
-            &Repr::ParamProblem { reason, pointer, header, data } => {
+            Repr::ParamProblem { reason, pointer, header } => {
                 packet.set_msg_type(Message::ParamProblem);
                 packet.set_msg_code(reason.into());
                 packet.set_param_problem_ptr(pointer);
-
-                emit_contained_packet(packet.payload_mut(), header, &data);
+                emit_quoted(packet, header);
             },
 
-            &Repr::EchoRequest { ident, seq_no, data } => {
-                packet.set_msg_type(Message::EchoRequest);
-                packet.set_msg_code(0);
-                packet.set_echo_ident(ident);
-                packet.set_echo_seq_no(seq_no);
-                let data_len = cmp::min(packet.payload_mut().len(), data.len());
-                packet.payload_mut()[..data_len].copy_from_slice(&data[..data_len])
-            },
+            Repr::__Nonexhaustive => unreachable!()
+        }
 
-            &Repr::EchoReply { ident, seq_no, data } => {
-                packet.set_msg_type(Message::EchoReply);
-                packet.set_msg_code(0);
-                packet.set_echo_ident(ident);
-                packet.set_echo_seq_no(seq_no);
-                let data_len = cmp::min(packet.payload_mut().len(), data.len());
-                packet.payload_mut()[..data_len].copy_from_slice(&data[..data_len])
-            },
+        if let Checksum::Manual { src_addr, dst_addr } = checksum {
+            packet.fill_checksum(src_addr, dst_addr)
+        } else {
+            // make sure we get a consistently zeroed checksum,
+            // since implementations might rely on it
+            packet.set_checksum(0);
+        }
+    }
+}
 
-            &Repr::Ndisc(ndisc) => {
-                ndisc.emit(packet)
-            },
+/// Parse the header of the packet quoted in an error message.
+fn parse_quoted(packet: &icmpv6) -> Result<v6::Repr> {
+    // The quoted datagram is usually truncated, so its payload length can not be checked against
+    // the buffer as for a received ip packet.
+    let quoted = packet.payload_slice();
+    if quoted.len() < field::IPV6_HEADER_LEN { return Err(Error::Truncated) }
+
+    let ip_packet = v6::packet::new_unchecked(quoted);
+    if ip_packet.version() != 6 { return Err(Error::Malformed) }
+
+    Ok(v6::Repr {
+        src_addr: ip_packet.src_addr(),
+        dst_addr: ip_packet.dst_addr(),
+        next_header: ip_packet.next_header(),
+        payload_len: usize::from(ip_packet.payload_len()),
+        hop_limit: ip_packet.hop_limit(),
+        dscp: ip_packet.traffic_class() >> 2,
+        ecn: ip::Ecn::from(ip_packet.traffic_class()),
+    })
+}
 
-            &Repr::Mld(mld) => {
-                mld.emit(packet)
-            },
+/// Emit the header of the packet quoted in an error message.
+fn emit_quoted(packet: &mut icmpv6, header: v6::Repr) {
+    let quoted = &mut packet.payload_mut_slice()[..field::IPV6_HEADER_LEN];
+    header.emit(v6::packet::new_unchecked_mut(quoted));
+}
 
-            &Repr::__Nonexhaustive => unreachable!(),
+impl<T: Payload> fmt::Display for Packet<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // The addresses for the checksum are not known here.
+        match Repr::parse(self, Checksum::Ignored) {
+            Ok(repr) => write!(f, "{}", repr),
+            Err(err) => {
+                write!(f, "ICMPv6 ({})", err)?;
+                write!(f, " type={:?}", self.msg_type())?;
+                match self.msg_type() {
+                    Message::DstUnreachable =>
+                        write!(f, " code={:?}", DstUnreachable::from(self.msg_code())),
+                    _ => write!(f, " code={}", self.msg_code())
+                }
+            }
         }
+    }
+}
 
-        if checksum_caps.icmpv6.tx() {
-            packet.fill_checksum(src_addr, dst_addr);
+impl fmt::Display for Repr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Repr::EchoRequest { ident, seq_no, payload } =>
+                write!(f, "ICMPv6 echo request id={} seq={} len={}",
+                       ident, seq_no, payload),
+            Repr::EchoReply { ident, seq_no, payload } =>
+                write!(f, "ICMPv6 echo reply id={} seq={} len={}",
+                       ident, seq_no, payload),
+            Repr::DstUnreachable { reason, .. } =>
+                write!(f, "ICMPv6 destination unreachable ({})",
+                       reason),
+            Repr::PktTooBig { mtu, .. } =>
+                write!(f, "ICMPv6 packet too big mtu={}",
+                       mtu),
+            Repr::TimeExceeded { reason, .. } =>
+                write!(f, "ICMPv6 time exceeded ({})",
+                       reason),
+            Repr::ParamProblem { reason, pointer, .. } =>
+                write!(f, "ICMPv6 parameter problem ({}) pointer={}",
+                       reason, pointer),
+            Repr::__Nonexhaustive => unreachable!()
+        }
+    }
+}
+
+impl PrettyPrint for icmpv6 {
+    fn pretty_print(buffer: &[u8], f: &mut fmt::Formatter,
+                    indent: &mut PrettyIndent) -> fmt::Result {
+        let packet = match icmpv6::new_checked(buffer) {
+            Err(err)   => return write!(f, "{}({})", indent, err),
+            Ok(packet) => packet
+        };
+
+        // Verify the packet content
+        let repr = match Repr::parse(packet, Checksum::Ignored) {
+            Err(err) => return write!(f, "{}({})", indent, err),
+            Ok(ip_repr) => ip_repr,
+        };
+
+        write!(f, "{}{}", indent, repr)?;
+        if packet.msg_type().is_error() {
+            indent.increase(f)?;
+            v6::packet::pretty_print(packet.payload_slice(), f, indent)
         } else {
-            // make sure we get a consistently zeroed checksum, since implementations might rely on it
-            packet.set_checksum(0);
+            Ok(())
         }
     }
 }
 
 #[cfg(test)]
 mod test {
-    use wire::{Ipv6Address, Ipv6Repr, IpProtocol};
-    use wire::ip::test::{MOCK_IP_ADDR_1, MOCK_IP_ADDR_2};
     use super::*;
 
     static ECHO_PACKET_BYTES: [u8; 12] =
@@ -742,7 +819,7 @@ mod test {
          0x12, 0x34, 0xab, 0xcd,
          0xaa, 0x00, 0x00, 0xff];
 
-    static ECHO_PACKET_PAYLOAD: [u8; 4] =
+    static ECHO_DATA_BYTES: [u8; 4] =
         [0xaa, 0x00, 0x00, 0xff];
 
     static PKT_TOO_BIG_BYTES: [u8; 60] =
@@ -762,137 +839,128 @@ mod test {
          0x00, 0x0c, 0x12, 0x4d,
          0xaa, 0x00, 0x00, 0xff];
 
-    static PKT_TOO_BIG_IP_PAYLOAD: [u8; 52] =
-        [0x60, 0x00, 0x00, 0x00,
-         0x00, 0x0c, 0x11, 0x40,
-         0xfe, 0x80, 0x00, 0x00,
-         0x00, 0x00, 0x00, 0x00,
-         0x00, 0x00, 0x00, 0x00,
-         0x00, 0x00, 0x00, 0x01,
-         0xfe, 0x80, 0x00, 0x00,
-         0x00, 0x00, 0x00, 0x00,
-         0x00, 0x00, 0x00, 0x00,
-         0x00, 0x00, 0x00, 0x02,
-         0xbf, 0x00, 0x00, 0x35,
-         0x00, 0x0c, 0x12, 0x4d,
-         0xaa, 0x00, 0x00, 0xff];
+    fn src_addr() -> v6::Address {
+        v6::Address::new(0xfe80, 0, 0, 0, 0, 0, 0, 1)
+    }
 
-    static PKT_TOO_BIG_UDP_PAYLOAD: [u8; 12] =
-        [0xbf, 0x00, 0x00, 0x35,
-         0x00, 0x0c, 0x12, 0x4d,
-         0xaa, 0x00, 0x00, 0xff];
+    fn dst_addr() -> v6::Address {
+        v6::Address::new(0xfe80, 0, 0, 0, 0, 0, 0, 2)
+    }
 
-    fn echo_packet_repr() -> Repr<'static> {
+    fn checksum() -> Checksum {
+        Checksum::Manual {
+            src_addr: src_addr().into(),
+            dst_addr: dst_addr().into(),
+        }
+    }
+
+    fn echo_packet_repr() -> Repr {
         Repr::EchoRequest {
             ident: 0x1234,
             seq_no: 0xabcd,
-            data: &ECHO_PACKET_PAYLOAD
+            payload: ECHO_DATA_BYTES.len(),
         }
     }
 
-    fn too_big_packet_repr() -> Repr<'static> {
+    fn too_big_packet_repr() -> Repr {
         Repr::PktTooBig {
             mtu: 1500,
-            header: Ipv6Repr {
-                src_addr: Ipv6Address([0xfe, 0x80, 0x00, 0x00,
-                                       0x00, 0x00, 0x00, 0x00,
-                                       0x00, 0x00, 0x00, 0x00,
-                                       0x00, 0x00, 0x00, 0x01]),
-                dst_addr: Ipv6Address([0xfe, 0x80, 0x00, 0x00,
-                                       0x00, 0x00, 0x00, 0x00,
-                                       0x00, 0x00, 0x00, 0x00,
-                                       0x00, 0x00, 0x00, 0x02]),
-                next_header: IpProtocol::Udp,
+            header: v6::Repr {
+                src_addr: src_addr(),
+                dst_addr: dst_addr(),
+                next_header: ip::Protocol::Udp,
                 payload_len: 12,
-                hop_limit: 0x40
+                hop_limit: 0x40,
+                dscp: 0,
+                ecn: ip::Ecn::NotEct,
             },
-            data: &PKT_TOO_BIG_UDP_PAYLOAD,
         }
     }
 
     #[test]
     fn test_echo_deconstruct() {
-        let packet = Packet::new_unchecked(&ECHO_PACKET_BYTES[..]);
+        let packet = icmpv6::new_unchecked(&ECHO_PACKET_BYTES[..]);
         assert_eq!(packet.msg_type(), Message::EchoRequest);
         assert_eq!(packet.msg_code(), 0);
         assert_eq!(packet.checksum(), 0x19b3);
         assert_eq!(packet.echo_ident(), 0x1234);
         assert_eq!(packet.echo_seq_no(), 0xabcd);
-        assert_eq!(packet.payload(), &ECHO_PACKET_PAYLOAD[..]);
-        assert_eq!(packet.verify_checksum(&MOCK_IP_ADDR_1, &MOCK_IP_ADDR_2), true);
+        assert_eq!(packet.payload_slice(), &ECHO_DATA_BYTES[..]);
+        assert_eq!(packet.verify_checksum(src_addr().into(), dst_addr().into()), true);
         assert!(!packet.msg_type().is_error());
     }
 
     #[test]
-    fn test_echo_construct() {
-        let mut bytes = vec![0xa5; 12];
-        let mut packet = Packet::new_unchecked(&mut bytes);
-        packet.set_msg_type(Message::EchoRequest);
-        packet.set_msg_code(0);
-        packet.set_echo_ident(0x1234);
-        packet.set_echo_seq_no(0xabcd);
-        packet.payload_mut().copy_from_slice(&ECHO_PACKET_PAYLOAD[..]);
-        packet.fill_checksum(&MOCK_IP_ADDR_1, &MOCK_IP_ADDR_2);
-        assert_eq!(&packet.into_inner()[..], &ECHO_PACKET_BYTES[..]);
-    }
-
-    #[test]
-    fn test_echo_repr_parse() {
-        let packet = Packet::new_unchecked(&ECHO_PACKET_BYTES[..]);
-        let repr = Repr::parse(&MOCK_IP_ADDR_1, &MOCK_IP_ADDR_2,
-                               &packet, &ChecksumCapabilities::default()).unwrap();
+    fn test_echo_parse() {
+        let packet = icmpv6::new_unchecked(&ECHO_PACKET_BYTES[..]);
+        let repr = Repr::parse(packet, checksum()).unwrap();
         assert_eq!(repr, echo_packet_repr());
+
+        // The checksum depends on the addresses of the pseudo header.
+        let swapped = Checksum::Manual {
+            src_addr: dst_addr().into(),
+            dst_addr: v6::Address::LOOPBACK.into(),
+        };
+        assert_eq!(Repr::parse(packet, swapped), Err(Error::WrongChecksum));
     }
 
     #[test]
     fn test_echo_emit() {
         let repr = echo_packet_repr();
         let mut bytes = vec![0xa5; repr.buffer_len()];
-        let mut packet = Packet::new_unchecked(&mut bytes);
-        repr.emit(&MOCK_IP_ADDR_1, &MOCK_IP_ADDR_2,
-                  &mut packet, &ChecksumCapabilities::default());
-        assert_eq!(&packet.into_inner()[..], &ECHO_PACKET_BYTES[..]);
+        let packet = icmpv6::new_unchecked_mut(&mut bytes);
+        packet.payload_mut_slice().copy_from_slice(&ECHO_DATA_BYTES[..]);
+        repr.emit(packet, checksum());
+        assert_eq!(packet.as_bytes(), &ECHO_PACKET_BYTES[..]);
     }
 
     #[test]
-    fn test_too_big_deconstruct() {
-        let packet = Packet::new_unchecked(&PKT_TOO_BIG_BYTES[..]);
-        assert_eq!(packet.msg_type(), Message::PktTooBig);
-        assert_eq!(packet.msg_code(), 0);
-        assert_eq!(packet.checksum(), 0x0fc9);
+    fn test_too_big_parse() {
+        let packet = icmpv6::new_unchecked(&PKT_TOO_BIG_BYTES[..]);
         assert_eq!(packet.pkt_too_big_mtu(), 1500);
-        assert_eq!(packet.payload(), &PKT_TOO_BIG_IP_PAYLOAD[..]);
-        assert_eq!(packet.verify_checksum(&MOCK_IP_ADDR_1, &MOCK_IP_ADDR_2), true);
         assert!(packet.msg_type().is_error());
+        let repr = Repr::parse(packet, checksum()).unwrap();
+        assert_eq!(repr, too_big_packet_repr());
+
+        // Less than the quoted ip header.
+        let packet = icmpv6::new_unchecked(&PKT_TOO_BIG_BYTES[..40]);
+        assert_eq!(Repr::parse(packet, Checksum::Ignored), Err(Error::Truncated));
     }
 
     #[test]
-    fn test_too_big_construct() {
-        let mut bytes = vec![0xa5; 60];
-        let mut packet = Packet::new_unchecked(&mut bytes);
-        packet.set_msg_type(Message::PktTooBig);
-        packet.set_msg_code(0);
-        packet.set_pkt_too_big_mtu(1500);
-        packet.payload_mut().copy_from_slice(&PKT_TOO_BIG_IP_PAYLOAD[..]);
-        packet.fill_checksum(&MOCK_IP_ADDR_1, &MOCK_IP_ADDR_2);
-        assert_eq!(&packet.into_inner()[..], &PKT_TOO_BIG_BYTES[..]);
+    fn test_too_big_emit() {
+        let repr = too_big_packet_repr();
+        assert_eq!(repr.buffer_len(), PKT_TOO_BIG_BYTES.len());
+        let mut bytes = vec![0xa5; repr.buffer_len()];
+        let packet = icmpv6::new_unchecked_mut(&mut bytes);
+        // The quoted payload is filled by the caller.
+        packet.payload_mut_slice()[40..].copy_from_slice(&PKT_TOO_BIG_BYTES[48..]);
+        repr.emit(packet, checksum());
+        assert_eq!(packet.as_bytes(), &PKT_TOO_BIG_BYTES[..]);
     }
 
     #[test]
-    fn test_too_big_repr_parse() {
-        let packet = Packet::new_unchecked(&PKT_TOO_BIG_BYTES[..]);
-        let repr = Repr::parse(&MOCK_IP_ADDR_1, &MOCK_IP_ADDR_2,
-                               &packet, &ChecksumCapabilities::default()).unwrap();
-        assert_eq!(repr, too_big_packet_repr());
+    fn test_error_len() {
+        // Only as much of a large packet is quoted as fits into the minimum MTU.
+        let repr = Repr::TimeExceeded {
+            reason: TimeExceeded::FragReassemExceeded,
+            header: v6::Repr {
+                payload_len: 4000,
+                ..match too_big_packet_repr() {
+                    Repr::PktTooBig { header, .. } => header,
+                    _ => unreachable!(),
+                }
+            },
+        };
+        assert_eq!(repr.buffer_len(), 1240);
     }
 
     #[test]
-    fn test_too_big_emit() {
-        let repr = too_big_packet_repr();
-        let mut bytes = vec![0xa5; repr.buffer_len()];
-        let mut packet = Packet::new_unchecked(&mut bytes);
-        repr.emit(&MOCK_IP_ADDR_1, &MOCK_IP_ADDR_2,
-                  &mut packet, &ChecksumCapabilities::default());
-        assert_eq!(&packet.into_inner()[..], &PKT_TOO_BIG_BYTES[..]);
+    fn test_check_len() {
+        let bytes = [0x80, 0x00, 0x00, 0x00,
+                     0x00, 0x00, 0x00, 0x00];
+        assert_eq!(Packet::new_checked(&bytes[..0], Checksum::Ignored), Err(Error::Truncated));
+        assert_eq!(Packet::new_checked(&bytes[..4], Checksum::Ignored), Err(Error::Truncated));
+        Packet::new_checked(&bytes[..], Checksum::Ignored).unwrap();
     }
 }
@@ -88,6 +88,68 @@ impl fmt::Display for Protocol {
     }
 }
 
+/// The Explicit Congestion Notification codepoint of an IP header, see [RFC3168].
+///
+/// These are the two low bits of the IPv4 type of service and of the IPv6 traffic class, right
+/// after the Differentiated Services Code Point.
+///
+/// [RFC3168]: https://tools.ietf.org/html/rfc3168#section-5
+#[derive(Debug, Default, Hash, PartialEq, Eq, Clone, Copy)]
+pub enum Ecn {
+    /// The transport is not ECN-capable.
+    #[default]
+    NotEct,
+    /// ECN-capable transport, the codepoint `ECT(1)`.
+    Ect1,
+    /// ECN-capable transport, the codepoint `ECT(0)`.
+    Ect0,
+    /// Congestion experienced, set by a router instead of dropping the packet.
+    Ce,
+}
+
+impl Ecn {
+    /// Check if the codepoint marks an ECN-capable transport.
+    ///
+    /// This is not the case for `Ce` although it can only be set on such packets.
+    pub fn is_ect(self) -> bool {
+        matches!(self, Ecn::Ect0 | Ecn::Ect1)
+    }
+}
+
+impl From<u8> for Ecn {
+    /// Convert the two lowest bits of the value.
+    fn from(value: u8) -> Self {
+        match value & 0x03 {
+            0b00 => Ecn::NotEct,
+            0b01 => Ecn::Ect1,
+            0b10 => Ecn::Ect0,
+            _ => Ecn::Ce,
+        }
+    }
+}
+
+impl From<Ecn> for u8 {
+    fn from(ecn: Ecn) -> u8 {
+        match ecn {
+            Ecn::NotEct => 0b00,
+            Ecn::Ect1 => 0b01,
+            Ecn::Ect0 => 0b10,
+            Ecn::Ce => 0b11,
+        }
+    }
+}
+
+impl fmt::Display for Ecn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Ecn::NotEct => write!(f, "Not-ECT"),
+            Ecn::Ect1 => write!(f, "ECT(1)"),
+            Ecn::Ect0 => write!(f, "ECT(0)"),
+            Ecn::Ce => write!(f, "CE"),
+        }
+    }
+}
+
 /// An internetworking address.
 #[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
 pub enum Address {
@@ -602,6 +664,30 @@ impl Repr {
         }
     }
 
+    /// Return the Differentiated Services Code Point.
+    ///
+    /// This is always zero for an unspecified representation.
+    pub fn dscp(&self) -> u8 {
+        match self {
+            Repr::Unspecified { .. } => 0,
+            Repr::Ipv4(repr) => repr.dscp,
+            Repr::Ipv6(repr) => repr.dscp,
+            Repr::__Nonexhaustive => unreachable!()
+        }
+    }
+
+    /// Return the Explicit Congestion Notification codepoint.
+    ///
+    /// This is always `NotEct` for an unspecified representation.
+    pub fn ecn(&self) -> Ecn {
+        match self {
+            Repr::Unspecified { .. } => Ecn::NotEct,
+            Repr::Ipv4(repr) => repr.ecn,
+            Repr::Ipv6(repr) => repr.ecn,
+            Repr::__Nonexhaustive => unreachable!()
+        }
+    }
+
     /// Convert an unspecified representation into a concrete one, or return
     /// `Err(Error::Unaddressable)` if not possible.
     ///
@@ -652,7 +738,9 @@ impl Repr {
                 }
                 Some(Repr::Ipv4(Ipv4Repr {
                     src_addr: src_addr?,
-                    dst_addr, protocol, payload_len, hop_limit
+                    dst_addr, protocol, payload_len, hop_limit,
+                    dscp: 0,
+                    ecn: Ecn::NotEct,
                 }))
             }
 
@@ -680,7 +768,9 @@ impl Repr {
                 Some(Repr::Ipv6(Ipv6Repr {
                     src_addr: src_addr?,
                     next_header: protocol,
-                    dst_addr, payload_len, hop_limit
+                    dst_addr, payload_len, hop_limit,
+                    dscp: 0,
+                    ecn: Ecn::NotEct,
                 }))
             }
 
@@ -693,7 +783,9 @@ impl Repr {
                     src_addr:    src_addr,
                     dst_addr:    dst_addr,
                     protocol:    protocol,
-                    payload_len: payload_len, hop_limit
+                    payload_len: payload_len, hop_limit,
+                    dscp:        0,
+                    ecn:         Ecn::NotEct,
                 }))
             }
 
@@ -707,7 +799,9 @@ impl Repr {
                     dst_addr:    dst_addr,
                     next_header: protocol,
                     payload_len: payload_len,
-                    hop_limit:   hop_limit
+                    hop_limit:   hop_limit,
+                    dscp:        0,
+                    ecn:         Ecn::NotEct,
                 }))
             }
 
@@ -952,7 +1046,9 @@ pub(crate) mod test {
                             dst_addr:  ip_addr_b,
                             $nxthdr:   proto,
                             hop_limit: 0x2a,
-                            payload_len
+                            payload_len,
+                            dscp:      0,
+                            ecn:       Ecn::NotEct,
                         }))
                     );
 
@@ -980,7 +1076,9 @@ pub(crate) mod test {
                             dst_addr:  ip_addr_b,
                             $nxthdr:   proto,
                             hop_limit: 64,
-                            payload_len
+                            payload_len,
+                            dscp:      0,
+                            ecn:       Ecn::NotEct,
                         }))
                     );
 
@@ -997,7 +1095,9 @@ pub(crate) mod test {
                             dst_addr:  ip_addr_b,
                             $nxthdr:   proto,
                             hop_limit: 64,
-                            payload_len
+                            payload_len,
+                            dscp:      0,
+                            ecn:       Ecn::NotEct,
                         }))
                     );
 
@@ -1014,7 +1114,9 @@ pub(crate) mod test {
                             dst_addr:  ip_addr_b,
                             $nxthdr:   proto,
                             hop_limit: 64,
-                            payload_len
+                            payload_len,
+                            dscp:      0,
+                            ecn:       Ecn::NotEct,
                         }))
                     );
 
@@ -1024,14 +1126,18 @@ pub(crate) mod test {
                             dst_addr:  ip_addr_b,
                             $nxthdr:   proto,
                             hop_limit: 255,
-                            payload_len
+                            payload_len,
+                            dscp:      0,
+                            ecn:       Ecn::NotEct,
                         }).lower(&[]),
                         Some($ip_repr($repr{
                             src_addr:  ip_addr_a,
                             dst_addr:  ip_addr_b,
                             $nxthdr:   proto,
                             hop_limit: 255,
-                            payload_len
+                            payload_len,
+                            dscp:      0,
+                            ecn:       Ecn::NotEct,
                         }))
                     );
 
@@ -1041,7 +1147,9 @@ pub(crate) mod test {
                             dst_addr:  ip_addr_b,
                             $nxthdr:   proto,
                             hop_limit: 255,
-                            payload_len
+                            payload_len,
+                            dscp:      0,
+                            ecn:       Ecn::NotEct,
                         }).lower(&[]),
                         None
                     );
@@ -1052,14 +1160,18 @@ pub(crate) mod test {
                             dst_addr:  ip_addr_b,
                             $nxthdr:   proto,
                             hop_limit: 64,
-                            payload_len
+                            payload_len,
+                            dscp:      0,
+                            ecn:       Ecn::NotEct,
                         }).lower(&[IpCidr::new($ip_addr(ip_addr_a), 24)]),
                         Some($ip_repr($repr{
                             src_addr:  ip_addr_a,
                             dst_addr:  ip_addr_b,
                             $nxthdr:   proto,
                             hop_limit: 64,
-                            payload_len
+                            payload_len,
+                            dscp:      0,
+                            ecn:       Ecn::NotEct,
                         }))
                     );
                 }
@@ -7,7 +7,7 @@ use crate::wire::{Checksum, Error, Reframe, Result, Payload, PayloadError, Paylo
 use crate::wire::pretty_print::{PrettyPrint, PrettyIndent};
 use crate::wire::field::Field;
 
-use super::ip::{Protocol, checksum, pretty_print_ip_payload};
+use super::ip::{Ecn, Protocol, checksum, pretty_print_ip_payload};
 
 /// Minimum MTU required of all links supporting IPv4. See [RFC 791 § 3.1].
 ///
@@ -889,6 +889,10 @@ pub struct Repr {
     pub payload_len: usize,
     /// The remaining hop limit of the packet.
     pub hop_limit:   u8,
+    /// The Differentiated Services Code Point, six bits.
+    pub dscp:        u8,
+    /// The Explicit Congestion Notification codepoint.
+    pub ecn:         Ecn,
 }
 
 impl Repr {
@@ -913,7 +917,9 @@ impl Repr {
             dst_addr:    packet.dst_addr(),
             protocol:    packet.protocol(),
             payload_len: payload_len,
-            hop_limit:   packet.hop_limit()
+            hop_limit:   packet.hop_limit(),
+            dscp:        packet.dscp(),
+            ecn:         Ecn::from(packet.ecn()),
         })
     }
 
@@ -927,8 +933,8 @@ impl Repr {
     pub fn emit(&self, packet: &mut ipv4, checksum: Checksum) {
         packet.set_version(4);
         packet.set_header_len(field::DST_ADDR.end as u8);
-        packet.set_dscp(0);
-        packet.set_ecn(0);
+        packet.set_dscp(self.dscp);
+        packet.set_ecn(self.ecn.into());
         let total_len = packet.header_len() as u16 + self.payload_len as u16;
         packet.set_total_len(total_len);
         packet.set_ident(0);
@@ -1121,7 +1127,9 @@ mod test {
             dst_addr:    Address([0x21, 0x22, 0x23, 0x24]),
             protocol:    Protocol::Icmp,
             payload_len: 4,
-            hop_limit:   64
+            hop_limit:   64,
+            dscp:        0,
+            ecn:         Ecn::NotEct,
         }
     }
 
@@ -1159,6 +1167,20 @@ mod test {
         assert_eq!(packet.as_bytes(), &REPR_PACKET_BYTES[..]);
     }
 
+    #[test]
+    fn test_traffic_class() {
+        let repr = Repr {
+            dscp: 0x2e,
+            ecn: Ecn::Ce,
+            ..packet_repr()
+        };
+        let mut bytes = vec![0xa5; repr.buffer_len() + REPR_PAYLOAD_BYTES.len()];
+        let mut packet = ipv4::new_unchecked_mut(&mut bytes);
+        repr.emit(&mut packet, Checksum::Manual);
+        assert_eq!(packet.as_bytes()[1], 0xbb);
+        assert_eq!(Repr::parse(&packet, Checksum::Manual), Ok(repr));
+    }
+
     #[test]
     fn test_unspecified() {
         assert!(Address::UNSPECIFIED.is_unspecified());
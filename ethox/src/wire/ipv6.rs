@@ -8,7 +8,7 @@ use crate::wire::{
     ethernet::Address as EthernetAddress
 };
 
-use super::ip::{Protocol, pretty_print_ip_payload};
+use super::ip::{Ecn, Protocol, pretty_print_ip_payload};
 
 /// Minimum MTU required of all links supporting IPv6. See [RFC 8200 § 5].
 ///
@@ -521,6 +521,8 @@ impl fmt::Display for Cidr {
 pub struct Packet<T: Payload> {
     buffer: T,
     repr: Repr,
+    /// The protocol and offset of the upper layer, after any extension headers.
+    upper: (Protocol, usize),
 }
 
 byte_wrapper! {
@@ -768,10 +770,7 @@ impl<T: Payload> Packet<T> {
             let packet = ipv6::new_checked(buffer.payload())?;
             Repr::parse(packet)?
         };
-        Ok(Packet {
-            buffer,
-            repr,
-        })
+        Ok(Self::new_unchecked(buffer, repr))
     }
 
     /// Get an immutable reference to the whole buffer.
@@ -796,7 +795,38 @@ impl<T: Payload> Packet<T> {
         Packet {
             buffer,
             repr,
+            upper: (repr.next_header, field::DST_ADDR.end),
+        }
+    }
+
+    /// The protocol of the upper layer, following all extension headers.
+    ///
+    /// This is the next header of the fixed header unless the extension headers have been walked
+    /// and the result recorded with [`set_upper`].
+    ///
+    /// [`set_upper`]: #method.set_upper
+    pub fn upper_protocol(&self) -> Protocol {
+        self.upper.0
+    }
+
+    /// The offset of the upper layer within the packet, following all extension headers.
+    ///
+    /// The payload of the packet starts at this offset.
+    pub fn upper_offset(&self) -> usize {
+        self.upper.1
+    }
+
+    /// Record the upper layer found after the extension headers.
+    ///
+    /// Returns `Err(Error::Truncated)` if the offset is not between the fixed header and the end
+    /// of the packet.
+    pub fn set_upper(&mut self, protocol: Protocol, offset: usize) -> Result<()> {
+        if offset < self.header_len() || offset > self.total_len() {
+            return Err(Error::Truncated);
         }
+
+        self.upper = (protocol, offset);
+        Ok(())
     }
 
     /// Consume the packet, returning the underlying buffer.
@@ -819,26 +849,28 @@ impl<T: Payload> ops::Deref for Packet<T> {
 
 impl<T: Payload> Payload for Packet<T> {
     fn payload(&self) -> &payload {
-        self.payload_slice().into()
+        let range = self.upper_offset()..self.total_len();
+        self.as_bytes()[range].into()
     }
 }
 
 impl<T: PayloadMut> PayloadMut for Packet<T> {
     fn payload_mut(&mut self) -> &mut payload {
-        ipv6::new_unchecked_mut(self.buffer.payload_mut())
-            .payload_mut_slice()
-            .into()
+        let offset = self.upper_offset();
+        let packet = ipv6::new_unchecked_mut(self.buffer.payload_mut());
+        let range = offset..packet.total_len();
+        (&mut packet.as_bytes_mut()[range]).into()
     }
 
     fn resize(&mut self, length: usize) -> core::result::Result<(), PayloadError> {
-        let hdr_len = self.header_len();
+        let hdr_len = self.upper_offset();
         self.buffer.resize(length + hdr_len)
     }
 
     fn reframe(&mut self, mut reframe: Reframe)
         -> core::result::Result<(), PayloadError>
     {
-        let hdr_len = self.header_len();
+        let hdr_len = self.upper_offset();
         reframe.within_header(hdr_len);
         self.buffer.reframe(reframe)
     }
@@ -875,7 +907,11 @@ pub struct Repr {
     /// Length of the payload including the extension headers.
     pub payload_len: usize,
     /// The 8-bit hop limit field.
-    pub hop_limit:   u8
+    pub hop_limit:   u8,
+    /// The Differentiated Services Code Point, the upper six bits of the traffic class.
+    pub dscp:        u8,
+    /// The Explicit Congestion Notification codepoint, the lower bits of the traffic class.
+    pub ecn:         Ecn,
 }
 
 impl Repr {
@@ -889,7 +925,9 @@ impl Repr {
             dst_addr:    packet.dst_addr(),
             next_header: packet.next_header(),
             payload_len: packet.payload_len() as usize,
-            hop_limit:   packet.hop_limit()
+            hop_limit:   packet.hop_limit(),
+            dscp:        packet.traffic_class() >> 2,
+            ecn:         Ecn::from(packet.traffic_class()),
         })
     }
 
@@ -904,7 +942,7 @@ impl Repr {
         // Make no assumptions about the original state of the packet buffer.
         // Make sure to set every byte.
         packet.set_version(6);
-        packet.set_traffic_class(0);
+        packet.set_traffic_class(self.dscp << 2 | u8::from(self.ecn));
         packet.set_flow_label(0);
         packet.set_payload_len(self.payload_len as u16);
         packet.set_hop_limit(self.hop_limit);
@@ -946,7 +984,7 @@ impl PrettyPrint for ipv6 {
 #[cfg(test)]
 mod test {
     use super::{Address, Error, Cidr};
-    use super::{ipv6, Protocol, Repr};
+    use super::{ipv6, Ecn, Protocol, Repr};
 
     use crate::wire::pretty_print::{PrettyPrinter};
     use crate::wire::ip::v4::Address as Ipv4Address;
@@ -1191,7 +1229,9 @@ mod test {
             dst_addr:    Address::LINK_LOCAL_ALL_NODES,
             next_header: Protocol::Udp,
             payload_len: 12,
-            hop_limit:   64
+            hop_limit:   64,
+            dscp:        0,
+            ecn:         Ecn::NotEct,
         }
     }
 
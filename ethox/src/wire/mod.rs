@@ -73,13 +73,15 @@ To emit an IP packet header into an octet buffer, and then parse it back:
 ```rust
 #
 # {
-use ethox::wire::{ip::v4, Checksum, ip::Protocol};
+use ethox::wire::{ip::v4, Checksum, ip::Ecn, ip::Protocol};
 let repr = v4::Repr {
     src_addr:    v4::Address::new(10, 0, 0, 1),
     dst_addr:    v4::Address::new(10, 0, 0, 2),
     protocol:    Protocol::Tcp,
     payload_len: 10,
-    hop_limit:   64
+    hop_limit:   64,
+    dscp:        0,
+    ecn:         Ecn::NotEct,
 };
 let mut buffer = vec![0; repr.buffer_len() + repr.payload_len];
 { // emission
@@ -148,7 +150,7 @@ mod raw {
     pub(crate) mod ipv6fragment;
     pub(crate) mod ipv6routing;
     pub(crate) mod icmpv4;
-    // mod icmpv6;
+    pub(crate) mod icmpv6;
     // mod icmp;
     // #[cfg(feature = "proto-igmp")]
     // mod igmp;
@@ -215,6 +217,7 @@ pub mod ip {
     pub use super::raw::ip::{
         Version,
         Protocol,
+        Ecn,
         Address,
         Endpoint,
         Repr,
@@ -291,6 +294,19 @@ pub mod icmpv4 {
     };
 }
 
+pub mod icmpv6 {
+    pub use super::raw::icmpv6::{
+        icmpv6 as packet,
+        Packet,
+        Repr,
+        Checksum,
+        Message,
+        DstUnreachable,
+        TimeExceeded,
+        ParamProblem,
+    };
+}
+
 /*
 #[cfg(feature = "proto-igmp")]
 pub use self::igmp::{
@@ -298,14 +314,6 @@ pub use self::igmp::{
     Repr as IgmpRepr,
     IgmpVersion};
 
-pub use self::icmpv6::{
-    Message as Icmpv6Message,
-    DstUnreachable as Icmpv6DstUnreachable,
-    TimeExceeded as Icmpv6TimeExceeded,
-    ParamProblem as Icmpv6ParamProblem,
-    Packet as Icmpv6Packet,
-    Repr as Icmpv6Repr};
-
 pub use self::icmp::Repr as IcmpRepr;
 */
 
@@ -344,8 +352,10 @@ pub mod tcp {
         Packet,
         Repr,
         Checksum,
+        FastOpenCookie,
         SeqNumber,
         TcpOption as Option,
+        Timestamp,
         Flags,
     };
 }
@@ -109,6 +109,8 @@ mod field {
     pub(crate) const OPT_WS:  u8 = 0x03;
     pub(crate) const OPT_SACKPERM: u8 = 0x04;
     pub(crate) const OPT_SACKRNG:  u8 = 0x05;
+    pub(crate) const OPT_TSTAMP:   u8 = 0x08;
+    pub(crate) const OPT_TFO:      u8 = 0x22;
 }
 
 impl<T: Payload> Packet<T> {
@@ -626,10 +628,81 @@ pub enum TcpOption<'a> {
     /// Specifies the selectively acknowledged ranges.
     /// Should only be sent if the remote sent `SackPermitted` originally.
     SackRange([Option<(u32, u32)>; 3]),
+    /// The timestamp values used for round-trip time measurement and PAWS.
+    /// Must only be sent on a connection if both SYN packets contained it.
+    Timestamp(Timestamp),
+    /// A fast open cookie, or a request for one if it is empty.
+    /// Should only be sent as part of a SYN packet.
+    FastOpen(&'a [u8]),
     /// Some user specified option not handled within the library itself.
     Unknown { kind: u8, data: &'a [u8] }
 }
 
+/// The content of the timestamp option, see [RFC7323].
+///
+/// [RFC7323]: https://tools.ietf.org/html/rfc7323#section-3
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub struct Timestamp {
+    /// The current value of the sender's timestamp clock (TSval).
+    pub tsval: u32,
+    /// The echo of the most recent timestamp value received from the remote (TSecr).
+    /// Only valid in segments with the ACK bit set.
+    pub tsecr: u32,
+}
+
+/// The content of a fast open cookie option, see [RFC7413].
+///
+/// An empty cookie is a request for the server to provide one.
+///
+/// [RFC7413]: https://tools.ietf.org/html/rfc7413#section-4.1.1
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy, Hash)]
+pub struct FastOpenCookie {
+    len: u8,
+    bytes: [u8; FastOpenCookie::MAX_LEN],
+}
+
+impl FastOpenCookie {
+    /// The minimum length of a non-empty cookie.
+    pub const MIN_LEN: usize = 4;
+
+    /// The maximum length of a cookie.
+    pub const MAX_LEN: usize = 16;
+
+    /// An empty cookie, requesting a cookie from the server.
+    pub const fn request() -> Self {
+        FastOpenCookie {
+            len: 0,
+            bytes: [0; FastOpenCookie::MAX_LEN],
+        }
+    }
+
+    /// Create a cookie from its bytes.
+    ///
+    /// Returns `None` if the length is not empty and not within the bounds of `MIN_LEN` and
+    /// `MAX_LEN`.
+    pub fn new(cookie: &[u8]) -> Option<Self> {
+        match cookie.len() {
+            0 => Some(FastOpenCookie::request()),
+            len if !(Self::MIN_LEN..=Self::MAX_LEN).contains(&len) => None,
+            len => {
+                let mut bytes = [0; FastOpenCookie::MAX_LEN];
+                bytes[..len].copy_from_slice(cookie);
+                Some(FastOpenCookie { len: len as u8, bytes })
+            },
+        }
+    }
+
+    /// Check if this cookie is empty, requesting a cookie.
+    pub fn is_request(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The bytes of the cookie.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..usize::from(self.len)]
+    }
+}
+
 impl<'a> TcpOption<'a> {
     /// Split the first option from a buffer.
     ///
@@ -701,6 +774,18 @@ impl<'a> TcpOption<'a> {
                         });
                         option = TcpOption::SackRange(sack_ranges);
                     },
+                    (field::OPT_TSTAMP, 10) =>
+                        option = TcpOption::Timestamp(Timestamp {
+                            tsval: NetworkEndian::read_u32(&data[..4]),
+                            tsecr: NetworkEndian::read_u32(&data[4..]),
+                        }),
+                    (field::OPT_TSTAMP, _) =>
+                        return Err(Error::Malformed),
+                    (field::OPT_TFO, n) if n == 2
+                        || (2 + FastOpenCookie::MIN_LEN..=2 + FastOpenCookie::MAX_LEN).contains(&n) =>
+                        option = TcpOption::FastOpen(data),
+                    (field::OPT_TFO, _) =>
+                        return Err(Error::Malformed),
                     (_, _) =>
                         option = TcpOption::Unknown { kind: kind, data: data }
                 }
@@ -718,6 +803,8 @@ impl<'a> TcpOption<'a> {
             TcpOption::WindowScale(_) => 3,
             TcpOption::SackPermitted => 2,
             TcpOption::SackRange(s) => s.iter().filter(|s| s.is_some()).count() * 8 + 2,
+            TcpOption::Timestamp(_) => 10,
+            TcpOption::FastOpen(cookie) => 2 + cookie.len(),
             TcpOption::Unknown { data, .. } => 2 + data.len()
         }
     }
@@ -766,6 +853,15 @@ impl<'a> TcpOption<'a> {
                             NetworkEndian::write_u32(&mut buffer[pos+4..], second);
                         });
                     }
+                    TcpOption::Timestamp(timestamp) => {
+                        buffer[0] = field::OPT_TSTAMP;
+                        NetworkEndian::write_u32(&mut buffer[2..], timestamp.tsval);
+                        NetworkEndian::write_u32(&mut buffer[6..], timestamp.tsecr);
+                    }
+                    TcpOption::FastOpen(cookie) => {
+                        buffer[0] = field::OPT_TFO;
+                        buffer[2..length].copy_from_slice(cookie)
+                    }
                     TcpOption::Unknown { kind, data: provided } => {
                         buffer[0] = kind;
                         buffer[2..].copy_from_slice(provided)
@@ -810,6 +906,12 @@ pub struct Repr {
     /// The selective acknowledgement ranges.
     /// See [`TcpOption::SackRange`](struct.TcpOption.html#variant.SackRange).
     pub sack_ranges:  [Option<(u32, u32)>; 3],
+    /// The timestamp option, if present.
+    /// See [`TcpOption::Timestamp`](struct.TcpOption.html#variant.Timestamp).
+    pub timestamp:    Option<Timestamp>,
+    /// The fast open cookie option, if present.
+    /// See [`TcpOption::FastOpen`](struct.TcpOption.html#variant.FastOpen).
+    pub fast_open:    Option<FastOpenCookie>,
     /// The length of the segment carried by the packet.
     pub payload_len:  u16,
 }
@@ -855,6 +957,8 @@ impl Repr {
             max_seg_size: None,
             sack_permitted: false,
             sack_ranges: [None; 3],
+            timestamp: None,
+            fast_open: None,
             payload_len: 0,
         });
         packet.check_len()?;
@@ -885,6 +989,8 @@ impl Repr {
         let mut options = packet.options();
         let mut sack_permitted = false;
         let mut sack_ranges = [None, None, None];
+        let mut timestamp = None;
+        let mut fast_open = None;
         while options.len() > 0 {
             let (next_options, option) = TcpOption::parse(options)?;
             match option {
@@ -908,6 +1014,10 @@ impl Repr {
                     sack_permitted = true,
                 TcpOption::SackRange(slice) =>
                     sack_ranges = slice,
+                TcpOption::Timestamp(value) =>
+                    timestamp = Some(value),
+                TcpOption::FastOpen(cookie) =>
+                    fast_open = FastOpenCookie::new(cookie),
                 _ => (),
             }
             options = next_options;
@@ -924,6 +1034,8 @@ impl Repr {
             max_seg_size: max_seg_size,
             sack_permitted: sack_permitted,
             sack_ranges:   sack_ranges,
+            timestamp,
+            fast_open,
             payload_len:  packet.payload_slice().len() as u16,
         })
     }
@@ -949,6 +1061,12 @@ impl Repr {
         if sack_range_len > 0 {
             length += sack_range_len + 2;
         }
+        if self.timestamp.is_some() {
+            length += 10;
+        }
+        if let Some(cookie) = &self.fast_open {
+            length += 2 + cookie.as_bytes().len();
+        }
         if length % 4 != 0 {
             length += 4 - length % 4;
         }
@@ -994,6 +1112,12 @@ impl Repr {
             } else if self.ack_number.is_some() && self.sack_ranges.iter().any(|s| s.is_some()) {
                 let tmp = options; options = TcpOption::SackRange(self.sack_ranges).emit(tmp);
             }
+            if let Some(value) = self.timestamp {
+                let tmp = options; options = TcpOption::Timestamp(value).emit(tmp);
+            }
+            if let Some(cookie) = &self.fast_open {
+                let tmp = options; options = TcpOption::FastOpen(cookie.as_bytes()).emit(tmp);
+            }
 
             if options.len() > 0 {
                 TcpOption::EndOfList.emit(options);
@@ -1050,6 +1174,10 @@ impl<'a, T: Payload + ?Sized> fmt::Display for Packet<&'a T> {
                     write!(f, " sACK")?,
                 TcpOption::SackRange(slice) =>
                     write!(f, " sACKr{:?}", slice)?, // debug print conveniently includes the []s
+                TcpOption::Timestamp(value) =>
+                    write!(f, " tsval={} tsecr={}", value.tsval, value.tsecr)?,
+                TcpOption::FastOpen(cookie) =>
+                    write!(f, " tfo={:?}", cookie)?,
                 TcpOption::Unknown { kind, .. } =>
                     write!(f, " opt({})", kind)?,
             }
@@ -1221,6 +1349,8 @@ mod test {
             max_seg_size: None,
             sack_permitted: false,
             sack_ranges:  [None, None, None],
+            timestamp:    None,
+            fast_open:    None,
             payload_len:  PAYLOAD_BYTES.len() as _,
         }
     }
@@ -1288,6 +1418,13 @@ mod test {
                                 0x00, 0x0d, 0x59, 0xf8, 0x00, 0x12, 0xb1, 0x28,
                                 0x00, 0x16, 0xe3, 0x60, 0x00, 0x26, 0x25, 0xa0,
                                 0x34, 0x3e, 0xfc, 0xea, 0x34, 0x40, 0xae, 0xf0]);
+        assert_option_parses!(TcpOption::Timestamp(Timestamp { tsval: 0x01020304, tsecr: 0xa0b0c0d0 }),
+                              &[0x08, 0x0a,
+                                0x01, 0x02, 0x03, 0x04, 0xa0, 0xb0, 0xc0, 0xd0]);
+        assert_option_parses!(TcpOption::FastOpen(&[]),
+                              &[0x22, 0x02]);
+        assert_option_parses!(TcpOption::FastOpen(&[1, 2, 3, 4, 5, 6, 7, 8]),
+                              &[0x22, 0x0a, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08]);
         assert_option_parses!(TcpOption::Unknown { kind: 12, data: &[1, 2, 3][..] },
                               &[0x0c, 0x05, 0x01, 0x02, 0x03])
     }
@@ -1306,5 +1443,9 @@ mod test {
                    Err(Error::Malformed));
         assert_eq!(TcpOption::parse(&[0x3, 0x02]),
                    Err(Error::Malformed));
+        assert_eq!(TcpOption::parse(&[0x8, 0x06, 0x00, 0x00, 0x00, 0x00]),
+                   Err(Error::Malformed));
+        assert_eq!(TcpOption::parse(&[0x22, 0x04, 0x00, 0x00]),
+                   Err(Error::Malformed));
     }
 }